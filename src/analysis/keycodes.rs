@@ -0,0 +1,526 @@
+use crate::error::{KarabinerPklError, Result};
+use crate::karabiner::{Config, FromEvent, SimpleFrom, SimpleTo, ToEvent};
+use miette::Diagnostic;
+use thiserror::Error;
+
+pub const KEY_CODES: &[&str] = &[
+    "a",
+    "b",
+    "c",
+    "d",
+    "e",
+    "f",
+    "g",
+    "h",
+    "i",
+    "j",
+    "k",
+    "l",
+    "m",
+    "n",
+    "o",
+    "p",
+    "q",
+    "r",
+    "s",
+    "t",
+    "u",
+    "v",
+    "w",
+    "x",
+    "y",
+    "z",
+    "1",
+    "2",
+    "3",
+    "4",
+    "5",
+    "6",
+    "7",
+    "8",
+    "9",
+    "0",
+    "return_or_enter",
+    "escape",
+    "delete_or_backspace",
+    "delete_forward",
+    "tab",
+    "spacebar",
+    "hyphen",
+    "equal_sign",
+    "open_bracket",
+    "close_bracket",
+    "backslash",
+    "non_us_pound",
+    "semicolon",
+    "quote",
+    "grave_accent_and_tilde",
+    "comma",
+    "period",
+    "slash",
+    "non_us_backslash",
+    "caps_lock",
+    "left_control",
+    "left_shift",
+    "left_option",
+    "left_command",
+    "right_control",
+    "right_shift",
+    "right_option",
+    "right_command",
+    "fn",
+    "left_alt",
+    "left_gui",
+    "right_alt",
+    "right_gui",
+    "f1",
+    "f2",
+    "f3",
+    "f4",
+    "f5",
+    "f6",
+    "f7",
+    "f8",
+    "f9",
+    "f10",
+    "f11",
+    "f12",
+    "f13",
+    "f14",
+    "f15",
+    "f16",
+    "f17",
+    "f18",
+    "f19",
+    "f20",
+    "f21",
+    "f22",
+    "f23",
+    "f24",
+    "up_arrow",
+    "down_arrow",
+    "left_arrow",
+    "right_arrow",
+    "page_up",
+    "page_down",
+    "home",
+    "end",
+    "print_screen",
+    "scroll_lock",
+    "pause",
+    "insert",
+    "application",
+    "help",
+    "power",
+    "execute",
+    "menu",
+    "select",
+    "stop",
+    "again",
+    "undo",
+    "cut",
+    "copy",
+    "paste",
+    "find",
+    "display_brightness_decrement",
+    "display_brightness_increment",
+    "dashboard",
+    "launchpad",
+    "mission_control",
+    "illumination_decrement",
+    "illumination_increment",
+    "rewind",
+    "play_or_pause",
+    "fastforward",
+    "mute",
+    "volume_decrement",
+    "volume_increment",
+    "volume_down",
+    "volume_up",
+    "eject",
+    "apple_display_brightness_decrement",
+    "apple_display_brightness_increment",
+    "apple_top_case_display_brightness_decrement",
+    "apple_top_case_display_brightness_increment",
+    "keypad_num_lock",
+    "keypad_slash",
+    "keypad_asterisk",
+    "keypad_hyphen",
+    "keypad_plus",
+    "keypad_enter",
+    "keypad_1",
+    "keypad_2",
+    "keypad_3",
+    "keypad_4",
+    "keypad_5",
+    "keypad_6",
+    "keypad_7",
+    "keypad_8",
+    "keypad_9",
+    "keypad_0",
+    "keypad_period",
+    "keypad_equal_sign",
+    "keypad_comma",
+    "japanese_eisuu",
+    "japanese_kana",
+    "japanese_pc_nfer",
+    "japanese_pc_xfer",
+    "japanese_pc_katakana",
+    "lang1",
+    "lang2",
+    "lang3",
+    "lang4",
+    "lang5",
+    "lang6",
+    "lang7",
+    "lang8",
+    "lang9",
+    "international1",
+    "international2",
+    "international3",
+    "international4",
+    "international5",
+    "international6",
+    "international7",
+    "international8",
+    "international9",
+    "vk_none",
+];
+
+pub const CONSUMER_KEY_CODES: &[&str] = &[
+    "power",
+    "display_brightness_increment",
+    "display_brightness_decrement",
+    "fastforward",
+    "rewind",
+    "scan_next_track",
+    "scan_previous_track",
+    "eject",
+    "play_or_pause",
+    "mute",
+    "volume_increment",
+    "volume_decrement",
+    "menu",
+    "dictation",
+    "voice_command",
+    "al_terminal_lock_or_screensaver",
+    "al_consumer_control_configuration",
+    "al_word_processor",
+    "al_text_editor",
+    "al_spreadsheet",
+    "al_graphics_editor",
+    "al_presentation_app",
+    "al_database_app",
+    "al_email_reader",
+    "al_voicemail",
+    "al_calendar_or_schedule",
+    "al_calculator",
+    "al_local_machine_browser",
+    "al_internet_browser",
+    "al_network_chat",
+    "al_keyboard_layout",
+    "ac_search",
+    "ac_home",
+    "ac_back",
+    "ac_forward",
+    "ac_refresh",
+    "ac_bookmarks",
+    "ac_pan",
+];
+
+const POINTING_BUTTON_COUNT: usize = 32;
+
+// Shorthands people reach for that don't survive an edit-distance search on their own.
+const WORD_ALIASES: &[(&str, &str)] = &[
+    ("ctrl", "control"),
+    ("ctl", "control"),
+    ("cmd", "command"),
+    ("alt", "option"),
+    ("opt", "option"),
+];
+
+const KEY_ALIASES: &[(&str, &str)] = &[
+    ("cmd", "left_command"),
+    ("ctrl", "left_control"),
+    ("alt", "left_option"),
+    ("opt", "left_option"),
+    ("shift", "left_shift"),
+    ("esc", "escape"),
+    ("enter", "return_or_enter"),
+    ("return", "return_or_enter"),
+    ("backspace", "delete_or_backspace"),
+    ("delete", "delete_or_backspace"),
+    ("del", "delete_or_backspace"),
+    ("space", "spacebar"),
+    ("caps", "caps_lock"),
+    ("minus", "hyphen"),
+    ("dash", "hyphen"),
+    ("equal", "equal_sign"),
+    ("equals", "equal_sign"),
+    ("grave", "grave_accent_and_tilde"),
+    ("backtick", "grave_accent_and_tilde"),
+    ("tilde", "grave_accent_and_tilde"),
+    ("up", "up_arrow"),
+    ("down", "down_arrow"),
+    ("left", "left_arrow"),
+    ("right", "right_arrow"),
+    ("pgup", "page_up"),
+    ("pgdn", "page_down"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeKind {
+    KeyCode,
+    ConsumerKeyCode,
+    PointingButton,
+}
+
+impl CodeKind {
+    fn field(self) -> &'static str {
+        match self {
+            CodeKind::KeyCode => "key_code",
+            CodeKind::ConsumerKeyCode => "consumer_key_code",
+            CodeKind::PointingButton => "pointing_button",
+        }
+    }
+
+    fn is_valid(self, value: &str) -> bool {
+        match self {
+            CodeKind::KeyCode => KEY_CODES.contains(&value),
+            CodeKind::ConsumerKeyCode => CONSUMER_KEY_CODES.contains(&value),
+            CodeKind::PointingButton => value
+                .strip_prefix("button")
+                .and_then(|n| n.parse::<usize>().ok())
+                .is_some_and(|n| (1..=POINTING_BUTTON_COUNT).contains(&n)),
+        }
+    }
+
+    fn candidates(self) -> Vec<String> {
+        match self {
+            CodeKind::KeyCode => KEY_CODES.iter().map(|s| s.to_string()).collect(),
+            CodeKind::ConsumerKeyCode => CONSUMER_KEY_CODES.iter().map(|s| s.to_string()).collect(),
+            CodeKind::PointingButton => (1..=POINTING_BUTTON_COUNT)
+                .map(|n| format!("button{n}"))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Unknown {} `{value}` at `{path}`", kind.field())]
+#[diagnostic(code(ankura::invalid_key_code))]
+pub struct InvalidKeyCode {
+    pub kind: CodeKind,
+    pub value: String,
+    pub path: String,
+    #[help]
+    pub help: Option<String>,
+}
+
+pub fn validate(config: &Config) -> Result<()> {
+    let mut problems = Vec::new();
+    let mut check = |kind: CodeKind, value: &str, path: String| {
+        if !kind.is_valid(value) {
+            let suggestions = suggest(kind, value);
+            let help = (!suggestions.is_empty()).then(|| {
+                let names: Vec<String> = suggestions.iter().map(|s| format!("`{s}`")).collect();
+                format!("did you mean {}?", names.join(" or "))
+            });
+            problems.push(InvalidKeyCode {
+                kind,
+                value: value.to_string(),
+                path,
+                help,
+            });
+        }
+    };
+
+    for (p, profile) in config.profiles.iter().enumerate() {
+        let base = format!("profiles[{p}]");
+        walk_simple(
+            &mut check,
+            &format!("{base}.simple_modifications"),
+            profile
+                .simple_modifications
+                .iter()
+                .map(|m| (&m.from, &m.to)),
+        );
+        walk_simple(
+            &mut check,
+            &format!("{base}.fn_function_keys"),
+            profile.fn_function_keys.iter().map(|m| (&m.from, &m.to)),
+        );
+
+        for (d, device) in profile.devices.iter().enumerate() {
+            walk_simple(
+                &mut check,
+                &format!("{base}.devices[{d}].simple_modifications"),
+                device.simple_modifications.iter().map(|m| (&m.from, &m.to)),
+            );
+            walk_simple(
+                &mut check,
+                &format!("{base}.devices[{d}].fn_function_keys"),
+                device.fn_function_keys.iter().map(|m| (&m.from, &m.to)),
+            );
+        }
+
+        for (r, rule) in profile.rules().iter().enumerate() {
+            for (m, manipulator) in rule.manipulators.iter().enumerate() {
+                let path = format!("{base}.complex_modifications.rules[{r}].manipulators[{m}]");
+                walk_from(&mut check, &format!("{path}.from"), &manipulator.from);
+
+                let to_lists = [
+                    ("to", &manipulator.to),
+                    ("to_if_alone", &manipulator.to_if_alone),
+                    ("to_if_held_down", &manipulator.to_if_held_down),
+                    ("to_after_key_up", &manipulator.to_after_key_up),
+                ];
+                for (field, events) in to_lists {
+                    walk_to_list(
+                        &mut check,
+                        &format!("{path}.{field}"),
+                        events.iter().flatten(),
+                    );
+                }
+
+                if let Some(delayed) = &manipulator.to_delayed_action {
+                    walk_to_list(
+                        &mut check,
+                        &format!("{path}.to_delayed_action.to_if_invoked"),
+                        delayed.to_if_invoked.iter(),
+                    );
+                    walk_to_list(
+                        &mut check,
+                        &format!("{path}.to_delayed_action.to_if_canceled"),
+                        delayed.to_if_canceled.iter(),
+                    );
+                }
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(KarabinerPklError::InvalidKeyCodes {
+            count: problems.len(),
+            problems,
+        })
+    }
+}
+
+type Check<'a> = dyn FnMut(CodeKind, &str, String) + 'a;
+
+fn walk_codes(
+    check: &mut Check<'_>,
+    path: &str,
+    key_code: &Option<String>,
+    consumer_key_code: &Option<String>,
+    pointing_button: &Option<String>,
+) {
+    let fields = [
+        (CodeKind::KeyCode, key_code),
+        (CodeKind::ConsumerKeyCode, consumer_key_code),
+        (CodeKind::PointingButton, pointing_button),
+    ];
+    for (kind, value) in fields {
+        if let Some(value) = value {
+            check(kind, value, format!("{path}.{}", kind.field()));
+        }
+    }
+}
+
+fn walk_from(check: &mut Check<'_>, path: &str, event: &FromEvent) {
+    walk_codes(
+        check,
+        path,
+        &event.key_code,
+        &event.consumer_key_code,
+        &event.pointing_button,
+    );
+
+    for (i, part) in event.simultaneous.iter().flatten().enumerate() {
+        walk_from(check, &format!("{path}.simultaneous[{i}]"), part);
+    }
+
+    if let Some(options) = &event.simultaneous_options {
+        walk_to_list(
+            check,
+            &format!("{path}.simultaneous_options.to_after_key_up"),
+            options.to_after_key_up.iter().flatten(),
+        );
+    }
+}
+
+fn walk_to_list<'a>(check: &mut Check<'_>, path: &str, events: impl Iterator<Item = &'a ToEvent>) {
+    for (i, event) in events.enumerate() {
+        walk_codes(
+            check,
+            &format!("{path}[{i}]"),
+            &event.key_code,
+            &event.consumer_key_code,
+            &event.pointing_button,
+        );
+    }
+}
+
+fn walk_simple<'a>(
+    check: &mut Check<'_>,
+    path: &str,
+    entries: impl Iterator<Item = (&'a SimpleFrom, &'a SimpleTo)>,
+) {
+    for (i, (from, to)) in entries.enumerate() {
+        let path = format!("{path}[{i}]");
+        match from {
+            SimpleFrom::KeyCode(code) => check(CodeKind::KeyCode, code, format!("{path}.from")),
+            SimpleFrom::Event(event) => walk_from(check, &format!("{path}.from"), event),
+        }
+        match to {
+            SimpleTo::KeyCode(code) => check(CodeKind::KeyCode, code, format!("{path}.to")),
+            SimpleTo::Events(events) => walk_to_list(check, &format!("{path}.to"), events.iter()),
+            SimpleTo::Event(event) => walk_to_list(
+                check,
+                &format!("{path}.to"),
+                std::iter::once(event.as_ref()),
+            ),
+        }
+    }
+}
+
+pub fn suggest(kind: CodeKind, value: &str) -> Vec<String> {
+    let normalized = normalize(value);
+    let candidates = kind.candidates();
+
+    if candidates.contains(&normalized) {
+        return vec![normalized];
+    }
+
+    let max_distance = (normalized.len() / 3).max(2);
+    let mut scored: Vec<(usize, String)> = candidates
+        .into_iter()
+        .map(|candidate| (strsim::levenshtein(&normalized, &candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    scored.sort();
+    scored.into_iter().take(3).map(|(_, name)| name).collect()
+}
+
+fn normalize(value: &str) -> String {
+    let lowered = value.trim().to_lowercase().replace(['-', ' '], "_");
+
+    if let Some((_, full)) = KEY_ALIASES.iter().find(|(alias, _)| *alias == lowered) {
+        return full.to_string();
+    }
+
+    lowered
+        .split('_')
+        .map(|part| {
+            WORD_ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == part)
+                .map(|(_, full)| *full)
+                .unwrap_or(part)
+        })
+        .collect::<Vec<_>>()
+        .join("_")
+}
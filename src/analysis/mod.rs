@@ -0,0 +1,436 @@
+pub mod keycodes;
+
+use crate::karabiner::{
+    Condition, Config, FromEvent, KeyOrder, Manipulator, ManipulatorType, Profile, VariableValue,
+};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FindingKind {
+    Duplicate,
+    Conflict,
+    Shadowed,
+    // Same binding on two different layer variables; only a clash while both layers are on.
+    LayerOverlap,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub rule_index: usize,
+    pub rule_description: String,
+    pub manipulator_index: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rule #{} \"{}\" (manipulator #{})",
+            self.rule_index + 1,
+            self.rule_description,
+            self.manipulator_index + 1
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub kind: FindingKind,
+    pub profile: String,
+    pub binding: String,
+    pub earlier: Location,
+    pub later: Location,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            FindingKind::Duplicate => write!(
+                f,
+                "[{}] `{}` in {} duplicates {}",
+                self.profile, self.binding, self.later, self.earlier
+            ),
+            FindingKind::Conflict => write!(
+                f,
+                "[{}] `{}` in {} conflicts with {}, which Karabiner matches first",
+                self.profile, self.binding, self.later, self.earlier
+            ),
+            FindingKind::Shadowed => write!(
+                f,
+                "[{}] `{}` in {} can never fire, it is shadowed by {}",
+                self.profile, self.binding, self.later, self.earlier
+            ),
+            FindingKind::LayerOverlap => write!(
+                f,
+                "[{}] `{}` in {} is on a different layer than {}, which Karabiner matches first while both layers are on",
+                self.profile, self.binding, self.later, self.earlier
+            ),
+        }
+    }
+}
+
+/// `layer_overlaps` also reports bindings that only clash while two layers are on together.
+pub fn analyze_config(config: &Config, layer_overlaps: bool) -> Vec<Finding> {
+    config
+        .profiles
+        .iter()
+        .flat_map(|profile| analyze_profile(profile, layer_overlaps))
+        .collect()
+}
+
+pub fn analyze_profile(profile: &Profile, layer_overlaps: bool) -> Vec<Finding> {
+    let mut entries = Vec::new();
+    for (rule_index, rule) in profile.rules().iter().enumerate() {
+        for (manipulator_index, manipulator) in rule.manipulators.iter().enumerate() {
+            if manipulator.manipulator_type != ManipulatorType::Basic {
+                continue;
+            }
+            let Some(trigger) = Trigger::of(&manipulator.from) else {
+                continue;
+            };
+            entries.push(Entry {
+                location: Location {
+                    rule_index,
+                    rule_description: rule.description.clone(),
+                    manipulator_index,
+                },
+                manipulator,
+                trigger,
+                modifiers: ModifierSpec::of(&manipulator.from),
+            });
+        }
+    }
+
+    let mut by_trigger: HashMap<&Trigger, Vec<usize>> = HashMap::new();
+    for (index, entry) in entries.iter().enumerate() {
+        by_trigger.entry(&entry.trigger).or_default().push(index);
+    }
+
+    let mut findings = Vec::new();
+    for (index, later) in entries.iter().enumerate() {
+        let candidates: Vec<(FindingKind, &Entry)> = by_trigger[&later.trigger]
+            .iter()
+            .take_while(|earlier| **earlier < index)
+            .filter_map(|earlier| classify(&entries[*earlier], later))
+            .collect();
+        // Only report the first earlier manipulator that wins over `later`; the rest are noise.
+        // A layer overlap is reported only when nothing wins outright.
+        let finding = candidates
+            .iter()
+            .find(|(kind, _)| *kind != FindingKind::LayerOverlap)
+            .or_else(|| candidates.first().filter(|_| layer_overlaps));
+
+        if let Some((kind, earlier)) = finding {
+            findings.push(Finding {
+                kind: *kind,
+                profile: profile.name.clone(),
+                binding: later.describe(),
+                earlier: earlier.location.clone(),
+                later: later.location.clone(),
+            });
+        }
+    }
+
+    findings
+}
+
+struct Entry<'a> {
+    location: Location,
+    manipulator: &'a Manipulator,
+    trigger: Trigger,
+    modifiers: ModifierSpec,
+}
+
+impl Entry<'_> {
+    fn describe(&self) -> String {
+        let mut parts: Vec<&str> = self
+            .modifiers
+            .mandatory
+            .iter()
+            .map(String::as_str)
+            .collect();
+        let trigger = self.trigger.to_string();
+        parts.push(&trigger);
+        parts.join("+")
+    }
+}
+
+fn classify<'a>(earlier: &'a Entry<'a>, later: &Entry<'_>) -> Option<(FindingKind, &'a Entry<'a>)> {
+    if earlier.manipulator == later.manipulator {
+        return Some((FindingKind::Duplicate, earlier));
+    }
+
+    let earlier_conditions = earlier
+        .manipulator
+        .conditions
+        .as_deref()
+        .unwrap_or_default();
+    let later_conditions = later.manipulator.conditions.as_deref().unwrap_or_default();
+
+    let overlap = conditions_overlap(earlier_conditions, later_conditions);
+    let conflict = if on_different_layers(earlier_conditions, later_conditions) {
+        FindingKind::LayerOverlap
+    } else {
+        FindingKind::Conflict
+    };
+
+    if earlier.modifiers == later.modifiers && overlap {
+        return Some((conflict, earlier));
+    }
+
+    if earlier.modifiers.covers(&later.modifiers)
+        && earlier_conditions
+            .iter()
+            .all(|condition| later_conditions.contains(condition))
+    {
+        return Some((FindingKind::Shadowed, earlier));
+    }
+
+    if earlier.modifiers.mandatory == later.modifiers.mandatory && overlap {
+        return Some((conflict, earlier));
+    }
+
+    None
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Trigger {
+    Key(String),
+    Simultaneous(Vec<String>),
+}
+
+impl Trigger {
+    fn of(from: &FromEvent) -> Option<Self> {
+        if let Some(events) = &from.simultaneous {
+            let mut keys: Vec<String> = events.iter().filter_map(event_identity).collect();
+            let ordered = from
+                .simultaneous_options
+                .as_ref()
+                .and_then(|options| options.key_down_order)
+                .is_some_and(|order| order != KeyOrder::Insensitive);
+            if !ordered {
+                keys.sort();
+            }
+            return Some(Trigger::Simultaneous(keys));
+        }
+
+        event_identity(from).map(Trigger::Key)
+    }
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trigger::Key(key) => write!(f, "{key}"),
+            Trigger::Simultaneous(keys) => write!(f, "{{{}}}", keys.join(" ")),
+        }
+    }
+}
+
+fn event_identity(event: &FromEvent) -> Option<String> {
+    event
+        .key_code
+        .clone()
+        .or_else(|| {
+            event
+                .consumer_key_code
+                .as_ref()
+                .map(|code| format!("consumer:{code}"))
+        })
+        .or_else(|| {
+            event
+                .pointing_button
+                .as_ref()
+                .map(|button| format!("pointer:{button}"))
+        })
+        .or_else(|| event.any.as_ref().map(|any| format!("any:{any}")))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ModifierSpec {
+    mandatory: BTreeSet<String>,
+    optional: BTreeSet<String>,
+}
+
+impl ModifierSpec {
+    fn of(from: &FromEvent) -> Self {
+        from.modifiers
+            .as_ref()
+            .map(|modifiers| Self {
+                mandatory: modifiers.mandatory.iter().cloned().collect(),
+                optional: modifiers.optional.iter().cloned().collect(),
+            })
+            .unwrap_or_default()
+    }
+
+    fn accepts_any(&self) -> bool {
+        self.optional.contains("any")
+    }
+
+    fn allows(&self, modifier: &str) -> bool {
+        self.accepts_any()
+            || self.mandatory.contains(modifier)
+            || self.optional.contains(modifier)
+            || sided_generic(modifier).is_some_and(|generic| {
+                self.mandatory.contains(generic) || self.optional.contains(generic)
+            })
+    }
+
+    // True when every modifier state that satisfies `other` also satisfies `self`.
+    fn covers(&self, other: &ModifierSpec) -> bool {
+        let mandatory_implied = self
+            .mandatory
+            .iter()
+            .all(|modifier| implied_by(modifier, &other.mandatory));
+
+        if !mandatory_implied {
+            return false;
+        }
+
+        if self.accepts_any() {
+            return true;
+        }
+
+        if other.accepts_any() {
+            return false;
+        }
+
+        other
+            .mandatory
+            .iter()
+            .chain(other.optional.iter())
+            .flat_map(|modifier| expand(modifier))
+            .all(|modifier| self.allows(modifier))
+    }
+}
+
+fn sided_generic(modifier: &str) -> Option<&'static str> {
+    match modifier {
+        "left_command" | "right_command" => Some("command"),
+        "left_control" | "right_control" => Some("control"),
+        "left_option" | "right_option" => Some("option"),
+        "left_shift" | "right_shift" => Some("shift"),
+        _ => None,
+    }
+}
+
+fn expand(modifier: &str) -> Vec<&str> {
+    match modifier {
+        "command" => vec!["left_command", "right_command"],
+        "control" => vec!["left_control", "right_control"],
+        "option" => vec!["left_option", "right_option"],
+        "shift" => vec!["left_shift", "right_shift"],
+        other => vec![other],
+    }
+}
+
+fn implied_by(modifier: &str, held: &BTreeSet<String>) -> bool {
+    held.contains(modifier)
+        || held
+            .iter()
+            .any(|other| sided_generic(other) == Some(modifier))
+}
+
+fn conditions_overlap(left: &[Condition], right: &[Condition]) -> bool {
+    !left
+        .iter()
+        .any(|a| right.iter().any(|b| mutually_exclusive(a, b)))
+}
+
+// Layer variables, like those `SimLayer` and variable layers set, can be on together (a SimLayer
+// held while a layer key is down), so rules on `nav` and on `sym` still overlap. They're told
+// apart from plain conflicts because most configs never hold two layers at once.
+fn on_different_layers(left: &[Condition], right: &[Condition]) -> bool {
+    let layers = |conditions: &[Condition]| -> BTreeSet<String> {
+        conditions
+            .iter()
+            .filter(|condition| {
+                condition.condition_type == "variable_if"
+                    && !matches!(
+                        condition.value,
+                        Some(VariableValue::Int(0) | VariableValue::Bool(false))
+                    )
+            })
+            .filter_map(|condition| condition.name.clone())
+            .collect()
+    };
+    let (left, right) = (layers(left), layers(right));
+    !left.is_subset(&right) && !right.is_subset(&left)
+}
+
+fn mutually_exclusive(a: &Condition, b: &Condition) -> bool {
+    let (a_type, b_type) = (a.condition_type.as_str(), b.condition_type.as_str());
+
+    if a_type == "variable_if" || a_type == "variable_unless" {
+        if a.name != b.name {
+            return false;
+        }
+        return match (a_type, b_type) {
+            ("variable_if", "variable_if") => a.value != b.value,
+            ("variable_if", "variable_unless") | ("variable_unless", "variable_if") => {
+                a.value == b.value
+            }
+            _ => false,
+        };
+    }
+
+    let Some((a_base, a_positive)) = split_condition_type(a_type) else {
+        return false;
+    };
+    let Some((b_base, b_positive)) = split_condition_type(b_type) else {
+        return false;
+    };
+    if a_base != b_base {
+        return false;
+    }
+
+    let (a_values, b_values) = (condition_values(a), condition_values(b));
+    if a_values.is_empty() || b_values.is_empty() {
+        return false;
+    }
+    match (a_positive, b_positive) {
+        (true, true) => a_values.is_disjoint(&b_values),
+        (true, false) => a_values.is_subset(&b_values),
+        (false, true) => b_values.is_subset(&a_values),
+        (false, false) => false,
+    }
+}
+
+fn split_condition_type(condition_type: &str) -> Option<(&str, bool)> {
+    if let Some(base) = condition_type.strip_suffix("_if") {
+        Some((base, true))
+    } else {
+        condition_type
+            .strip_suffix("_unless")
+            .map(|base| (base, false))
+    }
+}
+
+fn condition_values(condition: &Condition) -> BTreeSet<String> {
+    let mut values = BTreeSet::new();
+    values.extend(condition.bundle_identifiers.iter().flatten().cloned());
+    values.extend(
+        condition
+            .file_paths
+            .iter()
+            .flatten()
+            .map(|path| format!("path:{path}")),
+    );
+    values.extend(condition.keyboard_types.iter().flatten().cloned());
+    values.extend(
+        condition
+            .identifiers
+            .iter()
+            .flatten()
+            .map(|identifiers| serde_json::to_string(identifiers).unwrap_or_default()),
+    );
+    values.extend(
+        condition
+            .input_sources
+            .iter()
+            .flatten()
+            .map(|source| serde_json::to_string(source).unwrap_or_default()),
+    );
+    values
+}
@@ -0,0 +1,183 @@
+use crate::cli::homebrew_var_dir;
+use crate::error::{KarabinerPklError, Result};
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use std::fs::{File, OpenOptions};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+pub const DEFAULT_KEEP: usize = 20;
+
+// UTC, so ids sort in the order backups were taken across DST changes. Backups taken in the
+// same millisecond get a `-N` suffix.
+const ID_FORMAT: &str = "%Y%m%d-%H%M%S-%3f";
+const FILE_PREFIX: &str = "karabiner-";
+
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub id: String,
+    pub path: PathBuf,
+    pub created: DateTime<Local>,
+    pub size: u64,
+}
+
+/// Timestamped copies of karabiner.json taken before every write, newest kept.
+pub struct BackupStore {
+    dir: PathBuf,
+}
+
+impl BackupStore {
+    pub fn new() -> Result<Self> {
+        Ok(Self::at(homebrew_var_dir()?.join("ankura").join("backups")))
+    }
+
+    /// A store in `dir` instead of the Homebrew var directory.
+    pub fn at(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Copies `source` into the store and prunes everything beyond the newest `keep` backups.
+    /// Returns `None` when there is nothing to back up yet.
+    pub fn create(&self, source: &Path, keep: usize) -> Result<Option<Backup>> {
+        if !source.exists() {
+            return Ok(None);
+        }
+
+        std::fs::create_dir_all(&self.dir).map_err(|e| KarabinerPklError::BackupError {
+            message: format!("Failed to create {}: {e}", self.dir.display()),
+        })?;
+
+        let created = Utc::now();
+        let stamp = created.format(ID_FORMAT).to_string();
+        let mut sequence = 0;
+        let (id, path, mut file) = loop {
+            let id = match sequence {
+                0 => stamp.clone(),
+                n => format!("{stamp}-{n}"),
+            };
+            let path = self.dir.join(format!("{FILE_PREFIX}{id}.json"));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (id, path, file),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => sequence += 1,
+                Err(e) => {
+                    return Err(KarabinerPklError::BackupError {
+                        message: format!("Failed to create {}: {e}", path.display()),
+                    })
+                }
+            }
+        };
+
+        let size = File::open(source)
+            .and_then(|mut source| std::io::copy(&mut source, &mut file))
+            .map_err(|e| {
+                let _ = std::fs::remove_file(&path);
+                KarabinerPklError::BackupError {
+                    message: format!("Failed to back up {}: {e}", source.display()),
+                }
+            })?;
+        debug!("Backed up {} to {}", source.display(), path.display());
+
+        self.prune(keep.max(1))?;
+
+        Ok(Some(Backup {
+            id,
+            path,
+            created: created.with_timezone(&Local),
+            size,
+        }))
+    }
+
+    /// All backups, newest first.
+    pub fn list(&self) -> Result<Vec<Backup>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(KarabinerPklError::BackupError {
+                    message: format!("Failed to read {}: {e}", self.dir.display()),
+                })
+            }
+        };
+
+        let mut backups: Vec<(u32, Backup)> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                let id = path
+                    .file_name()?
+                    .to_str()?
+                    .strip_prefix(FILE_PREFIX)?
+                    .strip_suffix(".json")?
+                    .to_string();
+                let (naive, rest) = NaiveDateTime::parse_and_remainder(&id, ID_FORMAT).ok()?;
+                let sequence = match rest {
+                    "" => 0,
+                    rest => rest.strip_prefix('-')?.parse().ok()?,
+                };
+                let created = naive.and_utc().with_timezone(&Local);
+                let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                Some((
+                    sequence,
+                    Backup {
+                        id,
+                        path,
+                        created,
+                        size,
+                    },
+                ))
+            })
+            .collect();
+
+        backups.sort_by(|(a_sequence, a), (b_sequence, b)| {
+            (b.created, b_sequence).cmp(&(a.created, a_sequence))
+        });
+        Ok(backups.into_iter().map(|(_, backup)| backup).collect())
+    }
+
+    /// The backup with the given id (or unique id prefix), or the newest one.
+    pub fn find(&self, id: Option<&str>) -> Result<Backup> {
+        let mut backups = self.list()?;
+
+        let Some(id) = id else {
+            return backups
+                .into_iter()
+                .next()
+                .ok_or_else(|| KarabinerPklError::BackupError {
+                    message: format!("No backups found in {}", self.dir.display()),
+                });
+        };
+
+        // A full id is also a prefix of the backups taken in the same millisecond after it.
+        let mut matches: Vec<Backup> = match backups.iter().position(|backup| backup.id == id) {
+            Some(index) => vec![backups.swap_remove(index)],
+            None => backups
+                .into_iter()
+                .filter(|backup| backup.id.starts_with(id))
+                .collect(),
+        };
+
+        match matches.len() {
+            1 => Ok(matches.remove(0)),
+            0 => Err(KarabinerPklError::BackupError {
+                message: format!("No backup with id {id}; run `ankura backups list`"),
+            }),
+            count => Err(KarabinerPklError::BackupError {
+                message: format!("{count} backups match {id}; use the full id"),
+            }),
+        }
+    }
+
+    fn prune(&self, keep: usize) -> Result<()> {
+        for backup in self.list()?.into_iter().skip(keep) {
+            std::fs::remove_file(&backup.path).map_err(|e| KarabinerPklError::BackupError {
+                message: format!("Failed to remove old backup {}: {e}", backup.path.display()),
+            })?;
+            debug!("Removed old backup {}", backup.id);
+        }
+        Ok(())
+    }
+}
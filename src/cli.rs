@@ -1,18 +1,33 @@
+use crate::analysis;
+use crate::backup::BackupStore;
+use crate::compiler::cache::CompileCache;
 use crate::compiler::Compiler;
+use crate::daemon::control::{self, ControlSocket, Request, Response};
+use crate::daemon::state::{self, Liveness, StatusReport};
 use crate::daemon::Daemon;
+use crate::diff;
 use crate::error::{KarabinerPklError, Result};
+use crate::export::firmware::Layout;
+use crate::export::{self, Format};
 use crate::import;
+use crate::karabiner::{Config, VariableValue};
+use crate::merge::{self, MergePolicy};
+use crate::output::{self, OutputLock};
+use crate::settings::Settings;
+use crate::simulator::{self, Environment, Simulator, Timeline};
+use crate::snapshot;
+use crate::testing;
 use clap::{Parser, Subcommand};
-use serde_json::Value;
 use std::convert::TryInto;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::signal::unix::{signal, SignalKind};
+use tokio::signal::unix::{signal, Signal, SignalKind};
 use tokio::time::sleep;
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, warn};
 
 use libc::{self, c_int, pid_t, EPERM, ESRCH, SIGTERM};
 
@@ -46,11 +61,20 @@ pub enum Commands {
 
     Stop,
 
+    #[command(about = "Make the running daemon recompile now")]
+    Reload,
+
+    #[command(about = "Stop applying config changes until `ankura resume`")]
+    Pause,
+
+    #[command(about = "Apply config changes again, including any made while paused")]
+    Resume,
+
     Compile {
         #[arg(
             short,
             long,
-            help = "Override the profile name (default: uses config value or 'pkl')"
+            help = "Override the profile name, or pick one profile when the config defines several"
         )]
         profile_name: Option<String>,
 
@@ -60,9 +84,132 @@ pub enum Commands {
             help = "Output file path (default: ~/.config/karabiner/karabiner.json)"
         )]
         output: Option<String>,
+
+        #[arg(
+            long = "merge",
+            value_name = "FIELD=STRATEGY",
+            help = "Merge strategy (pkl, existing, deep) for devices, fn_function_keys, virtual_hid_keyboard, parameters, global or all; foreign_rules=before|after places rules ankura doesn't manage"
+        )]
+        merge: Vec<String>,
+    },
+
+    Check {
+        #[arg(long, help = "Treat manipulator conflicts and shadowing as errors")]
+        strict: bool,
+        #[arg(
+            long,
+            help = "Also report bindings on different layer variables, which clash only while both layers are on"
+        )]
+        layers: bool,
+    },
+
+    #[command(
+        about = "Show what compile would change in karabiner.json; exits 1 if anything differs"
+    )]
+    Diff {
+        #[arg(
+            short,
+            long,
+            help = "Override the profile name, or pick one profile when the config defines several"
+        )]
+        profile_name: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            help = "Karabiner file to compare against (default: ~/.config/karabiner/karabiner.json)"
+        )]
+        output: Option<String>,
+
+        #[arg(
+            long = "merge",
+            value_name = "FIELD=STRATEGY",
+            help = "Merge policy override, as for compile"
+        )]
+        merge: Vec<String>,
+
+        #[arg(long, help = "Print the diff as JSON")]
+        json: bool,
     },
 
-    Check,
+    #[command(
+        about = "Replay key presses through the compiled config and print what Karabiner would send"
+    )]
+    Simulate {
+        #[arg(
+            help = "Comma-separated steps, e.g. \"caps_lock down, h, caps_lock up\"; a bare key is a tap, `wait 300` advances the clock"
+        )]
+        input: String,
+
+        #[arg(
+            short,
+            long,
+            help = "Profile to simulate when the config defines several"
+        )]
+        profile_name: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "BUNDLE_ID",
+            help = "Frontmost application for frontmost_application conditions"
+        )]
+        app: Option<String>,
+
+        #[arg(
+            long = "var",
+            value_name = "NAME=VALUE",
+            help = "Set a variable before the first event"
+        )]
+        vars: Vec<String>,
+
+        #[arg(
+            long,
+            value_name = "MS",
+            default_value_t = simulator::timeline::DEFAULT_STEP_MS,
+            help = "Milliseconds between consecutive key events"
+        )]
+        step: u64,
+
+        #[arg(long, help = "Print the emitted events and variables as JSON")]
+        json: bool,
+    },
+
+    #[command(about = "Run keymap test suites against the compiled config; exits 1 on failure")]
+    Test {
+        #[arg(
+            required = true,
+            help = "Suite files (.toml, .pkl, .json) or directories of .toml and .pkl suites"
+        )]
+        paths: Vec<PathBuf>,
+
+        #[arg(
+            short,
+            long,
+            help = "Profile to test, overriding the suites' own `profile`"
+        )]
+        profile_name: Option<String>,
+    },
+
+    #[command(
+        about = "Write the compiled config to a snapshot file, or with --check fail when it no longer matches"
+    )]
+    Snapshot {
+        #[arg(
+            long,
+            help = "Compare instead of writing; exits 1 with a diff when the output changed"
+        )]
+        check: bool,
+
+        #[arg(
+            short,
+            long,
+            help = "Snapshot file (default: <config>.snapshot.json next to the config)"
+        )]
+        file: Option<PathBuf>,
+
+        #[arg(short, long, help = "Snapshot only this profile")]
+        profile_name: Option<String>,
+    },
 
     Logs {
         #[arg(short, long, default_value = "50")]
@@ -72,7 +219,11 @@ pub enum Commands {
         follow: bool,
     },
 
-    Status,
+    #[command(about = "Show whether the daemon is running and how its last compile went")]
+    Status {
+        #[arg(long, help = "Print the status as JSON")]
+        json: bool,
+    },
 
     Init {
         #[arg(short, long)]
@@ -90,6 +241,120 @@ pub enum Commands {
         )]
         name: Option<String>,
     },
+
+    #[command(
+        about = "Convert a karabiner.json into an ankura.pkl; with --verify, exits 1 if compiling it gives different rules"
+    )]
+    ImportJson {
+        #[arg(help = "Karabiner file to convert (default: ~/.config/karabiner/karabiner.json)")]
+        input: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            help = "Convert only this profile (default: every profile, as namedProfiles)"
+        )]
+        profile_name: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            help = "Pkl file to write, or - for stdout (default: the --config path)"
+        )]
+        output: Option<String>,
+
+        #[arg(short, long, help = "Overwrite the output file if it exists")]
+        force: bool,
+
+        #[arg(
+            long,
+            help = "Compile the generated file and compare its rules with the input"
+        )]
+        verify: bool,
+    },
+
+    #[command(about = "Convert a Goku karabiner.edn into an ankura.pkl")]
+    ImportEdn {
+        #[arg(help = "Goku file to convert (default: ~/.config/karabiner.edn)")]
+        input: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            help = "Convert only this profile (default: every profile, as namedProfiles)"
+        )]
+        profile_name: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            help = "Pkl file to write, or - for stdout (default: the --config path)"
+        )]
+        output: Option<String>,
+
+        #[arg(short, long, help = "Overwrite the output file if it exists")]
+        force: bool,
+    },
+
+    #[command(about = "Write the compiled config in another tool's format")]
+    Export {
+        #[arg(long, help = "Format to write: edn (Goku), kanata, qmk or zmk")]
+        format: String,
+
+        #[arg(
+            long,
+            help = "Keyboard layout for qmk and zmk: ansi, corne, or a layout file (default: ansi)"
+        )]
+        layout: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            help = "Profile to export (default: the selected profile)"
+        )]
+        profile_name: Option<String>,
+
+        #[arg(short, long, help = "File to write (default: stdout)")]
+        output: Option<String>,
+
+        #[arg(short, long, help = "Overwrite the output file if it exists")]
+        force: bool,
+    },
+
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+
+    Backups {
+        #[command(subcommand)]
+        command: BackupsCommand,
+    },
+
+    #[command(about = "Restore karabiner.json from a backup (default: the newest)")]
+    Rollback {
+        #[arg(help = "Backup id from `ankura backups list` (a unique prefix is enough)")]
+        id: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            help = "File to restore (default: ~/.config/karabiner/karabiner.json)"
+        )]
+        output: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BackupsCommand {
+    #[command(about = "List karabiner.json backups, newest first")]
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    #[command(about = "Remove all cached compile results")]
+    Clear,
 }
 
 pub async fn start_daemon(config_path: PathBuf, daemon_mode: bool, debug_log: bool) -> Result<()> {
@@ -203,6 +468,10 @@ impl Drop for PidFileGuard {
 }
 
 fn daemon_pid_file() -> Result<PathBuf> {
+    Ok(runtime_dir()?.join("ankura.pid"))
+}
+
+pub(crate) fn runtime_dir() -> Result<PathBuf> {
     let runtime_dir = homebrew_var_dir()?.join("run");
     fs::create_dir_all(&runtime_dir).map_err(|e| KarabinerPklError::DaemonError {
         message: format!(
@@ -211,10 +480,10 @@ fn daemon_pid_file() -> Result<PathBuf> {
         ),
     })?;
 
-    Ok(runtime_dir.join("ankura.pid"))
+    Ok(runtime_dir)
 }
 
-fn homebrew_var_dir() -> Result<PathBuf> {
+pub(crate) fn homebrew_var_dir() -> Result<PathBuf> {
     if let Some(prefix) = std::env::var_os("HOMEBREW_PREFIX") {
         let path = PathBuf::from(prefix).join("var");
         return Ok(path);
@@ -261,7 +530,10 @@ fn read_pid(path: &Path) -> Result<Option<ProcessId>> {
 
 async fn terminate_process(pid: ProcessId) -> Result<()> {
     send_signal(pid, SIGTERM)?;
+    wait_for_exit(pid).await
+}
 
+async fn wait_for_exit(pid: ProcessId) -> Result<()> {
     for _ in 0..50 {
         if !process_is_running(pid) {
             return Ok(());
@@ -301,54 +573,104 @@ fn send_signal(pid: ProcessId, signal: c_int) -> Result<()> {
     }
 }
 
-async fn wait_for_shutdown_signal() -> Result<()> {
-    let mut sigterm =
-        signal(SignalKind::terminate()).map_err(|e| KarabinerPklError::DaemonError {
-            message: format!("Failed to listen for SIGTERM: {e}"),
-        })?;
-
-    let mut sigint =
-        signal(SignalKind::interrupt()).map_err(|e| KarabinerPklError::DaemonError {
-            message: format!("Failed to listen for SIGINT: {e}"),
-        })?;
-
-    tokio::select! {
-        res = tokio::signal::ctrl_c() => {
-            res.map_err(|e| KarabinerPklError::DaemonError {
-                message: format!("Failed to listen for Ctrl+C: {e}"),
-            })?;
-        }
-        _ = sigterm.recv() => {}
-        _ = sigint.recv() => {}
-    }
-
-    Ok(())
+fn listen(kind: SignalKind, name: &str) -> Result<Signal> {
+    signal(kind).map_err(|e| KarabinerPklError::DaemonError {
+        message: format!("Failed to listen for {name}: {e}"),
+    })
 }
 
 async fn run_daemon(config_path: PathBuf) -> Result<()> {
     let pid_path = daemon_pid_file()?;
     let _pid_guard = PidFileGuard::claim(&pid_path)?;
 
-    let daemon = Daemon::new(config_path)?;
+    let daemon = Arc::new(Daemon::new(config_path)?);
+    let _socket = ControlSocket::bind(daemon.clone())?;
+
+    // Registered once: a signal arriving between two iterations stays queued on its stream.
+    let mut sigterm = listen(SignalKind::terminate(), "SIGTERM")?;
+    let mut sigint = listen(SignalKind::interrupt(), "SIGINT")?;
+    let mut sighup = listen(SignalKind::hangup(), "SIGHUP")?;
+
     daemon.start().await?;
 
     info!("Ankura daemon is running (pid {})", std::process::id());
 
-    wait_for_shutdown_signal().await?;
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => {
+                info!("SIGTERM received, stopping ankura daemon");
+                break;
+            }
+            _ = sigint.recv() => {
+                info!("SIGINT received, stopping ankura daemon");
+                break;
+            }
+            _ = daemon.shutdown_requested() => {
+                info!("Shutdown requested, stopping ankura daemon");
+                break;
+            }
+            _ = sighup.recv() => {
+                info!("SIGHUP received");
+                // Not awaited, so a shutdown is still handled while the config compiles.
+                let daemon = daemon.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = daemon.reload().await {
+                        error!("Reload failed: {e}");
+                    }
+                });
+            }
+        }
+    }
 
-    info!("Shutdown signal received, stopping ankura daemon");
     daemon.stop().await?;
 
     Ok(())
 }
 
+/// Sends a control request to the running daemon and prints its reply.
+pub async fn control_daemon(request: Request) -> Result<()> {
+    let response = match control::send(request).await {
+        Ok(response) => response,
+        Err(e) => {
+            if let KarabinerPklError::DaemonError { message } = &e {
+                println!("❌ {message}");
+            }
+            return Err(e);
+        }
+    };
+
+    match response {
+        Response::Ok { message } => {
+            println!("✅ {message}");
+            Ok(())
+        }
+        Response::Status { status } => {
+            let report = StatusReport {
+                daemon: Liveness::Running,
+                status: Some(status),
+            };
+            print!("{report}");
+            Ok(())
+        }
+        Response::Error { message } => {
+            println!("❌ {message}");
+            Err(KarabinerPklError::DaemonError { message })
+        }
+    }
+}
+
 pub async fn stop_daemon() -> Result<()> {
     let pid_path = daemon_pid_file()?;
 
     match read_pid(&pid_path)? {
         Some(pid) if process_is_running(pid) => {
             info!("Stopping ankura daemon (pid {pid})");
-            terminate_process(pid).await?;
+            // Daemons predating the control socket only understand SIGTERM.
+            if control::send(Request::Shutdown).await.is_ok() {
+                wait_for_exit(pid).await?;
+            } else {
+                terminate_process(pid).await?;
+            }
             if let Err(e) = fs::remove_file(&pid_path) {
                 if e.kind() != io::ErrorKind::NotFound {
                     warn!("Failed to remove pid file {}: {e}", pid_path.display());
@@ -358,6 +680,7 @@ pub async fn stop_daemon() -> Result<()> {
         }
         Some(pid) => {
             warn!("Found stale ankura pid file pointing to pid {pid}, removing it");
+            state::remove()?;
             if let Err(e) = fs::remove_file(&pid_path) {
                 if e.kind() != io::ErrorKind::NotFound {
                     warn!(
@@ -369,6 +692,7 @@ pub async fn stop_daemon() -> Result<()> {
             println!("Daemon is not running");
         }
         None => {
+            state::remove()?;
             println!("Daemon is not running");
         }
     }
@@ -380,26 +704,28 @@ pub async fn compile_once(
     config_path: PathBuf,
     profile_name: Option<&str>,
     output: Option<String>,
+    merge_overrides: &[String],
 ) -> Result<()> {
     let compiler = Compiler::new()?;
     let compiled_config = compiler.compile(&config_path, profile_name).await?;
 
-    let output_path = if let Some(path) = output {
-        PathBuf::from(path)
-    } else {
-        let home = dirs::home_dir().ok_or_else(|| KarabinerPklError::DaemonError {
-            message: "Could not find home directory".to_string(),
-        })?;
-        home.join(".config/karabiner/karabiner.json")
-    };
+    for finding in analysis::analyze_config(&compiled_config, false) {
+        warn!("{finding}");
+    }
 
-    let final_config = if output_path.exists() {
-        merge_configurations(&output_path, compiled_config)?
-    } else {
-        compiled_config
-    };
+    let output_path = karabiner_output_path(output)?;
+
+    let settings = Settings::from_config(&compiled_config)?;
+    let mut policy = settings.merge;
+    policy.apply_overrides(merge_overrides)?;
 
-    write_karabiner_config(&output_path, &final_config)?;
+    let path = output_path.clone();
+    let keep = settings.backups.keep;
+    with_output_lock(&output_path, move || {
+        let final_config = merge_configurations(&path, compiled_config, &policy)?;
+        write_karabiner_config(&path, &final_config, keep)
+    })
+    .await?;
 
     info!(
         "Successfully wrote configuration to {}",
@@ -408,20 +734,37 @@ pub async fn compile_once(
     Ok(())
 }
 
-pub async fn check_config(config_path: PathBuf) -> Result<()> {
+pub async fn check_config(config_path: PathBuf, strict: bool, layers: bool) -> Result<()> {
     println!("Checking configuration: {}", config_path.display());
 
     let compiler = Compiler::new()?;
-    match compiler.compile(&config_path, None).await {
-        Ok(_) => {
-            println!("✅ Configuration is valid!");
-            Ok(())
-        }
+    let config = match compiler.compile(&config_path, None).await {
+        Ok(config) => config,
         Err(e) => {
             println!("❌ Configuration is invalid:");
-            Err(e)
+            return Err(e);
         }
+    };
+
+    let findings = analysis::analyze_config(&config, layers);
+    if findings.is_empty() {
+        println!("✅ Configuration is valid!");
+        return Ok(());
     }
+
+    let marker = if strict { "❌" } else { "⚠️ " };
+    for finding in &findings {
+        println!("{marker} {finding}");
+    }
+
+    if strict {
+        return Err(KarabinerPklError::ManipulatorConflicts {
+            count: findings.len(),
+        });
+    }
+
+    println!("✅ Configuration is valid ({} warning(s))", findings.len());
+    Ok(())
 }
 
 pub fn show_logs(log_file: PathBuf, lines: usize, follow: bool) -> Result<()> {
@@ -445,10 +788,43 @@ pub fn show_logs(log_file: PathBuf, lines: usize, follow: bool) -> Result<()> {
     Ok(())
 }
 
-pub async fn show_status() -> Result<()> {
-    println!("ankura status:");
-    println!("  Daemon: stopped");
-    println!("  Config: ~/.config/ankura.pkl");
+pub async fn show_status(config_path: &Path, json: bool) -> Result<()> {
+    // A daemon that answers knows its state best; otherwise the state file tells what was left.
+    let report = match control::send(Request::Status).await {
+        Ok(Response::Status { status }) => StatusReport {
+            daemon: Liveness::Running,
+            status: Some(status),
+        },
+        _ => {
+            let status = state::load()?;
+            let daemon = match &status {
+                None => Liveness::Stopped,
+                // The pid file guards against a recycled pid belonging to some other process.
+                Some(status) => match read_pid(&daemon_pid_file()?)? {
+                    Some(pid)
+                        if u32::try_from(pid) == Ok(status.pid) && process_is_running(pid) =>
+                    {
+                        Liveness::Running
+                    }
+                    _ => Liveness::Stale,
+                },
+            };
+            StatusReport { daemon, status }
+        }
+    };
+
+    if json {
+        let json =
+            serde_json::to_string_pretty(&report).map_err(|e| KarabinerPklError::DaemonError {
+                message: format!("Failed to encode status: {e}"),
+            })?;
+        println!("{json}");
+    } else {
+        print!("{report}");
+        if report.status.is_none() {
+            println!("  Config: {}", config_path.display());
+        }
+    }
     Ok(())
 }
 
@@ -514,57 +890,368 @@ pub async fn add_import(source: String, name: Option<String>) -> Result<()> {
     Ok(())
 }
 
-pub fn merge_configurations(existing_path: &Path, new_config: Value) -> Result<Value> {
-    let existing_content =
-        std::fs::read_to_string(existing_path).map_err(|e| KarabinerPklError::ConfigReadError {
-            path: existing_path.to_path_buf(),
+/// Writes `input` as Pkl; with `verify`, returns whether compiling the result reproduces the
+/// rules, settings and simple modifications of `input`.
+pub async fn import_json(
+    config_path: PathBuf,
+    input: Option<String>,
+    profile_name: Option<&str>,
+    output: Option<String>,
+    force: bool,
+    verify: bool,
+) -> Result<bool> {
+    let input_path = karabiner_output_path(input)?;
+    let content =
+        std::fs::read_to_string(&input_path).map_err(|e| KarabinerPklError::ConfigReadError {
+            path: input_path.clone(),
             source: e,
         })?;
+    let source = Config::from_json_str(&content)?;
+    let generated = import::karabiner::generate(&source, profile_name, &input_path)?;
+
+    // With the Pkl on stdout, the report goes to stderr so the two can be redirected apart.
+    let to_stdout = output.as_deref() == Some("-");
+    let report = |line: String| {
+        if to_stdout {
+            eprintln!("{line}");
+        } else {
+            println!("{line}");
+        }
+    };
 
-    let mut existing_config: Value = serde_json::from_str(&existing_content)
-        .map_err(|e| KarabinerPklError::JsonParseError { source: e })?;
+    let output_path = match output {
+        Some(path) if path == "-" => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(config_path),
+    };
 
-    let mut new_profile = new_config["profiles"][0].clone();
-    let target_profile_name = new_profile["name"].as_str().unwrap_or("pkl");
+    let pkl_path = match &output_path {
+        Some(path) => {
+            if !write_generated(path, &generated.source, force)? {
+                return Ok(true);
+            }
+            report(format!(
+                "✅ Wrote {} from {}",
+                path.display(),
+                input_path.display()
+            ));
+            path.clone()
+        }
+        None => {
+            print!("{}", generated.source);
+            report(format!("✅ Converted {}", input_path.display()));
+            std::env::temp_dir().join(format!("ankura-import-{}.pkl", std::process::id()))
+        }
+    };
+    report(generated.summary.to_string().trim_end().to_string());
 
-    if !existing_config
-        .get("profiles")
-        .map(|p| p.is_array())
-        .unwrap_or(false)
-    {
-        existing_config["profiles"] = serde_json::json!([]);
+    if !verify {
+        return Ok(true);
     }
 
-    let profiles = existing_config["profiles"].as_array_mut().unwrap();
+    if output_path.is_none() {
+        output::write_atomic(&pkl_path, &generated.source).map_err(|e| {
+            KarabinerPklError::ConfigWriteError {
+                path: pkl_path.clone(),
+                source: e,
+            }
+        })?;
+    }
+    let compiled = Compiler::new()?.compile(&pkl_path, None).await;
+    if output_path.is_none() {
+        let _ = std::fs::remove_file(&pkl_path);
+    }
 
-    let existing_profile_index = profiles
-        .iter()
-        .position(|p| p["name"].as_str() == Some(target_profile_name));
-
-    if let Some(index) = existing_profile_index {
-        if let Some(selected) = profiles
-            .get(index)
-            .and_then(|profile| profile.get("selected"))
-            .and_then(|value| value.as_bool())
-        {
-            new_profile["selected"] = serde_json::Value::Bool(selected);
+    let changes = generated.check(&compiled?);
+    if changes.is_empty() {
+        report(format!(
+            "✅ Compiling it reproduces the rules of {}",
+            input_path.display()
+        ));
+        Ok(true)
+    } else {
+        report(format!(
+            "❌ Compiling it does not reproduce {}:\n",
+            input_path.display()
+        ));
+        report(changes.to_string().trim_end().to_string());
+        Ok(false)
+    }
+}
+
+pub fn import_edn(
+    config_path: PathBuf,
+    input: Option<String>,
+    profile_name: Option<&str>,
+    output: Option<String>,
+    force: bool,
+) -> Result<()> {
+    let input_path = match input {
+        Some(path) => PathBuf::from(path),
+        None => dirs::home_dir()
+            .ok_or_else(|| KarabinerPklError::DaemonError {
+                message: "Could not find home directory".to_string(),
+            })?
+            .join(".config/karabiner.edn"),
+    };
+    let content =
+        std::fs::read_to_string(&input_path).map_err(|e| KarabinerPklError::ConfigReadError {
+            path: input_path.clone(),
+            source: e,
+        })?;
+    let (source, summary) = import::goku::generate(&content, profile_name, &input_path)?;
+
+    match output {
+        Some(path) if path == "-" => {
+            print!("{source}");
+            eprintln!("✅ Converted {}", input_path.display());
+            eprintln!("{}", summary.to_string().trim_end());
+        }
+        output => {
+            let path = output.map_or(config_path, PathBuf::from);
+            if !write_generated(&path, &source, force)? {
+                return Ok(());
+            }
+            println!("✅ Wrote {} from {}", path.display(), input_path.display());
+            println!("{}", summary.to_string().trim_end());
+        }
+    }
+    Ok(())
+}
+
+pub async fn export_config(
+    config_path: PathBuf,
+    format: &str,
+    layout: Option<&str>,
+    profile_name: Option<&str>,
+    output: Option<String>,
+    force: bool,
+) -> Result<()> {
+    let format: Format = format.parse()?;
+    if layout.is_some() && !matches!(format, Format::Qmk | Format::Zmk) {
+        return Err(KarabinerPklError::ValidationError {
+            message: format!("--layout only applies to qmk and zmk, not {format}"),
+        });
+    }
+    let layout = Layout::load(layout.unwrap_or("ansi"))?;
+    let compiler = Compiler::new()?;
+    let config = compiler.compile(&config_path, profile_name).await?;
+    let exported = export::export(&config, format, &config_path, &layout)?;
+
+    match output {
+        None => {
+            print!("{}", exported.source);
+            eprintln!("✅ Exported {} as {format}", config_path.display());
+            eprintln!("{}", exported.summary.to_string().trim_end());
         }
-        profiles[index] = new_profile;
+        Some(path) => {
+            let path = PathBuf::from(path);
+            if !write_generated(&path, &exported.source, force)? {
+                return Ok(());
+            }
+            println!("✅ Wrote {} from {}", path.display(), config_path.display());
+            println!("{}", exported.summary.to_string().trim_end());
+        }
+    }
+    Ok(())
+}
+
+// Writes an imported or exported config, unless something is already there and `force` isn't set.
+fn write_generated(path: &Path, source: &str, force: bool) -> Result<bool> {
+    if path.exists() && !force {
+        println!("Configuration already exists at {}", path.display());
+        println!("Use --force to overwrite, or -o to write somewhere else");
+        return Ok(false);
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| KarabinerPklError::ConfigWriteError {
+            path: parent.to_path_buf(),
+            source: e,
+        })?;
+    }
+    output::write_atomic(path, source).map_err(|e| KarabinerPklError::ConfigWriteError {
+        path: path.to_path_buf(),
+        source: e,
+    })?;
+    Ok(true)
+}
+
+pub fn clear_cache() -> Result<()> {
+    let cache = CompileCache::new();
+    let removed = cache.clear()?;
+    println!(
+        "✅ Removed {removed} cached compile result(s) from {}",
+        cache.dir().display()
+    );
+    Ok(())
+}
+
+pub fn merge_configurations(
+    existing_path: &Path,
+    new_config: Config,
+    policy: &MergePolicy,
+) -> Result<Config> {
+    let existing_config = read_karabiner_config(existing_path)?;
+    merge::merge_config(existing_config, new_config, policy)
+}
+
+// A missing karabiner.json reads as an empty configuration.
+fn read_karabiner_config(path: &Path) -> Result<Config> {
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+
+    let content =
+        std::fs::read_to_string(path).map_err(|e| KarabinerPklError::ConfigReadError {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+    Config::from_json_str(&content)
+}
+
+/// Prints what `compile` would change and returns whether there is any difference.
+pub async fn diff_config(
+    config_path: PathBuf,
+    profile_name: Option<&str>,
+    output: Option<String>,
+    merge_overrides: &[String],
+    json: bool,
+) -> Result<bool> {
+    let compiler = Compiler::new()?;
+    let compiled_config = compiler.compile(&config_path, profile_name).await?;
+
+    let output_path = karabiner_output_path(output)?;
+    let mut policy = Settings::from_config(&compiled_config)?.merge;
+    policy.apply_overrides(merge_overrides)?;
+
+    let current = read_karabiner_config(&output_path)?;
+    let proposed = merge::merge_config(current.clone(), compiled_config, &policy)?;
+    let changes = diff::diff_configs(&current, &proposed);
+
+    if json {
+        let json = serde_json::to_string_pretty(&changes)
+            .map_err(|e| KarabinerPklError::JsonParseError { source: e })?;
+        println!("{json}");
     } else {
-        profiles.push(new_profile);
+        print!("{changes}");
     }
 
-    if existing_config.get("title").is_none() {
-        existing_config["title"] = new_config
-            .get("title")
-            .cloned()
-            .unwrap_or_else(|| serde_json::json!("Karabiner-Pkl Configuration"));
+    Ok(!changes.is_empty())
+}
+
+pub async fn simulate(
+    config_path: PathBuf,
+    input: &str,
+    profile_name: Option<&str>,
+    app: Option<String>,
+    vars: &[String],
+    step: u64,
+    json: bool,
+) -> Result<()> {
+    let timeline = Timeline::parse(input, step)?;
+    let variables = vars
+        .iter()
+        .map(|entry| parse_variable(entry))
+        .collect::<Result<_>>()?;
+
+    let compiler = Compiler::new()?;
+    let config = compiler.compile(&config_path, profile_name).await?;
+    let profile = config
+        .active_profile()
+        .ok_or_else(|| KarabinerPklError::ValidationError {
+            message: "Configuration has no profiles to simulate".to_string(),
+        })?;
+
+    let simulation = Simulator::new(profile)
+        .with_environment(Environment {
+            frontmost_application: app,
+            variables,
+            ..Default::default()
+        })
+        .run(&timeline);
+
+    if json {
+        let json = serde_json::to_string_pretty(&simulation)
+            .map_err(|e| KarabinerPklError::JsonParseError { source: e })?;
+        println!("{json}");
+    } else {
+        print!("{simulation}");
     }
+    Ok(())
+}
 
-    Ok(existing_config)
+/// Runs the suites and prints a report; returns whether every test passed.
+pub async fn run_tests(
+    config_path: PathBuf,
+    paths: &[PathBuf],
+    profile_name: Option<&str>,
+) -> Result<bool> {
+    let compiler = Compiler::new()?;
+    let report = testing::run(&compiler, &config_path, paths, profile_name).await?;
+    print!("{report}");
+    Ok(report.is_success())
 }
 
-pub fn write_karabiner_config(path: &Path, config: &Value) -> Result<()> {
+/// Writes or checks the snapshot; returns false when `check` finds a difference.
+pub async fn snapshot_config(
+    config_path: PathBuf,
+    file: Option<PathBuf>,
+    profile_name: Option<&str>,
+    check: bool,
+) -> Result<bool> {
+    let compiler = Compiler::new()?;
+    let config = compiler.compile(&config_path, profile_name).await?;
+    let path = file.unwrap_or_else(|| snapshot::default_path(&config_path));
+
+    if !check {
+        if snapshot::write(&path, &config)? {
+            println!("✅ Wrote snapshot to {}", path.display());
+        } else {
+            println!("✅ Snapshot {} is up to date", path.display());
+        }
+        return Ok(true);
+    }
+
+    match snapshot::check(&path, &config)? {
+        None => {
+            println!("✅ Compiled output matches {}", path.display());
+            Ok(true)
+        }
+        Some(mismatch) => {
+            println!("❌ Compiled output differs from {}:\n", path.display());
+            print!("{mismatch}");
+            println!("\nRun `ankura snapshot` to accept the new output.");
+            Ok(false)
+        }
+    }
+}
+
+fn parse_variable(entry: &str) -> Result<(String, VariableValue)> {
+    let (name, value) =
+        entry
+            .split_once('=')
+            .ok_or_else(|| KarabinerPklError::ValidationError {
+                message: format!("Expected NAME=VALUE for --var, got \"{entry}\""),
+            })?;
+
+    let value = match value.trim() {
+        "true" => VariableValue::Bool(true),
+        "false" => VariableValue::Bool(false),
+        other => other
+            .parse()
+            .map(VariableValue::Int)
+            .unwrap_or_else(|_| VariableValue::String(other.to_string())),
+    };
+    Ok((name.trim().to_string(), value))
+}
+
+pub fn write_karabiner_config(path: &Path, config: &Config, keep_backups: usize) -> Result<()> {
+    let pretty_json = config.to_json_pretty()?;
+    write_karabiner_json(path, &pretty_json, keep_backups)
+}
+
+// Every write first copies the current file into the backup store.
+fn write_karabiner_json(path: &Path, contents: &str, keep_backups: usize) -> Result<()> {
     if let Some(parent) = path.parent() {
         std::fs::create_dir_all(parent).map_err(|e| KarabinerPklError::KarabinerWriteError {
             path: parent.to_path_buf(),
@@ -572,13 +1259,98 @@ pub fn write_karabiner_config(path: &Path, config: &Value) -> Result<()> {
         })?;
     }
 
-    let pretty_json = serde_json::to_string_pretty(config)
-        .map_err(|e| KarabinerPklError::JsonParseError { source: e })?;
+    if std::fs::read_to_string(path).is_ok_and(|current| current == contents) {
+        debug!("{} is unchanged, skipping write", path.display());
+        return Ok(());
+    }
+
+    if let Some(backup) = BackupStore::new()?.create(path, keep_backups)? {
+        info!("Backed up previous configuration as {}", backup.id);
+    }
 
-    std::fs::write(path, pretty_json).map_err(|e| KarabinerPklError::KarabinerWriteError {
+    output::write_atomic(path, contents).map_err(|e| KarabinerPklError::KarabinerWriteError {
         path: path.to_path_buf(),
         source: e,
     })?;
 
     Ok(())
 }
+
+pub fn list_backups() -> Result<()> {
+    let store = BackupStore::new()?;
+    let backups = store.list()?;
+
+    if backups.is_empty() {
+        println!("No backups in {}", store.dir().display());
+        return Ok(());
+    }
+
+    println!("Backups in {} (newest first):", store.dir().display());
+    for backup in backups {
+        println!(
+            "  {}  {}  {:.1} KB",
+            backup.id,
+            backup.created.format("%Y-%m-%d %H:%M:%S"),
+            backup.size as f64 / 1024.0
+        );
+    }
+    Ok(())
+}
+
+pub async fn rollback(id: Option<&str>, output: Option<String>) -> Result<()> {
+    let backup = BackupStore::new()?.find(id)?;
+    let output_path = karabiner_output_path(output)?;
+
+    let contents =
+        std::fs::read_to_string(&backup.path).map_err(|e| KarabinerPklError::ConfigReadError {
+            path: backup.path.clone(),
+            source: e,
+        })?;
+    Config::from_json_str(&contents)?;
+
+    // The file being replaced is backed up too, so a rollback can itself be rolled back.
+    // Pruning is left to the next compile, which knows the configured retention.
+    let path = output_path.clone();
+    with_output_lock(&output_path, move || {
+        write_karabiner_json(&path, &contents, usize::MAX)
+    })
+    .await?;
+
+    println!(
+        "✅ Restored backup {} ({}) to {}",
+        backup.id,
+        backup.created.format("%Y-%m-%d %H:%M:%S"),
+        output_path.display()
+    );
+    Ok(())
+}
+
+// Waiting for the output lock and the file I/O block, so they run off the runtime's worker
+// threads, as the daemon's writes do.
+async fn with_output_lock(
+    output_path: &Path,
+    write: impl FnOnce() -> Result<()> + Send + 'static,
+) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let _lock = OutputLock::acquire()?;
+        write()
+    })
+    .await
+    .unwrap_or_else(|e| {
+        Err(KarabinerPklError::KarabinerWriteError {
+            path: output_path.to_path_buf(),
+            source: std::io::Error::other(e),
+        })
+    })
+}
+
+fn karabiner_output_path(output: Option<String>) -> Result<PathBuf> {
+    if let Some(path) = output {
+        return Ok(PathBuf::from(path));
+    }
+
+    let home = dirs::home_dir().ok_or_else(|| KarabinerPklError::DaemonError {
+        message: "Could not find home directory".to_string(),
+    })?;
+    Ok(home.join(".config/karabiner/karabiner.json"))
+}
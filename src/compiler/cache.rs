@@ -0,0 +1,179 @@
+use super::imports::{self, collect_pkl_files};
+use crate::error::{KarabinerPklError, Result};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+use tracing::debug;
+
+// Bump when the key layout or the cached payload changes.
+const CACHE_VERSION: &str = "1";
+
+// Entries kept after a `put`, most recently used first.
+const MAX_ENTRIES: usize = 32;
+
+/// Compiled JSON on disk, keyed by everything that can change what `pkl eval` prints.
+pub struct CompileCache {
+    dir: PathBuf,
+}
+
+impl CompileCache {
+    pub fn new() -> Self {
+        Self {
+            dir: Self::default_dir(),
+        }
+    }
+
+    pub fn default_dir() -> PathBuf {
+        dirs::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("ankura")
+            .join("compiled")
+    }
+
+    /// Hashes the config, its transitive imports, every module in the library directories,
+    /// the pkl version and the evaluation arguments. `None` when the output can depend on
+    /// something that isn't hashed: an `http(s):` import, or a `read()` of a file or `env:`.
+    pub fn key(
+        config_path: &Path,
+        module_paths: &[PathBuf],
+        pkl_version: &str,
+        arguments: &[String],
+    ) -> Option<String> {
+        let graph = imports::resolve(config_path, module_paths);
+        if let Some(uri) = graph
+            .unresolved
+            .iter()
+            .find(|uri| uri.starts_with("https:") || uri.starts_with("http:"))
+        {
+            debug!("Not caching: {} imports {uri}", config_path.display());
+            return None;
+        }
+        if let Some(module) = graph.modules.iter().find(|module| reads_resources(module)) {
+            debug!("Not caching: {} reads resources", module.display());
+            return None;
+        }
+
+        let mut modules = graph.modules;
+        for dir in module_paths {
+            let mut files = Vec::new();
+            collect_pkl_files(dir, &mut files);
+            modules.extend(files);
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(format!("ankura-cache-v{CACHE_VERSION}\0"));
+        hasher.update(format!("pkl {pkl_version}\0"));
+        for argument in arguments {
+            hasher.update(format!("arg {argument}\0"));
+        }
+        for module in &modules {
+            hasher.update(format!("module {}\0", module.display()));
+            match std::fs::read(module) {
+                Ok(contents) => {
+                    hasher.update((contents.len() as u64).to_le_bytes());
+                    hasher.update(&contents);
+                }
+                Err(_) => hasher.update("missing\0"),
+            }
+        }
+        for uri in &graph.unresolved {
+            hasher.update(format!("unresolved {uri}\0"));
+        }
+
+        Some(format!("{:x}", hasher.finalize()))
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        let path = self.entry_path(key);
+        let json = std::fs::read_to_string(&path).ok()?;
+        // A hit counts as a use, so the entry outlives older ones when pruning.
+        if let Ok(file) = std::fs::File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(json)
+    }
+
+    pub fn put(&self, key: &str, json: &str) {
+        let write = || -> std::io::Result<()> {
+            std::fs::create_dir_all(&self.dir)?;
+            let temp_path = self.dir.join(format!(".{key}.tmp"));
+            std::fs::write(&temp_path, json)?;
+            std::fs::rename(&temp_path, self.entry_path(key))
+        };
+
+        if let Err(e) = write() {
+            debug!("Failed to store compile cache entry {key}: {e}");
+        }
+        self.prune();
+    }
+
+    // Removes all but the `MAX_ENTRIES` most recently used entries.
+    fn prune(&self) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut entries: Vec<(SystemTime, PathBuf)> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| Some((path.metadata().ok()?.modified().ok()?, path)))
+            .collect();
+        if entries.len() <= MAX_ENTRIES {
+            return;
+        }
+        entries.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+        for (_, path) in &entries[MAX_ENTRIES..] {
+            if let Err(e) = std::fs::remove_file(path) {
+                debug!("Failed to prune {}: {e}", path.display());
+            }
+        }
+    }
+
+    pub fn clear(&self) -> Result<usize> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => {
+                return Err(KarabinerPklError::DaemonError {
+                    message: format!("Failed to read cache directory: {e}"),
+                })
+            }
+        };
+
+        let mut removed = 0;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_file() {
+                std::fs::remove_file(&path).map_err(|e| KarabinerPklError::DaemonError {
+                    message: format!("Failed to remove {}: {e}", path.display()),
+                })?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.json"))
+    }
+}
+
+// Whether a module reads a resource, e.g. `read("env:HOME")` or `read?("secrets.txt")`.
+fn reads_resources(module: &Path) -> bool {
+    static READ: OnceLock<Regex> = OnceLock::new();
+    let read = READ.get_or_init(|| Regex::new(r"\bread[?*]?\s*\(").expect("valid regex"));
+    std::fs::read_to_string(module).is_ok_and(|source| read.is_match(&source))
+}
+
+impl Default for CompileCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,127 @@
+use regex::Regex;
+use std::collections::{BTreeSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Every module a config pulls in, following `import`, `import*`, `amends` and `extends`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportGraph {
+    /// Local files, including the entry module itself.
+    pub modules: BTreeSet<PathBuf>,
+    /// Imports that resolve outside the file system (`package:`, `https:`) or could not be found.
+    pub unresolved: BTreeSet<String>,
+}
+
+pub fn resolve(entry: &Path, module_paths: &[PathBuf]) -> ImportGraph {
+    let mut graph = ImportGraph::default();
+    let mut queue = VecDeque::from([normalize(entry)]);
+
+    while let Some(module) = queue.pop_front() {
+        if !graph.modules.insert(module.clone()) {
+            continue;
+        }
+
+        let Ok(contents) = std::fs::read_to_string(&module) else {
+            continue;
+        };
+        let base_dir = module.parent().unwrap_or(Path::new("."));
+
+        for (uri, is_glob) in imports_of(&contents) {
+            if uri.starts_with("pkl:") {
+                continue;
+            }
+
+            let targets = if is_glob {
+                expand_glob(&uri, base_dir, module_paths)
+            } else {
+                resolve_uri(&uri, base_dir, module_paths)
+                    .into_iter()
+                    .collect()
+            };
+
+            if targets.is_empty() {
+                graph.unresolved.insert(uri);
+            }
+            for target in targets {
+                if !graph.modules.contains(&target) {
+                    queue.push_back(target);
+                }
+            }
+        }
+    }
+
+    graph
+}
+
+fn imports_of(contents: &str) -> Vec<(String, bool)> {
+    static IMPORT: OnceLock<Regex> = OnceLock::new();
+    let import_regex = IMPORT
+        .get_or_init(|| Regex::new(r#"\b(import\*?|amends|extends)\s*\(?\s*"([^"]+)""#).unwrap());
+
+    contents
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("//"))
+        .flat_map(|line| import_regex.captures_iter(line))
+        .map(|caps| (caps[2].to_string(), &caps[1] == "import*"))
+        .collect()
+}
+
+fn resolve_uri(uri: &str, base_dir: &Path, module_paths: &[PathBuf]) -> Option<PathBuf> {
+    if let Some(path) = uri.strip_prefix("modulepath:") {
+        let relative = path.trim_start_matches('/');
+        return module_paths
+            .iter()
+            .map(|dir| dir.join(relative))
+            .find(|candidate| candidate.is_file())
+            .map(|candidate| normalize(&candidate));
+    }
+
+    if let Some(path) = uri.strip_prefix("file://") {
+        return Some(normalize(Path::new(path)));
+    }
+
+    if uri.contains(':') {
+        return None;
+    }
+
+    Some(normalize(&base_dir.join(uri)))
+}
+
+// Pkl globs are hashed conservatively: every .pkl file under the glob's literal prefix.
+fn expand_glob(uri: &str, base_dir: &Path, module_paths: &[PathBuf]) -> Vec<PathBuf> {
+    let literal: String = uri
+        .split('/')
+        .take_while(|segment| !segment.contains(['*', '?', '[', '{']))
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let root = if literal.is_empty() {
+        Some(base_dir.to_path_buf())
+    } else {
+        resolve_uri(&literal, base_dir, module_paths)
+    };
+
+    let mut files = Vec::new();
+    if let Some(root) = root {
+        collect_pkl_files(&root, &mut files);
+    }
+    files
+}
+
+pub(crate) fn collect_pkl_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_pkl_files(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "pkl") {
+            files.push(normalize(&path));
+        }
+    }
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
@@ -1,16 +1,26 @@
+use crate::analysis::keycodes;
 use crate::error::{KarabinerPklError, Result};
-use regex::Regex;
+use crate::karabiner::Config;
+use crate::settings::Settings;
+use cache::CompileCache;
 use rust_embed::RustEmbed;
-use serde_json::Value;
+use server::{ModuleReader, PklServer, ResourceReader};
 use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::OnceLock;
-use tracing::debug;
+use std::sync::{Arc, Mutex, OnceLock};
+use tracing::{debug, info, warn};
 use which::which;
 
-const ANKURA_LIB_DIR: &str = "/opt/homebrew/var/lib/ankura";
+pub mod cache;
+pub mod imports;
+pub mod pkl_error;
+pub mod server;
+
+// Where a Homebrew install keeps the library. Configs `extends` it by this path.
+const HOMEBREW_LIB_DIR: &str = "/opt/homebrew/var/lib/ankura";
 
 #[derive(RustEmbed)]
 #[folder = "pkl/"]
@@ -20,6 +30,12 @@ struct PklLib;
 pub struct Compiler {
     pkl_path: PathBuf,
     embedded_lib_path: PathBuf,
+    cache: CompileCache,
+    pkl_version: OnceLock<String>,
+    use_server: bool,
+    server: Mutex<Option<Arc<PklServer>>>,
+    module_readers: Vec<Arc<dyn ModuleReader>>,
+    resource_readers: Vec<Arc<dyn ResourceReader>>,
 }
 
 impl Compiler {
@@ -31,10 +47,37 @@ impl Compiler {
         Ok(Self {
             pkl_path,
             embedded_lib_path,
+            cache: CompileCache::new(),
+            pkl_version: OnceLock::new(),
+            use_server: false,
+            server: Mutex::new(None),
+            module_readers: Vec::new(),
+            resource_readers: Vec::new(),
         })
     }
 
-    pub async fn compile(&self, config_path: &Path, profile_name: Option<&str>) -> Result<Value> {
+    /// A compiler that evaluates through a long-lived `pkl server` process, for the daemon.
+    /// Falls back to spawning `pkl eval` whenever the server is unavailable.
+    pub fn with_server() -> Result<Self> {
+        let mut compiler = Self::new()?;
+        compiler.use_server = true;
+        compiler.server_handle();
+        Ok(compiler)
+    }
+
+    /// Serves `import "<scheme>:..."` from Rust. Only honoured when evaluating through the server.
+    pub fn register_module_reader(&mut self, reader: Arc<dyn ModuleReader>) {
+        self.module_readers.push(reader);
+        self.reset_server();
+    }
+
+    /// Serves `read("<scheme>:...")` from Rust. Only honoured when evaluating through the server.
+    pub fn register_resource_reader(&mut self, reader: Arc<dyn ResourceReader>) {
+        self.resource_readers.push(reader);
+        self.reset_server();
+    }
+
+    pub async fn compile(&self, config_path: &Path, profile_name: Option<&str>) -> Result<Config> {
         debug!("Compiling {}", config_path.display());
 
         if !config_path.exists() {
@@ -47,122 +90,233 @@ impl Compiler {
             });
         }
 
+        let (module_paths, lib_dir) = self.module_paths()?;
+
+        let arguments = [
+            "--format=json".to_string(),
+            format!("--module-path={}", join_paths(&module_paths)),
+        ];
+        let cache_key =
+            CompileCache::key(config_path, &module_paths, self.pkl_version(), &arguments);
+
+        let cached = cache_key
+            .as_deref()
+            .and_then(|key| self.cache.get(key))
+            .and_then(|json| Config::from_json_str(&json).ok());
+
+        let mut config = match cached {
+            Some(config) => {
+                info!("Compile cache hit for {}", config_path.display());
+                config
+            }
+            None => {
+                debug!("Compile cache miss for {}", config_path.display());
+                let json_str = match self.evaluate(config_path, &module_paths).await? {
+                    Ok(json) => json,
+                    Err(stderr) => {
+                        debug!("pkl eval failed:\n{stderr}");
+                        return Err(self.pkl_error(&stderr, config_path, &lib_dir));
+                    }
+                };
+
+                let config = Config::from_json_str(&json_str)?;
+                if let Some(key) = &cache_key {
+                    self.cache.put(key, &json_str);
+                }
+                config
+            }
+        };
+
+        self.validate_config(&config)?;
+        keycodes::validate(&config)?;
+        Settings::from_config(&config)?;
+
+        if let Some(name) = profile_name {
+            Self::select_profile(&mut config, name)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Evaluates any Pkl module to JSON with the same module path as `compile`, uncached.
+    pub async fn evaluate_json(&self, module: &Path) -> Result<serde_json::Value> {
+        let (module_paths, lib_dir) = self.module_paths()?;
+
+        let json = match self.evaluate(module, &module_paths).await? {
+            Ok(json) => json,
+            Err(stderr) => return Err(self.pkl_error(&stderr, module, &lib_dir)),
+        };
+        serde_json::from_str(&json).map_err(|e| KarabinerPklError::JsonParseError { source: e })
+    }
+
+    // The embedded library, then the user's own library when it exists.
+    pub fn module_paths(&self) -> Result<(Vec<PathBuf>, PathBuf)> {
         let home = dirs::home_dir().ok_or_else(|| KarabinerPklError::DaemonError {
             message: "Could not find home directory".to_string(),
         })?;
         let lib_dir = home.join(".config/karabiner_pkl/lib");
 
-        let mut pkl_command = Command::new(&self.pkl_path);
-        pkl_command.args(["eval", "--format=json"]);
+        let mut module_paths = vec![self.embedded_lib_path.clone()];
 
-        let mut module_paths = vec![];
+        if lib_dir.exists() {
+            module_paths.push(lib_dir.clone());
+        }
 
-        module_paths.push(self.embedded_lib_path.to_string_lossy().to_string());
+        Ok((module_paths, lib_dir))
+    }
 
-        if lib_dir.exists() {
-            module_paths.push(lib_dir.to_string_lossy().to_string());
+    // The inner error carries Pkl's error text; the outer one means pkl itself could not run.
+    async fn evaluate(
+        &self,
+        config_path: &Path,
+        module_paths: &[PathBuf],
+    ) -> Result<std::result::Result<String, String>> {
+        if let Some(server) = self.server_handle() {
+            let module_path = config_path
+                .canonicalize()
+                .unwrap_or_else(|_| config_path.to_path_buf());
+
+            match server.evaluate(&module_path, module_paths, "json").await {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    warn!("pkl server failed, falling back to pkl eval: {e}");
+                    self.reset_server();
+                }
+            }
         }
 
-        pkl_command.arg("--module-path");
-        pkl_command.arg(module_paths.join(":"));
-        pkl_command.arg(config_path);
+        let module_path = join_paths(module_paths);
 
-        let output = pkl_command
+        // Dropping the future (a daemon timeout or a newer change) kills pkl.
+        let output = tokio::process::Command::new(&self.pkl_path)
+            .args(["eval", "--format=json", "--module-path", &module_path])
+            .arg(config_path)
+            .kill_on_drop(true)
             .output()
+            .await
             .map_err(|e| KarabinerPklError::DaemonError {
                 message: format!("Failed to execute pkl: {e}"),
             })?;
 
         if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            eprintln!("{stderr}");
-
-            let (error_msg, line_number) = Self::parse_pkl_error(&stderr, config_path);
-            return Err(KarabinerPklError::PklCompileError {
-                message: error_msg,
-                line: line_number,
-            });
+            return Ok(Err(String::from_utf8_lossy(&output.stderr).to_string()));
         }
 
-        let json_str = String::from_utf8_lossy(&output.stdout);
-        let config: Value = serde_json::from_str(&json_str)
-            .map_err(|e| KarabinerPklError::JsonParseError { source: e })?;
+        Ok(Ok(String::from_utf8_lossy(&output.stdout).to_string()))
+    }
 
-        self.validate_config(&config)?;
+    pub fn pkl_version(&self) -> &str {
+        self.pkl_version.get_or_init(|| {
+            Command::new(&self.pkl_path)
+                .arg("--version")
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+                .unwrap_or_else(|| "unknown".to_string())
+        })
+    }
 
-        let mut final_config = config;
-        if let Some(name) = profile_name {
-            if let Some(profiles) = final_config
-                .get_mut("profiles")
-                .and_then(|p| p.as_array_mut())
-            {
-                if let Some(first_profile) = profiles.get_mut(0) {
-                    first_profile["name"] = serde_json::json!(name);
+    // Returns the running server, restarting it if the previous process died.
+    fn server_handle(&self) -> Option<Arc<PklServer>> {
+        if !self.use_server {
+            return None;
+        }
+
+        let mut guard = self.server.lock().unwrap_or_else(|p| p.into_inner());
+        if let Some(server) = guard.as_ref().filter(|server| server.is_alive()) {
+            return Some(server.clone());
+        }
+
+        match PklServer::spawn(&self.pkl_path) {
+            Ok(server) => {
+                for reader in &self.module_readers {
+                    server.register_module_reader(reader.clone());
                 }
+                for reader in &self.resource_readers {
+                    server.register_resource_reader(reader.clone());
+                }
+                info!("Started pkl server");
+                let server = Arc::new(server);
+                *guard = Some(server.clone());
+                Some(server)
+            }
+            Err(e) => {
+                warn!("Could not start pkl server, using pkl eval: {e}");
+                *guard = None;
+                None
             }
         }
+    }
 
-        Ok(final_config)
+    fn reset_server(&self) {
+        self.server.lock().unwrap_or_else(|p| p.into_inner()).take();
     }
 
-    fn validate_config(&self, config: &Value) -> Result<()> {
-        if !config.is_object() {
+    fn validate_config(&self, config: &Config) -> Result<()> {
+        if config.profiles.is_empty() {
             return Err(KarabinerPklError::ValidationError {
-                message: "Configuration must be an object".to_string(),
+                message: "Configuration must contain at least one profile".to_string(),
             });
         }
 
-        if config.get("profiles").is_none() {
+        if let Some(index) = config
+            .profiles
+            .iter()
+            .position(|profile| profile.name.trim().is_empty())
+        {
             return Err(KarabinerPklError::ValidationError {
-                message: "Configuration must contain 'profiles' field".to_string(),
+                message: format!("profiles[{index}] must have a non-empty name"),
             });
         }
 
-        let profiles = config.get("profiles").unwrap();
-        if !profiles.is_array() {
+        let mut seen = HashSet::new();
+        if let Some(duplicate) = config
+            .profiles
+            .iter()
+            .find(|profile| !seen.insert(profile.name.as_str()))
+        {
             return Err(KarabinerPklError::ValidationError {
-                message: "'profiles' must be an array".to_string(),
+                message: format!("Profile \"{}\" is defined more than once", duplicate.name),
             });
         }
 
-        let profiles_array = profiles.as_array().unwrap();
-        if profiles_array.is_empty() {
+        Ok(())
+    }
+
+    // A single-profile config is renamed; with several profiles only the named one is kept.
+    fn select_profile(config: &mut Config, name: &str) -> Result<()> {
+        if config.profiles.len() == 1 {
+            config.profiles[0].name = name.to_string();
+            return Ok(());
+        }
+
+        if !config.profiles.iter().any(|profile| profile.name == name) {
+            let available: Vec<&str> = config.profiles.iter().map(|p| p.name.as_str()).collect();
             return Err(KarabinerPklError::ValidationError {
-                message: "Configuration must contain at least one profile".to_string(),
+                message: format!(
+                    "Profile \"{name}\" is not defined; available profiles: {}",
+                    available.join(", ")
+                ),
             });
         }
 
+        config.profiles.retain(|profile| profile.name == name);
         Ok(())
     }
 
-    fn parse_pkl_error(stderr: &str, config_path: &Path) -> (String, usize) {
-        static LINE_REGEX: OnceLock<Regex> = OnceLock::new();
-        let line_regex = LINE_REGEX.get_or_init(|| Regex::new(r"line (\d+)\)").unwrap());
+    fn pkl_error(&self, stderr: &str, config_path: &Path, lib_dir: &Path) -> KarabinerPklError {
+        let config_path = config_path
+            .canonicalize()
+            .unwrap_or_else(|_| config_path.to_path_buf());
+        let library_dirs = [self.embedded_lib_path.clone(), lib_dir.to_path_buf()];
 
-        let error_message = stderr
-            .lines()
-            .nth(1)
-            .map(|line| line.trim().to_string())
-            .unwrap_or_else(|| "Compilation failed".to_string());
-
-        let config_file_name = config_path
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("ankura.pkl");
-
-        let line_number = stderr
-            .lines()
-            .find(|line| line.contains(config_file_name))
-            .and_then(|line| line_regex.captures(line))
-            .and_then(|caps| caps.get(1))
-            .and_then(|m| m.as_str().parse::<usize>().ok())
-            .unwrap_or(0);
-
-        (error_message, line_number)
+        let diagnostic = pkl_error::parse(stderr).into_diagnostic(&config_path, &library_dirs);
+        KarabinerPklError::PklCompileError(Box::new(diagnostic))
     }
 
     pub fn materialize_pkl_lib() -> Result<PathBuf> {
-        let data_dir = PathBuf::from(ANKURA_LIB_DIR);
+        let data_dir = Self::lib_dir();
 
         debug!(
             "Attempting to materialize pkl files to {}",
@@ -227,7 +381,27 @@ impl Compiler {
         hasher.finish()
     }
 
+    /// `$ANKURA_LIB_DIR`, the Homebrew location when Homebrew is installed, or the user's data
+    /// directory, e.g. `~/.local/share/ankura/lib` on Linux.
     pub fn lib_dir() -> PathBuf {
-        PathBuf::from(ANKURA_LIB_DIR)
+        if let Some(dir) = std::env::var_os("ANKURA_LIB_DIR") {
+            return PathBuf::from(dir);
+        }
+        let homebrew = PathBuf::from(HOMEBREW_LIB_DIR);
+        if homebrew.ancestors().nth(2).is_some_and(Path::exists) {
+            return homebrew;
+        }
+        dirs::data_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("ankura")
+            .join("lib")
     }
 }
+
+fn join_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join(":")
+}
@@ -0,0 +1,261 @@
+use miette::{Diagnostic, NamedSource, SourceSpan};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PklFrame {
+    pub member: String,
+    pub uri: String,
+    pub path: Option<PathBuf>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub length: usize,
+    pub snippet: Option<String>,
+}
+
+impl PklFrame {
+    fn location(&self) -> String {
+        let name = self
+            .path
+            .as_deref()
+            .and_then(Path::file_name)
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.uri.clone());
+        match self.line {
+            Some(line) => format!("{name}:{line}"),
+            None => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PklErrorReport {
+    pub message: String,
+    pub frames: Vec<PklFrame>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("{message}")]
+#[diagnostic(code(ankura::pkl_compile_error))]
+pub struct PklDiagnostic {
+    pub message: String,
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    pub frames: Vec<PklFrame>,
+    #[source_code]
+    pub source_code: Option<NamedSource<String>>,
+    #[label("evaluated here")]
+    pub span: Option<SourceSpan>,
+    #[help]
+    pub help: Option<String>,
+}
+
+struct Snippet {
+    prefix: usize,
+    line: usize,
+    text: String,
+    column: Option<usize>,
+    length: usize,
+}
+
+pub fn parse(stderr: &str) -> PklErrorReport {
+    static SNIPPET: OnceLock<Regex> = OnceLock::new();
+    static CARET: OnceLock<Regex> = OnceLock::new();
+    static FRAME: OnceLock<Regex> = OnceLock::new();
+    let snippet_regex = SNIPPET.get_or_init(|| Regex::new(r"^(\s*(\d+) \| )(.*)$").unwrap());
+    let caret_regex = CARET.get_or_init(|| Regex::new(r"^(\s*)(\^+)\s*$").unwrap());
+    let frame_regex =
+        FRAME.get_or_init(|| Regex::new(r"^at (\S+) \((.+?)(?:, line (\d+))?\)$").unwrap());
+
+    let mut report = PklErrorReport::default();
+    let mut message_lines: Vec<&str> = Vec::new();
+    let mut in_message = true;
+    let mut pending: Option<Snippet> = None;
+
+    for raw_line in stderr.lines() {
+        let line = raw_line.trim_end();
+        if line.starts_with("––") && line.contains("Pkl Error") {
+            continue;
+        }
+
+        if let Some(caps) = snippet_regex.captures(line) {
+            in_message = false;
+            // Multi-line expressions print several source lines; the first one anchors the span.
+            if pending.is_some() {
+                continue;
+            }
+            pending = Some(Snippet {
+                prefix: caps[1].chars().count(),
+                line: caps[2].parse().unwrap_or(0),
+                text: caps[3].to_string(),
+                column: None,
+                length: 0,
+            });
+            continue;
+        }
+
+        if let Some(caps) = caret_regex.captures(line) {
+            if let Some(snippet) = pending.as_mut().filter(|s| s.column.is_none()) {
+                snippet.column = caps[1]
+                    .chars()
+                    .count()
+                    .checked_sub(snippet.prefix)
+                    .map(|column| column + 1);
+                snippet.length = caps[2].len();
+            }
+            continue;
+        }
+
+        if let Some(caps) = frame_regex.captures(line) {
+            in_message = false;
+            let uri = caps[2].to_string();
+            let snippet = pending.take();
+            let line = caps
+                .get(3)
+                .and_then(|m| m.as_str().parse().ok())
+                .or_else(|| snippet.as_ref().map(|s| s.line).filter(|line| *line > 0));
+
+            report.frames.push(PklFrame {
+                member: caps[1].to_string(),
+                path: uri_to_path(&uri),
+                uri,
+                line,
+                column: snippet.as_ref().and_then(|s| s.column),
+                length: snippet.as_ref().map_or(0, |s| s.length),
+                snippet: snippet.map(|s| s.text),
+            });
+            continue;
+        }
+
+        if in_message {
+            if line.trim().is_empty() {
+                if !message_lines.is_empty() {
+                    in_message = false;
+                }
+                continue;
+            }
+            message_lines.push(line.trim());
+        }
+    }
+
+    report.message = if message_lines.is_empty() {
+        "Compilation failed".to_string()
+    } else {
+        message_lines.join("\n")
+    };
+    report
+}
+
+impl PklErrorReport {
+    // Errors raised inside the embedded library (core.pkl, keys.pkl, ...) are reported at the
+    // first frame that lives outside it, i.e. where the user's config called into the library.
+    pub fn into_diagnostic(self, config_path: &Path, library_dirs: &[PathBuf]) -> PklDiagnostic {
+        let is_library = |frame: &PklFrame| {
+            frame
+                .path
+                .as_ref()
+                .is_none_or(|path| library_dirs.iter().any(|dir| path.starts_with(dir)))
+        };
+
+        let user_frame_index = self
+            .frames
+            .iter()
+            .position(|frame| frame.path.as_deref() == Some(config_path))
+            .or_else(|| self.frames.iter().position(|frame| !is_library(frame)));
+
+        let help = match user_frame_index {
+            Some(index) if index > 0 => {
+                let stack: Vec<String> = self.frames[..index]
+                    .iter()
+                    .rev()
+                    .map(|frame| format!("{} ({})", frame.member, frame.location()))
+                    .collect();
+                Some(format!("raised via {}", stack.join(" → ")))
+            }
+            _ => None,
+        };
+
+        let frame = user_frame_index.and_then(|index| self.frames.get(index));
+        let file = frame.and_then(|frame| frame.path.clone());
+        let line = frame.and_then(|frame| frame.line).unwrap_or(0);
+        let column = frame.and_then(|frame| frame.column).unwrap_or(1);
+
+        let (source_code, span) = match (frame, file.as_deref()) {
+            (Some(frame), Some(path)) if line > 0 => match std::fs::read_to_string(path) {
+                Ok(contents) => {
+                    let span = span_for(&contents, line, column, frame.length);
+                    (
+                        Some(NamedSource::new(path.display().to_string(), contents)),
+                        span,
+                    )
+                }
+                Err(_) => (None, None),
+            },
+            _ => (None, None),
+        };
+
+        PklDiagnostic {
+            message: self.message,
+            file,
+            line,
+            column,
+            frames: self.frames,
+            source_code,
+            span,
+            help,
+        }
+    }
+}
+
+fn span_for(contents: &str, line: usize, column: usize, length: usize) -> Option<SourceSpan> {
+    let mut offset = 0;
+    for (index, text) in contents.split_inclusive('\n').enumerate() {
+        if index + 1 == line {
+            let line_text = text.trim_end_matches(['\n', '\r']);
+            let start = line_text
+                .char_indices()
+                .nth(column.saturating_sub(1))
+                .map_or(line_text.len(), |(i, _)| i);
+            let len = if length == 0 {
+                line_text.len() - start
+            } else {
+                line_text[start..]
+                    .char_indices()
+                    .nth(length)
+                    .map_or(line_text.len() - start, |(i, _)| i)
+            };
+            return Some(SourceSpan::new((offset + start).into(), len));
+        }
+        offset += text.len();
+    }
+    None
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut decoded = Vec::with_capacity(path.len());
+    let bytes = path.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        // Works on bytes: the path may hold non-ASCII characters next to a `%`.
+        if let (b'%', Some(&[high, low])) = (bytes[i], bytes.get(i + 1..i + 3)) {
+            if let (Some(high), Some(low)) = (hex_digit(high), hex_digit(low)) {
+                decoded.push(high << 4 | low);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    Some(PathBuf::from(
+        String::from_utf8_lossy(&decoded).into_owned(),
+    ))
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
@@ -0,0 +1,588 @@
+use crate::error::{KarabinerPklError, Result};
+use rmpv::Value;
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::oneshot;
+use tracing::{debug, warn};
+
+// Message codes from Pkl's message passing API (https://pkl-lang.org/main/current/bindings-specification/message-passing-api.html).
+const CREATE_EVALUATOR_REQUEST: u8 = 0x20;
+const CREATE_EVALUATOR_RESPONSE: u8 = 0x21;
+const CLOSE_EVALUATOR: u8 = 0x22;
+const EVALUATE_REQUEST: u8 = 0x23;
+const EVALUATE_RESPONSE: u8 = 0x24;
+const LOG_MESSAGE: u8 = 0x25;
+const READ_RESOURCE_REQUEST: u8 = 0x26;
+const READ_RESOURCE_RESPONSE: u8 = 0x27;
+const READ_MODULE_REQUEST: u8 = 0x28;
+const READ_MODULE_RESPONSE: u8 = 0x29;
+const LIST_RESOURCES_REQUEST: u8 = 0x2a;
+const LIST_RESOURCES_RESPONSE: u8 = 0x2b;
+const LIST_MODULES_REQUEST: u8 = 0x2c;
+const LIST_MODULES_RESPONSE: u8 = 0x2d;
+
+const ALLOWED_MODULES: &[&str] = &[
+    "pkl:",
+    "repl:",
+    "file:",
+    "https:",
+    "package:",
+    "projectpackage:",
+    "modulepath:",
+];
+const ALLOWED_RESOURCES: &[&str] = &[
+    "env:",
+    "prop:",
+    "file:",
+    "https:",
+    "package:",
+    "projectpackage:",
+    "modulepath:",
+];
+
+pub struct PathElement {
+    pub name: String,
+    pub is_directory: bool,
+}
+
+/// A Pkl module source served from Rust, e.g. `import "ankura:layers.pkl"`.
+pub trait ModuleReader: Send + Sync {
+    fn scheme(&self) -> &str;
+
+    fn read(&self, uri: &str) -> std::result::Result<String, String>;
+
+    fn is_local(&self) -> bool {
+        true
+    }
+
+    fn has_hierarchical_uris(&self) -> bool {
+        false
+    }
+
+    fn is_globbable(&self) -> bool {
+        false
+    }
+
+    fn list(&self, _uri: &str) -> std::result::Result<Vec<PathElement>, String> {
+        Err(format!("Listing is not supported for {}:", self.scheme()))
+    }
+}
+
+/// A resource served from Rust, e.g. `read("ankura:version")`.
+pub trait ResourceReader: Send + Sync {
+    fn scheme(&self) -> &str;
+
+    fn read(&self, uri: &str) -> std::result::Result<Vec<u8>, String>;
+
+    fn has_hierarchical_uris(&self) -> bool {
+        false
+    }
+
+    fn is_globbable(&self) -> bool {
+        false
+    }
+
+    fn list(&self, _uri: &str) -> std::result::Result<Vec<PathElement>, String> {
+        Err(format!("Listing is not supported for {}:", self.scheme()))
+    }
+}
+
+#[derive(Default)]
+struct Readers {
+    modules: Vec<Arc<dyn ModuleReader>>,
+    resources: Vec<Arc<dyn ResourceReader>>,
+}
+
+type Pending = Arc<Mutex<HashMap<i64, oneshot::Sender<Value>>>>;
+
+/// A long-running `pkl server` process.
+///
+/// Pkl evaluators cache every module they load, so each evaluation gets a fresh evaluator
+/// inside the server; what is reused across daemon reloads is the warmed-up process.
+pub struct PklServer {
+    child: Mutex<Child>,
+    stdin: Arc<Mutex<BufWriter<ChildStdin>>>,
+    pending: Pending,
+    readers: Arc<RwLock<Readers>>,
+    next_request_id: AtomicI64,
+}
+
+impl PklServer {
+    pub fn spawn(pkl_path: &Path) -> Result<Self> {
+        let mut child = Command::new(pkl_path)
+            .arg("server")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| KarabinerPklError::DaemonError {
+                message: format!("Failed to start pkl server: {e}"),
+            })?;
+
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| KarabinerPklError::DaemonError {
+                message: "pkl server stdin unavailable".to_string(),
+            })?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| KarabinerPklError::DaemonError {
+                message: "pkl server stdout unavailable".to_string(),
+            })?;
+
+        let server = Self {
+            child: Mutex::new(child),
+            stdin: Arc::new(Mutex::new(BufWriter::new(stdin))),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            readers: Arc::new(RwLock::new(Readers::default())),
+            next_request_id: AtomicI64::new(1),
+        };
+
+        let pending = server.pending.clone();
+        let readers = server.readers.clone();
+        let stdin = server.stdin.clone();
+        std::thread::Builder::new()
+            .name("pkl-server-reader".to_string())
+            .spawn(move || {
+                let mut reader = BufReader::new(stdout);
+                loop {
+                    match rmpv::decode::read_value(&mut reader) {
+                        Ok(message) => dispatch(message, &pending, &readers, &stdin),
+                        Err(e) => {
+                            debug!("pkl server stream closed: {e}");
+                            break;
+                        }
+                    }
+                }
+                // Dropping the senders wakes every waiting request with an error.
+                pending.lock().unwrap_or_else(|p| p.into_inner()).clear();
+            })
+            .map_err(|e| KarabinerPklError::DaemonError {
+                message: format!("Failed to start pkl server reader thread: {e}"),
+            })?;
+
+        Ok(server)
+    }
+
+    pub fn register_module_reader(&self, reader: Arc<dyn ModuleReader>) {
+        self.readers
+            .write()
+            .unwrap_or_else(|p| p.into_inner())
+            .modules
+            .push(reader);
+    }
+
+    pub fn register_resource_reader(&self, reader: Arc<dyn ResourceReader>) {
+        self.readers
+            .write()
+            .unwrap_or_else(|p| p.into_inner())
+            .resources
+            .push(reader);
+    }
+
+    pub fn is_alive(&self) -> bool {
+        matches!(
+            self.child
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .try_wait(),
+            Ok(None)
+        )
+    }
+
+    /// Evaluates `module_path` and returns its rendered output.
+    ///
+    /// The outer error is a transport failure (the caller should fall back to `pkl eval`);
+    /// the inner one is the Pkl error text, formatted the same way as `pkl eval` stderr.
+    pub async fn evaluate(
+        &self,
+        module_path: &Path,
+        module_paths: &[PathBuf],
+        output_format: &str,
+    ) -> Result<std::result::Result<String, String>> {
+        let evaluator_id = self.create_evaluator(module_paths, output_format).await?;
+        let _evaluator = EvaluatorGuard {
+            server: self,
+            evaluator_id,
+        };
+
+        let request_id = self.next_request_id();
+        let body = map(vec![
+            ("requestId", Value::from(request_id)),
+            ("evaluatorId", Value::from(evaluator_id)),
+            ("moduleUri", Value::from(file_uri(module_path))),
+            ("expr", Value::from("output.text")),
+        ]);
+        let response = self.request(EVALUATE_REQUEST, request_id, body).await?;
+        if let Some(error) = field(&response, "error").and_then(Value::as_str) {
+            return Ok(Err(error.to_string()));
+        }
+
+        let result = field(&response, "result")
+            .and_then(Value::as_slice)
+            .ok_or_else(|| protocol_error("evaluate response has no result"))?;
+        let decoded = rmpv::decode::read_value(&mut &result[..])
+            .map_err(|e| protocol_error(&format!("invalid evaluate result: {e}")))?;
+        let text = decoded
+            .as_str()
+            .ok_or_else(|| protocol_error("evaluate result is not a string"))?;
+
+        Ok(Ok(text.to_string()))
+    }
+
+    async fn create_evaluator(&self, module_paths: &[PathBuf], output_format: &str) -> Result<i64> {
+        let (module_readers, resource_readers, module_schemes, resource_schemes) = {
+            let readers = self.readers.read().unwrap_or_else(|p| p.into_inner());
+            let modules: Vec<Value> = readers
+                .modules
+                .iter()
+                .map(|r| {
+                    map(vec![
+                        ("scheme", Value::from(r.scheme())),
+                        (
+                            "hasHierarchicalUris",
+                            Value::from(r.has_hierarchical_uris()),
+                        ),
+                        ("isGlobbable", Value::from(r.is_globbable())),
+                        ("isLocal", Value::from(r.is_local())),
+                    ])
+                })
+                .collect();
+            let resources: Vec<Value> = readers
+                .resources
+                .iter()
+                .map(|r| {
+                    map(vec![
+                        ("scheme", Value::from(r.scheme())),
+                        (
+                            "hasHierarchicalUris",
+                            Value::from(r.has_hierarchical_uris()),
+                        ),
+                        ("isGlobbable", Value::from(r.is_globbable())),
+                    ])
+                })
+                .collect();
+            let module_schemes: Vec<String> = readers
+                .modules
+                .iter()
+                .map(|r| format!("{}:", r.scheme()))
+                .collect();
+            let resource_schemes: Vec<String> = readers
+                .resources
+                .iter()
+                .map(|r| format!("{}:", r.scheme()))
+                .collect();
+            (modules, resources, module_schemes, resource_schemes)
+        };
+
+        let allowed_modules: Vec<Value> = ALLOWED_MODULES
+            .iter()
+            .map(|s| s.to_string())
+            .chain(module_schemes)
+            .map(Value::from)
+            .collect();
+        let allowed_resources: Vec<Value> = ALLOWED_RESOURCES
+            .iter()
+            .map(|s| s.to_string())
+            .chain(resource_schemes)
+            .map(Value::from)
+            .collect();
+        let env: Vec<(Value, Value)> = std::env::vars()
+            .map(|(k, v)| (Value::from(k), Value::from(v)))
+            .collect();
+
+        let request_id = self.next_request_id();
+        let body = map(vec![
+            ("requestId", Value::from(request_id)),
+            ("allowedModules", Value::from(allowed_modules)),
+            ("allowedResources", Value::from(allowed_resources)),
+            ("clientModuleReaders", Value::from(module_readers)),
+            ("clientResourceReaders", Value::from(resource_readers)),
+            (
+                "modulePaths",
+                Value::from(
+                    module_paths
+                        .iter()
+                        .map(|p| Value::from(p.to_string_lossy().to_string()))
+                        .collect::<Vec<_>>(),
+                ),
+            ),
+            ("env", Value::Map(env)),
+            ("properties", Value::Map(Vec::new())),
+            ("outputFormat", Value::from(output_format)),
+        ]);
+
+        let response = self
+            .request(CREATE_EVALUATOR_REQUEST, request_id, body)
+            .await?;
+        if let Some(error) = field(&response, "error").and_then(Value::as_str) {
+            return Err(KarabinerPklError::DaemonError {
+                message: format!("pkl server refused to create an evaluator: {error}"),
+            });
+        }
+
+        field(&response, "evaluatorId")
+            .and_then(Value::as_i64)
+            .ok_or_else(|| protocol_error("create evaluator response has no evaluatorId"))
+    }
+
+    async fn request(&self, code: u8, request_id: i64, body: Value) -> Result<Value> {
+        let (tx, rx) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .insert(request_id, tx);
+
+        if let Err(e) = self.send(code, body) {
+            self.pending
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .remove(&request_id);
+            return Err(e);
+        }
+
+        rx.await.map_err(|_| KarabinerPklError::DaemonError {
+            message: "pkl server exited before responding".to_string(),
+        })
+    }
+
+    fn send(&self, code: u8, body: Value) -> Result<()> {
+        write_message(&self.stdin, code, body).map_err(|e| KarabinerPklError::DaemonError {
+            message: format!("Failed to write to pkl server: {e}"),
+        })
+    }
+
+    fn next_request_id(&self) -> i64 {
+        self.next_request_id.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+// Closes the evaluator even when the evaluation is abandoned midway, e.g. because a newer
+// change cancelled the compile.
+struct EvaluatorGuard<'a> {
+    server: &'a PklServer,
+    evaluator_id: i64,
+}
+
+impl Drop for EvaluatorGuard<'_> {
+    fn drop(&mut self) {
+        let evaluator_id = self.evaluator_id;
+        if let Err(e) = self.server.send(
+            CLOSE_EVALUATOR,
+            map(vec![("evaluatorId", Value::from(evaluator_id))]),
+        ) {
+            debug!("Failed to close pkl evaluator {evaluator_id}: {e}");
+        }
+    }
+}
+
+impl Drop for PklServer {
+    fn drop(&mut self) {
+        let mut child = self.child.lock().unwrap_or_else(|p| p.into_inner());
+        if let Err(e) = child.kill() {
+            debug!("Failed to stop pkl server: {e}");
+        }
+        let _ = child.wait();
+    }
+}
+
+fn dispatch(
+    message: Value,
+    pending: &Pending,
+    readers: &RwLock<Readers>,
+    stdin: &Mutex<BufWriter<ChildStdin>>,
+) {
+    let Some([code, body]) = message
+        .as_array()
+        .and_then(|a| <&[Value; 2]>::try_from(a.as_slice()).ok())
+    else {
+        warn!("Ignoring malformed pkl server message");
+        return;
+    };
+    let Some(code) = code.as_u64().and_then(|c| u8::try_from(c).ok()) else {
+        warn!("Ignoring pkl server message without a code");
+        return;
+    };
+
+    let request_id = field(body, "requestId").and_then(Value::as_i64);
+    let evaluator_id = field(body, "evaluatorId").cloned().unwrap_or(Value::Nil);
+    let uri = field(body, "uri")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+
+    let reply = |code: u8, result: Vec<(&str, Value)>| {
+        let mut entries = vec![
+            ("requestId", Value::from(request_id.unwrap_or_default())),
+            ("evaluatorId", evaluator_id.clone()),
+        ];
+        entries.extend(result);
+        if let Err(e) = write_message(stdin, code, map(entries)) {
+            warn!("Failed to answer pkl server request: {e}");
+        }
+    };
+
+    match code {
+        CREATE_EVALUATOR_RESPONSE | EVALUATE_RESPONSE => {
+            let sender = request_id.and_then(|id| {
+                pending
+                    .lock()
+                    .unwrap_or_else(|p| p.into_inner())
+                    .remove(&id)
+            });
+            if let Some(sender) = sender {
+                let _ = sender.send(body.clone());
+            }
+        }
+        LOG_MESSAGE => {
+            let text = field(body, "message")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            debug!("pkl: {text}");
+        }
+        READ_MODULE_REQUEST => {
+            let reader = find_reader(
+                &readers.read().unwrap_or_else(|p| p.into_inner()).modules,
+                uri,
+                |r| r.scheme(),
+            );
+            let result = match reader {
+                Some(reader) => reader.read(uri),
+                None => Err(format!("No module reader registered for {uri}")),
+            };
+            reply(
+                READ_MODULE_RESPONSE,
+                match result {
+                    Ok(contents) => vec![("contents", Value::from(contents))],
+                    Err(error) => vec![("error", Value::from(error))],
+                },
+            );
+        }
+        READ_RESOURCE_REQUEST => {
+            let reader = find_reader(
+                &readers.read().unwrap_or_else(|p| p.into_inner()).resources,
+                uri,
+                |r| r.scheme(),
+            );
+            let result = match reader {
+                Some(reader) => reader.read(uri),
+                None => Err(format!("No resource reader registered for {uri}")),
+            };
+            reply(
+                READ_RESOURCE_RESPONSE,
+                match result {
+                    Ok(contents) => vec![("contents", Value::Binary(contents))],
+                    Err(error) => vec![("error", Value::from(error))],
+                },
+            );
+        }
+        LIST_MODULES_REQUEST => {
+            let reader = find_reader(
+                &readers.read().unwrap_or_else(|p| p.into_inner()).modules,
+                uri,
+                |r| r.scheme(),
+            );
+            let result = match reader {
+                Some(reader) => reader.list(uri),
+                None => Err(format!("No module reader registered for {uri}")),
+            };
+            reply(LIST_MODULES_RESPONSE, list_result(result));
+        }
+        LIST_RESOURCES_REQUEST => {
+            let reader = find_reader(
+                &readers.read().unwrap_or_else(|p| p.into_inner()).resources,
+                uri,
+                |r| r.scheme(),
+            );
+            let result = match reader {
+                Some(reader) => reader.list(uri),
+                None => Err(format!("No resource reader registered for {uri}")),
+            };
+            reply(LIST_RESOURCES_RESPONSE, list_result(result));
+        }
+        other => debug!("Ignoring pkl server message 0x{other:x}"),
+    }
+}
+
+fn find_reader<T: ?Sized>(
+    readers: &[Arc<T>],
+    uri: &str,
+    scheme: impl Fn(&T) -> &str,
+) -> Option<Arc<T>> {
+    let uri_scheme = uri.split(':').next().unwrap_or_default();
+    readers.iter().find(|r| scheme(r) == uri_scheme).cloned()
+}
+
+fn list_result(
+    result: std::result::Result<Vec<PathElement>, String>,
+) -> Vec<(&'static str, Value)> {
+    match result {
+        Ok(elements) => vec![(
+            "pathElements",
+            Value::from(
+                elements
+                    .into_iter()
+                    .map(|e| {
+                        map(vec![
+                            ("name", Value::from(e.name)),
+                            ("isDirectory", Value::from(e.is_directory)),
+                        ])
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+        )],
+        Err(error) => vec![("error", Value::from(error))],
+    }
+}
+
+fn write_message(
+    stdin: &Mutex<BufWriter<ChildStdin>>,
+    code: u8,
+    body: Value,
+) -> std::io::Result<()> {
+    let message = Value::Array(vec![Value::from(code), body]);
+    let mut stdin = stdin.lock().unwrap_or_else(|p| p.into_inner());
+    rmpv::encode::write_value(&mut *stdin, &message).map_err(std::io::Error::other)?;
+    stdin.flush()
+}
+
+fn map(entries: Vec<(&str, Value)>) -> Value {
+    Value::Map(
+        entries
+            .into_iter()
+            .map(|(key, value)| (Value::from(key), value))
+            .collect(),
+    )
+}
+
+fn field<'a>(body: &'a Value, name: &str) -> Option<&'a Value> {
+    body.as_map()?
+        .iter()
+        .find(|(key, _)| key.as_str() == Some(name))
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_nil())
+}
+
+fn protocol_error(message: &str) -> KarabinerPklError {
+    KarabinerPklError::DaemonError {
+        message: format!("Unexpected pkl server response: {message}"),
+    }
+}
+
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            other => uri.push_str(&format!("%{other:02X}")),
+        }
+    }
+    uri
+}
@@ -0,0 +1,194 @@
+use super::state::DaemonStatus;
+use super::Daemon;
+use crate::cli::runtime_dir;
+use crate::error::{KarabinerPklError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+// Long enough for a reload to compile a large config.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+// `ankura status` falls back to the state file rather than wait on a stuck daemon.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A command sent to the daemon, one JSON object per line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Request {
+    Reload,
+    Pause,
+    Resume,
+    Status,
+    Shutdown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "lowercase")]
+pub enum Response {
+    Ok { message: String },
+    Status { status: DaemonStatus },
+    Error { message: String },
+}
+
+pub fn socket_path() -> Result<PathBuf> {
+    Ok(runtime_dir()?.join("ankura.sock"))
+}
+
+/// The daemon's control socket; removed again when dropped.
+pub struct ControlSocket {
+    path: PathBuf,
+    task: JoinHandle<()>,
+}
+
+impl ControlSocket {
+    pub fn bind(daemon: Arc<Daemon>) -> Result<Self> {
+        let path = socket_path()?;
+        let socket_error = |e: std::io::Error| KarabinerPklError::DaemonError {
+            message: format!("Failed to bind control socket {}: {e}", path.display()),
+        };
+
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                return Err(KarabinerPklError::DaemonError {
+                    message: format!(
+                        "Another daemon is listening on {}; run `ankura stop` first",
+                        path.display()
+                    ),
+                });
+            }
+            debug!("Removing stale control socket {}", path.display());
+            fs::remove_file(&path).map_err(socket_error)?;
+        }
+
+        let listener = UnixListener::bind(&path).map_err(socket_error)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).map_err(socket_error)?;
+        debug!("Control socket listening on {}", path.display());
+
+        let task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let daemon = daemon.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = serve(stream, &daemon).await {
+                                warn!("Control connection failed: {e}");
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Failed to accept control connection: {e}"),
+                }
+            }
+        });
+
+        Ok(Self { path, task })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.task.abort();
+        if let Err(e) = fs::remove_file(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!(
+                    "Failed to remove control socket {}: {e}",
+                    self.path.display()
+                );
+            }
+        }
+    }
+}
+
+async fn serve(stream: UnixStream, daemon: &Daemon) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+
+    let response = match serde_json::from_str::<Request>(&line) {
+        Ok(request) => {
+            debug!("Control request: {request:?}");
+            handle(request, daemon).await
+        }
+        Err(e) => Response::Error {
+            message: format!("Invalid request: {e}"),
+        },
+    };
+
+    let mut reply = serde_json::to_string(&response)?;
+    reply.push('\n');
+    writer.write_all(reply.as_bytes()).await?;
+    writer.shutdown().await
+}
+
+async fn handle(request: Request, daemon: &Daemon) -> Response {
+    let ok = |message: &str| Response::Ok {
+        message: message.to_string(),
+    };
+
+    match request {
+        Request::Reload => match daemon.reload().await {
+            Ok(()) => ok("Configuration reloaded"),
+            Err(message) => Response::Error { message },
+        },
+        Request::Pause => match daemon.pause().await {
+            true => ok("Paused; changes will be applied on resume"),
+            false => ok("Already paused"),
+        },
+        Request::Resume => match daemon.resume().await {
+            Ok(true) => ok("Resumed and applied pending changes"),
+            Ok(false) => ok("Resumed"),
+            Err(message) => Response::Error { message },
+        },
+        Request::Status => Response::Status {
+            status: daemon.status().await,
+        },
+        Request::Shutdown => {
+            daemon.request_shutdown();
+            ok("Daemon is shutting down")
+        }
+    }
+}
+
+/// Sends one request to the running daemon.
+pub async fn send(request: Request) -> Result<Response> {
+    let path = socket_path()?;
+    let daemon_error = |message: String| KarabinerPklError::DaemonError { message };
+
+    let exchange = async {
+        let stream = UnixStream::connect(&path)
+            .await
+            .map_err(|_| daemon_error("Daemon is not running".to_string()))?;
+        let (reader, mut writer) = stream.into_split();
+
+        let mut line = serde_json::to_string(&request)
+            .map_err(|e| daemon_error(format!("Failed to encode request: {e}")))?;
+        line.push('\n');
+        writer
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|e| daemon_error(format!("Failed to send request to daemon: {e}")))?;
+
+        let mut reply = String::new();
+        BufReader::new(reader)
+            .read_line(&mut reply)
+            .await
+            .map_err(|e| daemon_error(format!("Failed to read daemon response: {e}")))?;
+
+        serde_json::from_str(&reply)
+            .map_err(|e| daemon_error(format!("Invalid daemon response: {e}")))
+    };
+
+    let timeout = match request {
+        Request::Status => STATUS_TIMEOUT,
+        _ => REQUEST_TIMEOUT,
+    };
+    tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| daemon_error("Timed out waiting for the daemon".to_string()))?
+}
@@ -1,35 +1,109 @@
+pub mod control;
+pub mod notifier;
+pub mod state;
+pub mod watch;
+
+use crate::analysis;
 use crate::cli::{merge_configurations, write_karabiner_config};
 use crate::compiler::Compiler;
 use crate::error::{KarabinerPklError, Result};
-use mac_notification_sys::Notification;
-use notify::{RecommendedWatcher, RecursiveMode};
-use notify_debouncer_mini::{new_debouncer, DebouncedEventKind, Debouncer};
-use std::ffi::OsString;
+use crate::karabiner::Config;
+use crate::output::OutputLock;
+use crate::settings::{DaemonSettings, Settings};
+use chrono::Local;
+use notifier::{NotificationManager, Notifier};
+use notify::RecommendedWatcher;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, DebouncedEventKind, Debouncer};
+use state::{CompileRecord, DaemonStatus};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::RwLock;
-use tracing::{debug, error, info};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Notify, RwLock};
+use tokio::task::AbortHandle;
+use tracing::{debug, error, info, warn};
+use watch::WatchSet;
 
 pub struct Daemon {
-    config_path: PathBuf,
-    compiler: Arc<Compiler>,
-    notification_manager: Arc<NotificationManager>,
+    shared: Arc<Shared>,
     is_running: Arc<RwLock<bool>>,
-    watcher: Arc<RwLock<Option<Debouncer<RecommendedWatcher>>>>,
+    shutdown: Notify,
+}
+
+// Everything a compile touches, shared with the watch task and the compile tasks.
+struct Shared {
+    config_path: PathBuf,
+    compiler: Compiler,
+    notification_manager: NotificationManager,
+    watcher: RwLock<Option<Debouncer<RecommendedWatcher>>>,
+    state: RwLock<DaemonState>,
+    // The compile currently running; a newer one aborts it so only the latest edit lands.
+    in_flight: Mutex<Option<AbortHandle>>,
+}
+
+// What `ankura status` reports, updated by the watcher task and control requests.
+struct DaemonState {
+    status: DaemonStatus,
+    // A change arrived while paused and is applied on resume.
+    pending_change: bool,
+    watch: WatchSet,
+    // From the last config that compiled; the defaults until then.
+    compile_timeout: Duration,
+}
+
+impl DaemonState {
+    fn persist(&self) {
+        if let Err(e) = state::save(&self.status) {
+            warn!("{e:?}");
+        }
+    }
 }
 
 impl Daemon {
     pub fn new(config_path: PathBuf) -> Result<Self> {
-        let compiler = Arc::new(Compiler::new()?);
-        let notification_manager = Arc::new(NotificationManager::new());
+        Self::with_notifications(config_path, NotificationManager::new())
+    }
+
+    /// Reports through `notifier` instead of the backend the config selects.
+    pub fn with_notifier(config_path: PathBuf, notifier: Arc<dyn Notifier>) -> Result<Self> {
+        Self::with_notifications(config_path, NotificationManager::pinned(notifier))
+    }
+
+    fn with_notifications(
+        config_path: PathBuf,
+        notification_manager: NotificationManager,
+    ) -> Result<Self> {
+        let compiler = Compiler::with_server()?;
+        let pkl_version = compiler.pkl_version().to_string();
+
+        let state = DaemonState {
+            status: DaemonStatus {
+                pid: std::process::id(),
+                config_path: config_path.clone(),
+                output_path: Shared::output_path(),
+                started_at: Local::now(),
+                uptime_secs: 0,
+                paused: false,
+                watched: Vec::new(),
+                last_compile: None,
+                ankura_version: env!("CARGO_PKG_VERSION").to_string(),
+                pkl_version,
+            },
+            pending_change: false,
+            watch: WatchSet::default(),
+            compile_timeout: Duration::from_secs(DaemonSettings::default().compile_timeout),
+        };
 
         Ok(Self {
-            config_path,
-            compiler,
-            notification_manager,
+            shared: Arc::new(Shared {
+                config_path,
+                compiler,
+                notification_manager,
+                watcher: RwLock::new(None),
+                state: RwLock::new(state),
+                in_flight: Mutex::new(None),
+            }),
             is_running: Arc::new(RwLock::new(false)),
-            watcher: Arc::new(RwLock::new(None)),
+            shutdown: Notify::new(),
         })
     }
 
@@ -45,88 +119,66 @@ impl Daemon {
         }
 
         info!("Starting ankura daemon");
-        debug!("Watching: {}", self.config_path.display());
-
-        self.compile_and_notify(None).await;
 
-        let (tx, rx) = std::sync::mpsc::channel();
-        let mut debouncer = new_debouncer(Duration::from_secs(5), tx)
-            .map_err(|e| KarabinerPklError::WatchError { source: e })?;
-
-        let config_dir = self.config_path.parent().unwrap_or(&self.config_path);
-        debouncer
-            .watcher()
-            .watch(config_dir, RecursiveMode::Recursive)
-            .map_err(|e| KarabinerPklError::WatchError { source: e })?;
+        // notify delivers events on its own thread; forwarding them into a tokio channel
+        // lets the watch task wait without tying up a runtime worker.
+        let (tx, mut rx) = mpsc::unbounded_channel::<DebounceEventResult>();
+        let debouncer = new_debouncer(Duration::from_secs(5), move |result| {
+            let _ = tx.send(result);
+        })
+        .map_err(|e| KarabinerPklError::WatchError { source: e })?;
 
         {
-            let mut watcher_guard = self.watcher.write().await;
+            let mut watcher_guard = self.shared.watcher.write().await;
             *watcher_guard = Some(debouncer);
         }
 
-        let compiler = self.compiler.clone();
-        let notification_manager = self.notification_manager.clone();
-        let config_path = self.config_path.clone();
+        // `ankura status` can see the daemon while the first compile is still running.
+        self.shared.state.read().await.persist();
+
+        // Also points the watcher at the config's import graph.
+        let _ = self.shared.compile_latest().await;
+
+        let shared = self.shared.clone();
         let is_running = self.is_running.clone();
-        let config_file_name = config_path.file_name().map(OsString::from);
-        let watcher = self.watcher.clone();
 
         tokio::spawn(async move {
-            enum WatchLoopExit {
-                ChannelClosed(std::sync::mpsc::RecvError),
-                Stopped,
-            }
-
-            let exit_reason = loop {
-                match rx.recv() {
-                    Ok(Ok(events)) => {
-                        let should_compile = events.iter().any(|event| {
-                            let is_target = if let Some(file_name) = &config_file_name {
-                                event.path.file_name().is_some_and(|name| name == file_name)
-                            } else {
-                                event.path == config_path
-                            };
-                            let is_settled = event.kind == DebouncedEventKind::Any;
-                            is_target && is_settled
-                        });
-
-                        if should_compile {
-                            debug!("Configuration file changed, recompiling...");
-                            Self::compile_with_notification(
-                                &compiler,
-                                &notification_manager,
-                                &config_path,
-                                None,
-                            )
-                            .await;
-                        }
-                    }
-                    Ok(Err(e)) => {
+            // Closed when `stop` drops the debouncer.
+            while let Some(result) = rx.recv().await {
+                let events = match result {
+                    Ok(events) => events,
+                    Err(e) => {
                         error!("Watch error: {:?}", e);
+                        continue;
                     }
-                    Err(e) => break WatchLoopExit::ChannelClosed(e),
-                }
+                };
 
-                if !*is_running.read().await {
-                    break WatchLoopExit::Stopped;
-                }
-            };
-
-            match exit_reason {
-                WatchLoopExit::ChannelClosed(e) => {
-                    if *is_running.read().await {
-                        error!("File watcher channel closed unexpectedly: {:?}", e);
-                    } else {
-                        debug!("File watcher channel closed");
-                    }
+                let watch = shared.state.read().await.watch.clone();
+                let should_compile = events.iter().any(|event| {
+                    event.kind == DebouncedEventKind::Any && watch.contains(&event.path)
+                });
+                if !should_compile {
+                    continue;
                 }
-                WatchLoopExit::Stopped => {
-                    debug!("File watcher loop stopping");
+
+                if shared.hold_if_paused().await {
+                    info!("Configuration file changed while paused; not applying it");
+                    continue;
                 }
+
+                debug!("Configuration file changed, recompiling...");
+                // Not awaited, so a change arriving mid-compile can cancel it.
+                let shared = shared.clone();
+                tokio::spawn(async move {
+                    let _ = shared.compile_latest().await;
+                });
             }
 
-            let mut watcher_guard = watcher.write().await;
-            watcher_guard.take();
+            if *is_running.read().await {
+                error!("File watcher channel closed unexpectedly");
+            } else {
+                debug!("File watcher loop stopping");
+            }
         });
 
         info!("Daemon started successfully");
@@ -137,112 +189,218 @@ impl Daemon {
         info!("Stopping ankura daemon");
         let mut is_running = self.is_running.write().await;
         *is_running = false;
-        let mut watcher_guard = self.watcher.write().await;
+        let mut watcher_guard = self.shared.watcher.write().await;
         watcher_guard.take();
+        self.shared.cancel_in_flight();
+        if let Err(e) = state::remove() {
+            warn!("{e:?}");
+        }
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub async fn compile_once(
-        &self,
-        profile_name: Option<&str>,
-        _output_path: Option<&str>,
-    ) -> Result<()> {
-        self.compile_and_notify(profile_name).await;
-        Ok(())
+    /// Recompiles now, even while paused. Returns the error shown to the user on failure.
+    pub async fn reload(&self) -> std::result::Result<(), String> {
+        info!("Reloading configuration");
+        self.shared.compile_latest().await
     }
 
-    async fn compile_and_notify(&self, profile_name: Option<&str>) {
-        Self::compile_with_notification(
-            &self.compiler,
-            &self.notification_manager,
-            &self.config_path,
-            profile_name,
-        )
-        .await;
-    }
-
-    async fn compile_with_notification(
-        compiler: &Arc<Compiler>,
-        notification_manager: &Arc<NotificationManager>,
-        config_path: &Path,
-        profile_name: Option<&str>,
-    ) {
-        match compiler.compile(config_path, profile_name).await {
-            Ok(config) => {
-                let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
-                let output_path = home.join(".config/karabiner/karabiner.json");
-
-                let final_config = if output_path.exists() {
-                    match merge_configurations(&output_path, config) {
-                        Ok(merged) => merged,
-                        Err(e) => {
-                            error!("Failed to merge configurations: {:?}", e);
-                            notification_manager.send_error(&format!("Merge failed: {e}"));
-                            return;
-                        }
-                    }
-                } else {
-                    config
-                };
+    /// Stops applying file changes; returns false if already paused.
+    pub async fn pause(&self) -> bool {
+        let mut state = self.shared.state.write().await;
+        let was_paused = std::mem::replace(&mut state.status.paused, true);
+        if !was_paused {
+            info!("Paused; file changes will not be applied");
+            state.persist();
+        }
+        !was_paused
+    }
+
+    /// Applies file changes again, recompiling if one arrived while paused.
+    /// Returns whether a recompile happened.
+    pub async fn resume(&self) -> std::result::Result<bool, String> {
+        let pending = {
+            let mut state = self.shared.state.write().await;
+            state.status.paused = false;
+            state.persist();
+            std::mem::take(&mut state.pending_change)
+        };
+        info!("Resumed");
 
-                match write_karabiner_config(&output_path, &final_config) {
+        if pending {
+            self.reload().await?;
+        }
+        Ok(pending)
+    }
+
+    pub async fn status(&self) -> DaemonStatus {
+        let mut status = self.shared.state.read().await.status.clone();
+        status.refresh_uptime();
+        status
+    }
+
+    pub fn request_shutdown(&self) {
+        self.shutdown.notify_one();
+    }
+
+    /// Resolves once a `shutdown` control request arrives.
+    pub async fn shutdown_requested(&self) {
+        self.shutdown.notified().await;
+    }
+}
+
+impl Shared {
+    /// Compiles on a task of its own, aborting whichever compile was still running.
+    async fn compile_latest(self: &Arc<Self>) -> std::result::Result<(), String> {
+        let shared = self.clone();
+        let task = tokio::spawn(async move { shared.compile().await });
+
+        let previous = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .replace(task.abort_handle());
+        if let Some(previous) = previous.filter(|previous| !previous.is_finished()) {
+            debug!("Cancelling the previous compile in favour of a newer change");
+            previous.abort();
+        }
+
+        match task.await {
+            Ok(result) => result,
+            Err(e) if e.is_cancelled() => Err("Superseded by a newer change".to_string()),
+            Err(e) => Err(format!("Compile task failed: {e}")),
+        }
+    }
+
+    fn cancel_in_flight(&self) {
+        if let Some(task) = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .take()
+        {
+            task.abort();
+        }
+    }
+
+    async fn hold_if_paused(&self) -> bool {
+        let mut state = self.state.write().await;
+        if state.status.paused {
+            state.pending_change = true;
+        }
+        state.status.paused
+    }
+
+    async fn compile(&self) -> std::result::Result<(), String> {
+        let started = Instant::now();
+        let timeout = self.state.read().await.compile_timeout;
+
+        let compiled =
+            tokio::time::timeout(timeout, self.compiler.compile(&self.config_path, None))
+                .await
+                .unwrap_or(Err(KarabinerPklError::CompileTimeout {
+                    seconds: timeout.as_secs(),
+                }));
+
+        let result = match compiled.and_then(|config| Ok((Settings::from_config(&config)?, config)))
+        {
+            Ok((settings, config)) => {
+                for finding in analysis::analyze_config(&config, false) {
+                    warn!("{finding}");
+                }
+                self.state.write().await.compile_timeout =
+                    Duration::from_secs(settings.daemon.compile_timeout);
+                self.notification_manager.configure(&settings.notifications);
+
+                // Waiting for the output lock and the file I/O block, so they run off the
+                // runtime's worker threads.
+                let output_path = Self::output_path();
+                let written = tokio::task::spawn_blocking(move || {
+                    Self::write_output(&output_path, config, &settings)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    Err(KarabinerPklError::DaemonError {
+                        message: format!("Write task failed: {e}"),
+                    })
+                });
+                match written {
                     Ok(_) => {
                         info!("Successfully compiled configuration");
-                        notification_manager.send_success("Karabiner configuration updated");
+                        self.notification_manager
+                            .send_success("Karabiner configuration updated");
+                        Ok(())
                     }
                     Err(e) => {
                         error!("Failed to write configuration: {:?}", e);
-                        notification_manager.send_error(&format!("Write failed: {e}"));
+                        let error_msg = format!("Write failed: {e}");
+                        self.notification_manager.send_error(&error_msg);
+                        Err(error_msg)
                     }
                 }
             }
             Err(e) => {
                 error!("Compilation failed: {:?}", e);
                 let error_msg = match &e {
-                    KarabinerPklError::PklCompileError { message, line } => {
-                        format!("{message}\nAt line {line}")
+                    KarabinerPklError::PklCompileError(diagnostic) => {
+                        format!("{}\nAt line {}", diagnostic.message, diagnostic.line)
                     }
                     _ => format!("Compilation failed: {e}"),
                 };
-                notification_manager.send_error(&error_msg);
+                self.notification_manager.send_error(&error_msg);
+                Err(error_msg)
             }
-        }
-    }
-}
-
-struct NotificationManager {
-    icon_path: &'static str,
-}
+        };
 
-impl NotificationManager {
-    pub fn new() -> Self {
-        #[cfg(debug_assertions)]
-        let icon_path = "./AppIcon.icns";
+        self.update_watches().await;
 
-        #[cfg(not(debug_assertions))]
-        let icon_path = "/opt/homebrew/share/ankura/AppIcon.icns";
-
-        Self { icon_path }
+        let mut state = self.state.write().await;
+        state.status.last_compile = Some(CompileRecord {
+            finished_at: Local::now(),
+            duration_ms: started.elapsed().as_millis() as u64,
+            error: result.clone().err(),
+        });
+        state.persist();
+        result
     }
 
-    pub fn send_success(&self, message: &str) {
-        self.send_notification("Ankura - Success", message);
-    }
+    // Imports may have changed whether or not the compile succeeded, and a failed compile
+    // is often fixed in one of them.
+    async fn update_watches(&self) {
+        let config_path = &self.config_path;
+        let watch = match self.compiler.module_paths() {
+            Ok((module_paths, lib_dir)) => WatchSet::resolve(config_path, &module_paths, &lib_dir),
+            Err(e) => {
+                warn!(
+                    "Failed to resolve imports of {}: {e:?}",
+                    config_path.display()
+                );
+                WatchSet {
+                    files: [config_path.to_path_buf()].into(),
+                    lib_dir: None,
+                }
+            }
+        };
 
-    pub fn send_error(&self, message: &str) {
-        self.send_notification("Ankura - Error", message);
+        let mut state = self.state.write().await;
+        if watch != state.watch {
+            if let Some(debouncer) = self.watcher.write().await.as_mut() {
+                watch.apply(&state.watch, debouncer.watcher());
+            }
+            state.status.watched = watch.paths();
+            state.watch = watch;
+        }
     }
 
-    fn send_notification(&self, title: &str, message: &str) {
-        let result = Notification::new()
-            .title(title)
-            .message(message)
-            .app_icon(self.icon_path)
-            .send();
+    fn output_path() -> PathBuf {
+        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
+        home.join(".config/karabiner/karabiner.json")
+    }
 
-        if let Err(e) = result {
-            error!("Failed to send notification: {}", e);
-        }
+    // Holds the output lock for the whole read-merge-write so a concurrent `ankura compile`
+    // can't interleave with it. Blocks, so the daemon runs it with `spawn_blocking`.
+    fn write_output(output_path: &Path, config: Config, settings: &Settings) -> Result<()> {
+        let _lock = OutputLock::acquire()?;
+        let final_config = merge_configurations(output_path, config, &settings.merge)?;
+        write_karabiner_config(output_path, &final_config, settings.backups.keep)
     }
 }
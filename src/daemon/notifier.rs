@@ -0,0 +1,279 @@
+use crate::settings::{NotificationBackend, NotificationSettings};
+#[cfg(target_os = "macos")]
+use mac_notification_sys::Notification;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Success,
+    Error,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Success => "success",
+            Level::Error => "error",
+        }
+    }
+}
+
+/// Where the daemon reports compile results.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, level: Level, title: &str, message: &str);
+}
+
+/// The notifier for the `system` backend.
+#[cfg(target_os = "macos")]
+pub fn system() -> Arc<dyn Notifier> {
+    Arc::new(MacNotifier::new())
+}
+
+// Notification Center only exists on macOS; elsewhere (e.g. CI) the log has to do.
+#[cfg(not(target_os = "macos"))]
+pub fn system() -> Arc<dyn Notifier> {
+    Arc::new(LogNotifier)
+}
+
+pub fn from_settings(settings: &NotificationSettings) -> Arc<dyn Notifier> {
+    match settings.backend {
+        NotificationBackend::System => system(),
+        NotificationBackend::Log => Arc::new(LogNotifier),
+        NotificationBackend::Command => match settings.command.split_first() {
+            Some((program, args)) => Arc::new(CommandNotifier {
+                program: program.clone(),
+                args: args.to_vec(),
+            }),
+            // Rejected by `Settings::from_config`; kept total so a bad value can't panic.
+            None => Arc::new(LogNotifier),
+        },
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub struct MacNotifier {
+    icon_path: Option<PathBuf>,
+}
+
+#[cfg(target_os = "macos")]
+impl MacNotifier {
+    pub fn new() -> Self {
+        Self {
+            icon_path: icon_path(),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl Default for MacNotifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl Notifier for MacNotifier {
+    fn notify(&self, _level: Level, title: &str, message: &str) {
+        let icon_path = self
+            .icon_path
+            .as_ref()
+            .map(|path| path.to_string_lossy().to_string());
+        let mut notification = Notification::new();
+        notification.title(title).message(message);
+        if let Some(icon_path) = &icon_path {
+            notification.app_icon(icon_path);
+        }
+
+        if let Err(e) = notification.send() {
+            error!("Failed to send notification: {}", e);
+        }
+    }
+}
+
+// Installed under the Homebrew prefix; a checkout has it at the repository root.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn icon_path() -> Option<PathBuf> {
+    let installed = crate::cli::homebrew_var_dir().ok().and_then(|var| {
+        var.parent()
+            .map(|prefix| prefix.join("share/ankura/AppIcon.icns"))
+    });
+    installed
+        .into_iter()
+        .chain([PathBuf::from("AppIcon.icns")])
+        .find(|path| path.is_file())
+}
+
+/// Writes notifications to the daemon log only.
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn notify(&self, level: Level, title: &str, message: &str) {
+        match level {
+            Level::Success => info!("{title}: {message}"),
+            Level::Error => warn!("{title}: {message}"),
+        }
+    }
+}
+
+/// Runs `program args... <title> <message>` with `ANKURA_NOTIFICATION_LEVEL` set to
+/// `success` or `error`, without waiting for it.
+pub struct CommandNotifier {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl Notifier for CommandNotifier {
+    fn notify(&self, level: Level, title: &str, message: &str) {
+        let spawned = Command::new(&self.program)
+            .args(&self.args)
+            .args([title, message])
+            .env("ANKURA_NOTIFICATION_LEVEL", level.as_str())
+            .stdin(Stdio::null())
+            .spawn();
+
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                error!("Failed to run notification command {}: {e}", self.program);
+                return;
+            }
+        };
+
+        let program = self.program.clone();
+        std::thread::spawn(move || match child.wait() {
+            Ok(status) if !status.success() => {
+                warn!("Notification command {program} exited with {status}")
+            }
+            Err(e) => warn!("Failed to wait for notification command {program}: {e}"),
+            Ok(_) => {}
+        });
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sent {
+    pub level: Level,
+    pub title: String,
+    pub message: String,
+}
+
+/// Keeps notifications in memory instead of showing them, for tests.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    sent: Mutex<Vec<Sent>>,
+}
+
+impl Recorder {
+    pub fn sent(&self) -> Vec<Sent> {
+        self.sent.lock().unwrap_or_else(|p| p.into_inner()).clone()
+    }
+}
+
+impl Notifier for Recorder {
+    fn notify(&self, level: Level, title: &str, message: &str) {
+        self.sent
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .push(Sent {
+                level,
+                title: title.to_string(),
+                message: message.to_string(),
+            });
+    }
+}
+
+/// Sends compile results through the configured notifier, dropping an error identical to
+/// the previous one within the dedupe window so a broken save isn't reported on every retry.
+pub struct NotificationManager {
+    notifier: Mutex<Arc<dyn Notifier>>,
+    settings: Mutex<NotificationSettings>,
+    // The notifier was chosen in code and config must not replace it.
+    pinned: bool,
+    last_error: Mutex<Option<(String, Instant)>>,
+}
+
+impl NotificationManager {
+    /// Starts with the defaults until a compiled config says otherwise.
+    pub fn new() -> Self {
+        let settings = NotificationSettings::default();
+        Self {
+            notifier: Mutex::new(from_settings(&settings)),
+            settings: Mutex::new(settings),
+            pinned: false,
+            last_error: Mutex::new(None),
+        }
+    }
+
+    /// Always uses `notifier`, whatever backend the config selects.
+    pub fn pinned(notifier: Arc<dyn Notifier>) -> Self {
+        Self {
+            notifier: Mutex::new(notifier),
+            settings: Mutex::new(NotificationSettings::default()),
+            pinned: true,
+            last_error: Mutex::new(None),
+        }
+    }
+
+    /// Applies the `ankura { notifications { ... } }` block of a freshly compiled config.
+    pub fn configure(&self, settings: &NotificationSettings) {
+        let mut current = self.settings.lock().unwrap_or_else(|p| p.into_inner());
+        if *current == *settings {
+            return;
+        }
+
+        if !self.pinned {
+            debug!("Using {:?} notifications", settings.backend);
+            *self.notifier.lock().unwrap_or_else(|p| p.into_inner()) = from_settings(settings);
+        }
+        *current = settings.clone();
+    }
+
+    pub fn send_success(&self, message: &str) {
+        self.last_error
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .take();
+        self.send(Level::Success, "Ankura - Success", message);
+    }
+
+    pub fn send_error(&self, message: &str) {
+        let window = Duration::from_secs(self.dedupe_window());
+        let mut last_error = self.last_error.lock().unwrap_or_else(|p| p.into_inner());
+        if let Some((previous, sent_at)) = last_error.as_ref() {
+            if previous == message && sent_at.elapsed() < window {
+                debug!("Not repeating an identical error notification");
+                return;
+            }
+        }
+        *last_error = Some((message.to_string(), Instant::now()));
+        drop(last_error);
+
+        self.send(Level::Error, "Ankura - Error", message);
+    }
+
+    fn dedupe_window(&self) -> u64 {
+        self.settings
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .dedupe_window
+    }
+
+    fn send(&self, level: Level, title: &str, message: &str) {
+        let notifier = self
+            .notifier
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .clone();
+        notifier.notify(level, title, message);
+    }
+}
+
+impl Default for NotificationManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,181 @@
+use crate::cli::runtime_dir;
+use crate::error::{KarabinerPklError, Result};
+use crate::output;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompileRecord {
+    pub finished_at: DateTime<Local>,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+/// What the daemon is doing, kept in `ankura.state.json` so `ankura status` works without
+/// reaching the daemon and still has something to say after a crash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub config_path: PathBuf,
+    pub output_path: PathBuf,
+    pub started_at: DateTime<Local>,
+    /// Recomputed from `started_at` whenever the state is read.
+    #[serde(default)]
+    pub uptime_secs: u64,
+    pub paused: bool,
+    pub watched: Vec<PathBuf>,
+    pub last_compile: Option<CompileRecord>,
+    pub ankura_version: String,
+    pub pkl_version: String,
+}
+
+impl DaemonStatus {
+    pub fn refresh_uptime(&mut self) {
+        self.uptime_secs = (Local::now() - self.started_at).num_seconds().max(0) as u64;
+    }
+}
+
+impl fmt::Display for DaemonStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  Config: {}", self.config_path.display())?;
+        writeln!(f, "  Output: {}", self.output_path.display())?;
+        writeln!(
+            f,
+            "  Started: {}",
+            self.started_at.format("%Y-%m-%d %H:%M:%S")
+        )?;
+        writeln!(
+            f,
+            "  Versions: ankura {}, {}",
+            self.ankura_version, self.pkl_version
+        )?;
+
+        writeln!(f, "  Watching:")?;
+        for path in &self.watched {
+            writeln!(f, "    {}", path.display())?;
+        }
+
+        let Some(record) = &self.last_compile else {
+            return writeln!(f, "  Last compile: none yet");
+        };
+        let finished = record.finished_at.format("%Y-%m-%d %H:%M:%S");
+        match &record.error {
+            None => writeln!(
+                f,
+                "  Last compile: ✅ {finished} ({}ms)",
+                record.duration_ms
+            ),
+            Some(error) => {
+                writeln!(
+                    f,
+                    "  Last compile: ❌ {finished} ({}ms)",
+                    record.duration_ms
+                )?;
+                for line in error.lines() {
+                    writeln!(f, "    {line}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
+
+pub fn state_path() -> Result<PathBuf> {
+    Ok(runtime_dir()?.join("ankura.state.json"))
+}
+
+pub fn save(status: &DaemonStatus) -> Result<()> {
+    let path = state_path()?;
+    let contents =
+        serde_json::to_string_pretty(status).map_err(|e| KarabinerPklError::DaemonError {
+            message: format!("Failed to encode daemon state: {e}"),
+        })?;
+    output::write_atomic(&path, &contents).map_err(|e| KarabinerPklError::DaemonError {
+        message: format!("Failed to write daemon state {}: {e}", path.display()),
+    })
+}
+
+/// The last state a daemon saved, or `None` if no daemon has left one behind.
+pub fn load() -> Result<Option<DaemonStatus>> {
+    let path = state_path()?;
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(KarabinerPklError::DaemonError {
+                message: format!("Failed to read daemon state {}: {e}", path.display()),
+            })
+        }
+    };
+
+    let mut status: DaemonStatus =
+        serde_json::from_str(&contents).map_err(|e| KarabinerPklError::DaemonError {
+            message: format!("Invalid daemon state {}: {e}", path.display()),
+        })?;
+    status.refresh_uptime();
+    Ok(Some(status))
+}
+
+pub fn remove() -> Result<()> {
+    let path = state_path()?;
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(KarabinerPklError::DaemonError {
+            message: format!("Failed to remove daemon state {}: {e}", path.display()),
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Liveness {
+    Running,
+    Stopped,
+    /// A state file is left behind but its daemon is gone, usually after a crash.
+    Stale,
+}
+
+/// `ankura status` output: the saved state, checked against the live process.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusReport {
+    pub daemon: Liveness,
+    #[serde(flatten)]
+    pub status: Option<DaemonStatus>,
+}
+
+impl fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "ankura status:")?;
+        let Some(status) = &self.status else {
+            return writeln!(f, "  Daemon: stopped");
+        };
+
+        match self.daemon {
+            Liveness::Running => writeln!(
+                f,
+                "  Daemon: {} (pid {}, up {})",
+                if status.paused { "paused" } else { "running" },
+                status.pid,
+                format_duration(status.uptime_secs)
+            )?,
+            Liveness::Stopped => writeln!(f, "  Daemon: stopped")?,
+            Liveness::Stale => writeln!(
+                f,
+                "  Daemon: ❌ not running; pid {} exited without cleaning up (state below is stale)",
+                status.pid
+            )?,
+        }
+        write!(f, "{status}")
+    }
+}
@@ -0,0 +1,101 @@
+use crate::compiler::imports;
+use notify::{RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// The files a config depends on, and what the daemon watches to see them change.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WatchSet {
+    /// The config and every local module in its import graph.
+    pub files: BTreeSet<PathBuf>,
+    /// The user library, watched recursively so modules added there are noticed too.
+    pub lib_dir: Option<PathBuf>,
+}
+
+impl WatchSet {
+    pub fn resolve(config_path: &Path, module_paths: &[PathBuf], lib_dir: &Path) -> Self {
+        let graph = imports::resolve(config_path, module_paths);
+        Self {
+            files: graph.modules,
+            lib_dir: lib_dir
+                .is_dir()
+                .then(|| lib_dir.canonicalize().unwrap_or(lib_dir.to_path_buf())),
+        }
+    }
+
+    /// Whether a change to `path` can affect the compiled config.
+    pub fn contains(&self, path: &Path) -> bool {
+        let path = normalize(path);
+        self.files.contains(&path)
+            || self.lib_dir.as_ref().is_some_and(|lib_dir| {
+                path.starts_with(lib_dir) && path.extension().is_some_and(|ext| ext == "pkl")
+            })
+    }
+
+    /// Everything watched, for `ankura status`.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.files.iter().chain(&self.lib_dir).cloned().collect()
+    }
+
+    // Editors often save by renaming a temp file over the original, which ends a watch on
+    // the file itself, so the parent directories are watched and events filtered instead.
+    fn dirs(&self) -> BTreeSet<PathBuf> {
+        self.files
+            .iter()
+            .filter(|file| {
+                !self
+                    .lib_dir
+                    .as_ref()
+                    .is_some_and(|lib_dir| file.starts_with(lib_dir))
+            })
+            .filter_map(|file| file.parent().map(Path::to_path_buf))
+            .filter(|dir| dir.is_dir())
+            .collect()
+    }
+
+    /// Moves `watcher` from the directories `previous` needed to the ones `self` needs.
+    pub fn apply(&self, previous: &WatchSet, watcher: &mut dyn Watcher) {
+        let (old_dirs, new_dirs) = (previous.dirs(), self.dirs());
+
+        for dir in old_dirs.difference(&new_dirs) {
+            debug!("Unwatching {}", dir.display());
+            let _ = watcher.unwatch(dir);
+        }
+        if previous.lib_dir != self.lib_dir {
+            if let Some(lib_dir) = &previous.lib_dir {
+                let _ = watcher.unwatch(lib_dir);
+            }
+        }
+
+        for dir in new_dirs.difference(&old_dirs) {
+            debug!("Watching {}", dir.display());
+            if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+                warn!("Failed to watch {}: {e}", dir.display());
+            }
+        }
+        if previous.lib_dir != self.lib_dir {
+            if let Some(lib_dir) = &self.lib_dir {
+                debug!("Watching {} recursively", lib_dir.display());
+                if let Err(e) = watcher.watch(lib_dir, RecursiveMode::Recursive) {
+                    warn!("Failed to watch {}: {e}", lib_dir.display());
+                }
+            }
+        }
+    }
+}
+
+// Matches the canonical paths in the import graph; a deleted file can't be canonicalized,
+// but its directory still can.
+fn normalize(path: &Path) -> PathBuf {
+    if let Ok(path) = path.canonicalize() {
+        return path;
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => parent
+            .canonicalize()
+            .map(|parent| parent.join(name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
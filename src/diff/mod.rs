@@ -0,0 +1,562 @@
+use crate::karabiner::{Config, FromEvent, Manipulator, Profile, Rule, ToEvent};
+use crate::merge::MANAGED_RULE_KEY;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    Changed,
+    /// Only the position differs; Karabiner applies the first match, so order matters.
+    Moved,
+}
+
+impl Change {
+    fn marker(self) -> char {
+        match self {
+            Change::Added => '+',
+            Change::Removed => '-',
+            Change::Changed => '~',
+            Change::Moved => '↕',
+        }
+    }
+}
+
+/// 1-based positions in the rule list, or in the rule, before and after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Moved {
+    pub from: usize,
+    pub to: usize,
+}
+
+impl fmt::Display for Moved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "moved from {} to {}", self.from, self.to)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: Value,
+    pub after: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ManipulatorChange {
+    pub change: Change,
+    pub binding: String,
+    /// Top-level manipulator keys that differ, for changed manipulators.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moved: Option<Moved>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleChange {
+    pub change: Change,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moved: Option<Moved>,
+    pub manipulators: Vec<ManipulatorChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProfileDiff {
+    pub name: String,
+    pub change: Change,
+    pub fields: Vec<FieldChange>,
+    pub rules: Vec<RuleChange>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ConfigDiff {
+    pub fields: Vec<FieldChange>,
+    pub profiles: Vec<ProfileDiff>,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.profiles.is_empty()
+    }
+}
+
+/// Semantic differences between the live karabiner.json (`current`) and what ankura would write.
+pub fn diff_configs(current: &Config, proposed: &Config) -> ConfigDiff {
+    let mut diff = ConfigDiff::default();
+
+    push_field(&mut diff.fields, "title", &current.title, &proposed.title);
+    push_field(
+        &mut diff.fields,
+        "global",
+        &current.global,
+        &proposed.global,
+    );
+
+    for profile in &proposed.profiles {
+        match current.profile(&profile.name) {
+            Some(existing) => {
+                let profile_diff = diff_profile(existing, profile);
+                if !profile_diff.fields.is_empty() || !profile_diff.rules.is_empty() {
+                    diff.profiles.push(profile_diff);
+                }
+            }
+            None => diff.profiles.push(ProfileDiff {
+                name: profile.name.clone(),
+                change: Change::Added,
+                fields: Vec::new(),
+                rules: profile
+                    .rules()
+                    .iter()
+                    .map(|rule| whole_rule(rule, Change::Added))
+                    .collect(),
+            }),
+        }
+    }
+
+    for profile in &current.profiles {
+        if proposed.profile(&profile.name).is_none() {
+            diff.profiles.push(ProfileDiff {
+                name: profile.name.clone(),
+                change: Change::Removed,
+                fields: Vec::new(),
+                rules: Vec::new(),
+            });
+        }
+    }
+
+    diff
+}
+
+fn diff_profile(current: &Profile, proposed: &Profile) -> ProfileDiff {
+    let mut fields = Vec::new();
+    push_field(
+        &mut fields,
+        "selected",
+        &current.selected,
+        &proposed.selected,
+    );
+    push_field(
+        &mut fields,
+        "virtual_hid_keyboard",
+        &current.virtual_hid_keyboard,
+        &proposed.virtual_hid_keyboard,
+    );
+    push_field(&mut fields, "devices", &current.devices, &proposed.devices);
+    push_field(
+        &mut fields,
+        "fn_function_keys",
+        &current.fn_function_keys,
+        &proposed.fn_function_keys,
+    );
+    push_field(
+        &mut fields,
+        "simple_modifications",
+        &current.simple_modifications,
+        &proposed.simple_modifications,
+    );
+    push_field(
+        &mut fields,
+        "parameters",
+        &current.parameters,
+        &proposed.parameters,
+    );
+    push_field(
+        &mut fields,
+        "complex_modifications.parameters",
+        &current
+            .complex_modifications
+            .as_ref()
+            .and_then(|m| m.parameters.as_ref()),
+        &proposed
+            .complex_modifications
+            .as_ref()
+            .and_then(|m| m.parameters.as_ref()),
+    );
+
+    ProfileDiff {
+        name: proposed.name.clone(),
+        change: Change::Changed,
+        fields,
+        rules: diff_rules(current.rules(), proposed.rules()),
+    }
+}
+
+// Rules are matched by description; repeated descriptions pair up in order of appearance.
+fn diff_rules(current: &[Rule], proposed: &[Rule]) -> Vec<RuleChange> {
+    let mut unmatched: Vec<Option<&Rule>> = current.iter().map(Some).collect();
+    let matches: Vec<Option<usize>> = proposed
+        .iter()
+        .map(|rule| {
+            let index = unmatched
+                .iter()
+                .position(|c| c.is_some_and(|c| c.description == rule.description))?;
+            unmatched[index] = None;
+            Some(index)
+        })
+        .collect();
+    let moves = moves(&matches);
+
+    let mut changes = Vec::new();
+    for ((rule, matched), moved) in proposed.iter().zip(matches).zip(moves) {
+        let Some(existing) = matched.map(|index| &current[index]) else {
+            changes.push(whole_rule(rule, Change::Added));
+            continue;
+        };
+
+        let manipulators = diff_manipulators(&existing.manipulators, &rule.manipulators);
+        let change = if !manipulators.is_empty() || rule_extra(existing) != rule_extra(rule) {
+            Change::Changed
+        } else if moved.is_some() {
+            Change::Moved
+        } else {
+            continue;
+        };
+        changes.push(RuleChange {
+            change,
+            description: rule.description.clone(),
+            moved,
+            manipulators,
+        });
+    }
+
+    changes.extend(
+        unmatched
+            .into_iter()
+            .flatten()
+            .map(|rule| whole_rule(rule, Change::Removed)),
+    );
+    changes
+}
+
+// `matches` holds, for each proposed item, the index of its counterpart in the current list.
+// Only items outside the longest run that kept its relative order count as moved, so an
+// insertion or removal doesn't make everything after it look moved.
+fn moves(matches: &[Option<usize>]) -> Vec<Option<Moved>> {
+    let paired: Vec<(usize, usize)> = matches
+        .iter()
+        .enumerate()
+        .filter_map(|(to, from)| from.map(|from| (from, to)))
+        .collect();
+
+    // Longest increasing subsequence of `from`; lists are short enough for the quadratic form.
+    let mut length = vec![1; paired.len()];
+    let mut previous: Vec<Option<usize>> = vec![None; paired.len()];
+    for i in 0..paired.len() {
+        for j in 0..i {
+            if paired[j].0 < paired[i].0 && length[j] + 1 > length[i] {
+                length[i] = length[j] + 1;
+                previous[i] = Some(j);
+            }
+        }
+    }
+    let mut kept = vec![false; paired.len()];
+    let mut cursor = (0..paired.len()).max_by_key(|&i| (length[i], std::cmp::Reverse(i)));
+    while let Some(i) = cursor {
+        kept[i] = true;
+        cursor = previous[i];
+    }
+
+    let mut moves = vec![None; matches.len()];
+    for ((from, to), kept) in paired.into_iter().zip(kept) {
+        if !kept {
+            moves[to] = Some(Moved {
+                from: from + 1,
+                to: to + 1,
+            });
+        }
+    }
+    moves
+}
+
+// The managed-rule marker is bookkeeping, not a change Karabiner would notice.
+fn rule_extra(rule: &Rule) -> Vec<(&String, &Value)> {
+    rule.extra
+        .iter()
+        .filter(|(key, _)| key.as_str() != MANAGED_RULE_KEY)
+        .collect()
+}
+
+fn whole_rule(rule: &Rule, change: Change) -> RuleChange {
+    RuleChange {
+        change,
+        description: rule.description.clone(),
+        moved: None,
+        manipulators: rule
+            .manipulators
+            .iter()
+            .map(|manipulator| single_manipulator(manipulator, change))
+            .collect(),
+    }
+}
+
+fn single_manipulator(manipulator: &Manipulator, change: Change) -> ManipulatorChange {
+    let value = to_value(manipulator);
+    ManipulatorChange {
+        change,
+        binding: describe(manipulator),
+        fields: Vec::new(),
+        moved: None,
+        before: (change == Change::Removed).then(|| value.clone()),
+        after: (change == Change::Added).then_some(value),
+    }
+}
+
+fn diff_manipulators(current: &[Manipulator], proposed: &[Manipulator]) -> Vec<ManipulatorChange> {
+    let mut unmatched: Vec<Option<&Manipulator>> = current.iter().map(Some).collect();
+    let mut matches: Vec<Option<usize>> = proposed
+        .iter()
+        .map(|manipulator| {
+            let index = unmatched.iter().position(|c| *c == Some(manipulator))?;
+            unmatched[index] = None;
+            Some(index)
+        })
+        .collect();
+
+    // A manipulator with the same trigger that is gone from the old rule counts as changed.
+    let mut changed = vec![false; proposed.len()];
+    for (i, manipulator) in proposed.iter().enumerate() {
+        if matches[i].is_some() {
+            continue;
+        }
+        if let Some(index) = unmatched
+            .iter()
+            .position(|c| c.is_some_and(|c| c.from == manipulator.from))
+        {
+            unmatched[index] = None;
+            matches[i] = Some(index);
+            changed[i] = true;
+        }
+    }
+    let moves = moves(&matches);
+
+    let mut changes = Vec::new();
+    for (i, manipulator) in proposed.iter().enumerate() {
+        match matches[i] {
+            Some(index) if changed[i] => {
+                let (before, after) = (to_value(&current[index]), to_value(manipulator));
+                changes.push(ManipulatorChange {
+                    change: Change::Changed,
+                    binding: describe(manipulator),
+                    fields: changed_keys(&before, &after),
+                    moved: moves[i],
+                    before: Some(before),
+                    after: Some(after),
+                });
+            }
+            Some(_) => {
+                if let Some(moved) = moves[i] {
+                    changes.push(ManipulatorChange {
+                        change: Change::Moved,
+                        binding: describe(manipulator),
+                        fields: Vec::new(),
+                        moved: Some(moved),
+                        before: None,
+                        after: None,
+                    });
+                }
+            }
+            None => changes.push(single_manipulator(manipulator, Change::Added)),
+        }
+    }
+
+    changes.extend(
+        unmatched
+            .into_iter()
+            .flatten()
+            .map(|manipulator| single_manipulator(manipulator, Change::Removed)),
+    );
+    changes
+}
+
+fn changed_keys(before: &Value, after: &Value) -> Vec<String> {
+    let (Some(before), Some(after)) = (before.as_object(), after.as_object()) else {
+        return Vec::new();
+    };
+    let mut keys: Vec<String> = before
+        .keys()
+        .chain(after.keys())
+        .filter(|key| before.get(*key) != after.get(*key))
+        .cloned()
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+fn push_field<T: Serialize + PartialEq>(
+    fields: &mut Vec<FieldChange>,
+    name: &str,
+    before: &T,
+    after: &T,
+) {
+    if before != after {
+        fields.push(FieldChange {
+            field: name.to_string(),
+            before: to_value(before),
+            after: to_value(after),
+        });
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+/// A compact `mods+key → output` summary of a manipulator.
+pub fn describe(manipulator: &Manipulator) -> String {
+    let trigger = describe_from(&manipulator.from);
+    let outputs = manipulator
+        .to
+        .as_deref()
+        .map(describe_to)
+        .filter(|to| !to.is_empty());
+    let alone = manipulator
+        .to_if_alone
+        .as_deref()
+        .map(describe_to)
+        .filter(|to| !to.is_empty());
+
+    match (outputs, alone) {
+        (Some(to), Some(alone)) => format!("{trigger} → {to} (alone: {alone})"),
+        (Some(to), None) => format!("{trigger} → {to}"),
+        (None, Some(alone)) => format!("{trigger} → (alone: {alone})"),
+        (None, None) => trigger,
+    }
+}
+
+fn describe_from(from: &FromEvent) -> String {
+    let key = if let Some(events) = &from.simultaneous {
+        let keys: Vec<String> = events.iter().map(describe_from).collect();
+        format!("{{{}}}", keys.join(" "))
+    } else {
+        from.key_code
+            .clone()
+            .or_else(|| from.consumer_key_code.clone())
+            .or_else(|| from.pointing_button.clone())
+            .or_else(|| from.any.as_ref().map(|any| format!("any {any}")))
+            .unwrap_or_else(|| "?".to_string())
+    };
+
+    let mut parts: Vec<String> = from
+        .modifiers
+        .iter()
+        .flat_map(|modifiers| modifiers.mandatory.iter().cloned())
+        .collect();
+    parts.push(key);
+    parts.join("+")
+}
+
+fn describe_to(events: &[ToEvent]) -> String {
+    events
+        .iter()
+        .filter_map(|event| {
+            if let Some(command) = &event.shell_command {
+                return Some(format!("`{command}`"));
+            }
+            if let Some(variable) = &event.set_variable {
+                let value = variable
+                    .value
+                    .as_ref()
+                    .map(ToString::to_string)
+                    .unwrap_or_default();
+                return Some(format!("{}={value}", variable.name));
+            }
+            let key = event
+                .key_code
+                .clone()
+                .or_else(|| event.consumer_key_code.clone())
+                .or_else(|| event.pointing_button.clone())?;
+            let mut parts = event.modifiers.clone().unwrap_or_default();
+            parts.push(key);
+            Some(parts.join("+"))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+
+        for field in &self.fields {
+            write_field(f, field, "")?;
+        }
+
+        for profile in &self.profiles {
+            match profile.change {
+                Change::Changed => writeln!(f, "Profile \"{}\":", profile.name)?,
+                change => writeln!(f, "{} profile \"{}\"", change.marker(), profile.name)?,
+            }
+
+            for field in &profile.fields {
+                write_field(f, field, "  ")?;
+            }
+
+            for rule in &profile.rules {
+                write!(
+                    f,
+                    "  {} rule \"{}\"",
+                    rule.change.marker(),
+                    rule.description
+                )?;
+                match rule.moved {
+                    Some(moved) => writeln!(f, " ({moved})")?,
+                    None => writeln!(f)?,
+                }
+                for manipulator in &rule.manipulators {
+                    write!(
+                        f,
+                        "      {} {}",
+                        manipulator.change.marker(),
+                        manipulator.binding
+                    )?;
+                    let mut notes = Vec::new();
+                    if !manipulator.fields.is_empty() {
+                        notes.push(format!("{} changed", manipulator.fields.join(", ")));
+                    }
+                    if let Some(moved) = manipulator.moved {
+                        notes.push(moved.to_string());
+                    }
+                    if notes.is_empty() {
+                        writeln!(f)?;
+                    } else {
+                        writeln!(f, " ({})", notes.join("; "))?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn write_field(f: &mut fmt::Formatter<'_>, field: &FieldChange, indent: &str) -> fmt::Result {
+    writeln!(
+        f,
+        "{indent}~ {}: {} → {}",
+        field.field,
+        compact(&field.before),
+        compact(&field.after)
+    )
+}
+
+fn compact(value: &Value) -> String {
+    let text = value.to_string();
+    if text.chars().count() > 80 {
+        let truncated: String = text.chars().take(77).collect();
+        format!("{truncated}...")
+    } else {
+        text
+    }
+}
@@ -1,3 +1,5 @@
+use crate::analysis::keycodes::InvalidKeyCode;
+use crate::compiler::pkl_error::PklDiagnostic;
 use miette::Diagnostic;
 use std::path::PathBuf;
 use thiserror::Error;
@@ -20,8 +22,15 @@ pub enum KarabinerPklError {
     },
 
     #[error("Pkl compilation failed")]
-    #[diagnostic(code(ankura::pkl_compile_error))]
-    PklCompileError { message: String, line: usize },
+    #[diagnostic(transparent)]
+    PklCompileError(#[source] Box<PklDiagnostic>),
+
+    #[error("Pkl did not finish within {seconds}s")]
+    #[diagnostic(
+        code(ankura::compile_timeout),
+        help("Raise the limit with `ankura {{ daemon {{ compile_timeout = 120 }} }}`")
+    )]
+    CompileTimeout { seconds: u64 },
 
     #[error("Invalid JSON output from Pkl")]
     #[diagnostic(
@@ -33,6 +42,25 @@ pub enum KarabinerPklError {
         source: serde_json::Error,
     },
 
+    #[error("Invalid Karabiner configuration at `{path}`")]
+    #[diagnostic(code(ankura::schema_error))]
+    SchemaError {
+        path: String,
+        #[help]
+        message: String,
+    },
+
+    #[error("Found {count} unknown key code(s)")]
+    #[diagnostic(
+        code(ankura::invalid_key_codes),
+        help("See docs/keys-reference.md for the identifiers Karabiner accepts")
+    )]
+    InvalidKeyCodes {
+        count: usize,
+        #[related]
+        problems: Vec<InvalidKeyCode>,
+    },
+
     #[error("Failed to write Karabiner configuration")]
     #[diagnostic(code(ankura::write_error))]
     KarabinerWriteError {
@@ -48,6 +76,13 @@ pub enum KarabinerPklError {
         message: String,
     },
 
+    #[error("Found {count} conflicting or unreachable manipulator(s)")]
+    #[diagnostic(
+        code(ankura::manipulator_conflicts),
+        help("Reorder or remove the rules listed above, or run without --strict to treat them as warnings")
+    )]
+    ManipulatorConflicts { count: usize },
+
     #[error("File watching error")]
     #[diagnostic(code(ankura::watch_error))]
     WatchError {
@@ -59,6 +94,36 @@ pub enum KarabinerPklError {
     #[diagnostic(code(ankura::daemon_error))]
     DaemonError { message: String },
 
+    #[error("Invalid key timeline: {message}")]
+    #[diagnostic(code(ankura::invalid_timeline))]
+    InvalidTimeline {
+        message: String,
+        #[help]
+        help: Option<String>,
+    },
+
+    #[error("Invalid test suite {}", .path.display())]
+    #[diagnostic(code(ankura::invalid_test_suite))]
+    InvalidTestSuite {
+        path: PathBuf,
+        #[help]
+        message: String,
+    },
+
+    #[error("Invalid EDN in {}:{line}:{column}", .path.display())]
+    #[diagnostic(code(ankura::invalid_edn))]
+    InvalidEdn {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        #[help]
+        message: String,
+    },
+
+    #[error("Backup error: {message}")]
+    #[diagnostic(code(ankura::backup_error))]
+    BackupError { message: String },
+
     #[error("Failed to write configuration file")]
     #[diagnostic(code(ankura::config_write_error))]
     ConfigWriteError {
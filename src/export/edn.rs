@@ -0,0 +1,1013 @@
+// `ankura export --format edn`: writes a profile as a Goku karabiner.edn.
+//
+// The reverse of `import::goku`: a manipulator Goku can express is written in its shorthand, a
+// simlayer's chords are folded back into `:simlayers`, and anything else is written as the raw
+// manipulator map, which Goku passes through unchanged.
+
+use super::{skip_profile_settings, Summary};
+use crate::import::edn::Edn;
+use crate::karabiner::{
+    Condition, DeviceIdentifiers, FromEvent, FromModifiers, InputSource, Manipulator,
+    ManipulatorParameters, ManipulatorType, MouseKey, Profile, SetVariable, SimultaneousOptions,
+    ToEvent, VariableValue,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::path::Path;
+
+// Goku's simlayer threshold when the config doesn't set `:simlayer-threshold`.
+const SIMLAYER_THRESHOLD: i64 = 250;
+
+const HYPER: [&str; 4] = ["left_command", "left_control", "left_option", "left_shift"];
+
+fn letter(modifier: &str) -> Option<char> {
+    Some(match modifier {
+        "left_command" => 'C',
+        "left_control" => 'T',
+        "left_option" => 'O',
+        "left_shift" => 'S',
+        "fn" => 'F',
+        "right_command" => 'Q',
+        "right_control" => 'W',
+        "right_option" => 'E',
+        "right_shift" => 'R',
+        "caps_lock" => 'P',
+        _ => return None,
+    })
+}
+
+/// Renders `profile`, compiled from `input`, as a karabiner.edn.
+pub fn render(profile: &Profile, input: &Path, summary: &mut Summary) -> String {
+    if !profile.simple_modifications.is_empty() {
+        summary.skipped.push(format!(
+            "{} simple modification(s)",
+            profile.simple_modifications.len()
+        ));
+    }
+    skip_profile_settings(profile, summary);
+
+    let manipulators: Vec<&Manipulator> = profile
+        .rules()
+        .iter()
+        .flat_map(|rule| &rule.manipulators)
+        .collect();
+    let mut export = Export::new(&manipulators);
+    let folded = export.fold_layers(&manipulators);
+
+    let mut groups = Vec::new();
+    let mut index = 0;
+    for rule in profile.rules() {
+        let mut items = Vec::new();
+        let mut current: Vec<Edn> = Vec::new();
+        for manipulator in &rule.manipulators {
+            index += 1;
+            summary.manipulators += 1;
+            if folded.contains(&(index - 1)) {
+                continue;
+            }
+            match export.manipulator(manipulator) {
+                Some((conditions, edn)) => {
+                    // A keyword or `[:condi ...]` sets the conditions for the rules after it.
+                    if conditions != current {
+                        items.push(match conditions.as_slice() {
+                            [keyword @ Edn::Keyword(_)] => keyword.clone(),
+                            _ => Edn::Vector(
+                                [Edn::Keyword("condi".to_string())]
+                                    .into_iter()
+                                    .chain(conditions.iter().cloned())
+                                    .collect(),
+                            ),
+                        });
+                        current = conditions;
+                    }
+                    items.push(edn);
+                }
+                None => {
+                    summary.verbatim += 1;
+                    items.push(
+                        serde_json::to_value(manipulator).map_or(Edn::Nil, |json| (&json).into()),
+                    );
+                }
+            }
+        }
+        if !items.is_empty() {
+            groups.push((rule.description.clone(), items));
+        }
+    }
+
+    let mut sections = vec![profiles(profile)];
+    if let Some(threshold) = export.threshold.filter(|t| *t != SIMLAYER_THRESHOLD) {
+        sections.push(format!(" :simlayer-threshold {threshold}"));
+    }
+    let definitions = [
+        (
+            "templates",
+            export
+                .templates
+                .iter()
+                .map(|(name, template)| (name.clone(), Edn::String(template.clone())))
+                .collect::<Vec<_>>(),
+        ),
+        (
+            "applications",
+            export
+                .applications
+                .iter()
+                .map(|(name, bundles)| {
+                    let bundles = bundles.iter().cloned().map(Edn::String).collect();
+                    (name.clone(), Edn::Vector(bundles))
+                })
+                .collect(),
+        ),
+        (
+            "devices",
+            export
+                .devices
+                .iter()
+                .map(|(name, identifiers)| {
+                    let json = serde_json::to_value(identifiers).unwrap_or_default();
+                    (name.clone(), (&json).into())
+                })
+                .collect(),
+        ),
+        (
+            "input-sources",
+            export
+                .input_sources
+                .iter()
+                .map(|(name, source)| {
+                    let json = serde_json::to_value(source).unwrap_or_default();
+                    (name.clone(), (&json).into())
+                })
+                .collect(),
+        ),
+        (
+            "simlayers",
+            export
+                .simlayers
+                .iter()
+                .map(|(name, trigger)| (name.clone(), map([("key", keyword(trigger))])))
+                .collect(),
+        ),
+        (
+            "layers",
+            export
+                .layers
+                .iter()
+                .map(|(name, layer)| (name.clone(), layer.clone()))
+                .collect(),
+        ),
+    ];
+    for (section, entries) in definitions {
+        if !entries.is_empty() {
+            summary
+                .definitions
+                .insert(format!(":{section}"), entries.len());
+            sections.push(block(section, &entries));
+        }
+    }
+    sections.push(main(&groups));
+
+    let mut out = format!(
+        ";; Generated by `ankura export --format edn` from {}.\n",
+        input.display()
+    );
+    let body = sections.join("\n");
+    let _ = writeln!(out, "{{{}}}", &body[1..]);
+    out
+}
+
+fn profiles(profile: &Profile) -> String {
+    let mut settings = vec![("default", Edn::Bool(true))];
+    if let Some(parameters) = profile
+        .complex_modifications
+        .as_ref()
+        .and_then(|complex| complex.parameters.as_ref())
+    {
+        for (key, value) in [
+            ("sim", parameters.simultaneous_threshold_milliseconds),
+            ("delay", parameters.to_delayed_action_delay_milliseconds),
+            ("alone", parameters.to_if_alone_timeout_milliseconds),
+            ("held", parameters.to_if_held_down_threshold_milliseconds),
+        ] {
+            if let Some(value) = value {
+                settings.push((key, Edn::Int(value)));
+            }
+        }
+    }
+    let name = if is_keyword(&profile.name) {
+        profile.name.clone()
+    } else {
+        "Default".to_string()
+    };
+    block("profiles", &[(name, map(settings))])
+}
+
+// ` :name {:a 1
+//          :b 2}`, one entry per line.
+fn block(name: &str, entries: &[(String, Edn)]) -> String {
+    let head = format!(" :{name} {{");
+    let pad = " ".repeat(head.len());
+    let mut out = head;
+    for (i, (key, value)) in entries.iter().enumerate() {
+        if i > 0 {
+            let _ = write!(out, "\n{pad}");
+        }
+        let _ = write!(out, ":{key} {value}");
+    }
+    out.push('}');
+    out
+}
+
+fn main(groups: &[(String, Vec<Edn>)]) -> String {
+    let mut out = " :main [".to_string();
+    for (i, (description, items)) in groups.iter().enumerate() {
+        if i > 0 {
+            out.push_str("\n        ");
+        }
+        let _ = write!(
+            out,
+            "{{:des {}\n         :rules [",
+            Edn::String(description.clone())
+        );
+        for (j, item) in items.iter().enumerate() {
+            if j > 0 {
+                out.push_str("\n                 ");
+            }
+            let _ = write!(out, "{item}");
+        }
+        out.push_str("]}");
+    }
+    out.push(']');
+    out
+}
+
+// The named definitions collected while writing `:main`.
+struct Export {
+    // Definition names may not shadow a variable, since a bare keyword condition is read as one.
+    names: HashSet<String>,
+    template_names: HashSet<String>,
+    templates: Vec<(String, String)>,
+    // (text before the argument, text after it) -> template name.
+    template_shapes: HashMap<(String, String), String>,
+    applications: Vec<(String, Vec<String>)>,
+    devices: Vec<(String, Vec<DeviceIdentifiers>)>,
+    input_sources: Vec<(String, InputSource)>,
+    simlayers: Vec<(String, String)>,
+    layers: Vec<(String, Edn)>,
+    threshold: Option<i64>,
+}
+
+impl Export {
+    fn new(manipulators: &[&Manipulator]) -> Self {
+        let names = manipulators
+            .iter()
+            .flat_map(|m| m.conditions.iter().flatten())
+            .filter_map(|condition| condition.name.clone())
+            .collect();
+
+        // A shell command becomes a template when another has the same shape.
+        let mut shapes: HashMap<(String, String), usize> = HashMap::new();
+        for manipulator in manipulators {
+            for event in events(manipulator) {
+                if let Some((before, _, after)) = event.shell_command.as_deref().and_then(split) {
+                    *shapes.entry((before, after)).or_default() += 1;
+                }
+            }
+        }
+        let mut export = Export {
+            names,
+            template_names: HashSet::new(),
+            templates: Vec::new(),
+            template_shapes: HashMap::new(),
+            applications: Vec::new(),
+            devices: Vec::new(),
+            input_sources: Vec::new(),
+            simlayers: Vec::new(),
+            layers: Vec::new(),
+            threshold: None,
+        };
+        let mut shapes: Vec<_> = shapes.into_iter().filter(|(_, n)| *n > 1).collect();
+        shapes.sort();
+        for ((before, after), _) in shapes {
+            let command = before.split_whitespace().next().unwrap_or_default();
+            let base = command.rsplit('/').next().unwrap_or_default();
+            let name = claim(&mut export.template_names, base, "shell");
+            export
+                .templates
+                .push((name.clone(), format!("{before}%s{after}")));
+            export.template_shapes.insert((before, after), name);
+        }
+        export
+    }
+
+    /// Finds the manipulators `:simlayers` and `:layers` regenerate, and returns their indexes.
+    fn fold_layers(&mut self, manipulators: &[&Manipulator]) -> HashSet<usize> {
+        let mut folded = HashSet::new();
+        for (index, manipulator) in manipulators.iter().enumerate() {
+            if let Some((name, trigger, threshold)) = chord(manipulator, manipulators) {
+                let known = self
+                    .simlayers
+                    .iter()
+                    .find(|(simlayer, _)| *simlayer == name);
+                let fits = match known {
+                    Some((_, known)) => *known == trigger && self.threshold == Some(threshold),
+                    None => self.threshold.is_none_or(|t| t == threshold),
+                };
+                if fits && is_keyword(&name) {
+                    if known.is_none() {
+                        self.simlayers.push((name, trigger));
+                        self.threshold = Some(threshold);
+                    }
+                    folded.insert(index);
+                }
+            }
+        }
+        for (index, manipulator) in manipulators.iter().enumerate() {
+            let Some((name, key, alone)) = layer_trigger(manipulator) else {
+                continue;
+            };
+            let taken = self.simlayers.iter().any(|(simlayer, _)| *simlayer == name)
+                || self.layers.iter().any(|(layer, _)| *layer == name);
+            if taken || !is_keyword(&name) {
+                continue;
+            }
+            let mut entries = vec![("key", keyword(&key))];
+            if *alone != [plain_to(&key)] {
+                match self.to(alone) {
+                    Some(alone) => entries.push(("alone", alone)),
+                    None => continue,
+                }
+            }
+            self.layers.push((name, map(entries)));
+            folded.insert(index);
+        }
+        folded
+    }
+
+    // The rule's conditions and `[from to nil options]`, if Goku can express it.
+    fn manipulator(&mut self, manipulator: &Manipulator) -> Option<(Vec<Edn>, Edn)> {
+        if manipulator.manipulator_type != ManipulatorType::Basic || !manipulator.extra.is_empty() {
+            return None;
+        }
+        let from = from(&manipulator.from)?;
+        let to = self.to(manipulator.to.as_deref().unwrap_or_default())?;
+
+        let mut options = Vec::new();
+        for (key, events) in [
+            ("alone", &manipulator.to_if_alone),
+            ("held", &manipulator.to_if_held_down),
+            ("afterup", &manipulator.to_after_key_up),
+        ] {
+            if let Some(events) = events {
+                options.push((key, self.to(events)?));
+            }
+        }
+        if let Some(delayed) = &manipulator.to_delayed_action {
+            if !delayed.extra.is_empty() {
+                return None;
+            }
+            let invoked = self.to(&delayed.to_if_invoked)?;
+            let canceled = self.to(&delayed.to_if_canceled)?;
+            options.push((
+                "delayed",
+                map([("invoked", invoked), ("canceled", canceled)]),
+            ));
+        }
+        if let Some(parameters) = &manipulator.parameters {
+            options.push(("params", params(parameters)?));
+        }
+
+        let conditions = self.conditions(manipulator.conditions.as_deref().unwrap_or_default())?;
+        let mut rule = vec![from, to];
+        if !options.is_empty() {
+            rule.push(Edn::Nil);
+            rule.push(map(options));
+        }
+        Some((conditions, Edn::Vector(rule)))
+    }
+
+    fn conditions(&mut self, conditions: &[Condition]) -> Option<Vec<Edn>> {
+        let mut names = Vec::new();
+        // Goku merges conditions of one kind into one, which would turn an "and" into an "or".
+        let mut kinds = HashSet::new();
+        for condition in conditions {
+            if !condition.extra.is_empty() {
+                return None;
+            }
+            let (kind, positive) = match condition.condition_type.rsplit_once('_')? {
+                (kind, "if") => (kind, true),
+                (kind, "unless") => (kind, false),
+                _ => return None,
+            };
+            let bang = if positive { "" } else { "!" };
+            if kind != "variable" && !kinds.insert(kind) {
+                return None;
+            }
+
+            match (kind, condition) {
+                (
+                    "frontmost_application",
+                    Condition {
+                        bundle_identifiers: Some(bundles),
+                        file_paths: None,
+                        ..
+                    },
+                ) => {
+                    let name = self.application(bundles);
+                    names.push(keyword(&format!("{bang}{name}")));
+                }
+                (
+                    "device",
+                    Condition {
+                        identifiers: Some(identifiers),
+                        ..
+                    },
+                ) => {
+                    let name = self.device(identifiers);
+                    names.push(keyword(&format!("{bang}{name}")));
+                }
+                (
+                    "input_source",
+                    Condition {
+                        input_sources: Some(sources),
+                        ..
+                    },
+                ) => {
+                    for source in sources {
+                        let name = self.input_source(source);
+                        names.push(keyword(&format!("{bang}{name}")));
+                    }
+                }
+                (
+                    "variable",
+                    Condition {
+                        name: Some(name),
+                        value: Some(value),
+                        ..
+                    },
+                ) => {
+                    if *value == VariableValue::Int(1) && is_keyword(name) {
+                        names.push(keyword(&format!("{bang}{name}")));
+                    } else if positive {
+                        names.push(Edn::Vector(vec![
+                            Edn::String(name.clone()),
+                            variable_value(value),
+                        ]));
+                    } else {
+                        return None;
+                    }
+                }
+                _ => return None,
+            }
+        }
+        Some(names)
+    }
+
+    fn application(&mut self, bundles: &[String]) -> String {
+        if let Some((name, _)) = self.applications.iter().find(|(_, b)| b == bundles) {
+            return name.clone();
+        }
+        // `^com\.google\.Chrome$` is :chrome.
+        let base = bundles.first().map_or("", |bundle| {
+            bundle
+                .trim_matches(['^', '$'])
+                .rsplit('.')
+                .find(|part| !part.is_empty())
+                .unwrap_or_default()
+        });
+        let name = claim(&mut self.names, &base.replace('\\', ""), "app");
+        self.applications.push((name.clone(), bundles.to_vec()));
+        name
+    }
+
+    fn device(&mut self, identifiers: &[DeviceIdentifiers]) -> String {
+        if let Some((name, _)) = self.devices.iter().find(|(_, i)| i == identifiers) {
+            return name.clone();
+        }
+        let base = match identifiers.first() {
+            Some(DeviceIdentifiers {
+                vendor_id: Some(vendor),
+                product_id: Some(product),
+                ..
+            }) => format!("device-{vendor}-{product}"),
+            _ => "device".to_string(),
+        };
+        let name = claim(&mut self.names, &base, "device");
+        self.devices.push((name.clone(), identifiers.to_vec()));
+        name
+    }
+
+    fn input_source(&mut self, source: &InputSource) -> String {
+        if let Some((name, _)) = self.input_sources.iter().find(|(_, s)| s == source) {
+            return name.clone();
+        }
+        let base = source
+            .language
+            .as_deref()
+            .or_else(|| source.input_source_id.as_deref()?.rsplit('.').next())
+            .unwrap_or_default();
+        let name = claim(&mut self.names, &format!("input-{base}"), "input");
+        self.input_sources.push((name.clone(), source.clone()));
+        name
+    }
+
+    fn to(&mut self, events: &[ToEvent]) -> Option<Edn> {
+        let mut items = events
+            .iter()
+            .map(|event| self.event(event))
+            .collect::<Option<Vec<_>>>()?;
+        match items.len() {
+            0 => Some(Edn::Nil),
+            1 => items.pop(),
+            _ => {
+                // Keep the list from reading as `["variable" value]` or a template call.
+                match &items[0] {
+                    Edn::String(command) if items.len() == 2 => {
+                        items[0] = map([("shell", Edn::String(command.clone()))]);
+                    }
+                    Edn::Keyword(key) if self.template_names.contains(key) => {
+                        items[0] = map([("key", items[0].clone())]);
+                    }
+                    _ => {}
+                }
+                Some(Edn::Vector(items))
+            }
+        }
+    }
+
+    fn event(&mut self, event: &ToEvent) -> Option<Edn> {
+        if event.sticky_modifier.is_some()
+            || event.software_function.is_some()
+            || !event.extra.is_empty()
+        {
+            return None;
+        }
+
+        if let Some(key) = &event.key_code {
+            let modifiers = event.modifiers.as_deref().unwrap_or_default();
+            let plain = ToEvent {
+                key_code: Some(key.clone()),
+                modifiers: event.modifiers.clone(),
+                ..Default::default()
+            };
+            if *event == plain {
+                if let Some(shorthand) = shorthand(key, modifiers, &[]) {
+                    return Some(keyword(&shorthand));
+                }
+            }
+        }
+        if let Some(command) = &event.shell_command {
+            if *event == shell(command) {
+                return Some(self.shell(command));
+            }
+        }
+        if let Some(variable) = &event.set_variable {
+            let only = ToEvent {
+                set_variable: Some(variable.clone()),
+                ..Default::default()
+            };
+            if *event == only {
+                return set_variable(variable);
+            }
+        }
+
+        let mut entries = Vec::new();
+        if let Some(key) = &event.key_code {
+            let modifiers = event.modifiers.as_deref().unwrap_or_default();
+            match shorthand(key, modifiers, &[]) {
+                Some(shorthand) => entries.push(("key", keyword(&shorthand))),
+                None => {
+                    entries.push(("key", keyword(key)));
+                    entries.push(("modi", keywords(modifiers)));
+                }
+            }
+        } else if let Some(modifiers) = &event.modifiers {
+            entries.push(("modi", keywords(modifiers)));
+        }
+        if let Some(key) = &event.consumer_key_code {
+            entries.push(("ckey", keyword(key)));
+        }
+        if let Some(button) = &event.pointing_button {
+            entries.push(("pkey", keyword(button)));
+        }
+        if let Some(command) = &event.shell_command {
+            entries.push(("shell", self.shell(command)));
+        }
+        if let Some(source) = &event.select_input_source {
+            let name = self.input_source(source);
+            entries.push(("input", keyword(&name)));
+        }
+        if let Some(variable) = &event.set_variable {
+            entries.push(("set", set_variable(variable)?));
+        }
+        if let Some(mouse) = &event.mouse_key {
+            entries.push(("mkey", mouse_key(mouse)));
+        }
+        for (key, flag) in [
+            ("lazy", event.lazy),
+            ("repeat", event.repeat),
+            ("halt", event.halt),
+        ] {
+            if let Some(flag) = flag {
+                entries.push((key, Edn::Bool(flag)));
+            }
+        }
+        if let Some(ms) = event.hold_down_milliseconds {
+            entries.push(("hold_down_ms", Edn::Int(ms)));
+        }
+        Some(map(entries))
+    }
+
+    fn shell(&self, command: &str) -> Edn {
+        match split(command) {
+            Some((before, argument, after)) => match self.template_shapes.get(&(before, after)) {
+                Some(name) => Edn::Vector(vec![keyword(name), Edn::String(argument)]),
+                None => Edn::String(command.to_string()),
+            },
+            None => Edn::String(command.to_string()),
+        }
+    }
+}
+
+// A chord `SimLayer` (or Goku) writes for a simlayer: both keys down set the layer's variable and
+// send the action. Only counted when the same action is also mapped under the variable, since
+// Goku writes the chord back from that rule.
+fn chord(manipulator: &Manipulator, all: &[&Manipulator]) -> Option<(String, String, i64)> {
+    let [trigger, key] = manipulator.from.simultaneous.as_deref()? else {
+        return None;
+    };
+    let (trigger, key) = (trigger.key_code.as_ref()?, key.key_code.as_ref()?);
+    if manipulator.from.simultaneous.as_deref()? != [plain_from(trigger), plain_from(key)] {
+        return None;
+    }
+    let SimultaneousOptions {
+        to_after_key_up: Some(after),
+        extra,
+        ..
+    } = manipulator.from.simultaneous_options.as_ref()?
+    else {
+        return None;
+    };
+    let [ToEvent {
+        set_variable: Some(off),
+        ..
+    }] = after.as_slice()
+    else {
+        return None;
+    };
+    let [on, actions @ ..] = manipulator.to.as_deref()? else {
+        return None;
+    };
+    let name = &off.name;
+    let expected = FromEvent {
+        key_code: None,
+        modifiers: manipulator.from.modifiers.clone(),
+        simultaneous: manipulator.from.simultaneous.clone(),
+        simultaneous_options: manipulator.from.simultaneous_options.clone(),
+        ..Default::default()
+    };
+    let plain = extra.is_empty()
+        && manipulator.from == expected
+        && after[0] == variable(name, 0)
+        && *on == variable(name, 1)
+        && manipulator.to_if_alone.is_none()
+        && manipulator.to_if_held_down.is_none()
+        && manipulator.to_after_key_up.is_none()
+        && manipulator.to_delayed_action.is_none()
+        && manipulator.extra.is_empty();
+    if !plain {
+        return None;
+    }
+    let threshold = match &manipulator.parameters {
+        None => SIMLAYER_THRESHOLD,
+        Some(parameters) => {
+            let threshold = parameters.simultaneous_threshold_milliseconds?;
+            let only = ManipulatorParameters {
+                simultaneous_threshold_milliseconds: Some(threshold),
+                ..Default::default()
+            };
+            if *parameters != only {
+                return None;
+            }
+            threshold
+        }
+    };
+
+    let under = FromEvent {
+        key_code: Some(key.clone()),
+        modifiers: manipulator.from.modifiers.clone(),
+        ..Default::default()
+    };
+    let layer = Condition {
+        condition_type: "variable_if".to_string(),
+        name: Some(name.clone()),
+        value: Some(VariableValue::Int(1)),
+        ..Default::default()
+    };
+    let others = manipulator.conditions.clone().unwrap_or_default();
+    let mapped = all.iter().any(|other| {
+        let conditions = other.conditions.as_deref().unwrap_or_default();
+        let rest: Vec<Condition> = conditions
+            .iter()
+            .filter(|condition| **condition != layer)
+            .cloned()
+            .collect();
+        other.from == under
+            && other.to.as_deref().unwrap_or_default() == actions
+            && conditions.contains(&layer)
+            && rest == others
+    });
+    mapped.then(|| (name.clone(), trigger.clone(), threshold))
+}
+
+// A variable layer's key: held sets the variable, tapped sends `to_if_alone`.
+fn layer_trigger(manipulator: &Manipulator) -> Option<(String, String, &[ToEvent])> {
+    let key = manipulator.from.key_code.as_ref()?;
+    let [ToEvent {
+        set_variable: Some(on),
+        ..
+    }] = manipulator.to.as_deref()?
+    else {
+        return None;
+    };
+    let name = &on.name;
+    let alone = manipulator.to_if_alone.as_deref()?;
+    let expected = Manipulator {
+        from: FromEvent {
+            key_code: Some(key.clone()),
+            modifiers: Some(FromModifiers {
+                mandatory: Vec::new(),
+                optional: vec!["any".to_string()],
+            }),
+            ..Default::default()
+        },
+        to: Some(vec![variable(name, 1)]),
+        to_after_key_up: Some(vec![variable(name, 0)]),
+        to_if_alone: Some(alone.to_vec()),
+        ..Default::default()
+    };
+    (*manipulator == expected).then(|| (name.clone(), key.clone(), alone))
+}
+
+fn from(event: &FromEvent) -> Option<Edn> {
+    if event.simultaneous_options.is_some() || !event.extra.is_empty() {
+        return None;
+    }
+    let modifiers = event.modifiers.as_ref();
+    let (mandatory, optional) = modifiers.map_or((&[][..], &[][..]), |m| {
+        (m.mandatory.as_slice(), m.optional.as_slice())
+    });
+
+    if let Some(keys) = &event.simultaneous {
+        let only = FromEvent {
+            simultaneous: Some(keys.clone()),
+            modifiers: event.modifiers.clone(),
+            ..Default::default()
+        };
+        if *event != only {
+            return None;
+        }
+        let keys = keys
+            .iter()
+            .map(|key| {
+                let code = key.key_code.as_ref()?;
+                (*key == plain_from(code)).then(|| keyword(code))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        return Some(match modifiers {
+            None => Edn::Vector(keys),
+            Some(modifiers) => map([("sim", Edn::Vector(keys)), ("modi", modi(modifiers))]),
+        });
+    }
+
+    let set = [
+        event.key_code.is_some(),
+        event.consumer_key_code.is_some(),
+        event.pointing_button.is_some(),
+        event.any.is_some(),
+    ];
+    if set.iter().filter(|set| **set).count() != 1 {
+        return None;
+    }
+    if let Some(key) = &event.key_code {
+        return Some(match shorthand(key, mandatory, optional) {
+            Some(shorthand) => keyword(&shorthand),
+            None => map([("key", keyword(key)), ("modi", modi(modifiers?))]),
+        });
+    }
+    let mut entries = match (&event.consumer_key_code, &event.pointing_button, &event.any) {
+        (Some(key), _, _) => vec![("ckey", keyword(key))],
+        (_, Some(button), _) => vec![("pkey", keyword(button))],
+        (_, _, Some(any)) => vec![("any", keyword(any))],
+        _ => return None,
+    };
+    if let Some(modifiers) = modifiers {
+        entries.push(("modi", modi(modifiers)));
+    }
+    Some(map(entries))
+}
+
+/// Goku's `!CTa` for a with ⌘⌃, `##a` for any optional modifiers, `!!a` for all four.
+fn shorthand(key: &str, mandatory: &[String], optional: &[String]) -> Option<String> {
+    let mut out = String::new();
+    let hyper =
+        mandatory.len() == HYPER.len() && HYPER.iter().all(|m| mandatory.contains(&m.to_string()));
+    if hyper {
+        out.push_str("!!");
+    } else if !mandatory.is_empty() {
+        out.push('!');
+        for modifier in mandatory {
+            out.push(letter(modifier)?);
+        }
+    }
+    if optional == ["any"] {
+        out.push_str("##");
+    } else if !optional.is_empty() {
+        out.push('#');
+        for modifier in optional {
+            out.push(letter(modifier)?);
+        }
+    }
+    out.push_str(key);
+    Some(out)
+}
+
+fn modi(modifiers: &FromModifiers) -> Edn {
+    let mut entries = Vec::new();
+    if !modifiers.mandatory.is_empty() {
+        entries.push(("mandatory", keywords(&modifiers.mandatory)));
+    }
+    if !modifiers.optional.is_empty() {
+        entries.push(("optional", keywords(&modifiers.optional)));
+    }
+    map(entries)
+}
+
+fn params(parameters: &ManipulatorParameters) -> Option<Edn> {
+    if parameters.mouse_motion_to_scroll_speed.is_some() || !parameters.extra.is_empty() {
+        return None;
+    }
+    let entries = [
+        ("sim", parameters.simultaneous_threshold_milliseconds),
+        ("delay", parameters.to_delayed_action_delay_milliseconds),
+        ("alone", parameters.to_if_alone_timeout_milliseconds),
+        ("held", parameters.to_if_held_down_threshold_milliseconds),
+    ];
+    Some(map(entries.into_iter().filter_map(|(key, value)| {
+        value.map(|value| (key, Edn::Int(value)))
+    })))
+}
+
+fn set_variable(variable: &SetVariable) -> Option<Edn> {
+    if !variable.extra.is_empty() {
+        return None;
+    }
+    Some(Edn::Vector(vec![
+        Edn::String(variable.name.clone()),
+        variable_value(variable.value.as_ref()?),
+    ]))
+}
+
+fn variable_value(value: &VariableValue) -> Edn {
+    match value {
+        VariableValue::Bool(value) => Edn::Bool(*value),
+        VariableValue::Int(value) => Edn::Int(*value),
+        VariableValue::String(value) => Edn::String(value.clone()),
+    }
+}
+
+fn mouse_key(mouse: &MouseKey) -> Edn {
+    let mut entries = Vec::new();
+    for (key, value) in [
+        ("x", mouse.x),
+        ("y", mouse.y),
+        ("vwheel", mouse.vertical_wheel),
+        ("hwheel", mouse.horizontal_wheel),
+    ] {
+        if let Some(value) = value {
+            entries.push((key, Edn::Int(value)));
+        }
+    }
+    if let Some(speed) = mouse.speed_multiplier {
+        entries.push(("speed", Edn::Float(speed)));
+    }
+    map(entries)
+}
+
+// Everything around the first quoted argument of a shell command: `open -a '`, `Safari`, `'`.
+fn split(command: &str) -> Option<(String, String, String)> {
+    if command.contains('%') {
+        return None;
+    }
+    let start = command.find(['\'', '"'])?;
+    let quote = &command[start..=start];
+    let end = start + 1 + command[start + 1..].find(quote)?;
+    let argument = &command[start + 1..end];
+    if argument.is_empty() || argument.contains('\\') {
+        return None;
+    }
+    Some((
+        command[..=start].to_string(),
+        argument.to_string(),
+        command[end..].to_string(),
+    ))
+}
+
+/// A keyword name that doesn't collide with `taken`, made from `base`.
+fn claim(taken: &mut HashSet<String>, base: &str, fallback: &str) -> String {
+    let cleaned: String = base
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect::<String>()
+        .trim_matches('-')
+        .to_string();
+    let base = if is_keyword(&cleaned) {
+        cleaned
+    } else {
+        fallback.to_string()
+    };
+    let mut name = base.to_lowercase();
+    let mut n = 1;
+    while taken.contains(&name) {
+        n += 1;
+        name = format!("{}-{n}", base.to_lowercase());
+    }
+    taken.insert(name.clone());
+    name
+}
+
+fn is_keyword(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.?*+<>=".contains(c))
+}
+
+fn keyword(name: &str) -> Edn {
+    Edn::Keyword(name.to_string())
+}
+
+fn keywords(names: &[String]) -> Edn {
+    Edn::Vector(names.iter().map(|name| keyword(name)).collect())
+}
+
+fn map<'a>(entries: impl IntoIterator<Item = (&'a str, Edn)>) -> Edn {
+    Edn::Map(
+        entries
+            .into_iter()
+            .map(|(key, value)| (keyword(key), value))
+            .collect(),
+    )
+}
+
+fn events(manipulator: &Manipulator) -> impl Iterator<Item = &ToEvent> {
+    let delayed = manipulator.to_delayed_action.iter().flat_map(|delayed| {
+        delayed
+            .to_if_invoked
+            .iter()
+            .chain(delayed.to_if_canceled.iter())
+    });
+    [
+        &manipulator.to,
+        &manipulator.to_if_alone,
+        &manipulator.to_if_held_down,
+        &manipulator.to_after_key_up,
+    ]
+    .into_iter()
+    .flatten()
+    .flatten()
+    .chain(delayed)
+}
+
+fn variable(name: &str, value: i64) -> ToEvent {
+    ToEvent {
+        set_variable: Some(SetVariable {
+            name: name.to_string(),
+            value: Some(VariableValue::Int(value)),
+            extra: Default::default(),
+        }),
+        ..Default::default()
+    }
+}
+
+fn plain_from(key: &str) -> FromEvent {
+    FromEvent {
+        key_code: Some(key.to_string()),
+        ..Default::default()
+    }
+}
+
+fn plain_to(key: &str) -> ToEvent {
+    ToEvent {
+        key_code: Some(key.to_string()),
+        ..Default::default()
+    }
+}
+
+fn shell(command: &str) -> ToEvent {
+    ToEvent {
+        shell_command: Some(command.to_string()),
+        ..Default::default()
+    }
+}
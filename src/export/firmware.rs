@@ -0,0 +1,757 @@
+// `ankura export --format qmk` and `--format zmk`: writes a profile's layers as a keyboard
+// firmware keymap.
+//
+// Firmware maps physical positions, so the keymap follows a `Layout`: the board's keys as
+// Karabiner key codes, in the order its layout macro or devicetree lists them. Rules go to the
+// base layer or the layer of the variable they require, keys that hold a layer become `MO()`
+// or `LT()`, dual-use modifiers become `MT()`, and a layer held on a modifier keeps sending
+// that modifier with the keys it doesn't map.
+
+use super::{layer, simlayer, skip_profile_settings, switch, Summary, BASE};
+use crate::error::{self, KarabinerPklError};
+use crate::karabiner::{FromModifiers, Manipulator, Profile, SimpleFrom, SimpleTo, ToEvent};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Firmware {
+    Qmk,
+    Zmk,
+}
+
+// Karabiner key codes with their QMK and ZMK names.
+const KEYS: &[(&str, &str, &str)] = &[
+    ("a", "KC_A", "A"),
+    ("b", "KC_B", "B"),
+    ("c", "KC_C", "C"),
+    ("d", "KC_D", "D"),
+    ("e", "KC_E", "E"),
+    ("f", "KC_F", "F"),
+    ("g", "KC_G", "G"),
+    ("h", "KC_H", "H"),
+    ("i", "KC_I", "I"),
+    ("j", "KC_J", "J"),
+    ("k", "KC_K", "K"),
+    ("l", "KC_L", "L"),
+    ("m", "KC_M", "M"),
+    ("n", "KC_N", "N"),
+    ("o", "KC_O", "O"),
+    ("p", "KC_P", "P"),
+    ("q", "KC_Q", "Q"),
+    ("r", "KC_R", "R"),
+    ("s", "KC_S", "S"),
+    ("t", "KC_T", "T"),
+    ("u", "KC_U", "U"),
+    ("v", "KC_V", "V"),
+    ("w", "KC_W", "W"),
+    ("x", "KC_X", "X"),
+    ("y", "KC_Y", "Y"),
+    ("z", "KC_Z", "Z"),
+    ("1", "KC_1", "N1"),
+    ("2", "KC_2", "N2"),
+    ("3", "KC_3", "N3"),
+    ("4", "KC_4", "N4"),
+    ("5", "KC_5", "N5"),
+    ("6", "KC_6", "N6"),
+    ("7", "KC_7", "N7"),
+    ("8", "KC_8", "N8"),
+    ("9", "KC_9", "N9"),
+    ("0", "KC_0", "N0"),
+    ("return_or_enter", "KC_ENT", "RET"),
+    ("escape", "KC_ESC", "ESC"),
+    ("delete_or_backspace", "KC_BSPC", "BSPC"),
+    ("delete_forward", "KC_DEL", "DEL"),
+    ("tab", "KC_TAB", "TAB"),
+    ("spacebar", "KC_SPC", "SPACE"),
+    ("hyphen", "KC_MINS", "MINUS"),
+    ("equal_sign", "KC_EQL", "EQUAL"),
+    ("open_bracket", "KC_LBRC", "LBKT"),
+    ("close_bracket", "KC_RBRC", "RBKT"),
+    ("backslash", "KC_BSLS", "BSLH"),
+    ("non_us_pound", "KC_NUHS", "NUHS"),
+    ("semicolon", "KC_SCLN", "SEMI"),
+    ("quote", "KC_QUOT", "SQT"),
+    ("grave_accent_and_tilde", "KC_GRV", "GRAVE"),
+    ("comma", "KC_COMM", "COMMA"),
+    ("period", "KC_DOT", "DOT"),
+    ("slash", "KC_SLSH", "FSLH"),
+    ("non_us_backslash", "KC_NUBS", "NUBS"),
+    ("caps_lock", "KC_CAPS", "CAPS"),
+    ("f1", "KC_F1", "F1"),
+    ("f2", "KC_F2", "F2"),
+    ("f3", "KC_F3", "F3"),
+    ("f4", "KC_F4", "F4"),
+    ("f5", "KC_F5", "F5"),
+    ("f6", "KC_F6", "F6"),
+    ("f7", "KC_F7", "F7"),
+    ("f8", "KC_F8", "F8"),
+    ("f9", "KC_F9", "F9"),
+    ("f10", "KC_F10", "F10"),
+    ("f11", "KC_F11", "F11"),
+    ("f12", "KC_F12", "F12"),
+    ("f13", "KC_F13", "F13"),
+    ("f14", "KC_F14", "F14"),
+    ("f15", "KC_F15", "F15"),
+    ("f16", "KC_F16", "F16"),
+    ("f17", "KC_F17", "F17"),
+    ("f18", "KC_F18", "F18"),
+    ("f19", "KC_F19", "F19"),
+    ("f20", "KC_F20", "F20"),
+    ("print_screen", "KC_PSCR", "PSCRN"),
+    ("scroll_lock", "KC_SCRL", "SLCK"),
+    ("pause", "KC_PAUS", "PAUSE_BREAK"),
+    ("insert", "KC_INS", "INS"),
+    ("home", "KC_HOME", "HOME"),
+    ("page_up", "KC_PGUP", "PG_UP"),
+    ("end", "KC_END", "END"),
+    ("page_down", "KC_PGDN", "PG_DN"),
+    ("right_arrow", "KC_RGHT", "RIGHT"),
+    ("left_arrow", "KC_LEFT", "LEFT"),
+    ("down_arrow", "KC_DOWN", "DOWN"),
+    ("up_arrow", "KC_UP", "UP"),
+    ("keypad_num_lock", "KC_NUM", "KP_NUM"),
+    ("keypad_slash", "KC_PSLS", "KP_SLASH"),
+    ("keypad_asterisk", "KC_PAST", "KP_ASTERISK"),
+    ("keypad_hyphen", "KC_PMNS", "KP_MINUS"),
+    ("keypad_plus", "KC_PPLS", "KP_PLUS"),
+    ("keypad_enter", "KC_PENT", "KP_ENTER"),
+    ("keypad_1", "KC_P1", "KP_N1"),
+    ("keypad_2", "KC_P2", "KP_N2"),
+    ("keypad_3", "KC_P3", "KP_N3"),
+    ("keypad_4", "KC_P4", "KP_N4"),
+    ("keypad_5", "KC_P5", "KP_N5"),
+    ("keypad_6", "KC_P6", "KP_N6"),
+    ("keypad_7", "KC_P7", "KP_N7"),
+    ("keypad_8", "KC_P8", "KP_N8"),
+    ("keypad_9", "KC_P9", "KP_N9"),
+    ("keypad_0", "KC_P0", "KP_N0"),
+    ("keypad_period", "KC_PDOT", "KP_DOT"),
+    ("keypad_equal_sign", "KC_PEQL", "KP_EQUAL"),
+    ("application", "KC_APP", "K_APP"),
+    ("left_control", "KC_LCTL", "LCTRL"),
+    ("left_shift", "KC_LSFT", "LSHFT"),
+    ("left_option", "KC_LALT", "LALT"),
+    ("left_command", "KC_LGUI", "LGUI"),
+    ("right_control", "KC_RCTL", "RCTRL"),
+    ("right_shift", "KC_RSFT", "RSHFT"),
+    ("right_option", "KC_RALT", "RALT"),
+    ("right_command", "KC_RGUI", "RGUI"),
+    ("volume_increment", "KC_VOLU", "C_VOL_UP"),
+    ("volume_decrement", "KC_VOLD", "C_VOL_DN"),
+    ("mute", "KC_MUTE", "C_MUTE"),
+    ("play_or_pause", "KC_MPLY", "C_PP"),
+    ("scan_next_track", "KC_MNXT", "C_NEXT"),
+    ("fastforward", "KC_MNXT", "C_NEXT"),
+    ("scan_previous_track", "KC_MPRV", "C_PREV"),
+    ("rewind", "KC_MPRV", "C_PREV"),
+    ("display_brightness_increment", "KC_BRIU", "C_BRI_UP"),
+    ("display_brightness_decrement", "KC_BRID", "C_BRI_DN"),
+];
+
+// Modifier keys with QMK's wrapper and mod mask, and ZMK's wrapper, e.g. `LCTL(KC_A)`,
+// `MOD_LCTL` and `LC(A)`.
+const MODIFIERS: &[(&str, &str, &str, &str)] = &[
+    ("left_control", "LCTL", "MOD_LCTL", "LC"),
+    ("left_shift", "LSFT", "MOD_LSFT", "LS"),
+    ("left_option", "LALT", "MOD_LALT", "LA"),
+    ("left_command", "LGUI", "MOD_LGUI", "LG"),
+    ("right_control", "RCTL", "MOD_RCTL", "RC"),
+    ("right_shift", "RSFT", "MOD_RSFT", "RS"),
+    ("right_option", "RALT", "MOD_RALT", "RA"),
+    ("right_command", "RGUI", "MOD_RGUI", "RG"),
+];
+
+// `LAYOUT_60_ansi`'s 61 keys. The bottom row is Ctrl, GUI, Alt, Space, Alt, GUI, Menu, Ctrl.
+const ANSI: &str = "
+escape 1 2 3 4 5 6 7 8 9 0 hyphen equal_sign delete_or_backspace
+tab q w e r t y u i o p open_bracket close_bracket backslash
+caps_lock a s d f g h j k l semicolon quote return_or_enter
+left_shift z x c v b n m comma period slash right_shift
+left_control left_command left_option spacebar right_option right_command _ right_control
+";
+
+const CORNE: &str = "
+tab q w e r t y u i o p delete_or_backspace
+left_control a s d f g h j k l semicolon quote
+left_shift z x c v b n m comma period slash escape
+left_command left_option spacebar return_or_enter right_command right_option
+";
+
+fn modifier(
+    name: &str,
+) -> Option<&'static (&'static str, &'static str, &'static str, &'static str)> {
+    // Karabiner's side-less modifiers are sent as the left one.
+    let name = match name {
+        "control" => "left_control",
+        "shift" => "left_shift",
+        "option" => "left_option",
+        "command" => "left_command",
+        name => name,
+    };
+    MODIFIERS.iter().find(|(code, ..)| *code == name)
+}
+
+fn known(key_code: &str) -> Result<(), String> {
+    if KEYS.iter().any(|(code, ..)| *code == key_code) {
+        Ok(())
+    } else {
+        Err(format!("{key_code} has no firmware keycode"))
+    }
+}
+
+/// The keys of a board, as Karabiner key codes in the order its keymap lists them.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    /// QMK's layout macro, e.g. `LAYOUT_split_3x6_3`.
+    pub name: String,
+    /// `None` for a position no Karabiner key code stands for.
+    pub rows: Vec<Vec<Option<String>>>,
+}
+
+impl Layout {
+    /// A built-in layout (`ansi` or `corne`), or a layout file.
+    pub fn load(spec: &str) -> error::Result<Self> {
+        match spec {
+            "ansi" => Self::parse("LAYOUT_60_ansi", ANSI, Path::new(spec)),
+            "corne" => Self::parse("LAYOUT_split_3x6_3", CORNE, Path::new(spec)),
+            path => {
+                let path = PathBuf::from(path);
+                let source = std::fs::read_to_string(&path).map_err(|source| {
+                    KarabinerPklError::ConfigReadError {
+                        path: path.clone(),
+                        source,
+                    }
+                })?;
+                Self::parse("LAYOUT", &source, &path)
+            }
+        }
+    }
+
+    // One row per line of key codes, `_` for a position with none, `#` for comments, and an
+    // optional `macro NAME` line.
+    fn parse(name: &str, source: &str, path: &Path) -> error::Result<Self> {
+        let mut layout = Layout {
+            name: name.to_string(),
+            rows: Vec::new(),
+        };
+        for (number, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace().peekable();
+            if words.peek().is_none() {
+                continue;
+            }
+            if let Some(name) = line.trim().strip_prefix("macro ") {
+                layout.name = name.trim().to_string();
+                continue;
+            }
+            let row = words
+                .map(|word| match word {
+                    "_" => Ok(None),
+                    code => known(code).map(|()| Some(code.to_string())),
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|reason| KarabinerPklError::ValidationError {
+                    message: format!("{}:{}: {reason}", path.display(), number + 1),
+                })?;
+            layout.rows.push(row);
+        }
+        if layout.rows.is_empty() {
+            return Err(KarabinerPklError::ValidationError {
+                message: format!("{} has no keys", path.display()),
+            });
+        }
+        Ok(layout)
+    }
+
+    fn keys(&self) -> impl Iterator<Item = Option<&str>> {
+        self.rows.iter().flatten().map(Option::as_deref)
+    }
+
+    fn position(&self, key_code: &str) -> Option<usize> {
+        self.keys().position(|key| key == Some(key_code))
+    }
+}
+
+/// Renders the layers of `profile`, compiled from `input`, as a keymap for `layout`.
+pub fn render(
+    profile: &Profile,
+    input: &Path,
+    summary: &mut Summary,
+    firmware: Firmware,
+    layout: &Layout,
+) -> String {
+    skip_profile_settings(profile, summary);
+
+    let mut keymap = Keymap {
+        layout,
+        layers: vec![(BASE.to_string(), BTreeMap::new())],
+    };
+
+    for modification in &profile.simple_modifications {
+        if let Err(reason) = keymap.simple(&modification.from, &modification.to) {
+            summary
+                .skipped
+                .push(format!("simple modification: {reason}"));
+        }
+    }
+
+    // Simlayer chords become a layer tap on their trigger, after everything else has had the
+    // chance to claim that key.
+    let mut simlayers: Vec<(String, String)> = Vec::new();
+    for rule in profile.rules() {
+        for manipulator in &rule.manipulators {
+            summary.manipulators += 1;
+            if let Some(simlayer) = simlayer(manipulator) {
+                if !simlayers.contains(&simlayer) {
+                    simlayers.push(simlayer);
+                }
+                continue;
+            }
+            if let Err(reason) = keymap.manipulator(manipulator) {
+                summary
+                    .skipped
+                    .push(format!("rule {:?}: {reason}", rule.description));
+            }
+        }
+    }
+    for (layer, trigger) in simlayers {
+        let tap = Key {
+            code: trigger.clone(),
+            modifiers: Vec::new(),
+        };
+        match keymap.bind(BASE, &trigger, Binding::LayerTap(layer.clone(), tap)) {
+            Ok(()) => {
+                keymap.layer(&layer);
+                summary.skipped.push(format!(
+                    "simlayer {layer:?}: held {trigger} switches to it instead of a chord"
+                ))
+            }
+            Err(reason) => summary
+                .skipped
+                .push(format!("simlayer {layer:?}: {reason}")),
+        }
+    }
+
+    summary
+        .definitions
+        .insert("layer".to_string(), keymap.layers.len());
+    match firmware {
+        Firmware::Qmk => keymap.qmk(input, summary),
+        Firmware::Zmk => keymap.zmk(input, summary),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Key {
+    code: String,
+    modifiers: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Binding {
+    Key(Key),
+    None,
+    // The layer while held.
+    Momentary(String),
+    LayerTap(String, Key),
+    ModTap(Vec<&'static str>, Key),
+}
+
+struct Keymap<'a> {
+    layout: &'a Layout,
+    // Each layer's binding per layout position.
+    layers: Vec<(String, BTreeMap<usize, Binding>)>,
+}
+
+impl Keymap<'_> {
+    fn simple(&mut self, from: &SimpleFrom, to: &SimpleTo) -> Result<(), String> {
+        let from = match from {
+            SimpleFrom::KeyCode(key) => key.clone(),
+            SimpleFrom::Event(event) => event
+                .key_code
+                .clone()
+                .ok_or("only key codes can be remapped")?,
+        };
+        let binding = match to {
+            SimpleTo::KeyCode(key) => {
+                known(key)?;
+                Binding::Key(Key {
+                    code: key.clone(),
+                    modifiers: Vec::new(),
+                })
+            }
+            SimpleTo::Event(event) => keys(std::slice::from_ref(event))?,
+            SimpleTo::Events(events) => keys(events)?,
+        };
+        self.bind(BASE, &from, binding)
+    }
+
+    fn manipulator(&mut self, manipulator: &Manipulator) -> Result<(), String> {
+        let conditions = manipulator.conditions.as_deref().unwrap_or_default();
+        let layer = layer(conditions)?;
+
+        if manipulator.from.simultaneous.is_some() {
+            return Err("simultaneous keys need combos, which aren't exported".to_string());
+        }
+        let key = manipulator
+            .from
+            .key_code
+            .as_ref()
+            .ok_or("only key codes can be remapped")?;
+        // Actions firmware can't express at all are the more useful reason.
+        let binding = binding(manipulator)?;
+        // Only layers become firmware layers; a real modifier stays a plain key, so a
+        // modifier combination would need the firmware's key overrides.
+        if let Some(FromModifiers { mandatory, .. }) = &manipulator.from.modifiers {
+            if !mandatory.is_empty() {
+                return Err(format!(
+                    "{key} with {} held needs a key override, which isn't exported",
+                    mandatory.join("+")
+                ));
+            }
+        }
+        let switched = match &binding {
+            Binding::Momentary(layer) | Binding::LayerTap(layer, _) => Some(layer.clone()),
+            _ => None,
+        };
+        self.bind(&layer, key, binding)?;
+        // A layer nothing switches to isn't exported.
+        if let Some(switched) = switched {
+            self.layer(&switched);
+        }
+        Ok(())
+    }
+
+    fn layer(&mut self, layer: &str) -> &mut BTreeMap<usize, Binding> {
+        let index = match self.layers.iter().position(|(name, _)| name == layer) {
+            Some(index) => index,
+            None => {
+                self.layers.push((layer.to_string(), BTreeMap::new()));
+                self.layers.len() - 1
+            }
+        };
+        &mut self.layers[index].1
+    }
+
+    fn bind(&mut self, layer: &str, key: &str, binding: Binding) -> Result<(), String> {
+        let position = self
+            .layout
+            .position(key)
+            .ok_or_else(|| format!("{key} isn't on the layout"))?;
+        let keys = self.layer(layer);
+        if keys.contains_key(&position) {
+            // Karabiner applies the first matching manipulator, so the first one is kept.
+            return Err(format!("{key} is already mapped in layer {layer}"));
+        }
+        keys.insert(position, binding);
+        Ok(())
+    }
+
+    fn qmk(&self, input: &Path, summary: &Summary) -> String {
+        let mut out = header("qmk", input, summary);
+        out.push_str("\n#include QMK_KEYBOARD_H\n\nenum layers {\n");
+        for (layer, _) in &self.layers {
+            let _ = writeln!(out, "    _{},", identifier(layer));
+        }
+        out.push_str("};\n\nconst uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {\n");
+
+        let total = self.layout.keys().count();
+        let rows = self.rows(|layer, position, binding| {
+            let mut token = match (binding, self.layout.keys().nth(position).flatten()) {
+                (Some(binding), _) => qmk(binding),
+                (None, Some(code)) if layer == BASE => qmk_key(code, &[]),
+                (None, None) => "KC_NO".to_string(),
+                (None, _) => "KC_TRNS".to_string(),
+            };
+            if position + 1 < total {
+                token.push(',');
+            }
+            token
+        });
+        for ((layer, _), rows) in self.layers.iter().zip(rows) {
+            let _ = writeln!(
+                out,
+                "    [_{}] = {}(\n{}    ),",
+                identifier(layer),
+                self.layout.name,
+                lines(&rows, 8)
+            );
+        }
+        out.push_str("};\n");
+        out
+    }
+
+    fn zmk(&self, input: &Path, summary: &Summary) -> String {
+        let mut out = header("zmk", input, summary);
+        out.push_str("\n#include <behaviors.dtsi>\n#include <dt-bindings/zmk/keys.h>\n\n");
+        for (index, (layer, _)) in self.layers.iter().enumerate() {
+            let _ = writeln!(out, "#define {} {index}", zmk_layer(layer));
+        }
+        out.push_str("\n/ {\n    keymap {\n        compatible = \"zmk,keymap\";\n");
+
+        let rows = self.rows(|layer, position, binding| {
+            match (binding, self.layout.keys().nth(position).flatten()) {
+                (Some(binding), _) => zmk(binding),
+                (None, Some(code)) if layer == BASE => format!("&kp {}", zmk_key(code, &[])),
+                (None, None) => "&none".to_string(),
+                (None, _) => "&trans".to_string(),
+            }
+        });
+        for ((layer, _), rows) in self.layers.iter().zip(rows) {
+            let _ = write!(
+                out,
+                "\n        {}_layer {{\n            bindings = <\n{}            >;\n        }};\n",
+                identifier(layer).to_lowercase(),
+                lines(&rows, 16)
+            );
+        }
+        out.push_str("    };\n};\n");
+        out
+    }
+
+    // Every layer's tokens by layout row, padded so each column lines up across layers.
+    fn rows(
+        &self,
+        token: impl Fn(&str, usize, Option<&Binding>) -> String,
+    ) -> Vec<Vec<Vec<String>>> {
+        let mut layers: Vec<Vec<Vec<String>>> = self
+            .layers
+            .iter()
+            .map(|(layer, bindings)| {
+                let mut position = 0;
+                self.layout
+                    .rows
+                    .iter()
+                    .map(|row| {
+                        row.iter()
+                            .map(|_| {
+                                position += 1;
+                                token(layer, position - 1, bindings.get(&(position - 1)))
+                            })
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        for (row, keys) in self.layout.rows.iter().enumerate() {
+            for column in 0..keys.len() {
+                let width = layers
+                    .iter()
+                    .map(|rows| rows[row][column].len())
+                    .max()
+                    .unwrap_or_default();
+                for rows in &mut layers {
+                    let token = &mut rows[row][column];
+                    *token = format!("{token:<width$}");
+                }
+            }
+        }
+        layers
+    }
+}
+
+// What the key does: a layer or mod tap when the rule has `to_if_alone`, and a layer
+// switch when it sets a variable until released.
+fn binding(manipulator: &Manipulator) -> Result<Binding, String> {
+    if manipulator.to_delayed_action.is_some() {
+        return Err("to_delayed_action isn't supported".to_string());
+    }
+    let to = manipulator.to.as_deref().unwrap_or_default();
+    let held = manipulator.to_if_held_down.as_deref();
+    if held.is_some() && !to.is_empty() {
+        return Err("to_if_held_down alongside to isn't supported".to_string());
+    }
+    let hold = held.unwrap_or(to);
+
+    let switched = match manipulator.to_after_key_up.as_deref() {
+        None => None,
+        Some(after) => Some(switch(to, after).ok_or("to_after_key_up isn't supported")?),
+    };
+
+    let Some(alone) = &manipulator.to_if_alone else {
+        return match switched {
+            Some(layer) => Ok(Binding::Momentary(layer)),
+            None => keys(hold),
+        };
+    };
+    let tap = match keys(alone)? {
+        Binding::Key(key) if key.modifiers.is_empty() => key,
+        _ => return Err("a layer or mod tap can only tap a plain key".to_string()),
+    };
+    match switched {
+        Some(layer) => Ok(Binding::LayerTap(layer, tap)),
+        None => Ok(Binding::ModTap(modifiers(hold)?, tap)),
+    }
+}
+
+fn header(format: &str, input: &Path, summary: &Summary) -> String {
+    let mut out = format!(
+        "// Generated by `ankura export --format {format}` from {}.\n",
+        input.display()
+    );
+    if !summary.skipped.is_empty() {
+        out.push_str("//\n// Not exported:\n");
+        for skipped in &summary.skipped {
+            let _ = writeln!(out, "//   {skipped}");
+        }
+    }
+    out
+}
+
+fn lines(rows: &[Vec<String>], indent: usize) -> String {
+    let mut out = String::new();
+    for row in rows {
+        let _ = writeln!(out, "{:indent$}{}", "", row.join(" ").trim_end());
+    }
+    out
+}
+
+// A binding that sends `events`: nothing, or a single key with its modifiers.
+fn keys(events: &[ToEvent]) -> Result<Binding, String> {
+    let event = match events {
+        [] => return Ok(Binding::None),
+        [event] => event,
+        events => return Err(format!("a sequence of {} keys needs a macro", events.len())),
+    };
+    if event.shell_command.is_some() {
+        return Err("shell_command (macOS only)".to_string());
+    }
+    if event.select_input_source.is_some() {
+        return Err("select_input_source (macOS only)".to_string());
+    }
+    if event.software_function.is_some() {
+        return Err("software_function (macOS only)".to_string());
+    }
+    if event.set_variable.is_some() {
+        return Err("set_variable outside a held layer key".to_string());
+    }
+    if event.pointing_button.is_some() || event.mouse_key.is_some() {
+        return Err("mouse keys aren't supported".to_string());
+    }
+    if event.sticky_modifier.is_some() {
+        return Err("sticky_modifier isn't supported".to_string());
+    }
+
+    let code = event
+        .key_code
+        .as_ref()
+        .or(event.consumer_key_code.as_ref())
+        .ok_or("an empty to event")?;
+    known(code)?;
+    let modifiers = event
+        .modifiers
+        .iter()
+        .flatten()
+        .map(|name| {
+            modifier(name)
+                .map(|(code, ..)| *code)
+                .ok_or_else(|| format!("{name} can't be sent"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Binding::Key(Key {
+        code: code.clone(),
+        modifiers,
+    }))
+}
+
+// The modifiers a dual-use key holds: one event whose key and modifiers are all modifiers.
+fn modifiers(events: &[ToEvent]) -> Result<Vec<&'static str>, String> {
+    let Binding::Key(key) = keys(events)? else {
+        return Err("a mod tap has to hold a modifier".to_string());
+    };
+    let (held, ..) = modifier(&key.code)
+        .ok_or_else(|| format!("{} isn't a modifier, so it can't be held", key.code))?;
+    let mut modifiers = key.modifiers;
+    modifiers.push(held);
+    Ok(modifiers)
+}
+
+fn qmk_key(code: &str, modifiers: &[&str]) -> String {
+    let name = KEYS
+        .iter()
+        .find(|(key, ..)| *key == code)
+        .map_or("KC_NO", |(_, qmk, _)| *qmk);
+    modifiers
+        .iter()
+        .rev()
+        .fold(name.to_string(), |inner, held| {
+            let (_, wrapper, ..) = modifier(held).unwrap_or(&MODIFIERS[0]);
+            format!("{wrapper}({inner})")
+        })
+}
+
+fn qmk(binding: &Binding) -> String {
+    match binding {
+        Binding::Key(key) => qmk_key(&key.code, &key.modifiers),
+        Binding::None => "KC_NO".to_string(),
+        Binding::Momentary(layer) => format!("MO(_{})", identifier(layer)),
+        Binding::LayerTap(layer, tap) => {
+            format!("LT(_{}, {})", identifier(layer), qmk_key(&tap.code, &[]))
+        }
+        Binding::ModTap(held, tap) => {
+            let masks: Vec<&str> = held
+                .iter()
+                .filter_map(|held| modifier(held).map(|(_, _, mask, _)| *mask))
+                .collect();
+            format!("MT({}, {})", masks.join(" | "), qmk_key(&tap.code, &[]))
+        }
+    }
+}
+
+fn zmk_key(code: &str, modifiers: &[&str]) -> String {
+    let name = KEYS
+        .iter()
+        .find(|(key, ..)| *key == code)
+        .map_or("NONE", |(.., zmk)| *zmk);
+    modifiers
+        .iter()
+        .rev()
+        .fold(name.to_string(), |inner, held| {
+            let (.., wrapper) = modifier(held).unwrap_or(&MODIFIERS[0]);
+            format!("{wrapper}({inner})")
+        })
+}
+
+fn zmk(binding: &Binding) -> String {
+    match binding {
+        Binding::Key(key) => format!("&kp {}", zmk_key(&key.code, &key.modifiers)),
+        Binding::None => "&none".to_string(),
+        Binding::Momentary(layer) => format!("&mo {}", zmk_layer(layer)),
+        Binding::LayerTap(layer, tap) => {
+            format!("&lt {} {}", zmk_layer(layer), zmk_key(&tap.code, &[]))
+        }
+        // `&mt` holds one keycode, so any other modifiers wrap the last one.
+        Binding::ModTap(held, tap) => match held.split_last() {
+            Some((last, rest)) => {
+                format!("&mt {} {}", zmk_key(last, rest), zmk_key(&tap.code, &[]))
+            }
+            None => format!("&kp {}", zmk_key(&tap.code, &[])),
+        },
+    }
+}
+
+// ZMK layer macros, prefixed so they can't shadow a keycode from `keys.h`, like `SPACE`.
+fn zmk_layer(name: &str) -> String {
+    format!("L_{}", identifier(name))
+}
+
+// Layer names as C identifiers, e.g. `tab-mode` as `TAB_MODE`.
+fn identifier(name: &str) -> String {
+    let identifier: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if identifier.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("L{identifier}")
+    } else {
+        identifier
+    }
+}
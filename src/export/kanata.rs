@@ -0,0 +1,576 @@
+// `ankura export --format kanata`: writes a profile as a kanata configuration.
+//
+// kanata remaps keys by position: `defsrc` lists the keys, and each `deflayer` gives their
+// action in that layer. Karabiner rules become base-layer actions, rules gated on a variable
+// become a layer of that name, and the keys setting the variable switch to it while held.
+
+use super::{layer, simlayer, skip_profile_settings, switch, Summary, BASE};
+use crate::karabiner::{
+    FromEvent, FromModifiers, Manipulator, Profile, SimpleFrom, SimpleTo, ToEvent,
+};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+// Karabiner's defaults, for profiles that don't set them.
+const ALONE_TIMEOUT: i64 = 1000;
+const SIMULTANEOUS_THRESHOLD: i64 = 50;
+
+// Keys per line in `defsrc` and `deflayer`.
+const ROW: usize = 14;
+
+// Karabiner key codes and their kanata names, in keyboard order, which `defsrc` follows.
+const KEYS: &[(&str, &str)] = &[
+    ("escape", "esc"),
+    ("f1", "f1"),
+    ("f2", "f2"),
+    ("f3", "f3"),
+    ("f4", "f4"),
+    ("f5", "f5"),
+    ("f6", "f6"),
+    ("f7", "f7"),
+    ("f8", "f8"),
+    ("f9", "f9"),
+    ("f10", "f10"),
+    ("f11", "f11"),
+    ("f12", "f12"),
+    ("f13", "f13"),
+    ("f14", "f14"),
+    ("f15", "f15"),
+    ("f16", "f16"),
+    ("f17", "f17"),
+    ("f18", "f18"),
+    ("f19", "f19"),
+    ("f20", "f20"),
+    ("grave_accent_and_tilde", "grv"),
+    ("1", "1"),
+    ("2", "2"),
+    ("3", "3"),
+    ("4", "4"),
+    ("5", "5"),
+    ("6", "6"),
+    ("7", "7"),
+    ("8", "8"),
+    ("9", "9"),
+    ("0", "0"),
+    ("hyphen", "-"),
+    ("equal_sign", "="),
+    ("delete_or_backspace", "bspc"),
+    ("tab", "tab"),
+    ("q", "q"),
+    ("w", "w"),
+    ("e", "e"),
+    ("r", "r"),
+    ("t", "t"),
+    ("y", "y"),
+    ("u", "u"),
+    ("i", "i"),
+    ("o", "o"),
+    ("p", "p"),
+    ("open_bracket", "["),
+    ("close_bracket", "]"),
+    ("backslash", "\\"),
+    ("caps_lock", "caps"),
+    ("a", "a"),
+    ("s", "s"),
+    ("d", "d"),
+    ("f", "f"),
+    ("g", "g"),
+    ("h", "h"),
+    ("j", "j"),
+    ("k", "k"),
+    ("l", "l"),
+    ("semicolon", ";"),
+    ("quote", "'"),
+    ("non_us_pound", "nubs"),
+    ("return_or_enter", "ret"),
+    ("left_shift", "lsft"),
+    ("non_us_backslash", "102d"),
+    ("z", "z"),
+    ("x", "x"),
+    ("c", "c"),
+    ("v", "v"),
+    ("b", "b"),
+    ("n", "n"),
+    ("m", "m"),
+    ("comma", ","),
+    ("period", "."),
+    ("slash", "/"),
+    ("right_shift", "rsft"),
+    ("left_control", "lctl"),
+    ("left_option", "lalt"),
+    ("left_command", "lmet"),
+    ("spacebar", "spc"),
+    ("right_command", "rmet"),
+    ("right_option", "ralt"),
+    ("right_control", "rctl"),
+    ("application", "menu"),
+    ("print_screen", "prnt"),
+    ("scroll_lock", "slck"),
+    ("pause", "pause"),
+    ("insert", "ins"),
+    ("home", "home"),
+    ("page_up", "pgup"),
+    ("delete_forward", "del"),
+    ("end", "end"),
+    ("page_down", "pgdn"),
+    ("up_arrow", "up"),
+    ("left_arrow", "left"),
+    ("down_arrow", "down"),
+    ("right_arrow", "rght"),
+    ("keypad_num_lock", "nlck"),
+    ("keypad_slash", "kp/"),
+    ("keypad_asterisk", "kp*"),
+    ("keypad_hyphen", "kp-"),
+    ("keypad_7", "kp7"),
+    ("keypad_8", "kp8"),
+    ("keypad_9", "kp9"),
+    ("keypad_plus", "kp+"),
+    ("keypad_4", "kp4"),
+    ("keypad_5", "kp5"),
+    ("keypad_6", "kp6"),
+    ("keypad_1", "kp1"),
+    ("keypad_2", "kp2"),
+    ("keypad_3", "kp3"),
+    ("keypad_enter", "kprt"),
+    ("keypad_0", "kp0"),
+    ("keypad_period", "kp."),
+    ("volume_increment", "volu"),
+    ("volume_decrement", "vold"),
+    ("mute", "mute"),
+    ("play_or_pause", "pp"),
+    ("scan_next_track", "next"),
+    ("fastforward", "next"),
+    ("scan_previous_track", "prev"),
+    ("rewind", "prev"),
+];
+
+// Output modifiers as kanata's key prefixes, e.g. `C-M-a`.
+fn prefix(modifier: &str) -> Option<&'static str> {
+    Some(match modifier {
+        "left_control" | "control" => "C-",
+        "left_shift" | "shift" => "S-",
+        "left_option" | "option" => "A-",
+        "left_command" | "command" => "M-",
+        "right_control" => "RC-",
+        "right_shift" => "RS-",
+        "right_option" => "RA-",
+        "right_command" => "RM-",
+        _ => return None,
+    })
+}
+
+fn position(key_code: &str) -> Option<usize> {
+    KEYS.iter().position(|(code, _)| *code == key_code)
+}
+
+fn name(key_code: &str) -> Result<&'static str, String> {
+    KEYS.iter()
+        .find(|(code, _)| *code == key_code)
+        .map(|(_, name)| *name)
+        .ok_or_else(|| format!("{key_code} has no kanata name"))
+}
+
+/// Renders `profile`, compiled from `input`, as a kanata `.kbd`.
+pub fn render(profile: &Profile, input: &Path, summary: &mut Summary) -> String {
+    skip_profile_settings(profile, summary);
+
+    let parameters = profile
+        .complex_modifications
+        .as_ref()
+        .and_then(|complex| complex.parameters.as_ref());
+    let mut kanata = Kanata {
+        layers: vec![(BASE.to_string(), BTreeMap::new())],
+        chords: Vec::new(),
+        alone_timeout: parameters
+            .and_then(|p| p.to_if_alone_timeout_milliseconds)
+            .unwrap_or(ALONE_TIMEOUT),
+        chord_timeout: parameters
+            .and_then(|p| p.simultaneous_threshold_milliseconds)
+            .unwrap_or(SIMULTANEOUS_THRESHOLD),
+    };
+
+    for modification in &profile.simple_modifications {
+        if let Err(reason) = kanata.simple(&modification.from, &modification.to) {
+            summary
+                .skipped
+                .push(format!("simple modification: {reason}"));
+        }
+    }
+
+    // Simlayer chords are written as a hold on their trigger, after everything else has had
+    // the chance to claim that key.
+    let mut simlayers: Vec<(String, String)> = Vec::new();
+    for rule in profile.rules() {
+        for manipulator in &rule.manipulators {
+            summary.manipulators += 1;
+            if let Some(simlayer) = simlayer(manipulator) {
+                if !simlayers.contains(&simlayer) {
+                    simlayers.push(simlayer);
+                }
+                continue;
+            }
+            if let Err(reason) = kanata.manipulator(manipulator) {
+                summary
+                    .skipped
+                    .push(format!("rule {:?}: {reason}", rule.description));
+            }
+        }
+    }
+    for (layer, trigger) in simlayers {
+        let hold = format!(
+            "(tap-hold {0} {0} {1} (layer-while-held {2}))",
+            kanata.alone_timeout,
+            name(&trigger).unwrap_or_default(),
+            identifier(&layer)
+        );
+        match kanata.bind(BASE, &trigger, hold) {
+            Ok(()) => {
+                kanata.layer(&layer);
+                summary.skipped.push(format!(
+                    "simlayer {layer:?}: held {trigger} switches to it instead of a chord"
+                ))
+            }
+            Err(reason) => summary
+                .skipped
+                .push(format!("simlayer {layer:?}: {reason}")),
+        }
+    }
+    kanata.attach_chords();
+
+    let out = kanata.render(input, summary);
+    summary
+        .definitions
+        .insert("deflayer".to_string(), kanata.layers.len());
+    if !kanata.chords.is_empty() {
+        summary
+            .definitions
+            .insert("defchords".to_string(), kanata.chords.len());
+    }
+    out
+}
+
+struct Kanata {
+    // Each layer's action per `KEYS` position.
+    layers: Vec<(String, BTreeMap<usize, String>)>,
+    chords: Vec<(Vec<String>, String)>,
+    alone_timeout: i64,
+    chord_timeout: i64,
+}
+
+impl Kanata {
+    fn simple(&mut self, from: &SimpleFrom, to: &SimpleTo) -> Result<(), String> {
+        let from = match from {
+            SimpleFrom::KeyCode(key) => key.clone(),
+            SimpleFrom::Event(event) => event
+                .key_code
+                .clone()
+                .ok_or("only key codes can be remapped")?,
+        };
+        let action = match to {
+            SimpleTo::KeyCode(key) => name(key)?.to_string(),
+            SimpleTo::Event(event) => self.action(std::slice::from_ref(event))?,
+            SimpleTo::Events(events) => self.action(events)?,
+        };
+        self.bind(BASE, &from, action)
+    }
+
+    fn manipulator(&mut self, manipulator: &Manipulator) -> Result<(), String> {
+        let conditions = manipulator.conditions.as_deref().unwrap_or_default();
+        let layer = layer(conditions)?;
+
+        if let Some(keys) = &manipulator.from.simultaneous {
+            if layer != BASE {
+                return Err("chords only work in the base layer".to_string());
+            }
+            let keys = keys
+                .iter()
+                .map(|key| match &key.key_code {
+                    Some(code) if *key == plain(code) => Ok(code.clone()),
+                    _ => Err("chords of modified keys aren't supported".to_string()),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            for key in &keys {
+                name(key)?;
+            }
+            let action = self.binding(manipulator)?;
+            self.chords.push((keys, action));
+            return Ok(());
+        }
+
+        let key = manipulator
+            .from
+            .key_code
+            .as_ref()
+            .ok_or("only key codes can be remapped")?;
+        // Conditions and actions kanata can't express at all are the more useful reason.
+        let action = self.binding(manipulator)?;
+        if let Some(FromModifiers { mandatory, .. }) = &manipulator.from.modifiers {
+            if !mandatory.is_empty() {
+                return Err(format!(
+                    "{key} with {} held can't be a layer key",
+                    mandatory.join("+")
+                ));
+            }
+        }
+        self.bind(&layer, key, action)
+    }
+
+    // What the key does: a tap-hold when the rule has `to_if_alone`, and a layer switch when
+    // it sets a variable until released.
+    fn binding(&mut self, manipulator: &Manipulator) -> Result<String, String> {
+        if manipulator.to_delayed_action.is_some() {
+            return Err("to_delayed_action isn't supported".to_string());
+        }
+        let to = manipulator.to.as_deref().unwrap_or_default();
+        let held = manipulator.to_if_held_down.as_deref();
+        if held.is_some() && !to.is_empty() {
+            return Err("to_if_held_down alongside to isn't supported".to_string());
+        }
+
+        let hold = match manipulator.to_after_key_up.as_deref() {
+            None => self.action(held.unwrap_or(to))?,
+            Some(after) => {
+                let layer = switch(to, after).ok_or("to_after_key_up isn't supported")?;
+                self.layer(&layer);
+                format!("(layer-while-held {})", identifier(&layer))
+            }
+        };
+
+        match &manipulator.to_if_alone {
+            None => Ok(hold),
+            Some(alone) => {
+                let timeout = manipulator
+                    .parameters
+                    .as_ref()
+                    .and_then(|p| p.to_if_alone_timeout_milliseconds)
+                    .unwrap_or(self.alone_timeout);
+                // Like Karabiner, pressing another key while it's down makes it a hold.
+                Ok(format!(
+                    "(tap-hold-press {timeout} {timeout} {} {hold})",
+                    self.action(alone)?
+                ))
+            }
+        }
+    }
+
+    fn action(&self, events: &[ToEvent]) -> Result<String, String> {
+        let mut actions = events.iter().map(event).collect::<Result<Vec<_>, _>>()?;
+        Ok(match actions.len() {
+            0 => "XX".to_string(),
+            1 => actions.remove(0),
+            _ => format!("(macro {})", actions.join(" ")),
+        })
+    }
+
+    fn layer(&mut self, layer: &str) -> &mut BTreeMap<usize, String> {
+        let index = match self.layers.iter().position(|(name, _)| name == layer) {
+            Some(index) => index,
+            None => {
+                self.layers.push((layer.to_string(), BTreeMap::new()));
+                self.layers.len() - 1
+            }
+        };
+        &mut self.layers[index].1
+    }
+
+    fn bind(&mut self, layer: &str, key: &str, action: String) -> Result<(), String> {
+        let position = position(key).ok_or_else(|| format!("{key} has no kanata name"))?;
+        let keys = self.layer(layer);
+        if keys.contains_key(&position) {
+            // Karabiner applies the first matching manipulator, so the first one is kept.
+            return Err(format!("{key} is already mapped in layer {layer}"));
+        }
+        keys.insert(position, action);
+        Ok(())
+    }
+
+    // Every key in a chord sends `(chord chords key)`, and on its own does what it did before.
+    fn attach_chords(&mut self) {
+        let mut members: Vec<String> = Vec::new();
+        for (keys, _) in &self.chords {
+            for key in keys {
+                if !members.contains(key) {
+                    members.push(key.clone());
+                }
+            }
+        }
+        for key in members.into_iter().rev() {
+            let kanata = name(&key).unwrap_or_default();
+            let position = position(&key).unwrap_or_default();
+            let alone = self.layers[0]
+                .1
+                .insert(position, format!("(chord chords {kanata})"))
+                .unwrap_or_else(|| kanata.to_string());
+            self.chords.insert(0, (vec![key], alone));
+        }
+    }
+
+    fn render(&self, input: &Path, summary: &Summary) -> String {
+        let mut out = format!(
+            ";; Generated by `ankura export --format kanata` from {}.\n",
+            input.display()
+        );
+        if !summary.skipped.is_empty() {
+            out.push_str(";;\n;; Not exported:\n");
+            for skipped in &summary.skipped {
+                let _ = writeln!(out, ";;   {skipped}");
+            }
+        }
+        out.push_str("\n(defcfg\n  process-unmapped-keys yes\n)\n");
+
+        let positions: Vec<usize> = {
+            let mut positions: Vec<usize> = self
+                .layers
+                .iter()
+                .flat_map(|(_, keys)| keys.keys().copied())
+                .collect();
+            positions.sort_unstable();
+            positions.dedup();
+            positions
+        };
+
+        // Long actions go in `defalias` so the layers stay aligned with `defsrc`.
+        let mut aliases: Vec<(String, String)> = Vec::new();
+        let layers: Vec<(&str, Vec<String>)> = self
+            .layers
+            .iter()
+            .map(|(layer, keys)| {
+                let row = positions
+                    .iter()
+                    .map(|position| match keys.get(position) {
+                        None => "_".to_string(),
+                        Some(action) if !action.starts_with('(') => action.clone(),
+                        Some(action) => {
+                            let key = KEYS[*position].1;
+                            let alias = if layer == BASE {
+                                key.to_string()
+                            } else {
+                                format!("{}.{key}", identifier(layer))
+                            };
+                            aliases.push((alias.clone(), action.clone()));
+                            format!("@{alias}")
+                        }
+                    })
+                    .collect();
+                (layer.as_str(), row)
+            })
+            .collect();
+
+        let source: Vec<String> = positions
+            .iter()
+            .map(|position| KEYS[*position].1.to_string())
+            .collect();
+        let widths: Vec<usize> = (0..positions.len())
+            .map(|column| {
+                layers
+                    .iter()
+                    .map(|(_, row)| row[column].len())
+                    .chain([source[column].len()])
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let _ = write!(out, "\n(defsrc{})\n", rows(&source, &widths));
+        if !aliases.is_empty() {
+            out.push_str("\n(defalias\n");
+            for (alias, action) in &aliases {
+                let _ = writeln!(out, "  {alias} {action}");
+            }
+            out.push_str(")\n");
+        }
+        if !self.chords.is_empty() {
+            let _ = writeln!(out, "\n(defchords chords {}", self.chord_timeout);
+            for (keys, action) in &self.chords {
+                let keys: Vec<&str> = keys
+                    .iter()
+                    .map(|key| name(key).unwrap_or_default())
+                    .collect();
+                let _ = writeln!(out, "  ({}) {action}", keys.join(" "));
+            }
+            out.push_str(")\n");
+        }
+        for (layer, row) in &layers {
+            let _ = write!(
+                out,
+                "\n(deflayer {}{})\n",
+                identifier(layer),
+                rows(row, &widths)
+            );
+        }
+        out
+    }
+}
+
+// Lines of `ROW` padded columns, each on its own indented line.
+fn rows(tokens: &[String], widths: &[usize]) -> String {
+    let mut out = String::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if i % ROW == 0 {
+            out.push_str("\n ");
+        }
+        let _ = write!(out, " {token:<width$}", width = widths[i]);
+    }
+    let trimmed: Vec<&str> = out.lines().map(str::trim_end).collect();
+    trimmed.join("\n") + "\n"
+}
+
+fn event(event: &ToEvent) -> Result<String, String> {
+    if event.shell_command.is_some() {
+        return Err("shell_command (macOS only)".to_string());
+    }
+    if event.select_input_source.is_some() {
+        return Err("select_input_source (macOS only)".to_string());
+    }
+    if event.software_function.is_some() {
+        return Err("software_function (macOS only)".to_string());
+    }
+    if event.set_variable.is_some() {
+        return Err("set_variable outside a held layer key".to_string());
+    }
+    if event.mouse_key.is_some() || event.sticky_modifier.is_some() {
+        return Err("mouse_key and sticky_modifier aren't supported".to_string());
+    }
+
+    let key = match (
+        &event.key_code,
+        &event.consumer_key_code,
+        &event.pointing_button,
+    ) {
+        (Some(key), _, _) | (_, Some(key), _) => name(key)?,
+        (_, _, Some(button)) => match button.as_str() {
+            "button1" => "mlft",
+            "button2" => "mrgt",
+            "button3" => "mmid",
+            other => return Err(format!("{other} has no kanata name")),
+        },
+        _ => return Err("an empty to event".to_string()),
+    };
+    let mut out = String::new();
+    for modifier in event.modifiers.iter().flatten() {
+        out.push_str(prefix(modifier).ok_or_else(|| format!("{modifier} can't be sent"))?);
+    }
+    out.push_str(key);
+    Ok(out)
+}
+
+fn plain(key: &str) -> FromEvent {
+    FromEvent {
+        key_code: Some(key.to_string()),
+        ..Default::default()
+    }
+}
+
+// Layer names as kanata identifiers.
+fn identifier(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
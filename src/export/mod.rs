@@ -0,0 +1,204 @@
+pub mod edn;
+pub mod firmware;
+pub mod kanata;
+
+use crate::error::{KarabinerPklError, Result};
+use crate::karabiner::{Condition, Config, Manipulator, Profile, ToEvent, VariableValue};
+use firmware::{Firmware, Layout};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+// The layer rules with no conditions go to.
+pub(super) const BASE: &str = "base";
+
+/// What `ankura export` can write the compiled config as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A Goku `karabiner.edn`.
+    Edn,
+    /// A kanata `.kbd`, for Linux and Windows.
+    Kanata,
+    /// A QMK `keymap.c`.
+    Qmk,
+    /// A ZMK `.keymap`.
+    Zmk,
+}
+
+impl FromStr for Format {
+    type Err = KarabinerPklError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "edn" | "goku" => Ok(Self::Edn),
+            "kanata" | "kbd" => Ok(Self::Kanata),
+            "qmk" => Ok(Self::Qmk),
+            "zmk" => Ok(Self::Zmk),
+            other => Err(KarabinerPklError::ValidationError {
+                message: format!(
+                    "Unknown export format \"{other}\"; expected edn, kanata, qmk or zmk"
+                ),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Edn => f.write_str("edn"),
+            Self::Kanata => f.write_str("kanata"),
+            Self::Qmk => f.write_str("qmk"),
+            Self::Zmk => f.write_str("zmk"),
+        }
+    }
+}
+
+pub struct Exported {
+    pub source: String,
+    pub summary: Summary,
+}
+
+/// What an export did with the profile, printed after writing it.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub manipulators: usize,
+    /// Manipulators written in the format's own raw form rather than its shorthand.
+    pub verbatim: usize,
+    /// Named definitions, by the section they went to.
+    pub definitions: BTreeMap<String, usize>,
+    pub skipped: Vec<String>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.verbatim > 0 {
+            writeln!(
+                f,
+                "  Manipulators: {} ({} written verbatim)",
+                self.manipulators, self.verbatim
+            )?;
+        } else {
+            writeln!(f, "  Manipulators: {}", self.manipulators)?;
+        }
+        if !self.definitions.is_empty() {
+            let definitions: Vec<String> = self
+                .definitions
+                .iter()
+                .map(|(section, count)| format!("{count} {section}"))
+                .collect();
+            writeln!(f, "  Definitions: {}", definitions.join(", "))?;
+        }
+
+        if !self.skipped.is_empty() {
+            writeln!(f, "\nNot exported:")?;
+            for skipped in &self.skipped {
+                writeln!(f, "  - {skipped}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Renders the selected (or first) profile of `config`, compiled from `input`. Firmware
+/// formats place keys by `layout`.
+pub fn export(config: &Config, format: Format, input: &Path, layout: &Layout) -> Result<Exported> {
+    let profile = config
+        .active_profile()
+        .ok_or_else(|| KarabinerPklError::ValidationError {
+            message: "Configuration has no profiles to export".to_string(),
+        })?;
+
+    let mut summary = Summary::default();
+    for other in &config.profiles {
+        if other.name != profile.name {
+            summary
+                .skipped
+                .push(format!("profile \"{}\": pick it with -p", other.name));
+        }
+    }
+
+    let source = match format {
+        Format::Edn => edn::render(profile, input, &mut summary),
+        Format::Kanata => kanata::render(profile, input, &mut summary),
+        Format::Qmk => firmware::render(profile, input, &mut summary, Firmware::Qmk, layout),
+        Format::Zmk => firmware::render(profile, input, &mut summary, Firmware::Zmk, layout),
+    };
+    Ok(Exported { source, summary })
+}
+
+// What no export format carries over from a profile.
+fn skip_profile_settings(profile: &Profile, summary: &mut Summary) {
+    if !profile.fn_function_keys.is_empty() {
+        summary.skipped.push("fn_function_keys".to_string());
+    }
+    if !profile.devices.is_empty() {
+        summary
+            .skipped
+            .push(format!("{} device setting(s)", profile.devices.len()));
+    }
+}
+
+// The layer a manipulator belongs to: the base layer, or the variable it requires to be 1.
+pub(super) fn layer(conditions: &[Condition]) -> std::result::Result<String, String> {
+    match conditions {
+        [] => Ok(BASE.to_string()),
+        [Condition {
+            condition_type,
+            name: Some(name),
+            value: Some(VariableValue::Int(1)),
+            ..
+        }] if condition_type == "variable_if" => Ok(name.clone()),
+        conditions => {
+            let kinds: Vec<String> = conditions
+                .iter()
+                .map(|condition| match &condition.name {
+                    Some(name) => format!("{} {name}", condition.condition_type),
+                    None => condition.condition_type.clone(),
+                })
+                .collect();
+            let macos = conditions.iter().any(|condition| {
+                condition
+                    .condition_type
+                    .starts_with("frontmost_application")
+            });
+            Err(format!(
+                "{} condition{}",
+                kinds.join(", "),
+                if macos { " (macOS only)" } else { "" }
+            ))
+        }
+    }
+}
+
+// A chord `SimLayer` (or Goku) writes to turn a simlayer on: its name and trigger key.
+pub(super) fn simlayer(manipulator: &Manipulator) -> Option<(String, String)> {
+    let [trigger, _] = manipulator.from.simultaneous.as_deref()? else {
+        return None;
+    };
+    let after = manipulator
+        .from
+        .simultaneous_options
+        .as_ref()?
+        .to_after_key_up
+        .as_deref()?;
+    let on = manipulator.to.as_deref()?.first()?.set_variable.as_ref()?;
+    let off = after.first()?.set_variable.as_ref()?;
+    if on.name != off.name || on.value != Some(VariableValue::Int(1)) {
+        return None;
+    }
+    Some((on.name.clone(), trigger.key_code.clone()?))
+}
+
+// The layer a key turns on while held: `to` sets a variable to 1 and key up sets it back to 0.
+pub(super) fn switch(to: &[ToEvent], after: &[ToEvent]) -> Option<String> {
+    let ([on], [off]) = (to, after) else {
+        return None;
+    };
+    let (on, off) = (on.set_variable.as_ref()?, off.set_variable.as_ref()?);
+    let plain = on.name == off.name
+        && on.value == Some(VariableValue::Int(1))
+        && off.value == Some(VariableValue::Int(0));
+    plain.then(|| on.name.clone())
+}
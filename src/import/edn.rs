@@ -0,0 +1,405 @@
+use serde_json::{Map, Number, Value};
+use std::fmt;
+
+/// An EDN value, as much of it as Goku configs use. Maps keep the order they were written in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edn {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Char(char),
+    /// `:name`, without the colon.
+    Keyword(String),
+    Symbol(String),
+    List(Vec<Edn>),
+    Vector(Vec<Edn>),
+    Set(Vec<Edn>),
+    Map(Vec<(Edn, Edn)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdnError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Edn {
+    pub fn as_keyword(&self) -> Option<&str> {
+        match self {
+            Edn::Keyword(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// The elements of a vector or list.
+    pub fn as_seq(&self) -> Option<&[Edn]> {
+        match self {
+            Edn::Vector(items) | Edn::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&[(Edn, Edn)]> {
+        match self {
+            Edn::Map(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    /// The value under `:key` in a map.
+    pub fn get(&self, key: &str) -> Option<&Edn> {
+        self.as_map()?
+            .iter()
+            .find(|(k, _)| k.as_keyword() == Some(key))
+            .map(|(_, value)| value)
+    }
+
+    /// The JSON for a raw Karabiner map, with keywords as strings.
+    pub fn to_json(&self) -> Option<Value> {
+        Some(match self {
+            Edn::Nil => Value::Null,
+            Edn::Bool(value) => Value::Bool(*value),
+            Edn::Int(value) => Value::from(*value),
+            Edn::Float(value) => Value::Number(Number::from_f64(*value)?),
+            Edn::String(text) | Edn::Keyword(text) | Edn::Symbol(text) => {
+                Value::String(text.clone())
+            }
+            Edn::Char(c) => Value::String(c.to_string()),
+            Edn::List(items) | Edn::Vector(items) | Edn::Set(items) => {
+                Value::Array(items.iter().map(Edn::to_json).collect::<Option<_>>()?)
+            }
+            Edn::Map(entries) => {
+                let mut map = Map::new();
+                for (key, value) in entries {
+                    let key = match key {
+                        Edn::Keyword(name) | Edn::String(name) => name.clone(),
+                        _ => return None,
+                    };
+                    map.insert(key, value.to_json()?);
+                }
+                Value::Object(map)
+            }
+        })
+    }
+}
+
+impl From<&Value> for Edn {
+    // Object keys become keywords, the way Goku writes Karabiner's own maps.
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => Edn::Nil,
+            Value::Bool(value) => Edn::Bool(*value),
+            Value::Number(number) => match number.as_i64() {
+                Some(value) => Edn::Int(value),
+                None => Edn::Float(number.as_f64().unwrap_or_default()),
+            },
+            Value::String(text) => Edn::String(text.clone()),
+            Value::Array(items) => Edn::Vector(items.iter().map(Edn::from).collect()),
+            Value::Object(map) => Edn::Map(
+                map.iter()
+                    .map(|(key, value)| (Edn::Keyword(key.clone()), Edn::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Edn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn join(f: &mut fmt::Formatter<'_>, items: &[Edn]) -> fmt::Result {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(" ")?;
+                }
+                write!(f, "{item}")?;
+            }
+            Ok(())
+        }
+
+        match self {
+            Edn::Nil => f.write_str("nil"),
+            Edn::Bool(value) => write!(f, "{value}"),
+            Edn::Int(value) => write!(f, "{value}"),
+            Edn::Float(value) => write!(f, "{value:?}"),
+            Edn::String(value) => {
+                f.write_str("\"")?;
+                for c in value.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\t' => f.write_str("\\t")?,
+                        '\r' => f.write_str("\\r")?,
+                        c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                        c => write!(f, "{c}")?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Edn::Char(value) => write!(f, "\\{value}"),
+            Edn::Keyword(name) => write!(f, ":{name}"),
+            Edn::Symbol(name) => f.write_str(name),
+            Edn::List(items) => {
+                f.write_str("(")?;
+                join(f, items)?;
+                f.write_str(")")
+            }
+            Edn::Vector(items) => {
+                f.write_str("[")?;
+                join(f, items)?;
+                f.write_str("]")
+            }
+            Edn::Set(items) => {
+                f.write_str("#{")?;
+                join(f, items)?;
+                f.write_str("}")
+            }
+            Edn::Map(entries) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "{key} {value}")?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// Reads a single top-level value.
+pub fn parse(source: &str) -> Result<Edn, EdnError> {
+    let mut reader = Reader {
+        source,
+        chars: source.char_indices().collect(),
+        pos: 0,
+    };
+    let value = reader
+        .value()?
+        .ok_or_else(|| reader.error("Expected a value"))?;
+    reader.skip_whitespace();
+    if reader.pos < reader.chars.len() {
+        return Err(reader.error("Unexpected content after the top-level value"));
+    }
+    Ok(value)
+}
+
+struct Reader<'a> {
+    source: &'a str,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).map(|(_, c)| *c)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn error(&self, message: impl Into<String>) -> EdnError {
+        let offset = self
+            .chars
+            .get(self.pos)
+            .map_or(self.source.len(), |(offset, _)| *offset);
+        let before = &self.source[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        EdnError {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    // Commas are whitespace in EDN.
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == ',' {
+                self.pos += 1;
+            } else if c == ';' {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    // `None` at a closing delimiter or the end of input.
+    fn value(&mut self) -> Result<Option<Edn>, EdnError> {
+        loop {
+            self.skip_whitespace();
+            let Some(c) = self.peek() else {
+                return Ok(None);
+            };
+            let value = match c {
+                ')' | ']' | '}' => return Ok(None),
+                '(' => Edn::List(self.collection(')')?),
+                '[' => Edn::Vector(self.collection(']')?),
+                '{' => self.map()?,
+                '"' => Edn::String(self.string()?),
+                '\\' => self.character()?,
+                '#' => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some('{') => Edn::Set(self.collection('}')?),
+                        // `#_` discards the next value.
+                        Some('_') => {
+                            self.pos += 1;
+                            self.value()?
+                                .ok_or_else(|| self.error("Expected a value after #_"))?;
+                            continue;
+                        }
+                        // A tagged value reads as the value itself.
+                        _ => {
+                            self.token();
+                            self.value()?
+                                .ok_or_else(|| self.error("Expected a value after a tag"))?
+                        }
+                    }
+                }
+                _ => self.atom()?,
+            };
+            return Ok(Some(value));
+        }
+    }
+
+    fn collection(&mut self, close: char) -> Result<Vec<Edn>, EdnError> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        while let Some(item) = self.value()? {
+            items.push(item);
+        }
+        match self.next() {
+            Some(c) if c == close => Ok(items),
+            Some(c) => {
+                self.pos -= 1;
+                Err(self.error(format!("Expected `{close}`, found `{c}`")))
+            }
+            None => Err(self.error(format!("Expected `{close}` before the end of the file"))),
+        }
+    }
+
+    fn map(&mut self) -> Result<Edn, EdnError> {
+        let start = self.pos;
+        let items = self.collection('}')?;
+        if !items.len().is_multiple_of(2) {
+            self.pos = start;
+            return Err(self.error("A map needs an even number of forms"));
+        }
+        let mut entries = Vec::with_capacity(items.len() / 2);
+        let mut items = items.into_iter();
+        while let (Some(key), Some(value)) = (items.next(), items.next()) {
+            entries.push((key, value));
+        }
+        Ok(Edn::Map(entries))
+    }
+
+    fn string(&mut self) -> Result<String, EdnError> {
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(value),
+                Some('\\') => match self.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('r') => value.push('\r'),
+                    Some(c @ ('"' | '\\')) => value.push(c),
+                    Some('u') => {
+                        let hex: String = (0..4).filter_map(|_| self.next()).collect();
+                        let c = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| self.error(format!("Invalid escape \\u{hex}")))?;
+                        value.push(c);
+                    }
+                    Some(c) => {
+                        self.pos -= 1;
+                        return Err(self.error(format!("Unknown escape \\{c}")));
+                    }
+                    None => break,
+                },
+                Some(c) => value.push(c),
+                None => break,
+            }
+        }
+        Err(self.error("Unterminated string"))
+    }
+
+    fn character(&mut self) -> Result<Edn, EdnError> {
+        self.pos += 1;
+        let name = self.token();
+        let c = match name.as_str() {
+            "newline" => '\n',
+            "space" => ' ',
+            "tab" => '\t',
+            "return" => '\r',
+            _ => {
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => return Err(self.error(format!("Unknown character \\{name}"))),
+                }
+            }
+        };
+        Ok(Edn::Char(c))
+    }
+
+    fn token(&mut self) -> String {
+        let mut token = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || matches!(c, ',' | ';' | '(' | ')' | '[' | ']' | '{' | '}' | '"')
+            {
+                break;
+            }
+            token.push(c);
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn atom(&mut self) -> Result<Edn, EdnError> {
+        let start = self.pos;
+        let token = self.token();
+        if token.is_empty() {
+            return Err(self.error(format!("Unexpected `{}`", self.peek().unwrap_or(' '))));
+        }
+
+        if let Some(name) = token.strip_prefix(':') {
+            return Ok(Edn::Keyword(name.to_string()));
+        }
+        match token.as_str() {
+            "nil" => return Ok(Edn::Nil),
+            "true" => return Ok(Edn::Bool(true)),
+            "false" => return Ok(Edn::Bool(false)),
+            _ => {}
+        }
+
+        let numeric = token
+            .trim_start_matches(['-', '+'])
+            .starts_with(|c: char| c.is_ascii_digit());
+        if !numeric {
+            return Ok(Edn::Symbol(token));
+        }
+        let number = token.trim_end_matches(['N', 'M']);
+        if let Ok(value) = number.parse::<i64>() {
+            return Ok(Edn::Int(value));
+        }
+        if let Ok(value) = number.parse::<f64>() {
+            return Ok(Edn::Float(value));
+        }
+        self.pos = start;
+        Err(self.error(format!("Invalid number `{token}`")))
+    }
+}
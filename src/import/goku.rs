@@ -0,0 +1,1166 @@
+// `ankura import-edn`: turns a Goku karabiner.edn into an ankura.pkl.
+//
+// Rules are first built as Karabiner manipulators, the way Goku would write them, and then go
+// through the same conversion as `import-json`, so helpers are used wherever they fit.
+// Simlayer rules with a single key and action are collected into `SimLayer`s instead, when the
+// config's simlayer threshold is the one `SimLayer` uses.
+
+use super::edn::{self, Edn};
+use super::karabiner::{module, rule_items, settings, Summary};
+use super::patterns::{self, Construct};
+use super::pkl::{string, Expr, Item, Object};
+use crate::analysis::keycodes::{self, CodeKind, KEY_CODES};
+use crate::error::{KarabinerPklError, Result};
+use crate::karabiner::{
+    ComplexModificationParameters, Condition, DelayedAction, DeviceIdentifiers, FromEvent,
+    FromModifiers, InputSource, KeyOrder, KeyUpWhen, Manipulator, ManipulatorParameters, MouseKey,
+    Rule, SetVariable, SimultaneousOptions, ToEvent, VariableValue,
+};
+use std::collections::HashMap;
+use std::path::Path;
+
+type Conv<T> = std::result::Result<T, String>;
+
+const SECTIONS: &[&str] = &[
+    "profiles",
+    "templates",
+    "applications",
+    "devices",
+    "input-sources",
+    "simlayers",
+    "simlayer-threshold",
+    "layers",
+    "tos",
+    "froms",
+    "main",
+];
+
+// Goku's simlayer threshold when the config doesn't set `:simlayer-threshold`.
+const SIMLAYER_THRESHOLD: i64 = 250;
+
+const HYPER: [&str; 4] = ["left_command", "left_control", "left_option", "left_shift"];
+
+fn modifier(letter: char) -> Option<&'static str> {
+    Some(match letter {
+        'C' => "left_command",
+        'T' => "left_control",
+        'O' => "left_option",
+        'S' => "left_shift",
+        'F' => "fn",
+        'Q' => "right_command",
+        'W' => "right_control",
+        'E' => "right_option",
+        'R' => "right_shift",
+        'P' => "caps_lock",
+        _ => return None,
+    })
+}
+
+/// Converts `source`, the contents of `input`, into an ankura.pkl.
+pub fn generate(
+    source: &str,
+    profile_name: Option<&str>,
+    input: &Path,
+) -> Result<(String, Summary)> {
+    let config = edn::parse(source).map_err(|e| KarabinerPklError::InvalidEdn {
+        path: input.to_path_buf(),
+        line: e.line,
+        column: e.column,
+        message: e.message,
+    })?;
+    if config.as_map().is_none() {
+        return Err(KarabinerPklError::InvalidEdn {
+            path: input.to_path_buf(),
+            line: 1,
+            column: 1,
+            message: "A Goku config is a map, e.g. {:main [...]}".to_string(),
+        });
+    }
+
+    let mut summary = Summary::default();
+    let goku = Goku::read(&config, &mut summary);
+    let profiles = profiles(&config, profile_name, input)?;
+
+    let mut module = module("import-edn", input);
+    let rules = goku.rules(config.get("main"), &mut summary);
+
+    if let [(name, parameters)] = profiles.as_slice() {
+        module.set("name", string(name));
+        module.set("rules", Expr::List(rules));
+        module.set("settings", settings(parameters));
+    } else {
+        module.set("rules", Expr::List(rules));
+        let mut named = Object::amend();
+        for (name, parameters) in &profiles {
+            named = named.entry(name, Object::amend().prop("settings", settings(parameters)));
+        }
+        module.set("namedProfiles", named);
+    }
+
+    Ok((module.render(), summary))
+}
+
+// Profile names with their `:sim`, `:delay`, `:alone` and `:held`.
+fn profiles(
+    config: &Edn,
+    profile_name: Option<&str>,
+    input: &Path,
+) -> Result<Vec<(String, ComplexModificationParameters)>> {
+    let mut profiles = Vec::new();
+    for (name, profile) in config
+        .get("profiles")
+        .and_then(Edn::as_map)
+        .unwrap_or_default()
+    {
+        let Some(name) = name.as_keyword() else {
+            continue;
+        };
+        let int = |key| match profile.get(key) {
+            Some(Edn::Int(value)) => Some(*value),
+            _ => None,
+        };
+        profiles.push((
+            name.to_string(),
+            ComplexModificationParameters {
+                simultaneous_threshold_milliseconds: int("sim"),
+                to_delayed_action_delay_milliseconds: int("delay"),
+                to_if_alone_timeout_milliseconds: int("alone"),
+                to_if_held_down_threshold_milliseconds: int("held"),
+                ..Default::default()
+            },
+        ));
+    }
+    if profiles.is_empty() {
+        profiles.push(("Default".to_string(), Default::default()));
+    }
+
+    match profile_name {
+        None => Ok(profiles),
+        Some(name) => {
+            let available: Vec<String> = profiles.iter().map(|(name, _)| name.clone()).collect();
+            profiles
+                .into_iter()
+                .find(|(profile, _)| profile == name)
+                .map(|profile| vec![profile])
+                .ok_or_else(|| KarabinerPklError::ValidationError {
+                    message: format!(
+                        "Profile \"{name}\" is not in {}; available profiles: {}",
+                        input.display(),
+                        available.join(", ")
+                    ),
+                })
+        }
+    }
+}
+
+struct SimLayerDef {
+    key: String,
+}
+
+struct LayerDef {
+    key: String,
+    alone: Vec<ToEvent>,
+}
+
+// The named things `:main` refers to.
+#[derive(Default)]
+struct Goku {
+    templates: HashMap<String, String>,
+    applications: HashMap<String, Vec<String>>,
+    devices: HashMap<String, Vec<DeviceIdentifiers>>,
+    input_sources: HashMap<String, InputSource>,
+    simlayers: Vec<(String, SimLayerDef)>,
+    layers: Vec<(String, LayerDef)>,
+    tos: HashMap<String, Vec<ToEvent>>,
+    froms: HashMap<String, FromEvent>,
+    simlayer_threshold: i64,
+}
+
+// Where a group's manipulators, or a simlayer's collected entries, go in `rules`.
+enum Entry {
+    Rule(Rule),
+    SimLayer(String),
+}
+
+impl Goku {
+    fn read(config: &Edn, summary: &mut Summary) -> Self {
+        let mut goku = Goku {
+            simlayer_threshold: SIMLAYER_THRESHOLD,
+            ..Default::default()
+        };
+
+        for (key, _) in config.as_map().unwrap_or_default() {
+            let known = key.as_keyword().is_some_and(|key| SECTIONS.contains(&key));
+            if !known {
+                summary.skipped.push(format!("{key}: not supported"));
+            }
+        }
+        if let Some(Edn::Int(threshold)) = config.get("simlayer-threshold") {
+            goku.simlayer_threshold = *threshold;
+        }
+
+        for (name, value) in section(config, "templates") {
+            match value {
+                Edn::String(template) => {
+                    goku.templates.insert(name, template.clone());
+                }
+                _ => summary.skipped.push(format!(
+                    ":templates :{name}: only string templates are supported"
+                )),
+            }
+        }
+        for (name, value) in section(config, "applications") {
+            match strings(value) {
+                Some(bundles) => {
+                    goku.applications.insert(name, bundles);
+                }
+                None => summary.skipped.push(format!(
+                    ":applications :{name}: expected a vector of bundle ids"
+                )),
+            }
+        }
+        for (name, value) in section(config, "devices") {
+            match devices(value) {
+                Ok(identifiers) => {
+                    goku.devices.insert(name, identifiers);
+                }
+                Err(reason) => summary.skipped.push(format!(":devices :{name}: {reason}")),
+            }
+        }
+        for (name, value) in section(config, "input-sources") {
+            match input_source(value) {
+                Ok(source) => {
+                    goku.input_sources.insert(name, source);
+                }
+                Err(reason) => summary
+                    .skipped
+                    .push(format!(":input-sources :{name}: {reason}")),
+            }
+        }
+        for (name, value) in section(config, "simlayers") {
+            match goku.simlayer(value) {
+                Ok(simlayer) => goku.simlayers.push((name, simlayer)),
+                Err(reason) => summary
+                    .skipped
+                    .push(format!(":simlayers :{name}: {reason}")),
+            }
+        }
+        // Before :layers and :tos, whose actions may use them.
+        for (name, value) in section(config, "froms") {
+            match goku.from(value) {
+                Ok(from) => {
+                    goku.froms.insert(name, from);
+                }
+                Err(reason) => summary.skipped.push(format!(":froms :{name}: {reason}")),
+            }
+        }
+        for (name, value) in section(config, "tos") {
+            match goku.to(value) {
+                Ok(to) => {
+                    goku.tos.insert(name, to);
+                }
+                Err(reason) => summary.skipped.push(format!(":tos :{name}: {reason}")),
+            }
+        }
+        for (name, value) in section(config, "layers") {
+            match goku.layer(value) {
+                Ok(layer) => goku.layers.push((name, layer)),
+                Err(reason) => summary.skipped.push(format!(":layers :{name}: {reason}")),
+            }
+        }
+        goku
+    }
+
+    fn simlayer(&self, value: &Edn) -> Conv<SimLayerDef> {
+        only_keys(value, &["key"])?;
+        let key = value
+            .get("key")
+            .and_then(Edn::as_keyword)
+            .ok_or("missing :key")?;
+        known_key(key)?;
+        Ok(SimLayerDef {
+            key: key.to_string(),
+        })
+    }
+
+    fn layer(&self, value: &Edn) -> Conv<LayerDef> {
+        only_keys(value, &["key", "alone"])?;
+        let key = value
+            .get("key")
+            .and_then(Edn::as_keyword)
+            .ok_or("missing :key")?;
+        known_key(key)?;
+        let alone = match value.get("alone") {
+            Some(alone) => self.to(alone)?,
+            None => vec![key_event(key, Vec::new())],
+        };
+        Ok(LayerDef {
+            key: key.to_string(),
+            alone,
+        })
+    }
+
+    fn is_simlayer(&self, name: &str) -> Option<&SimLayerDef> {
+        self.simlayers
+            .iter()
+            .find(|(simlayer, _)| simlayer == name)
+            .map(|(_, def)| def)
+    }
+
+    /// The `rules` list: one rule per `:main` group, plus the simlayers and layer triggers.
+    fn rules(&self, main: Option<&Edn>, summary: &mut Summary) -> Vec<Item> {
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut simlayer_maps: HashMap<String, Vec<(String, ToEvent)>> = HashMap::new();
+
+        for (name, layer) in &self.layers {
+            entries.push(Entry::Rule(Rule {
+                description: format!("{name} layer"),
+                manipulators: vec![layer_trigger(name, layer)],
+                extra: Default::default(),
+            }));
+        }
+
+        let groups = match main {
+            None => &[][..],
+            Some(main) => match main.as_seq() {
+                Some(groups) => groups,
+                None => {
+                    summary
+                        .skipped
+                        .push(":main: expected a vector of rule groups".to_string());
+                    &[]
+                }
+            },
+        };
+
+        for group in groups {
+            let description = match group.get("des") {
+                Some(Edn::String(des)) => des.clone(),
+                _ => String::new(),
+            };
+            let label = format!("{:?}", description);
+            let Some(rules) = group.get("rules").and_then(Edn::as_seq) else {
+                summary
+                    .skipped
+                    .push(format!("group {label}: no :rules vector"));
+                continue;
+            };
+
+            let mut manipulators = Vec::new();
+            let mut conditions: Vec<Edn> = Vec::new();
+            for rule in rules {
+                // A keyword, or `[:condi ...]`, sets the conditions for the rules after it.
+                if rule.as_keyword().is_some() {
+                    conditions = vec![rule.clone()];
+                    continue;
+                }
+                if let Some([Edn::Keyword(condi), rest @ ..]) = rule.as_seq() {
+                    if condi == "condi" {
+                        conditions = rest.to_vec();
+                        continue;
+                    }
+                }
+
+                let mapped = |layer: &str, key: &str| {
+                    simlayer_maps
+                        .get(layer)
+                        .is_some_and(|maps| maps.iter().any(|(mapped, _)| mapped == key))
+                };
+                match self.rule(rule, &conditions, &mapped) {
+                    Ok(Converted::SimLayer(layer, key, to)) => {
+                        let maps = simlayer_maps.entry(layer.clone()).or_default();
+                        if maps.is_empty() {
+                            entries.push(Entry::SimLayer(layer));
+                        }
+                        maps.push((key, *to));
+                    }
+                    Ok(Converted::Manipulators(converted)) => manipulators.extend(converted),
+                    Err(reason) => summary
+                        .skipped
+                        .push(format!("group {label}: {rule}: {reason}")),
+                }
+            }
+
+            if !manipulators.is_empty() {
+                entries.push(Entry::Rule(Rule {
+                    description,
+                    manipulators,
+                    extra: Default::default(),
+                }));
+            }
+        }
+
+        let mut items = Vec::new();
+        let mut pending: Vec<Rule> = Vec::new();
+        for entry in entries {
+            match entry {
+                Entry::Rule(rule) => pending.push(rule),
+                Entry::SimLayer(name) => {
+                    items.extend(rule_items(&std::mem::take(&mut pending), summary).0);
+                    let trigger = self.is_simlayer(&name).map_or("", |def| def.key.as_str());
+                    let maps = &simlayer_maps[&name];
+                    let entries: Vec<(&str, &ToEvent)> =
+                        maps.iter().map(|(key, to)| (key.as_str(), to)).collect();
+                    match patterns::sim_layer_object(
+                        trigger,
+                        &name,
+                        self.simlayer_threshold,
+                        &entries,
+                    ) {
+                        Some(layer) => {
+                            items.push(layer.into());
+                            *summary.constructs.entry(Construct::SimLayer).or_default() += 1;
+                            summary.rules += 1;
+                            summary.skipped.push(format!(
+                                ":simlayers :{name}: uninterrupted key down, which SimLayer's chord \
+                                 doesn't require"
+                            ));
+                        }
+                        None => summary
+                            .skipped
+                            .push(format!(":simlayers :{name}: an action can't be converted")),
+                    }
+                }
+            }
+        }
+        items.extend(rule_items(&pending, summary).0);
+        items
+    }
+
+    // `[from to conditions options]`, under the group's current `conditions`.
+    // `mapped` says whether a simlayer already has an entry for a key.
+    fn rule(
+        &self,
+        rule: &Edn,
+        group_conditions: &[Edn],
+        mapped: &dyn Fn(&str, &str) -> bool,
+    ) -> Conv<Converted> {
+        // A raw Karabiner manipulator, used as is.
+        if let Edn::Map(_) = rule {
+            let manipulator = rule
+                .to_json()
+                .and_then(|json| serde_json::from_value(json).ok())
+                .ok_or("not a Karabiner manipulator")?;
+            return Ok(Converted::Manipulators(vec![manipulator]));
+        }
+
+        let parts = rule
+            .as_seq()
+            .ok_or("expected a rule vector or a condition")?;
+        let (from, to, rule_conditions, options) = match parts {
+            [from, to] => (from, to, None, None),
+            [from, to, conditions] => (from, to, Some(conditions), None),
+            [from, to, conditions, options] => (from, to, Some(conditions), Some(options)),
+            _ => return Err("expected [from to conditions options]".to_string()),
+        };
+
+        let mut names = group_conditions.to_vec();
+        match rule_conditions {
+            None | Some(Edn::Nil) => {}
+            Some(Edn::Vector(items)) if !is_variable(items) => names.extend(items.iter().cloned()),
+            Some(condition) => names.push(condition.clone()),
+        }
+
+        let from_event = self.from(from)?;
+        let to_events = self.to(to)?;
+
+        // The simlayer this rule belongs to, if any; the rest are ordinary conditions.
+        let simlayer = names.iter().find_map(|name| {
+            let name = name.as_keyword()?;
+            self.is_simlayer(name).map(|def| (name.to_string(), def))
+        });
+        let others: Vec<Edn> = names
+            .iter()
+            .filter(|name| {
+                simlayer
+                    .as_ref()
+                    .is_none_or(|(simlayer, _)| name.as_keyword() != Some(simlayer.as_str()))
+            })
+            .cloned()
+            .collect();
+        let conditions = self.conditions(&others)?;
+
+        let mut manipulator = Manipulator {
+            from: from_event,
+            to: (!to_events.is_empty()).then_some(to_events),
+            ..Default::default()
+        };
+        if let Some(options) = options {
+            self.options(&mut manipulator, options)?;
+        }
+
+        let Some((name, def)) = simlayer else {
+            manipulator.conditions = (!conditions.is_empty()).then_some(conditions);
+            return Ok(Converted::Manipulators(vec![manipulator]));
+        };
+
+        let key = manipulator
+            .from
+            .key_code
+            .clone()
+            .ok_or("simlayer rules need a key")?;
+        let plain = same_keys(&manipulator.from)
+            && !mapped(&name, &key)
+            && conditions.is_empty()
+            && options.is_none()
+            && manipulator.to.as_ref().is_some_and(|to| to.len() == 1);
+        if plain {
+            let to = manipulator
+                .to
+                .as_ref()
+                .map(|to| to[0].clone())
+                .unwrap_or_default();
+            return Ok(Converted::SimLayer(name, key, Box::new(to)));
+        }
+
+        // What Goku writes for a simlayer rule: the chord that turns the layer on, and the key
+        // on its own while it's on.
+        let variable = |value| ToEvent {
+            set_variable: Some(SetVariable {
+                name: name.clone(),
+                value: Some(VariableValue::Int(value)),
+                extra: Default::default(),
+            }),
+            ..Default::default()
+        };
+        let chord = Manipulator {
+            parameters: Some(ManipulatorParameters {
+                simultaneous_threshold_milliseconds: Some(self.simlayer_threshold),
+                ..Default::default()
+            }),
+            from: FromEvent {
+                simultaneous: Some(vec![key_from(&def.key), key_from(&key)]),
+                modifiers: manipulator.from.modifiers.clone(),
+                simultaneous_options: Some(SimultaneousOptions {
+                    detect_key_down_uninterruptedly: Some(true),
+                    key_down_order: Some(KeyOrder::Strict),
+                    key_up_order: Some(KeyOrder::StrictInverse),
+                    key_up_when: Some(KeyUpWhen::Any),
+                    to_after_key_up: Some(vec![variable(0)]),
+                    extra: Default::default(),
+                }),
+                ..Default::default()
+            },
+            to: Some(
+                [variable(1)]
+                    .into_iter()
+                    .chain(manipulator.to.clone().unwrap_or_default())
+                    .collect(),
+            ),
+            conditions: (!conditions.is_empty()).then(|| conditions.clone()),
+            ..Default::default()
+        };
+        let mut layered = conditions;
+        layered.insert(0, variable_condition(&name, VariableValue::Int(1), true));
+        manipulator.conditions = Some(layered);
+        Ok(Converted::Manipulators(vec![chord, manipulator]))
+    }
+
+    fn from(&self, from: &Edn) -> Conv<FromEvent> {
+        match from {
+            Edn::Keyword(name) => {
+                if let Some(from) = self.froms.get(name) {
+                    return Ok(from.clone());
+                }
+                let (key, mandatory, optional) = key_with_modifiers(name)?;
+                Ok(FromEvent {
+                    key_code: Some(key),
+                    modifiers: from_modifiers(mandatory, optional),
+                    ..Default::default()
+                })
+            }
+            Edn::Vector(keys) => Ok(FromEvent {
+                simultaneous: Some(
+                    keys.iter()
+                        .map(|key| self.from(key))
+                        .collect::<Conv<Vec<_>>>()?,
+                ),
+                ..Default::default()
+            }),
+            Edn::Map(_) => {
+                only_keys(from, &["key", "ckey", "pkey", "any", "modi", "sim"])?;
+                let mut event = FromEvent::default();
+                if let Some(key) = from.get("key") {
+                    let (key, mandatory, optional) =
+                        key_with_modifiers(key.as_keyword().ok_or(":key must be a keyword")?)?;
+                    event.key_code = Some(key);
+                    event.modifiers = from_modifiers(mandatory, optional);
+                }
+                event.consumer_key_code = keyword(from, "ckey")?;
+                event.pointing_button = keyword(from, "pkey")?;
+                event.any = keyword(from, "any")?;
+                if let Some(modi) = from.get("modi") {
+                    event.modifiers = Some(self.modi(modi)?);
+                }
+                if let Some(sim) = from.get("sim") {
+                    event.simultaneous = self.from(sim)?.simultaneous;
+                }
+                Ok(event)
+            }
+            other => Err(format!("can't read {other} as a from key")),
+        }
+    }
+
+    fn modi(&self, modi: &Edn) -> Conv<FromModifiers> {
+        match modi {
+            Edn::Keyword(name) => Ok(FromModifiers {
+                mandatory: vec![name.clone()],
+                optional: Vec::new(),
+            }),
+            Edn::Vector(_) => Ok(FromModifiers {
+                mandatory: keywords(modi)?,
+                optional: Vec::new(),
+            }),
+            Edn::Map(_) => {
+                only_keys(modi, &["mandatory", "optional"])?;
+                Ok(FromModifiers {
+                    mandatory: modi
+                        .get("mandatory")
+                        .map(keywords)
+                        .transpose()?
+                        .unwrap_or_default(),
+                    optional: modi
+                        .get("optional")
+                        .map(keywords)
+                        .transpose()?
+                        .unwrap_or_default(),
+                })
+            }
+            other => Err(format!("can't read {other} as modifiers")),
+        }
+    }
+
+    fn to(&self, to: &Edn) -> Conv<Vec<ToEvent>> {
+        match to {
+            Edn::Nil => Ok(Vec::new()),
+            Edn::Keyword(name) => {
+                if let Some(events) = self.tos.get(name) {
+                    return Ok(events.clone());
+                }
+                let (key, mandatory, optional) = key_with_modifiers(name)?;
+                if !optional.is_empty() {
+                    return Err(format!(
+                        ":{name}: optional modifiers only apply to from keys"
+                    ));
+                }
+                Ok(vec![key_event(&key, mandatory)])
+            }
+            Edn::String(command) => Ok(vec![shell(command)]),
+            Edn::Vector(items) => match items.as_slice() {
+                [Edn::Keyword(template), args @ ..] if self.templates.contains_key(template) => {
+                    Ok(vec![shell(&self.template(template, args)?)])
+                }
+                items if is_variable(items) => Ok(vec![ToEvent {
+                    set_variable: Some(set_variable(items)?),
+                    ..Default::default()
+                }]),
+                items => items.iter().try_fold(Vec::new(), |mut events, item| {
+                    events.extend(self.to(item)?);
+                    Ok(events)
+                }),
+            },
+            Edn::Map(_) => self.to_map(to).map(|event| vec![event]),
+            other => Err(format!("can't read {other} as an action")),
+        }
+    }
+
+    fn to_map(&self, to: &Edn) -> Conv<ToEvent> {
+        only_keys(
+            to,
+            &[
+                "key",
+                "modi",
+                "ckey",
+                "pkey",
+                "shell",
+                "input",
+                "set",
+                "mkey",
+                "lazy",
+                "repeat",
+                "halt",
+                "hold_down_ms",
+            ],
+        )?;
+        let mut event = ToEvent::default();
+        if let Some(key) = to.get("key") {
+            let name = key.as_keyword().ok_or(":key must be a keyword")?;
+            let (key, mandatory, optional) = key_with_modifiers(name)?;
+            if !optional.is_empty() {
+                return Err(format!(
+                    ":{name}: optional modifiers only apply to from keys"
+                ));
+            }
+            event.key_code = Some(key);
+            event.modifiers = (!mandatory.is_empty()).then_some(mandatory);
+        }
+        if let Some(modi) = to.get("modi") {
+            let modi = self.modi(modi)?;
+            if !modi.optional.is_empty() {
+                return Err("optional modifiers only apply to from keys".to_string());
+            }
+            event.modifiers = Some(modi.mandatory);
+        }
+        event.consumer_key_code = keyword(to, "ckey")?;
+        event.pointing_button = keyword(to, "pkey")?;
+        if let Some(shell) = to.get("shell") {
+            event.shell_command = Some(match shell {
+                Edn::String(command) => command.clone(),
+                Edn::Vector(items) => match items.as_slice() {
+                    [Edn::Keyword(template), args @ ..] => self.template(template, args)?,
+                    _ => return Err(format!("can't read {shell} as a shell command")),
+                },
+                other => return Err(format!("can't read {other} as a shell command")),
+            });
+        }
+        if let Some(input) = to.get("input") {
+            let name = input
+                .as_keyword()
+                .ok_or(":input must name an input source")?;
+            let source = self
+                .input_sources
+                .get(name)
+                .ok_or_else(|| format!("unknown input source :{name}"))?;
+            event.select_input_source = Some(source.clone());
+        }
+        if let Some(set) = to.get("set") {
+            let items = set.as_seq().ok_or(":set takes [\"name\" value]")?;
+            event.set_variable = Some(set_variable(items)?);
+        }
+        if let Some(mkey) = to.get("mkey") {
+            event.mouse_key = Some(mouse_key(mkey)?);
+        }
+        event.lazy = flag(to, "lazy")?;
+        event.repeat = flag(to, "repeat")?;
+        event.halt = flag(to, "halt")?;
+        if let Some(ms) = to.get("hold_down_ms") {
+            match ms {
+                Edn::Int(ms) => event.hold_down_milliseconds = Some(*ms),
+                other => return Err(format!(":hold_down_ms must be a number, not {other}")),
+            }
+        }
+        Ok(event)
+    }
+
+    // Goku templates are format strings; each `%s` takes the next argument.
+    fn template(&self, name: &str, args: &[Edn]) -> Conv<String> {
+        let template = &self.templates[name];
+        let mut pieces = template.split("%s");
+        let mut command = pieces.next().unwrap_or_default().to_string();
+        let mut args = args.iter();
+        for piece in pieces {
+            let arg = args
+                .next()
+                .ok_or_else(|| format!("template :{name} needs more arguments"))?;
+            match arg {
+                Edn::String(value) | Edn::Keyword(value) | Edn::Symbol(value) => {
+                    command.push_str(value)
+                }
+                Edn::Int(value) => command.push_str(&value.to_string()),
+                Edn::Float(value) => command.push_str(&value.to_string()),
+                other => return Err(format!("can't use {other} as a template argument")),
+            }
+            command.push_str(piece);
+        }
+        if args.next().is_some() {
+            return Err(format!("too many arguments for template :{name}"));
+        }
+        Ok(command)
+    }
+
+    fn conditions(&self, names: &[Edn]) -> Conv<Vec<Condition>> {
+        let mut conditions: Vec<Condition> = Vec::new();
+        for name in names {
+            if let Some(items) = name.as_seq() {
+                let variable = set_variable(items)?;
+                let value = variable.value.unwrap_or(VariableValue::Int(1));
+                conditions.push(variable_condition(&variable.name, value, true));
+                continue;
+            }
+            let keyword = name
+                .as_keyword()
+                .ok_or_else(|| format!("can't read {name} as a condition"))?;
+            let (name, positive) = match keyword.strip_prefix('!') {
+                Some(name) => (name, false),
+                None => (keyword, true),
+            };
+            let suffix = if positive { "if" } else { "unless" };
+
+            // Goku folds every application (or device) condition of a rule into one.
+            let mut merge = |kind: &str, update: &dyn Fn(&mut Condition)| {
+                let condition_type = format!("{kind}_{suffix}");
+                match conditions
+                    .iter_mut()
+                    .find(|condition| condition.condition_type == condition_type)
+                {
+                    Some(condition) => update(condition),
+                    None => {
+                        let mut condition = Condition {
+                            condition_type,
+                            ..Default::default()
+                        };
+                        update(&mut condition);
+                        conditions.push(condition);
+                    }
+                }
+            };
+
+            if let Some(bundles) = self.applications.get(name) {
+                merge("frontmost_application", &|condition| {
+                    condition
+                        .bundle_identifiers
+                        .get_or_insert_with(Vec::new)
+                        .extend(bundles.iter().cloned())
+                });
+            } else if let Some(identifiers) = self.devices.get(name) {
+                merge("device", &|condition| {
+                    condition
+                        .identifiers
+                        .get_or_insert_with(Vec::new)
+                        .extend(identifiers.iter().cloned())
+                });
+            } else if let Some(source) = self.input_sources.get(name) {
+                merge("input_source", &|condition| {
+                    condition
+                        .input_sources
+                        .get_or_insert_with(Vec::new)
+                        .push(source.clone())
+                });
+            } else {
+                // Anything else is a variable, which is what layers and simlayers set.
+                conditions.push(variable_condition(name, VariableValue::Int(1), positive));
+            }
+        }
+        Ok(conditions)
+    }
+
+    fn options(&self, manipulator: &mut Manipulator, options: &Edn) -> Conv<()> {
+        only_keys(options, &["alone", "held", "afterup", "delayed", "params"])?;
+        if let Some(alone) = options.get("alone") {
+            manipulator.to_if_alone = Some(self.to(alone)?);
+        }
+        if let Some(held) = options.get("held") {
+            manipulator.to_if_held_down = Some(self.to(held)?);
+        }
+        if let Some(afterup) = options.get("afterup") {
+            manipulator.to_after_key_up = Some(self.to(afterup)?);
+        }
+        if let Some(delayed) = options.get("delayed") {
+            only_keys(delayed, &["invoked", "canceled"])?;
+            let events = |key| delayed.get(key).map_or(Ok(Vec::new()), |to| self.to(to));
+            manipulator.to_delayed_action = Some(DelayedAction {
+                to_if_invoked: events("invoked")?,
+                to_if_canceled: events("canceled")?,
+                extra: Default::default(),
+            });
+        }
+        if let Some(params) = options.get("params") {
+            only_keys(params, &["sim", "delay", "alone", "held"])?;
+            let int = |key| match params.get(key) {
+                None => Ok(None),
+                Some(Edn::Int(value)) => Ok(Some(*value)),
+                Some(other) => Err(format!(":params :{key} must be a number, not {other}")),
+            };
+            manipulator.parameters = Some(ManipulatorParameters {
+                simultaneous_threshold_milliseconds: int("sim")?,
+                to_delayed_action_delay_milliseconds: int("delay")?,
+                to_if_alone_timeout_milliseconds: int("alone")?,
+                to_if_held_down_threshold_milliseconds: int("held")?,
+                ..Default::default()
+            });
+        }
+        Ok(())
+    }
+}
+
+enum Converted {
+    /// A `[key action]` under a simlayer alone, for its `SimLayer`.
+    SimLayer(String, String, Box<ToEvent>),
+    Manipulators(Vec<Manipulator>),
+}
+
+// What Goku writes for a layer: holding the key sets the variable, tapping it sends `alone`.
+fn layer_trigger(name: &str, layer: &LayerDef) -> Manipulator {
+    let variable = |value| ToEvent {
+        set_variable: Some(SetVariable {
+            name: name.to_string(),
+            value: Some(VariableValue::Int(value)),
+            extra: Default::default(),
+        }),
+        ..Default::default()
+    };
+    Manipulator {
+        from: FromEvent {
+            key_code: Some(layer.key.clone()),
+            modifiers: from_modifiers(Vec::new(), vec!["any".to_string()]),
+            ..Default::default()
+        },
+        to: Some(vec![variable(1)]),
+        to_after_key_up: Some(vec![variable(0)]),
+        to_if_alone: Some(layer.alone.clone()),
+        ..Default::default()
+    }
+}
+
+/// Splits Goku's modifier prefixes off a key: `!CTa` is a with ⌘⌃, `##a` with any
+/// modifiers optional, `!!a` with all four.
+fn key_with_modifiers(name: &str) -> Conv<(String, Vec<String>, Vec<String>)> {
+    let mut mandatory = Vec::new();
+    let mut optional = Vec::new();
+    let mut rest = name;
+    loop {
+        if let Some(after) = rest.strip_prefix("!!") {
+            mandatory.extend(HYPER.map(String::from));
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("##") {
+            optional.push("any".to_string());
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('!') {
+            rest = modifier_letters(after, &mut mandatory);
+        } else if let Some(after) = rest.strip_prefix('#') {
+            rest = modifier_letters(after, &mut optional);
+        } else {
+            break;
+        }
+    }
+    if rest.is_empty() {
+        return Err(format!(":{name} has no key"));
+    }
+    known_key(rest)?;
+    Ok((rest.to_string(), mandatory, optional))
+}
+
+// Goku hands keyword names to Karabiner as they are, so a name Karabiner doesn't know would
+// become a rule that never fires.
+fn known_key(key: &str) -> Conv<()> {
+    if KEY_CODES.contains(&key) {
+        return Ok(());
+    }
+
+    let suggestions = keycodes::suggest(CodeKind::KeyCode, key);
+    if suggestions.is_empty() {
+        return Err(format!("unknown key_code `{key}`"));
+    }
+    let names: Vec<String> = suggestions.iter().map(|s| format!("`{s}`")).collect();
+    Err(format!(
+        "unknown key_code `{key}`, did you mean {}?",
+        names.join(" or ")
+    ))
+}
+
+fn modifier_letters<'a>(letters: &'a str, modifiers: &mut Vec<String>) -> &'a str {
+    let mut rest = letters;
+    while let Some(letter) = rest.chars().next() {
+        match modifier(letter) {
+            Some(name) => {
+                modifiers.push(name.to_string());
+                rest = &rest[letter.len_utf8()..];
+            }
+            None => break,
+        }
+    }
+    rest
+}
+
+fn from_modifiers(mandatory: Vec<String>, optional: Vec<String>) -> Option<FromModifiers> {
+    (!mandatory.is_empty() || !optional.is_empty()).then_some(FromModifiers {
+        mandatory,
+        optional,
+    })
+}
+
+fn key_event(key: &str, modifiers: Vec<String>) -> ToEvent {
+    ToEvent {
+        key_code: Some(key.to_string()),
+        modifiers: (!modifiers.is_empty()).then_some(modifiers),
+        ..Default::default()
+    }
+}
+
+fn key_from(key: &str) -> FromEvent {
+    FromEvent {
+        key_code: Some(key.to_string()),
+        ..Default::default()
+    }
+}
+
+// Whether `from` is only a key code, as simlayer rules need.
+fn same_keys(from: &FromEvent) -> bool {
+    from.key_code
+        .as_deref()
+        .is_some_and(|key| *from == key_from(key))
+}
+
+fn shell(command: &str) -> ToEvent {
+    ToEvent {
+        shell_command: Some(command.to_string()),
+        ..Default::default()
+    }
+}
+
+fn variable_condition(name: &str, value: VariableValue, positive: bool) -> Condition {
+    Condition {
+        condition_type: if positive {
+            "variable_if"
+        } else {
+            "variable_unless"
+        }
+        .to_string(),
+        name: Some(name.to_string()),
+        value: Some(value),
+        ..Default::default()
+    }
+}
+
+// `["name" value]`, Goku's way of writing a variable.
+fn is_variable(items: &[Edn]) -> bool {
+    matches!(items, [Edn::String(_), _])
+}
+
+fn set_variable(items: &[Edn]) -> Conv<SetVariable> {
+    let [Edn::String(name), value] = items else {
+        return Err("expected [\"name\" value]".to_string());
+    };
+    let value = match value {
+        Edn::Int(value) => VariableValue::Int(*value),
+        Edn::Bool(value) => VariableValue::Bool(*value),
+        Edn::String(value) | Edn::Keyword(value) => VariableValue::String(value.clone()),
+        other => return Err(format!("can't use {other} as a variable value")),
+    };
+    Ok(SetVariable {
+        name: name.clone(),
+        value: Some(value),
+        extra: Default::default(),
+    })
+}
+
+fn devices(value: &Edn) -> Conv<Vec<DeviceIdentifiers>> {
+    let items = value.as_seq().ok_or("expected a vector of identifiers")?;
+    items
+        .iter()
+        .map(|item| {
+            only_keys(
+                item,
+                &[
+                    "vendor_id",
+                    "product_id",
+                    "is_keyboard",
+                    "is_pointing_device",
+                ],
+            )?;
+            let int = |key| match item.get(key) {
+                Some(Edn::Int(value)) => Some(*value),
+                _ => None,
+            };
+            Ok(DeviceIdentifiers {
+                vendor_id: int("vendor_id"),
+                product_id: int("product_id"),
+                is_keyboard: flag(item, "is_keyboard")?,
+                is_pointing_device: flag(item, "is_pointing_device")?,
+                extra: Default::default(),
+            })
+        })
+        .collect()
+}
+
+fn input_source(value: &Edn) -> Conv<InputSource> {
+    only_keys(value, &["language", "input_source_id", "input_mode_id"])?;
+    let text = |key| match value.get(key) {
+        Some(Edn::String(text)) => Some(text.clone()),
+        _ => None,
+    };
+    Ok(InputSource {
+        language: text("language"),
+        input_source_id: text("input_source_id"),
+        input_mode_id: text("input_mode_id"),
+        ..Default::default()
+    })
+}
+
+fn mouse_key(value: &Edn) -> Conv<MouseKey> {
+    only_keys(value, &["x", "y", "vwheel", "hwheel", "speed"])?;
+    let int = |key| match value.get(key) {
+        None => Ok(None),
+        Some(Edn::Int(value)) => Ok(Some(*value)),
+        Some(other) => Err(format!(":mkey :{key} must be a number, not {other}")),
+    };
+    Ok(MouseKey {
+        x: int("x")?,
+        y: int("y")?,
+        vertical_wheel: int("vwheel")?,
+        horizontal_wheel: int("hwheel")?,
+        speed_multiplier: match value.get("speed") {
+            None => None,
+            Some(Edn::Float(speed)) => Some(*speed),
+            Some(Edn::Int(speed)) => Some(*speed as f64),
+            Some(other) => return Err(format!(":mkey :speed must be a number, not {other}")),
+        },
+        ..Default::default()
+    })
+}
+
+// The entries of a top-level `{:name value}` section, by name.
+fn section<'a>(config: &'a Edn, name: &str) -> Vec<(String, &'a Edn)> {
+    config
+        .get(name)
+        .and_then(Edn::as_map)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|(key, value)| Some((key.as_keyword()?.to_string(), value)))
+        .collect()
+}
+
+fn only_keys(map: &Edn, allowed: &[&str]) -> Conv<()> {
+    let entries = map
+        .as_map()
+        .ok_or_else(|| format!("expected a map, not {map}"))?;
+    match entries
+        .iter()
+        .find(|(key, _)| !key.as_keyword().is_some_and(|key| allowed.contains(&key)))
+    {
+        Some((key, _)) => Err(format!("{key} is not supported")),
+        None => Ok(()),
+    }
+}
+
+fn keyword(map: &Edn, key: &str) -> Conv<Option<String>> {
+    match map.get(key) {
+        None => Ok(None),
+        Some(Edn::Keyword(name)) => Ok(Some(name.clone())),
+        Some(other) => Err(format!(":{key} must be a keyword, not {other}")),
+    }
+}
+
+fn keywords(value: &Edn) -> Conv<Vec<String>> {
+    match value {
+        Edn::Keyword(name) => Ok(vec![name.clone()]),
+        _ => value
+            .as_seq()
+            .and_then(|items| {
+                items
+                    .iter()
+                    .map(|item| item.as_keyword().map(String::from))
+                    .collect()
+            })
+            .ok_or_else(|| format!("expected keywords, not {value}")),
+    }
+}
+
+fn flag(map: &Edn, key: &str) -> Conv<Option<bool>> {
+    match map.get(key) {
+        None => Ok(None),
+        Some(Edn::Bool(value)) => Ok(Some(*value)),
+        Some(other) => Err(format!(":{key} must be true or false, not {other}")),
+    }
+}
+
+fn strings(value: &Edn) -> Option<Vec<String>> {
+    value
+        .as_seq()?
+        .iter()
+        .map(|item| match item {
+            Edn::String(text) => Some(text.clone()),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,952 @@
+// `ankura import-json`: turns a karabiner.json back into an ankura.pkl.
+
+use super::patterns::{self, Construct};
+use super::pkl::{
+    boolean, float, int, list, null, quote, string, strings, Expr, Item, Module, Object,
+};
+use crate::compiler::Compiler;
+use crate::diff::{diff_configs, ConfigDiff};
+use crate::error::{KarabinerPklError, Result};
+use crate::karabiner::{
+    ComplexModificationParameters, ComplexModifications, Condition, Config, DelayedAction, Device,
+    DeviceIdentifiers, FromEvent, InputSource, Manipulator, ManipulatorParameters, ManipulatorType,
+    MouseKey, Profile, Rule, SetVariable, SimpleFrom, SimpleModification, SimpleTo,
+    SimultaneousOptions, ToEvent, VariableValue,
+};
+use crate::merge::MANAGED_RULE_KEY;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+const CONDITION_TYPES: &[&str] = &[
+    "frontmost_application_if",
+    "frontmost_application_unless",
+    "device_if",
+    "device_unless",
+    "keyboard_type_if",
+    "keyboard_type_unless",
+    "input_source_if",
+    "input_source_unless",
+    "variable_if",
+    "variable_unless",
+];
+
+const KEYBOARD_TYPES: &[&str] = &["ansi", "iso", "jis"];
+
+// What Karabiner-Elements uses when karabiner.json leaves a parameter out. config.pkl has
+// its own defaults, so the generated settings always spell all four out.
+const DEFAULT_PARAMETERS: [i64; 4] = [50, 500, 1000, 500];
+
+/// The generated module and what went into it.
+#[derive(Debug)]
+pub struct Generated {
+    pub source: String,
+    /// The profiles converted, holding only the rules that made it into the Pkl.
+    pub imported: Config,
+    pub summary: Summary,
+}
+
+impl Generated {
+    /// How `compiled`, the generated module's output, differs from what was imported.
+    pub fn check(&self, compiled: &Config) -> ConfigDiff {
+        let names: Vec<String> = self
+            .imported
+            .profiles
+            .iter()
+            .map(|profile| profile.name.clone())
+            .collect();
+        diff_configs(
+            &comparable(&self.imported, &names),
+            &comparable(compiled, &names),
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub rules: usize,
+    pub constructs: BTreeMap<Construct, usize>,
+    pub simple_modifications: usize,
+    pub devices: usize,
+    /// Everything left out of the Pkl, with the reason.
+    pub skipped: Vec<String>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let constructs: Vec<String> = self
+            .constructs
+            .iter()
+            .map(|(construct, count)| format!("{count} {}", construct.name()))
+            .collect();
+        if constructs.is_empty() {
+            writeln!(f, "  Rules: {}", self.rules)?;
+        } else {
+            writeln!(f, "  Rules: {} as {}", self.rules, constructs.join(", "))?;
+        }
+        writeln!(f, "  Simple modifications: {}", self.simple_modifications)?;
+        writeln!(f, "  Devices: {}", self.devices)?;
+
+        if !self.skipped.is_empty() {
+            writeln!(f, "\nNot converted:")?;
+            for skipped in &self.skipped {
+                writeln!(f, "  - {skipped}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Converts every profile of `config`, or only `profile_name`.
+pub fn generate(config: &Config, profile_name: Option<&str>, input: &Path) -> Result<Generated> {
+    let profiles: Vec<&Profile> = match profile_name {
+        Some(name) => vec![config.profile(name).ok_or_else(|| {
+            let available: Vec<&str> = config.profiles.iter().map(|p| p.name.as_str()).collect();
+            KarabinerPklError::ValidationError {
+                message: format!(
+                    "Profile \"{name}\" is not in {}; available profiles: {}",
+                    input.display(),
+                    available.join(", ")
+                ),
+            }
+        })?],
+        None => config.profiles.iter().collect(),
+    };
+    if profiles.is_empty() {
+        return Err(KarabinerPklError::ValidationError {
+            message: format!("{} has no profiles to import", input.display()),
+        });
+    }
+
+    let mut summary = Summary::default();
+    let mut module = module("import-json", input);
+
+    let mut imported = Config::default();
+    if let [profile] = profiles.as_slice() {
+        module.set("name", string(&profile.name));
+        let (properties, kept) = convert_profile(profile, &mut summary);
+        for (name, value) in properties {
+            module.set(name, value);
+        }
+        imported.profiles.push(kept);
+    } else {
+        let mut named = Object::amend();
+        for profile in &profiles {
+            let (properties, kept) = convert_profile(profile, &mut summary);
+            let mut object = Object::amend();
+            for (name, value) in properties {
+                object = object.prop(name, value);
+            }
+            named = named.entry(&profile.name, object);
+            imported.profiles.push(kept);
+        }
+        module.set("namedProfiles", named);
+    }
+
+    Ok(Generated {
+        source: module.render(),
+        imported,
+        summary,
+    })
+}
+
+/// An empty config extending the embedded config.pkl, noting where it came from.
+pub fn module(command: &str, input: &Path) -> Module {
+    let mut module = Module::extending(Compiler::lib_dir().join("config.pkl").to_string_lossy());
+    module.header(format!(
+        "Generated by `ankura {command}` from {}.",
+        input.display()
+    ));
+    module.set("keys", Object::new("Keys"));
+    module
+}
+
+// The config.pkl properties describing one profile, in the order they are written, and the
+// profile with only the rules they cover.
+fn convert_profile(
+    profile: &Profile,
+    summary: &mut Summary,
+) -> (Vec<(&'static str, Expr)>, Profile) {
+    let label = format!("profile {}", quote(&profile.name));
+    let mut left: Vec<String> = Vec::new();
+    if !profile.fn_function_keys.is_empty() {
+        left.push("fn_function_keys".to_string());
+    }
+    if profile.parameters.is_some() {
+        left.push("parameters".to_string());
+    }
+    left.extend(set_keys(&profile.extra));
+
+    let complex = profile.complex_modifications.clone().unwrap_or_default();
+    left.extend(set_keys(&complex.extra));
+    let parameters = complex.parameters.clone().unwrap_or_default();
+    left.extend(
+        set_keys(&parameters.extra)
+            .into_iter()
+            .map(|key| format!("complex_modifications.parameters.{key}")),
+    );
+    if !left.is_empty() {
+        summary.skipped.push(format!(
+            "{label}: {} (kept from karabiner.json by the merge policy on compile)",
+            left.join(", ")
+        ));
+    }
+
+    let (rules, kept) = rule_items(&complex.rules, summary);
+    let rules = Expr::List(rules);
+    let mut properties = vec![("rules", rules), ("settings", settings(&parameters).into())];
+
+    let simple = simple_modifications(&profile.simple_modifications, &label, summary);
+    summary.simple_modifications += simple.len();
+    if !simple.is_empty() {
+        properties.push(("simpleModifications", list(simple)));
+    }
+
+    let mut devices = Vec::new();
+    for device in &profile.devices {
+        match convert_device(device, &label, summary) {
+            Ok(device) => {
+                summary.devices += 1;
+                devices.push(device);
+            }
+            Err(reason) => summary.skipped.push(format!(
+                "{label}: device {}: {reason}",
+                describe_device(&device.identifiers)
+            )),
+        }
+    }
+    if !devices.is_empty() {
+        properties.push(("devices", list(devices)));
+    }
+
+    let mut imported = profile.clone();
+    imported.complex_modifications = Some(ComplexModifications {
+        rules: kept,
+        ..complex
+    });
+    (properties, imported)
+}
+
+/// The `rules` entries for `rules`, using the library's helpers where they reproduce a rule,
+/// and the rules that made it in.
+pub fn rule_items(rules: &[Rule], summary: &mut Summary) -> (Vec<Item>, Vec<Rule>) {
+    let rules: Vec<Rule> = rules
+        .iter()
+        .cloned()
+        .map(|mut rule| {
+            rule.extra.remove(MANAGED_RULE_KEY);
+            rule
+        })
+        .collect();
+
+    let mut items: Vec<Item> = Vec::new();
+    let mut kept = Vec::new();
+    let mut index = 0;
+    while index < rules.len() {
+        if let Some((layer, count)) = patterns::layer(&rules[index..]) {
+            items.push(layer.into());
+            *summary.constructs.entry(Construct::Layer).or_default() += 1;
+            summary.rules += count;
+            kept.extend_from_slice(&rules[index..index + count]);
+            index += count;
+            continue;
+        }
+
+        let rule = &rules[index];
+        index += 1;
+        let converted = if let Some((construct, call)) = patterns::hyper_key(rule) {
+            Ok((construct, call))
+        } else if let Some(layer) = patterns::sim_layer(rule) {
+            Ok((Construct::SimLayer, layer))
+        } else if let Some(dual_use) = patterns::dual_use_rule(rule) {
+            Ok((Construct::DualUse, dual_use))
+        } else {
+            literal_rule(rule).map(|rule| (Construct::Literal, rule))
+        };
+
+        match converted {
+            Ok((construct, expr)) => {
+                items.push(expr.into());
+                *summary.constructs.entry(construct).or_default() += 1;
+                summary.rules += 1;
+                kept.push(rule.clone());
+            }
+            Err(reason) => {
+                let description = quote(&rule.description);
+                items.push(Item::comment(format!(
+                    "Not converted: {description} ({reason})"
+                )));
+                summary
+                    .skipped
+                    .push(format!("rule {description}: {reason}"));
+            }
+        }
+    }
+    (items, kept)
+}
+
+fn literal_rule(rule: &Rule) -> std::result::Result<Expr, String> {
+    unsupported(&rule.extra, "rule")?;
+    let mut manipulators = Vec::new();
+    for manipulator in &rule.manipulators {
+        let object = match patterns::dual_use(manipulator) {
+            Some(dual_use) => dual_use,
+            None => convert_manipulator(manipulator)?,
+        };
+        manipulators.push(Item {
+            comments: manipulator.description.iter().cloned().collect(),
+            expr: Some(object.into()),
+        });
+    }
+    Ok(Object::new("Rule")
+        .prop("description", string(&rule.description))
+        .prop("manipulators", Expr::List(manipulators))
+        .into())
+}
+
+fn convert_manipulator(manipulator: &Manipulator) -> std::result::Result<Object, String> {
+    unsupported(&manipulator.extra, "manipulator")?;
+    let mut object = Object::new("Manipulator");
+    if manipulator.manipulator_type != ManipulatorType::Basic {
+        object = object.prop("type", variant(&manipulator.manipulator_type));
+    }
+    object = object.prop("from", from_event(&manipulator.from)?);
+    if let Some(to) = &manipulator.to {
+        object = object.prop("to", to_events(to)?);
+    }
+    if let Some(to_if_alone) = &manipulator.to_if_alone {
+        object = object.prop("to_if_alone", to_events(to_if_alone)?);
+    }
+    behaviour(object, manipulator)
+}
+
+/// Adds what a manipulator sets besides `from`, `to` and `to_if_alone`.
+pub fn behaviour(
+    mut object: Object,
+    manipulator: &Manipulator,
+) -> std::result::Result<Object, String> {
+    if let Some(events) = &manipulator.to_if_held_down {
+        object = object.prop("to_if_held_down", to_events(events)?);
+    }
+    if let Some(events) = &manipulator.to_after_key_up {
+        object = object.prop("to_after_key_up", to_events(events)?);
+    }
+    if let Some(delayed) = &manipulator.to_delayed_action {
+        object = object.prop("to_delayed_action", delayed_action(delayed)?);
+    }
+    if let Some(conditions) = &manipulator.conditions {
+        let conditions = conditions
+            .iter()
+            .map(condition)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        object = object.prop("conditions", list(conditions));
+    }
+    if let Some(parameters) = &manipulator.parameters {
+        object = object.prop("parameters", manipulator_parameters(parameters)?);
+    }
+    Ok(object)
+}
+
+fn bare_key(event: &ToEvent) -> Option<&str> {
+    let code = event.key_code.as_deref()?;
+    let bare = ToEvent {
+        key_code: Some(code.to_string()),
+        ..Default::default()
+    };
+    same(event, &bare).then_some(code)
+}
+
+/// A `core.Action`: `keys.escape` or `"escape"` for a plain key, else a `ToEvent`.
+pub fn action(event: &ToEvent) -> std::result::Result<Expr, String> {
+    match bare_key(event) {
+        Some(code) => Ok(patterns::key(code).unwrap_or_else(|| string(code))),
+        None => to_event(event).map(Into::into),
+    }
+}
+
+/// A `karabiner.Event`, for the places a plain string isn't accepted.
+pub fn event(event: &ToEvent) -> std::result::Result<Expr, String> {
+    match bare_key(event).and_then(patterns::key) {
+        Some(key) => Ok(key),
+        None => to_event(event).map(Into::into),
+    }
+}
+
+fn to_events(events: &[ToEvent]) -> std::result::Result<Expr, String> {
+    let events = events
+        .iter()
+        .map(to_event)
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(list(events.into_iter().map(Expr::from)))
+}
+
+fn to_event(event: &ToEvent) -> std::result::Result<Object, String> {
+    unsupported(&event.extra, "to event")?;
+    if event.sticky_modifier.is_some() {
+        return Err("sticky_modifier is not supported".to_string());
+    }
+    if event.software_function.is_some() {
+        return Err("software_function is not supported".to_string());
+    }
+
+    let mut object = Object::new("ToEvent");
+    if let Some(code) = &event.key_code {
+        object = object.prop("key_code", string(code));
+    }
+    if let Some(code) = &event.consumer_key_code {
+        object = object.prop("consumer_key_code", string(code));
+    }
+    if let Some(button) = &event.pointing_button {
+        object = object.prop("pointing_button", string(button));
+    }
+    if let Some(modifiers) = &event.modifiers {
+        object = object.prop("modifiers", modifier_list(modifiers)?);
+    }
+    if let Some(command) = &event.shell_command {
+        object = object.prop("shell_command", string(command));
+    }
+    if let Some(source) = &event.select_input_source {
+        object = object.prop("select_input_source", input_source(source));
+    }
+    if let Some(variable) = &event.set_variable {
+        object = object.prop("set_variable", set_variable(variable)?);
+    }
+    if let Some(mouse_key) = &event.mouse_key {
+        object = object.prop("mouse_key", mouse(mouse_key));
+    }
+    for (name, flag) in [
+        ("lazy", event.lazy),
+        ("repeat", event.repeat),
+        ("halt", event.halt),
+    ] {
+        if let Some(flag) = flag {
+            object = object.prop(name, boolean(flag));
+        }
+    }
+    if let Some(milliseconds) = event.hold_down_milliseconds {
+        object = object.prop("hold_down_milliseconds", int(milliseconds));
+    }
+    Ok(object)
+}
+
+/// A literal `FromEvent`, with a plain list for `modifiers` when nothing is optional.
+pub fn from_event(event: &FromEvent) -> std::result::Result<Expr, String> {
+    unsupported(&event.extra, "from event")?;
+
+    let mut object = Object::new("FromEvent");
+    if let Some(code) = &event.key_code {
+        object = object.prop("key_code", string(code));
+    }
+    if let Some(code) = &event.consumer_key_code {
+        object = object.prop("consumer_key_code", string(code));
+    }
+    if let Some(button) = &event.pointing_button {
+        object = object.prop("pointing_button", string(button));
+    }
+    if let Some(any) = &event.any {
+        if !["key_code", "consumer_key_code", "pointing_button"].contains(&any.as_str()) {
+            return Err(format!("unknown `any` value \"{any}\""));
+        }
+        object = object.prop("any", string(any));
+    }
+    if let Some(modifiers) = &event.modifiers {
+        if modifiers.optional.is_empty() {
+            if !modifiers.mandatory.is_empty() {
+                object = object.prop("modifiers", modifier_list(&modifiers.mandatory)?);
+            }
+        } else {
+            let mut from_modifiers = Object::new("FromModifiers");
+            if !modifiers.mandatory.is_empty() {
+                from_modifiers =
+                    from_modifiers.prop("mandatory", modifier_list(&modifiers.mandatory)?);
+            }
+            from_modifiers = from_modifiers.prop("optional", modifier_list(&modifiers.optional)?);
+            object = object.prop("modifiers", from_modifiers);
+        }
+    }
+    if let Some(simultaneous) = &event.simultaneous {
+        let events = simultaneous
+            .iter()
+            .map(from_event)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        object = object.prop("simultaneous", list(events));
+    }
+    if let Some(options) = &event.simultaneous_options {
+        object = object.prop("simultaneous_options", simultaneous_options(options)?);
+    }
+    Ok(object.into())
+}
+
+fn modifier_list(modifiers: &[String]) -> std::result::Result<Expr, String> {
+    match modifiers
+        .iter()
+        .find(|modifier| !patterns::is_modifier(modifier))
+    {
+        Some(unknown) => Err(format!("unknown modifier \"{unknown}\"")),
+        None => Ok(strings(modifiers)),
+    }
+}
+
+// SimultaneousOptions fills in Karabiner's usual values, so the ones karabiner.json leaves
+// out are set to null to keep them out of the output.
+fn simultaneous_options(options: &SimultaneousOptions) -> std::result::Result<Object, String> {
+    unsupported(&options.extra, "simultaneous_options")?;
+    let or_null = |value: Option<Expr>| value.unwrap_or_else(null);
+    let mut object = Object::new("SimultaneousOptions")
+        .prop(
+            "detect_key_down_uninterruptedly",
+            or_null(options.detect_key_down_uninterruptedly.map(boolean)),
+        )
+        .prop(
+            "key_down_order",
+            or_null(options.key_down_order.as_ref().map(variant)),
+        )
+        .prop(
+            "key_up_order",
+            or_null(options.key_up_order.as_ref().map(variant)),
+        )
+        .prop(
+            "key_up_when",
+            or_null(options.key_up_when.as_ref().map(variant)),
+        );
+    if let Some(events) = &options.to_after_key_up {
+        object = object.prop("to_after_key_up", to_events(events)?);
+    }
+    Ok(object)
+}
+
+fn delayed_action(delayed: &DelayedAction) -> std::result::Result<Object, String> {
+    unsupported(&delayed.extra, "to_delayed_action")?;
+    Ok(Object::new("DelayedAction")
+        .prop("to_if_invoked", to_events(&delayed.to_if_invoked)?)
+        .prop("to_if_canceled", to_events(&delayed.to_if_canceled)?))
+}
+
+fn condition(condition: &Condition) -> std::result::Result<Expr, String> {
+    unsupported(&condition.extra, "condition")?;
+    if !CONDITION_TYPES.contains(&condition.condition_type.as_str()) {
+        return Err(format!(
+            "unknown condition type \"{}\"",
+            condition.condition_type
+        ));
+    }
+
+    let mut object = Object::new("Condition").prop("type", string(&condition.condition_type));
+    if let Some(bundles) = &condition.bundle_identifiers {
+        object = object.prop("bundle_identifiers", strings(bundles));
+    }
+    if let Some(paths) = &condition.file_paths {
+        object = object.prop("file_paths", strings(paths));
+    }
+    if let Some(identifiers) = &condition.identifiers {
+        let matchers = identifiers
+            .iter()
+            .map(device_matcher)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        object = object.prop("identifiers", list(matchers));
+    }
+    if let Some(sources) = &condition.input_sources {
+        object = object.prop("input_sources", list(sources.iter().map(input_source)));
+    }
+    if let Some(types) = &condition.keyboard_types {
+        if let Some(unknown) = types
+            .iter()
+            .find(|kind| !KEYBOARD_TYPES.contains(&kind.as_str()))
+        {
+            return Err(format!("unknown keyboard type \"{unknown}\""));
+        }
+        object = object.prop("keyboard_types", strings(types));
+    }
+    if let Some(name) = &condition.name {
+        object = object.prop("name", string(name));
+    }
+    if let Some(value) = &condition.value {
+        object = object.prop("value", variable_value(value));
+    }
+    Ok(object.into())
+}
+
+fn device_matcher(identifiers: &DeviceIdentifiers) -> std::result::Result<Expr, String> {
+    unsupported(&identifiers.extra, "device condition")?;
+    let mut object = Object::new("DeviceMatcher");
+    if let Some(id) = identifiers.vendor_id {
+        object = object.prop("vendor_id", int(id));
+    }
+    if let Some(id) = identifiers.product_id {
+        object = object.prop("product_id", int(id));
+    }
+    if let Some(flag) = identifiers.is_keyboard {
+        object = object.prop("is_keyboard", boolean(flag));
+    }
+    if let Some(flag) = identifiers.is_pointing_device {
+        object = object.prop("is_pointing_device", boolean(flag));
+    }
+    Ok(object.into())
+}
+
+fn input_source(source: &InputSource) -> Expr {
+    let mut object = Object::new("InputSource");
+    for (name, value) in [
+        ("language", &source.language),
+        ("input_source_id", &source.input_source_id),
+        ("input_mode_id", &source.input_mode_id),
+    ] {
+        if let Some(value) = value {
+            object = object.prop(name, string(value));
+        }
+    }
+    object.into()
+}
+
+fn set_variable(variable: &SetVariable) -> std::result::Result<Object, String> {
+    unsupported(&variable.extra, "set_variable")?;
+    let value = variable
+        .value
+        .as_ref()
+        .ok_or_else(|| format!("set_variable \"{}\" has no value", variable.name))?;
+    Ok(Object::new("SetVariable")
+        .prop("name", string(&variable.name))
+        .prop("value", variable_value(value)))
+}
+
+fn variable_value(value: &VariableValue) -> Expr {
+    match value {
+        VariableValue::Bool(flag) => boolean(*flag),
+        VariableValue::Int(number) => int(*number),
+        VariableValue::String(text) => string(text),
+    }
+}
+
+fn mouse(mouse_key: &MouseKey) -> Expr {
+    let mut object = Object::new("MouseKey");
+    for (name, value) in [
+        ("x", mouse_key.x),
+        ("y", mouse_key.y),
+        ("vertical_wheel", mouse_key.vertical_wheel),
+        ("horizontal_wheel", mouse_key.horizontal_wheel),
+    ] {
+        if let Some(value) = value {
+            object = object.prop(name, int(value));
+        }
+    }
+    if let Some(multiplier) = mouse_key.speed_multiplier {
+        object = object.prop("speed_multiplier", float(multiplier));
+    }
+    object.into()
+}
+
+fn manipulator_parameters(parameters: &ManipulatorParameters) -> std::result::Result<Expr, String> {
+    unsupported(&parameters.extra, "manipulator parameters")?;
+    let mut object = Object::new("ManipulatorParameters");
+    for (name, value) in [
+        (
+            "basic.simultaneous_threshold_milliseconds",
+            parameters.simultaneous_threshold_milliseconds,
+        ),
+        (
+            "basic.to_delayed_action_delay_milliseconds",
+            parameters.to_delayed_action_delay_milliseconds,
+        ),
+        (
+            "basic.to_if_alone_timeout_milliseconds",
+            parameters.to_if_alone_timeout_milliseconds,
+        ),
+        (
+            "basic.to_if_held_down_threshold_milliseconds",
+            parameters.to_if_held_down_threshold_milliseconds,
+        ),
+        (
+            "mouse_motion_to_scroll.speed",
+            parameters.mouse_motion_to_scroll_speed,
+        ),
+    ] {
+        if let Some(value) = value {
+            object = object.prop(name, int(value));
+        }
+    }
+    Ok(object.into())
+}
+
+/// `settings`, with Karabiner's defaults for anything `parameters` leaves out.
+pub fn settings(parameters: &ComplexModificationParameters) -> Object {
+    let parameters = settings_values(parameters);
+    let mut object = Object::new("ComplexModificationParameters");
+    for (name, value) in [
+        (
+            "basic.simultaneous_threshold_milliseconds",
+            parameters.simultaneous_threshold_milliseconds,
+        ),
+        (
+            "basic.to_delayed_action_delay_milliseconds",
+            parameters.to_delayed_action_delay_milliseconds,
+        ),
+        (
+            "basic.to_if_alone_timeout_milliseconds",
+            parameters.to_if_alone_timeout_milliseconds,
+        ),
+        (
+            "basic.to_if_held_down_threshold_milliseconds",
+            parameters.to_if_held_down_threshold_milliseconds,
+        ),
+    ] {
+        object = object.prop(name, int(value.unwrap_or_default()));
+    }
+    object
+}
+
+// config.pkl only models key-to-key simple modifications.
+fn key_to_key(modification: &SimpleModification) -> Option<(&str, &str)> {
+    let from = match &modification.from {
+        SimpleFrom::KeyCode(code) => code.as_str(),
+        SimpleFrom::Event(event) => {
+            let code = event.key_code.as_deref()?;
+            let bare = FromEvent {
+                key_code: Some(code.to_string()),
+                ..Default::default()
+            };
+            same(event.as_ref(), &bare).then_some(code)?
+        }
+    };
+    let to = match &modification.to {
+        SimpleTo::KeyCode(code) => code.as_str(),
+        SimpleTo::Event(event) => bare_key(event)?,
+        SimpleTo::Events(events) => match events.as_slice() {
+            [event] => bare_key(event)?,
+            _ => return None,
+        },
+    };
+    Some((from, to))
+}
+
+fn simple_modifications(
+    modifications: &[SimpleModification],
+    label: &str,
+    summary: &mut Summary,
+) -> Vec<Expr> {
+    let mut converted = Vec::new();
+    for modification in modifications {
+        match key_to_key(modification) {
+            Some((from, to)) => converted.push(
+                Object::new("SimpleModification")
+                    .prop("from", string(from))
+                    .prop("to", string(to))
+                    .into(),
+            ),
+            None => {
+                summary.skipped.push(format!(
+                    "{label}: simple modification {}: only key-to-key modifications are supported",
+                    serde_json::to_string(modification).unwrap_or_default()
+                ));
+            }
+        }
+    }
+    converted
+}
+
+// karabiner.json lists every device Karabiner has seen; the merge matches them by identifiers,
+// where a missing flag and `false` are the same.
+fn convert_device(
+    device: &Device,
+    label: &str,
+    summary: &mut Summary,
+) -> std::result::Result<Expr, String> {
+    let identifiers = &device.identifiers;
+    let (Some(vendor_id), Some(product_id)) = (identifiers.vendor_id, identifiers.product_id)
+    else {
+        return Err("no vendor_id and product_id to match on".to_string());
+    };
+    if let Some(key) = set_keys(&identifiers.extra).first() {
+        return Err(format!(
+            "matched on `{key}`, which config.pkl has no field for"
+        ));
+    }
+
+    let mut matcher = Object::new("DeviceIdentifiers")
+        .prop("vendor_id", int(vendor_id))
+        .prop("product_id", int(product_id));
+    if identifiers.is_keyboard != Some(true) {
+        matcher = matcher.prop("is_keyboard", boolean(false));
+    }
+    if identifiers.is_pointing_device == Some(true) {
+        matcher = matcher.prop("is_pointing_device", boolean(true));
+    }
+
+    let mut object = Object::new("Device").prop("identifiers", matcher);
+    for (name, flag) in [
+        ("ignore", device.ignore),
+        (
+            "disable_built_in_keyboard_if_exists",
+            device.disable_built_in_keyboard_if_exists,
+        ),
+        ("manipulate_caps_lock_led", device.manipulate_caps_lock_led),
+    ] {
+        if flag == Some(true) {
+            object = object.prop(name, boolean(true));
+        }
+    }
+
+    let device_label = format!("{label}: device {}", describe_device(identifiers));
+    let simple = simple_modifications(&device.simple_modifications, &device_label, summary);
+    summary.simple_modifications += simple.len();
+    if !simple.is_empty() {
+        object = object.prop("simple_modifications", list(simple));
+    }
+
+    let mut left = set_keys(&device.extra);
+    if !device.fn_function_keys.is_empty() {
+        left.insert(0, "fn_function_keys".to_string());
+    }
+    if !left.is_empty() {
+        summary.skipped.push(format!(
+            "{device_label}: {} (kept from karabiner.json by the merge policy on compile)",
+            left.join(", ")
+        ));
+    }
+    Ok(object.into())
+}
+
+fn describe_device(identifiers: &DeviceIdentifiers) -> String {
+    match (identifiers.vendor_id, identifiers.product_id) {
+        (Some(vendor), Some(product)) => format!("{vendor}:{product}"),
+        _ => "without ids".to_string(),
+    }
+}
+
+// Keys karabiner.json sets that the Rust model has no field for; a null is the same as
+// leaving the key out.
+fn set_keys(extra: &Map<String, Value>) -> Vec<String> {
+    extra
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+fn unsupported(extra: &Map<String, Value>, what: &str) -> std::result::Result<(), String> {
+    match set_keys(extra).first() {
+        Some(key) => Err(format!("`{key}` in a {what} is not supported")),
+        None => Ok(()),
+    }
+}
+
+// Serde's name for an enum variant, e.g. `strict_inverse`.
+fn variant<T: Serialize>(value: &T) -> Expr {
+    match serde_json::to_value(value) {
+        Ok(Value::String(name)) => string(&name),
+        _ => null(),
+    }
+}
+
+/// Whether two values serialize to the same JSON once nulls are dropped.
+pub fn same<A: Serialize, B: Serialize>(a: &A, b: &B) -> bool {
+    match (serde_json::to_value(a), serde_json::to_value(b)) {
+        (Ok(mut a), Ok(mut b)) => {
+            strip_nulls(&mut a);
+            strip_nulls(&mut b);
+            a == b
+        }
+        _ => false,
+    }
+}
+
+fn strip_nulls(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|_, value| !value.is_null());
+            map.values_mut().for_each(strip_nulls);
+        }
+        Value::Array(values) => values.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+fn normalized<T: Serialize + DeserializeOwned + Clone>(value: &T) -> T {
+    serde_json::to_value(value)
+        .ok()
+        .map(|mut json| {
+            strip_nulls(&mut json);
+            json
+        })
+        .and_then(|json| serde_json::from_value(json).ok())
+        .unwrap_or_else(|| value.clone())
+}
+
+// The part of `config` an import carries over, for `--verify` to compare: rules without the
+// ankura marker or manipulator descriptions, parameters with Karabiner's defaults filled in,
+// and key-to-key simple modifications.
+fn comparable(config: &Config, profiles: &[String]) -> Config {
+    let profiles = profiles
+        .iter()
+        .filter_map(|name| config.profile(name))
+        .map(|profile| {
+            let complex = profile.complex_modifications.clone().unwrap_or_default();
+            let parameters = complex.parameters.unwrap_or_default();
+            let rules = complex
+                .rules
+                .iter()
+                .map(|rule| {
+                    let mut rule = normalized(rule);
+                    rule.extra.remove(MANAGED_RULE_KEY);
+                    for manipulator in &mut rule.manipulators {
+                        manipulator.description = None;
+                    }
+                    rule
+                })
+                .collect();
+            let simple_modifications = profile
+                .simple_modifications
+                .iter()
+                .filter_map(key_to_key)
+                .map(|(from, to)| SimpleModification {
+                    from: SimpleFrom::KeyCode(from.to_string()),
+                    to: SimpleTo::KeyCode(to.to_string()),
+                    extra: Map::new(),
+                })
+                .collect();
+
+            Profile {
+                name: profile.name.clone(),
+                simple_modifications,
+                complex_modifications: Some(ComplexModifications {
+                    parameters: Some(settings_values(&parameters)),
+                    rules,
+                    extra: Map::new(),
+                }),
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    Config {
+        profiles,
+        ..Default::default()
+    }
+}
+
+fn settings_values(parameters: &ComplexModificationParameters) -> ComplexModificationParameters {
+    let [simultaneous, delayed, alone, held] = DEFAULT_PARAMETERS;
+    ComplexModificationParameters {
+        simultaneous_threshold_milliseconds: Some(
+            parameters
+                .simultaneous_threshold_milliseconds
+                .unwrap_or(simultaneous),
+        ),
+        to_delayed_action_delay_milliseconds: Some(
+            parameters
+                .to_delayed_action_delay_milliseconds
+                .unwrap_or(delayed),
+        ),
+        to_if_alone_timeout_milliseconds: Some(
+            parameters.to_if_alone_timeout_milliseconds.unwrap_or(alone),
+        ),
+        to_if_held_down_threshold_milliseconds: Some(
+            parameters
+                .to_if_held_down_threshold_milliseconds
+                .unwrap_or(held),
+        ),
+        extra: Map::new(),
+    }
+}
@@ -1,3 +1,9 @@
+pub mod edn;
+pub mod goku;
+pub mod karabiner;
+pub mod patterns;
+pub mod pkl;
+
 use crate::error::{KarabinerPklError, Result};
 use std::path::{Path, PathBuf};
 use tracing::{info, warn};
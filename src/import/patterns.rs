@@ -0,0 +1,725 @@
+// The manipulators core.pkl and builtins.pkl generate, replayed in Rust so the importer can
+// tell when a rule is one of them. A rule only becomes `new DualUse { ... }` and friends when
+// expanding that call gives back exactly the same JSON; everything else stays literal.
+
+use super::karabiner::{action, behaviour, event, from_event, same};
+use super::pkl::{int, string, strings, Expr, Object};
+use crate::karabiner::{
+    Condition, FromEvent, FromModifiers, KeyOrder, KeyUpWhen, Manipulator, ManipulatorParameters,
+    ManipulatorType, Rule, SetVariable, SimultaneousOptions, ToEvent, VariableValue,
+};
+
+/// The library construct a rule was turned back into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Construct {
+    DualUse,
+    Layer,
+    SimLayer,
+    HyperKey,
+    HyperKeyDualUse,
+    Literal,
+}
+
+impl Construct {
+    pub fn name(self) -> &'static str {
+        match self {
+            Construct::DualUse => "core.DualUse",
+            Construct::Layer => "core.Layer",
+            Construct::SimLayer => "core.SimLayer",
+            Construct::HyperKey => "builtins.hyperKey",
+            Construct::HyperKeyDualUse => "builtins.hyperKeyDualUse",
+            Construct::Literal => "karabiner.Rule",
+        }
+    }
+}
+
+/// `karabiner.Modifier`, which is also what `karabiner.isMod` accepts.
+pub const MODIFIERS: &[&str] = &[
+    "caps_lock",
+    "left_command",
+    "left_control",
+    "left_option",
+    "left_shift",
+    "right_command",
+    "right_control",
+    "right_option",
+    "right_shift",
+    "fn",
+    "command",
+    "control",
+    "option",
+    "shift",
+    "any",
+];
+
+/// `keys.validKeyCodes`, checked whenever core.BasicMap is given a key as a string.
+const VALID_KEY_CODES: &[&str] = &[
+    "a",
+    "b",
+    "c",
+    "d",
+    "e",
+    "f",
+    "g",
+    "h",
+    "i",
+    "j",
+    "k",
+    "l",
+    "m",
+    "n",
+    "o",
+    "p",
+    "q",
+    "r",
+    "s",
+    "t",
+    "u",
+    "v",
+    "w",
+    "x",
+    "y",
+    "z",
+    "0",
+    "1",
+    "2",
+    "3",
+    "4",
+    "5",
+    "6",
+    "7",
+    "8",
+    "9",
+    "f1",
+    "f2",
+    "f3",
+    "f4",
+    "f5",
+    "f6",
+    "f7",
+    "f8",
+    "f9",
+    "f10",
+    "f11",
+    "f12",
+    "f13",
+    "f14",
+    "f15",
+    "f16",
+    "f17",
+    "f18",
+    "f19",
+    "f20",
+    "f21",
+    "f22",
+    "f23",
+    "f24",
+    "escape",
+    "return_or_enter",
+    "tab",
+    "spacebar",
+    "hyphen",
+    "equal_sign",
+    "open_bracket",
+    "close_bracket",
+    "backslash",
+    "non_us_pound",
+    "semicolon",
+    "quote",
+    "grave_accent_and_tilde",
+    "comma",
+    "period",
+    "slash",
+    "non_us_backslash",
+    "caps_lock",
+    "scroll_lock",
+    "num_lock",
+    "left_shift",
+    "left_control",
+    "left_option",
+    "left_command",
+    "right_shift",
+    "right_control",
+    "right_option",
+    "right_command",
+    "fn",
+    "up_arrow",
+    "down_arrow",
+    "left_arrow",
+    "right_arrow",
+    "page_up",
+    "page_down",
+    "home",
+    "end",
+    "delete_or_backspace",
+    "delete_forward",
+    "print_screen",
+    "pause",
+    "insert",
+    "volume_up",
+    "volume_down",
+    "mute",
+    "play",
+    "stop",
+    "rewind",
+    "fast_forward",
+    "brightness_up",
+    "brightness_down",
+    "keypad_0",
+    "keypad_1",
+    "keypad_2",
+    "keypad_3",
+    "keypad_4",
+    "keypad_5",
+    "keypad_6",
+    "keypad_7",
+    "keypad_8",
+    "keypad_9",
+    "keypad_num_lock",
+    "keypad_slash",
+    "keypad_asterisk",
+    "keypad_hyphen",
+    "keypad_plus",
+    "keypad_enter",
+    "keypad_period",
+    "keypad_equal_sign",
+    "keypad_comma",
+    "international1",
+    "international2",
+    "international3",
+    "international4",
+    "international5",
+    "international6",
+    "international7",
+    "international8",
+    "international9",
+    "lang1",
+    "lang2",
+    "lang3",
+    "lang4",
+    "lang5",
+    "lang6",
+    "lang7",
+    "lang8",
+    "lang9",
+    "japanese_eisuu",
+    "japanese_kana",
+    "japanese_pc_nfer",
+    "japanese_pc_xfer",
+    "japanese_pc_katakana",
+    "help",
+    "menu",
+    "application",
+    "power",
+    "execute",
+    "find",
+    "select",
+    "again",
+    "undo",
+    "cut",
+    "copy",
+    "paste",
+    "play_or_pause",
+    "volume_increment",
+    "volume_decrement",
+];
+
+/// Key codes with an event in `keys.Keys`, under the camelCase of the code.
+const KEYS: &[&str] = &[
+    "a",
+    "b",
+    "c",
+    "d",
+    "e",
+    "f",
+    "g",
+    "h",
+    "i",
+    "j",
+    "k",
+    "l",
+    "m",
+    "n",
+    "o",
+    "p",
+    "q",
+    "r",
+    "s",
+    "t",
+    "u",
+    "v",
+    "w",
+    "x",
+    "y",
+    "z",
+    "0",
+    "1",
+    "2",
+    "3",
+    "4",
+    "5",
+    "6",
+    "7",
+    "8",
+    "9",
+    "spacebar",
+    "tab",
+    "escape",
+    "return_or_enter",
+    "delete_or_backspace",
+    "delete_forward",
+    "left_arrow",
+    "right_arrow",
+    "up_arrow",
+    "down_arrow",
+    "left_command",
+    "left_control",
+    "left_option",
+    "left_shift",
+    "right_command",
+    "right_control",
+    "right_option",
+    "right_shift",
+    "fn",
+    "f1",
+    "f2",
+    "f3",
+    "f4",
+    "f5",
+    "f6",
+    "f7",
+    "f8",
+    "f9",
+    "f10",
+    "f11",
+    "f12",
+    "semicolon",
+    "comma",
+    "period",
+    "slash",
+    "backslash",
+    "quote",
+    "grave_accent_and_tilde",
+    "hyphen",
+    "equal_sign",
+    "open_bracket",
+    "close_bracket",
+    "page_up",
+    "page_down",
+    "home",
+    "end",
+    "caps_lock",
+    "play_or_pause",
+    "volume_increment",
+    "volume_decrement",
+    "mute",
+];
+
+const DIGITS: [&str; 10] = [
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+];
+
+/// The properties of `core_types.BaseLayer`, in declaration order, by key code.
+const LAYER_KEYS: &[&str] = &[
+    "a",
+    "s",
+    "d",
+    "f",
+    "g",
+    "h",
+    "j",
+    "k",
+    "l",
+    "semicolon",
+    "q",
+    "w",
+    "e",
+    "r",
+    "t",
+    "y",
+    "u",
+    "i",
+    "o",
+    "p",
+    "z",
+    "x",
+    "c",
+    "v",
+    "b",
+    "n",
+    "m",
+    "comma",
+    "period",
+    "1",
+    "2",
+    "3",
+    "4",
+    "5",
+    "6",
+    "7",
+    "8",
+    "9",
+    "0",
+];
+
+// The four modifiers builtins.hyperKey folds onto its trigger, innermost first.
+const HYPER: [&str; 4] = ["left_command", "left_shift", "left_option", "left_control"];
+
+/// `keys.leftControl` for a key code the generated config's `keys` has.
+pub fn key(code: &str) -> Option<Expr> {
+    if !KEYS.contains(&code) {
+        return None;
+    }
+    let name = match code.parse::<usize>() {
+        Ok(digit) => DIGITS[digit].to_string(),
+        Err(_) => camel_case(code),
+    };
+    Some(Expr::Raw(format!("keys.{name}")))
+}
+
+pub fn is_modifier(code: &str) -> bool {
+    MODIFIERS.contains(&code)
+}
+
+fn camel_case(code: &str) -> String {
+    let mut parts = code.split('_');
+    let mut name = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            name.push(first.to_ascii_uppercase());
+            name.extend(chars);
+        }
+    }
+    name
+}
+
+// A key as core.BasicMap accepts it: a `keys` event, or a string it validates.
+fn trigger(code: &str) -> Option<Expr> {
+    key(code).or_else(|| VALID_KEY_CODES.contains(&code).then(|| string(code)))
+}
+
+fn key_event(code: &str) -> FromEvent {
+    FromEvent {
+        key_code: Some(code.to_string()),
+        ..Default::default()
+    }
+}
+
+fn single(events: &Option<Vec<ToEvent>>) -> Option<&ToEvent> {
+    match events.as_deref() {
+        Some([event]) => Some(event),
+        _ => None,
+    }
+}
+
+/// `new DualUse { key; tap; hold }`, with anything else the manipulator sets amended on.
+pub fn dual_use(manipulator: &Manipulator) -> Option<Object> {
+    if manipulator.manipulator_type != ManipulatorType::Basic
+        || manipulator.extra.values().any(|value| !value.is_null())
+    {
+        return None;
+    }
+    let hold = single(&manipulator.to)?;
+    let tap = single(&manipulator.to_if_alone)?;
+
+    let code = manipulator
+        .from
+        .key_code
+        .as_deref()
+        .filter(|code| same(&manipulator.from, &key_event(code)));
+    let key = match code.and_then(key) {
+        Some(key) => key,
+        None => from_event(&manipulator.from).ok()?,
+    };
+
+    let object = Object::new("DualUse")
+        .prop("key", key)
+        .prop("tap", action(tap).ok()?)
+        .prop("hold", event(hold).ok()?);
+    behaviour(object, manipulator).ok()
+}
+
+/// A rule holding a single `DualUse`, which is what `DualUse.build()` makes of it.
+pub fn dual_use_rule(rule: &Rule) -> Option<Expr> {
+    let [manipulator] = rule.manipulators.as_slice() else {
+        return None;
+    };
+    if manipulator.description.is_some() {
+        return None;
+    }
+    let mut object = dual_use(manipulator)?;
+    if !rule.description.is_empty() {
+        object = object.prop("description", string(&rule.description));
+    }
+    Some(object.into())
+}
+
+// core.pkl `BasicMap { mod; key; action }`, as each entry of a `Layer` builds it.
+fn layer_entry(rule: &Rule) -> Option<(&[String], &str, &ToEvent)> {
+    let [manipulator] = rule.manipulators.as_slice() else {
+        return None;
+    };
+    if !rule.description.is_empty() {
+        return None;
+    }
+    let key = manipulator.from.key_code.as_deref()?;
+    let modifiers = manipulator.from.modifiers.as_ref()?.mandatory.as_slice();
+    let to = single(&manipulator.to)?;
+
+    let expected = Manipulator {
+        from: FromEvent {
+            key_code: Some(key.to_string()),
+            modifiers: Some(FromModifiers {
+                mandatory: modifiers.to_vec(),
+                optional: Vec::new(),
+            }),
+            ..Default::default()
+        },
+        to: Some(vec![to.clone()]),
+        ..Default::default()
+    };
+    let valid = VALID_KEY_CODES.contains(&key)
+        && !modifiers.is_empty()
+        && modifiers.iter().all(|modifier| is_modifier(modifier));
+    (valid && same(manipulator, &expected)).then_some((modifiers, key, to))
+}
+
+/// `new Layer { modifier; h = ...; j = ... }` for a run of rules it would build, one per key.
+/// Returns the layer and how many rules it stands for.
+pub fn layer(rules: &[Rule]) -> Option<(Expr, usize)> {
+    let (modifiers, _, _) = layer_entry(rules.first()?)?;
+
+    let mut entries: Vec<(&str, Expr)> = Vec::new();
+    for rule in rules {
+        let Some((entry_modifiers, key, to)) = layer_entry(rule) else {
+            break;
+        };
+        if entry_modifiers != modifiers || entries.iter().any(|(seen, _)| *seen == key) {
+            break;
+        }
+        let Ok(value) = action(to) else {
+            break;
+        };
+        entries.push((key, value));
+    }
+    if entries.len() < 2 {
+        return None;
+    }
+
+    let modifier = match modifiers {
+        [modifier] => string(modifier),
+        _ => strings(modifiers),
+    };
+    let object = Object::new("Layer").prop("modifier", modifier);
+    let count = entries.len();
+    Some((layer_mappings(object, "mappings", entries).into(), count))
+}
+
+// The layer's own properties (`h = ...`) only when Pkl would list them in the same order;
+// otherwise an explicit `mappings` keeps the order of the source.
+fn layer_mappings(mut object: Object, mapping: &str, entries: Vec<(&str, Expr)>) -> Object {
+    let positions: Option<Vec<usize>> = entries
+        .iter()
+        .map(|(key, _)| LAYER_KEYS.iter().position(|known| known == key))
+        .collect();
+    let in_order = positions.is_some_and(|positions| positions.windows(2).all(|w| w[0] < w[1]));
+
+    if in_order {
+        for (key, value) in entries {
+            object = object.prop(key, value);
+        }
+        object
+    } else {
+        let mut mappings = Object::amend();
+        for (key, value) in entries {
+            mappings = mappings.entry(key, value);
+        }
+        object.prop(mapping, mappings)
+    }
+}
+
+// The two manipulators `SimLayer.build()` emits per key: the chord that turns the layer on,
+// and the key itself while the layer variable is set.
+fn sim_layer_manipulators(
+    trigger: &str,
+    layer: &str,
+    threshold: i64,
+    entries: &[(&str, &ToEvent)],
+) -> Vec<Manipulator> {
+    let variable = |value| ToEvent {
+        set_variable: Some(SetVariable {
+            name: layer.to_string(),
+            value: Some(VariableValue::Int(value)),
+            extra: Default::default(),
+        }),
+        ..Default::default()
+    };
+
+    entries
+        .iter()
+        .flat_map(|(key, to)| {
+            [
+                Manipulator {
+                    parameters: Some(ManipulatorParameters {
+                        simultaneous_threshold_milliseconds: Some(threshold),
+                        ..Default::default()
+                    }),
+                    from: FromEvent {
+                        simultaneous: Some(vec![key_event(trigger), key_event(key)]),
+                        simultaneous_options: Some(SimultaneousOptions {
+                            detect_key_down_uninterruptedly: Some(false),
+                            key_down_order: Some(KeyOrder::Strict),
+                            key_up_order: Some(KeyOrder::StrictInverse),
+                            key_up_when: Some(KeyUpWhen::Any),
+                            to_after_key_up: Some(vec![variable(0)]),
+                            extra: Default::default(),
+                        }),
+                        ..Default::default()
+                    },
+                    to: Some(vec![variable(1), (*to).clone()]),
+                    ..Default::default()
+                },
+                Manipulator {
+                    from: key_event(key),
+                    conditions: Some(vec![Condition {
+                        condition_type: "variable_if".to_string(),
+                        name: Some(layer.to_string()),
+                        value: Some(VariableValue::Int(1)),
+                        ..Default::default()
+                    }]),
+                    to: Some(vec![(*to).clone()]),
+                    ..Default::default()
+                },
+            ]
+        })
+        .collect()
+}
+
+/// `new SimLayer { trigger; threshold; maps { ... } }`. Its entries go through `maps`, which
+/// takes them verbatim and in order.
+pub fn sim_layer(rule: &Rule) -> Option<Expr> {
+    let first = rule.manipulators.first()?;
+    let trigger = first
+        .from
+        .simultaneous
+        .as_ref()?
+        .first()?
+        .key_code
+        .as_deref()?;
+    let layer = first
+        .to
+        .as_ref()?
+        .first()?
+        .set_variable
+        .as_ref()?
+        .name
+        .as_str();
+    let threshold = first
+        .parameters
+        .as_ref()?
+        .simultaneous_threshold_milliseconds?;
+    if rule.description != format!("Simultaneous {trigger} layer")
+        || !rule.manipulators.len().is_multiple_of(2)
+    {
+        return None;
+    }
+
+    let mut entries: Vec<(&str, &ToEvent)> = Vec::new();
+    for pair in rule.manipulators.chunks(2) {
+        let key = pair[1].from.key_code.as_deref()?;
+        let to = single(&pair[1].to)?;
+        if entries.iter().any(|(seen, _)| *seen == key) {
+            return None;
+        }
+        entries.push((key, to));
+    }
+    if !same(
+        &rule.manipulators,
+        &sim_layer_manipulators(trigger, layer, threshold, &entries),
+    ) {
+        return None;
+    }
+
+    sim_layer_object(trigger, layer, threshold, &entries)
+}
+
+/// The chord threshold `core.SimLayer` builds with unless told otherwise.
+pub const SIM_LAYER_THRESHOLD: i64 = 200;
+
+/// `new SimLayer { ... }` for `entries`, which must have distinct keys.
+pub fn sim_layer_object(
+    trigger: &str,
+    layer: &str,
+    threshold: i64,
+    entries: &[(&str, &ToEvent)],
+) -> Option<Expr> {
+    let mut object = Object::new("SimLayer").prop("trigger", string(trigger));
+    if layer != format!("{trigger}-layer") {
+        object = object.prop("layerName", string(layer));
+    }
+    if threshold != SIM_LAYER_THRESHOLD {
+        object = object.prop("threshold", int(threshold));
+    }
+    let mut maps = Object::amend();
+    for (key, to) in entries {
+        maps = maps.entry(*key, event(to).ok()?);
+    }
+    Some(object.prop("maps", maps).into())
+}
+
+// builtins.hyperKey folds ⌃⌥⇧⌘ onto its trigger with `Event.and` from karabiner.pkl. A
+// modifier trigger collects all four; any other key keeps only the last one folded on.
+fn hyper(trigger: &str) -> ToEvent {
+    let mut modifiers: Vec<String> = Vec::new();
+    for modifier in HYPER {
+        if is_modifier(trigger) {
+            modifiers.push(modifier.to_string());
+        } else {
+            modifiers = vec![modifier.to_string()];
+        }
+    }
+    ToEvent {
+        key_code: Some(trigger.to_string()),
+        modifiers: Some(modifiers),
+        ..Default::default()
+    }
+}
+
+/// `builtins.hyperKey(...)` or `builtins.hyperKeyDualUse(...)`, recognised by the
+/// description they give their rule.
+pub fn hyper_key(rule: &Rule) -> Option<(Construct, Expr)> {
+    let (construct, code) = if let Some(rest) = rule.description.strip_prefix("Hyper Key: ") {
+        (Construct::HyperKey, rest.strip_suffix(" to ⌃⌥⇧⌘")?)
+    } else {
+        let rest = rule.description.strip_prefix("Dual-Use Hyper Key: ")?;
+        (
+            Construct::HyperKeyDualUse,
+            rest.strip_suffix(" to ⌃⌥⇧⌘/escape")?,
+        )
+    };
+
+    let mut expected = Manipulator {
+        from: key_event(code),
+        to: Some(vec![hyper(code)]),
+        ..Default::default()
+    };
+    if construct == Construct::HyperKeyDualUse {
+        expected.to_if_alone = Some(vec![ToEvent {
+            key_code: Some("escape".to_string()),
+            ..Default::default()
+        }]);
+    }
+    let expected = Rule {
+        description: rule.description.clone(),
+        manipulators: vec![expected],
+        extra: Default::default(),
+    };
+    if !same(rule, &expected) {
+        return None;
+    }
+
+    let argument = trigger(code)?.render(0);
+    let function = construct.name();
+    Some((construct, Expr::Raw(format!("{function}({argument})"))))
+}
@@ -0,0 +1,356 @@
+use std::fmt::Write;
+
+// Objects and lists that fit within this many columns are written on one line.
+const WIDTH: usize = 100;
+
+const KEYWORDS: &[&str] = &[
+    "abstract",
+    "amends",
+    "as",
+    "case",
+    "class",
+    "const",
+    "delete",
+    "else",
+    "extends",
+    "external",
+    "false",
+    "fixed",
+    "for",
+    "function",
+    "hidden",
+    "if",
+    "import",
+    "in",
+    "is",
+    "let",
+    "local",
+    "module",
+    "new",
+    "nothing",
+    "null",
+    "open",
+    "out",
+    "outer",
+    "override",
+    "protected",
+    "read",
+    "record",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "trace",
+    "true",
+    "typealias",
+    "unknown",
+    "vararg",
+    "when",
+];
+
+/// A Pkl expression, built up by the importers and rendered with [`Expr::render`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// Written as is: literals, `keys.escape`, `builtins.hyperKey(...)`.
+    Raw(String),
+    List(Vec<Item>),
+    Object(Object),
+}
+
+/// A list element, or just a comment when `expr` is `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub comments: Vec<String>,
+    pub expr: Option<Expr>,
+}
+
+impl Item {
+    pub fn comment(text: impl Into<String>) -> Self {
+        Self {
+            comments: vec![text.into()],
+            expr: None,
+        }
+    }
+}
+
+impl From<Expr> for Item {
+    fn from(expr: Expr) -> Self {
+        Self {
+            comments: Vec::new(),
+            expr: Some(expr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    /// `new Manipulator { ... }`; `None` amends the value already there (`settings { ... }`).
+    pub class: Option<String>,
+    pub members: Vec<Member>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub key: Key,
+    pub value: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Key {
+    /// `name = value`
+    Property(String),
+    /// `["name"] = value`
+    Entry(String),
+}
+
+impl Object {
+    pub fn new(class: impl Into<String>) -> Self {
+        Self {
+            class: Some(class.into()),
+            members: Vec::new(),
+        }
+    }
+
+    pub fn amend() -> Self {
+        Self {
+            class: None,
+            members: Vec::new(),
+        }
+    }
+
+    pub fn prop(mut self, name: impl Into<String>, value: impl Into<Expr>) -> Self {
+        self.push(Key::Property(name.into()), value.into());
+        self
+    }
+
+    pub fn entry(mut self, key: impl Into<String>, value: impl Into<Expr>) -> Self {
+        self.push(Key::Entry(key.into()), value.into());
+        self
+    }
+
+    pub fn push(&mut self, key: Key, value: Expr) {
+        self.members.push(Member { key, value });
+    }
+}
+
+impl From<Object> for Expr {
+    fn from(object: Object) -> Self {
+        Expr::Object(object)
+    }
+}
+
+pub fn string(value: &str) -> Expr {
+    Expr::Raw(quote(value))
+}
+
+pub fn int(value: i64) -> Expr {
+    Expr::Raw(value.to_string())
+}
+
+pub fn float(value: f64) -> Expr {
+    // `{:?}` keeps the decimal point, so `1.0` isn't read back as an Int.
+    Expr::Raw(format!("{value:?}"))
+}
+
+pub fn boolean(value: bool) -> Expr {
+    Expr::Raw(value.to_string())
+}
+
+pub fn null() -> Expr {
+    Expr::Raw("null".to_string())
+}
+
+pub fn list<I: Into<Item>>(items: impl IntoIterator<Item = I>) -> Expr {
+    Expr::List(items.into_iter().map(Into::into).collect())
+}
+
+pub fn strings<S: AsRef<str>>(values: &[S]) -> Expr {
+    list(values.iter().map(|value| string(value.as_ref())))
+}
+
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            // Also keeps `\(` from starting an interpolation.
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{{{:x}}}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A property name, backquoted unless it is a plain identifier.
+pub fn identifier(name: &str) -> String {
+    let mut chars = name.chars();
+    let plain = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        && !KEYWORDS.contains(&name);
+    if plain {
+        name.to_string()
+    } else {
+        format!("`{name}`")
+    }
+}
+
+impl Expr {
+    /// Renders the expression as if it started a line indented by `indent` columns.
+    pub fn render(&self, indent: usize) -> String {
+        self.render_at(indent, indent)
+    }
+
+    // `column` is where the expression starts, after whatever precedes it on its line.
+    fn render_at(&self, indent: usize, column: usize) -> String {
+        if let Some(inline) = self.inline() {
+            if column + inline.chars().count() <= WIDTH {
+                return inline;
+            }
+        }
+
+        let pad = " ".repeat(indent + 2);
+        let mut out = String::new();
+        match self {
+            Expr::Raw(raw) => out.push_str(raw),
+            Expr::List(items) => {
+                out.push_str("List(\n");
+                let last = items.iter().rposition(|item| item.expr.is_some());
+                for (i, item) in items.iter().enumerate() {
+                    for comment in &item.comments {
+                        let _ = writeln!(out, "{pad}// {comment}");
+                    }
+                    if let Some(expr) = &item.expr {
+                        let comma = if Some(i) == last { "" } else { "," };
+                        let _ = writeln!(out, "{pad}{}{comma}", expr.render(indent + 2));
+                    }
+                }
+                let _ = write!(out, "{})", " ".repeat(indent));
+            }
+            Expr::Object(object) => {
+                if let Some(class) = &object.class {
+                    let _ = write!(out, "new {class} ");
+                }
+                out.push_str("{\n");
+                for member in &object.members {
+                    let _ = writeln!(out, "{pad}{}", member.render(indent + 2));
+                }
+                let _ = write!(out, "{}}}", " ".repeat(indent));
+            }
+        }
+        out
+    }
+
+    // The single-line form, unless something in it needs a line of its own.
+    fn inline(&self) -> Option<String> {
+        match self {
+            Expr::Raw(raw) => Some(raw.clone()),
+            Expr::List(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| match (&item.expr, item.comments.is_empty()) {
+                        (Some(expr), true) => expr.inline(),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                Some(format!("List({})", items.join(", ")))
+            }
+            Expr::Object(object) => {
+                let rendered = object
+                    .members
+                    .iter()
+                    .map(Member::inline)
+                    .collect::<Option<Vec<_>>>()?;
+                let head = object
+                    .class
+                    .as_ref()
+                    .map(|class| format!("new {class} "))
+                    .unwrap_or_default();
+                if rendered.is_empty() {
+                    Some(format!("{head}{{}}"))
+                } else {
+                    Some(format!("{head}{{ {} }}", rendered.join("; ")))
+                }
+            }
+        }
+    }
+}
+
+impl Member {
+    pub fn render(&self, indent: usize) -> String {
+        let key = self.key();
+        let prefix = self.prefix(&key);
+        let column = indent + prefix.chars().count();
+        format!("{prefix}{}", self.value.render_at(indent, column))
+    }
+
+    fn inline(&self) -> Option<String> {
+        let key = self.key();
+        Some(format!("{}{}", self.prefix(&key), self.value.inline()?))
+    }
+
+    fn key(&self) -> String {
+        match &self.key {
+            Key::Property(name) => identifier(name),
+            Key::Entry(name) => format!("[{}]", quote(name)),
+        }
+    }
+
+    // Amending an existing value drops the `=`: `settings { ... }`.
+    fn prefix(&self, key: &str) -> String {
+        match &self.value {
+            Expr::Object(Object { class: None, .. }) => format!("{key} "),
+            _ => format!("{key} = "),
+        }
+    }
+}
+
+/// A module that extends the ankura library, written one top-level member at a time.
+pub struct Module {
+    header: Vec<String>,
+    extends: String,
+    members: Vec<Member>,
+}
+
+impl Module {
+    pub fn extending(uri: impl Into<String>) -> Self {
+        Self {
+            header: Vec::new(),
+            extends: uri.into(),
+            members: Vec::new(),
+        }
+    }
+
+    pub fn header(&mut self, line: impl Into<String>) {
+        self.header.push(line.into());
+    }
+
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<Expr>) {
+        self.members.push(Member {
+            key: Key::Property(name.into()),
+            value: value.into(),
+        });
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for line in &self.header {
+            let _ = writeln!(out, "// {line}");
+        }
+        let _ = writeln!(out, "extends {}", quote(&self.extends));
+        for member in &self.members {
+            out.push('\n');
+            let _ = writeln!(out, "{}", member.render(0));
+        }
+        out
+    }
+}
@@ -0,0 +1,555 @@
+use crate::error::{KarabinerPklError, Result};
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+
+pub type KeyCode = String;
+pub type ConsumerKeyCode = String;
+pub type PointingButton = String;
+pub type Modifier = String;
+
+// Every struct keeps the keys it doesn't model in `extra` so that fields written by
+// Karabiner-Elements itself survive a read-merge-write cycle untouched.
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global: Option<Global>,
+    #[serde(default)]
+    pub profiles: Vec<Profile>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Global {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_for_updates_on_startup: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub show_in_menu_bar: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub show_profile_name_in_menu_bar: Option<bool>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    #[serde(default)]
+    pub selected: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub virtual_hid_keyboard: Option<VirtualHidKeyboard>,
+    #[serde(default)]
+    pub devices: Vec<Device>,
+    #[serde(default)]
+    pub fn_function_keys: Vec<FnFunctionKey>,
+    #[serde(default)]
+    pub simple_modifications: Vec<SimpleModification>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub complex_modifications: Option<ComplexModifications>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Parameters>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct VirtualHidKeyboard {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country_code: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indicate_sticky_modifier_keys_state: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mouse_key_xy_scale: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyboard_type_v2: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Device {
+    pub identifiers: DeviceIdentifiers,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignore: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disable_built_in_keyboard_if_exists: Option<bool>,
+    #[serde(default)]
+    pub fn_function_keys: Vec<FnFunctionKey>,
+    #[serde(default)]
+    pub simple_modifications: Vec<SimpleModification>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manipulate_caps_lock_led: Option<bool>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DeviceIdentifiers {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_keyboard: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_pointing_device: Option<bool>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// config.pkl emits `from`/`to` as bare key codes while Karabiner writes event objects,
+// so both shapes are accepted for simple modifications and fn function keys.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SimpleFrom {
+    KeyCode(KeyCode),
+    Event(Box<FromEvent>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SimpleTo {
+    KeyCode(KeyCode),
+    Events(Vec<ToEvent>),
+    Event(Box<ToEvent>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimpleModification {
+    pub from: SimpleFrom,
+    pub to: SimpleTo,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FnFunctionKey {
+    pub from: SimpleFrom,
+    pub to: SimpleTo,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ComplexModifications {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<ComplexModificationParameters>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ComplexModificationParameters {
+    #[serde(
+        rename = "basic.simultaneous_threshold_milliseconds",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub simultaneous_threshold_milliseconds: Option<i64>,
+    #[serde(
+        rename = "basic.to_delayed_action_delay_milliseconds",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub to_delayed_action_delay_milliseconds: Option<i64>,
+    #[serde(
+        rename = "basic.to_if_alone_timeout_milliseconds",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub to_if_alone_timeout_milliseconds: Option<i64>,
+    #[serde(
+        rename = "basic.to_if_held_down_threshold_milliseconds",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub to_if_held_down_threshold_milliseconds: Option<i64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Rule {
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub manipulators: Vec<Manipulator>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ManipulatorType {
+    #[default]
+    Basic,
+    MouseMotionToScroll,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Manipulator {
+    #[serde(rename = "type", default)]
+    pub manipulator_type: ManipulatorType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub from: FromEvent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<Vec<ToEvent>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_if_alone: Option<Vec<ToEvent>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_if_held_down: Option<Vec<ToEvent>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_after_key_up: Option<Vec<ToEvent>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_delayed_action: Option<DelayedAction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conditions: Option<Vec<Condition>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<ManipulatorParameters>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct FromEvent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_code: Option<KeyCode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consumer_key_code: Option<ConsumerKeyCode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pointing_button: Option<PointingButton>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub any: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modifiers: Option<FromModifiers>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub simultaneous: Option<Vec<FromEvent>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub simultaneous_options: Option<SimultaneousOptions>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// `karabiner.Event.modifiers` in the Pkl library is a plain list, which Karabiner only
+// accepts on `to` events. A list on `from` is read as the mandatory set and written back
+// in the `{ mandatory, optional }` form Karabiner expects.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct FromModifiers {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mandatory: Vec<Modifier>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub optional: Vec<Modifier>,
+}
+
+impl<'de> Deserialize<'de> for FromModifiers {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Spec {
+            #[serde(default, deserialize_with = "one_or_many")]
+            mandatory: Vec<Modifier>,
+            #[serde(default, deserialize_with = "one_or_many")]
+            optional: Vec<Modifier>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            List(Vec<Modifier>),
+            Spec(Spec),
+        }
+
+        match Repr::deserialize(deserializer).map_err(|_| {
+            de::Error::custom(
+                "expected a list of modifiers or an object with `mandatory`/`optional` lists",
+            )
+        })? {
+            Repr::List(mandatory) => Ok(Self {
+                mandatory,
+                optional: Vec::new(),
+            }),
+            Repr::Spec(spec) => Ok(Self {
+                mandatory: spec.mandatory,
+                optional: spec.optional,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ToEvent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_code: Option<KeyCode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consumer_key_code: Option<ConsumerKeyCode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pointing_button: Option<PointingButton>,
+    #[serde(
+        default,
+        deserialize_with = "optional_one_or_many",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub modifiers: Option<Vec<Modifier>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell_command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub select_input_source: Option<InputSource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub set_variable: Option<SetVariable>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mouse_key: Option<MouseKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sticky_modifier: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub software_function: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lazy: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub halt: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hold_down_milliseconds: Option<i64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct DelayedAction {
+    #[serde(default)]
+    pub to_if_invoked: Vec<ToEvent>,
+    #[serde(default)]
+    pub to_if_canceled: Vec<ToEvent>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Condition {
+    #[serde(rename = "type")]
+    pub condition_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle_identifiers: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_paths: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identifiers: Option<Vec<DeviceIdentifiers>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_sources: Option<Vec<InputSource>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyboard_types: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<VariableValue>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum VariableValue {
+    Bool(bool),
+    Int(i64),
+    String(String),
+}
+
+impl fmt::Display for VariableValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VariableValue::Bool(value) => write!(f, "{value}"),
+            VariableValue::Int(value) => write!(f, "{value}"),
+            VariableValue::String(value) => write!(f, "\"{value}\""),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyOrder {
+    #[default]
+    Insensitive,
+    Strict,
+    StrictInverse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyUpWhen {
+    #[default]
+    Any,
+    All,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct SimultaneousOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detect_key_down_uninterruptedly: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_down_order: Option<KeyOrder>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_up_order: Option<KeyOrder>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_up_when: Option<KeyUpWhen>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_after_key_up: Option<Vec<ToEvent>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct InputSource {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_source_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_mode_id: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SetVariable {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<VariableValue>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct MouseKey {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vertical_wheel: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub horizontal_wheel: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed_multiplier: Option<f64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ManipulatorParameters {
+    #[serde(
+        rename = "basic.simultaneous_threshold_milliseconds",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub simultaneous_threshold_milliseconds: Option<i64>,
+    #[serde(
+        rename = "basic.to_delayed_action_delay_milliseconds",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub to_delayed_action_delay_milliseconds: Option<i64>,
+    #[serde(
+        rename = "basic.to_if_alone_timeout_milliseconds",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub to_if_alone_timeout_milliseconds: Option<i64>,
+    #[serde(
+        rename = "basic.to_if_held_down_threshold_milliseconds",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub to_if_held_down_threshold_milliseconds: Option<i64>,
+    #[serde(
+        rename = "mouse_motion_to_scroll.speed",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub mouse_motion_to_scroll_speed: Option<i64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Parameters {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delay_milliseconds_before_open_device: Option<i64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+fn optional_one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    struct Wrapper(#[serde(deserialize_with = "one_or_many")] Vec<String>);
+
+    Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(values)| values))
+}
+
+impl Config {
+    pub fn from_json_str(json: &str) -> Result<Self> {
+        let deserializer = &mut serde_json::Deserializer::from_str(json);
+        serde_path_to_error::deserialize(deserializer).map_err(|e| {
+            let path = e.path().to_string();
+            let inner = e.into_inner();
+            if inner.is_syntax() || inner.is_eof() || inner.is_io() {
+                KarabinerPklError::JsonParseError { source: inner }
+            } else {
+                KarabinerPklError::SchemaError {
+                    path,
+                    message: inner.to_string(),
+                }
+            }
+        })
+    }
+
+    pub fn to_json_pretty(&self) -> Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| KarabinerPklError::JsonParseError { source: e })
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    /// The profile Karabiner would apply: the selected one, or else the first.
+    pub fn active_profile(&self) -> Option<&Profile> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.selected)
+            .or_else(|| self.profiles.first())
+    }
+}
+
+impl Profile {
+    pub fn rules(&self) -> &[Rule] {
+        self.complex_modifications
+            .as_ref()
+            .map(|complex| complex.rules.as_slice())
+            .unwrap_or_default()
+    }
+}
@@ -1,8 +1,19 @@
+pub mod analysis;
+pub mod backup;
 pub mod cli;
 pub mod compiler;
 pub mod daemon;
+pub mod diff;
 pub mod error;
+pub mod export;
 pub mod import;
+pub mod karabiner;
 pub mod logging;
+pub mod merge;
+pub mod output;
+pub mod settings;
+pub mod simulator;
+pub mod snapshot;
+pub mod testing;
 
 pub use error::{KarabinerPklError, Result};
@@ -1,11 +1,16 @@
-use ankura::cli::{self, Cli, Commands};
+use ankura::cli::{self, BackupsCommand, CacheCommand, Cli, Commands};
+use ankura::daemon::control::Request;
 use ankura::error::Result;
 use ankura::logging;
 use clap::Parser;
 use std::path::PathBuf;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> miette::Result<()> {
+    run().await.map_err(miette::Report::new)
+}
+
+async fn run() -> Result<()> {
     let cli = Cli::parse();
 
     let _ = logging::init_logging(cli.debug_log);
@@ -17,18 +22,128 @@ async fn main() -> Result<()> {
             cli::start_daemon(config_path, daemon_mode, cli.debug_log).await
         }
         Commands::Stop => cli::stop_daemon().await,
+        Commands::Reload => cli::control_daemon(Request::Reload).await,
+        Commands::Pause => cli::control_daemon(Request::Pause).await,
+        Commands::Resume => cli::control_daemon(Request::Resume).await,
         Commands::Compile {
             profile_name,
             output,
-        } => cli::compile_once(config_path, profile_name.as_deref(), output).await,
-        Commands::Check => cli::check_config(config_path).await,
+            merge,
+        } => cli::compile_once(config_path, profile_name.as_deref(), output, &merge).await,
+        Commands::Check { strict, layers } => cli::check_config(config_path, strict, layers).await,
+        Commands::Diff {
+            profile_name,
+            output,
+            merge,
+            json,
+        } => {
+            let has_changes =
+                cli::diff_config(config_path, profile_name.as_deref(), output, &merge, json)
+                    .await?;
+            if has_changes {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Commands::Simulate {
+            input,
+            profile_name,
+            app,
+            vars,
+            step,
+            json,
+        } => {
+            cli::simulate(
+                config_path,
+                &input,
+                profile_name.as_deref(),
+                app,
+                &vars,
+                step,
+                json,
+            )
+            .await
+        }
+        Commands::Test {
+            paths,
+            profile_name,
+        } => {
+            let passed = cli::run_tests(config_path, &paths, profile_name.as_deref()).await?;
+            if !passed {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Commands::Snapshot {
+            check,
+            file,
+            profile_name,
+        } => {
+            let matches =
+                cli::snapshot_config(config_path, file, profile_name.as_deref(), check).await?;
+            if !matches {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
         Commands::Logs { lines, follow } => {
             let log_file = get_log_file()?;
             cli::show_logs(log_file, lines, follow)
         }
-        Commands::Status => cli::show_status().await,
+        Commands::Status { json } => cli::show_status(&config_path, json).await,
         Commands::Init { force } => cli::init_config(config_path, force).await,
         Commands::Add { source, name } => cli::add_import(source, name).await,
+        Commands::ImportJson {
+            input,
+            profile_name,
+            output,
+            force,
+            verify,
+        } => {
+            let verified = cli::import_json(
+                config_path,
+                input,
+                profile_name.as_deref(),
+                output,
+                force,
+                verify,
+            )
+            .await?;
+            if !verified {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Commands::ImportEdn {
+            input,
+            profile_name,
+            output,
+            force,
+        } => cli::import_edn(config_path, input, profile_name.as_deref(), output, force),
+        Commands::Export {
+            format,
+            layout,
+            profile_name,
+            output,
+            force,
+        } => {
+            cli::export_config(
+                config_path,
+                &format,
+                layout.as_deref(),
+                profile_name.as_deref(),
+                output,
+                force,
+            )
+            .await
+        }
+        Commands::Cache {
+            command: CacheCommand::Clear,
+        } => cli::clear_cache(),
+        Commands::Backups {
+            command: BackupsCommand::List,
+        } => cli::list_backups(),
+        Commands::Rollback { id, output } => cli::rollback(id.as_deref(), output).await,
     }
 }
 
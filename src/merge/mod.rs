@@ -0,0 +1,383 @@
+use crate::error::{KarabinerPklError, Result};
+use crate::karabiner::{ComplexModifications, Config, Profile, Rule};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeStrategy {
+    /// The compiled value replaces whatever is in karabiner.json.
+    Pkl,
+    /// Whatever is in karabiner.json is kept; the compiled value only fills a gap.
+    Existing,
+    /// Objects are merged key by key with compiled values winning; lists are merged by identity.
+    Deep,
+}
+
+impl FromStr for MergeStrategy {
+    type Err = KarabinerPklError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "pkl" | "pkl-wins" => Ok(Self::Pkl),
+            "existing" | "keep-existing" => Ok(Self::Existing),
+            "deep" | "deep-merge" => Ok(Self::Deep),
+            other => Err(KarabinerPklError::ValidationError {
+                message: format!(
+                    "Unknown merge strategy \"{other}\"; expected pkl, existing or deep"
+                ),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for MergeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pkl => write!(f, "pkl"),
+            Self::Existing => write!(f, "existing"),
+            Self::Deep => write!(f, "deep"),
+        }
+    }
+}
+
+/// Key added to every rule ankura writes, so later merges can tell them apart from rules
+/// enabled through the Karabiner-Elements UI or the complex modifications gallery.
+pub const MANAGED_RULE_KEY: &str = "ankura_managed";
+
+/// Where rules ankura doesn't manage go relative to the compiled ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RulePlacement {
+    Before,
+    #[default]
+    After,
+}
+
+impl FromStr for RulePlacement {
+    type Err = KarabinerPklError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "before" => Ok(Self::Before),
+            "after" => Ok(Self::After),
+            other => Err(KarabinerPklError::ValidationError {
+                message: format!("Unknown rule placement \"{other}\"; expected before or after"),
+            }),
+        }
+    }
+}
+
+/// How each Karabiner-owned field is reconciled with an existing karabiner.json.
+/// Mirrors `MergePolicy` in `pkl/config.pkl`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MergePolicy {
+    pub devices: MergeStrategy,
+    pub fn_function_keys: MergeStrategy,
+    pub virtual_hid_keyboard: MergeStrategy,
+    pub parameters: MergeStrategy,
+    pub global: MergeStrategy,
+    pub foreign_rules: RulePlacement,
+}
+
+impl Default for MergePolicy {
+    fn default() -> Self {
+        Self {
+            devices: MergeStrategy::Deep,
+            fn_function_keys: MergeStrategy::Deep,
+            // config.pkl always emits a default keyboard, which would clobber the one chosen in the UI.
+            virtual_hid_keyboard: MergeStrategy::Existing,
+            parameters: MergeStrategy::Deep,
+            global: MergeStrategy::Deep,
+            foreign_rules: RulePlacement::After,
+        }
+    }
+}
+
+impl MergePolicy {
+    /// Applies `field=strategy` (or `foreign_rules=before|after`) overrides from the command line.
+    pub fn apply_overrides(&mut self, overrides: &[String]) -> Result<()> {
+        for entry in overrides {
+            let (field, value) =
+                entry
+                    .split_once('=')
+                    .ok_or_else(|| KarabinerPklError::ValidationError {
+                        message: format!("Expected FIELD=STRATEGY for --merge, got \"{entry}\""),
+                    })?;
+            let value = value.trim();
+
+            match field.trim() {
+                "devices" => self.devices = value.parse()?,
+                "fn_function_keys" => self.fn_function_keys = value.parse()?,
+                "virtual_hid_keyboard" => self.virtual_hid_keyboard = value.parse()?,
+                "parameters" => self.parameters = value.parse()?,
+                "global" => self.global = value.parse()?,
+                "foreign_rules" => self.foreign_rules = value.parse()?,
+                "all" => {
+                    let strategy = value.parse()?;
+                    self.devices = strategy;
+                    self.fn_function_keys = strategy;
+                    self.virtual_hid_keyboard = strategy;
+                    self.parameters = strategy;
+                    self.global = strategy;
+                }
+                other => {
+                    return Err(KarabinerPklError::ValidationError {
+                        message: format!(
+                            "Unknown merge field \"{other}\"; expected devices, fn_function_keys, virtual_hid_keyboard, parameters, global, foreign_rules or all"
+                        ),
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Merges every compiled profile and the `global` section into `existing`.
+/// Only Karabiner's own keys are taken from `compiled`; ankura settings never reach the output.
+pub fn merge_config(existing: Config, compiled: Config, policy: &MergePolicy) -> Result<Config> {
+    let mut merged = existing;
+
+    for mut profile in compiled.profiles {
+        mark_managed(&mut profile);
+        match merged.profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing_profile) => {
+                *existing_profile = merge_profile(existing_profile, profile, policy)?;
+            }
+            None => merged.profiles.push(profile),
+        }
+    }
+
+    merged.global = merge_option(merged.global.take(), compiled.global, policy.global)?;
+
+    if merged.title.is_none() {
+        merged.title = Some(
+            compiled
+                .title
+                .unwrap_or_else(|| "Karabiner-Pkl Configuration".to_string()),
+        );
+    }
+
+    Ok(merged)
+}
+
+fn merge_profile(existing: &Profile, compiled: Profile, policy: &MergePolicy) -> Result<Profile> {
+    let mut merged = compiled;
+    merged.selected = existing.selected;
+
+    merged.devices = merge_list(
+        &existing.devices,
+        std::mem::take(&mut merged.devices),
+        policy.devices,
+        "identifiers",
+    )?;
+    merged.fn_function_keys = merge_list(
+        &existing.fn_function_keys,
+        std::mem::take(&mut merged.fn_function_keys),
+        policy.fn_function_keys,
+        "from",
+    )?;
+    merged.virtual_hid_keyboard = merge_option(
+        existing.virtual_hid_keyboard.clone(),
+        merged.virtual_hid_keyboard.take(),
+        policy.virtual_hid_keyboard,
+    )?;
+    merged.parameters = merge_option(
+        existing.parameters.clone(),
+        merged.parameters.take(),
+        policy.parameters,
+    )?;
+    merged.complex_modifications = merge_rules(
+        existing.complex_modifications.as_ref(),
+        merged.complex_modifications.take(),
+        policy.foreign_rules,
+    );
+
+    // Keys Karabiner added that Pkl doesn't know about are carried over.
+    for (key, value) in &existing.extra {
+        merged
+            .extra
+            .entry(key.clone())
+            .or_insert_with(|| value.clone());
+    }
+
+    Ok(merged)
+}
+
+fn mark_managed(profile: &mut Profile) {
+    for rule in profile
+        .complex_modifications
+        .iter_mut()
+        .flat_map(|modifications| modifications.rules.iter_mut())
+    {
+        rule.extra
+            .insert(MANAGED_RULE_KEY.to_string(), Value::Bool(true));
+    }
+}
+
+pub fn is_managed(rule: &Rule) -> bool {
+    rule.extra.get(MANAGED_RULE_KEY) == Some(&Value::Bool(true))
+}
+
+// Compiled rules replace every rule ankura wrote before. Unmarked rules sharing a description
+// with a compiled rule date from before rules were marked and are treated as ours too.
+fn merge_rules(
+    existing: Option<&ComplexModifications>,
+    compiled: Option<ComplexModifications>,
+    placement: RulePlacement,
+) -> Option<ComplexModifications> {
+    let Some(existing) = existing else {
+        return compiled;
+    };
+
+    let mut merged = compiled.unwrap_or_else(|| ComplexModifications {
+        parameters: existing.parameters.clone(),
+        ..Default::default()
+    });
+
+    let compiled_descriptions: HashSet<&str> = merged
+        .rules
+        .iter()
+        .map(|rule| rule.description.as_str())
+        .collect();
+    let foreign: Vec<Rule> = existing
+        .rules
+        .iter()
+        .filter(|rule| {
+            !is_managed(rule) && !compiled_descriptions.contains(rule.description.as_str())
+        })
+        .cloned()
+        .collect();
+
+    match placement {
+        RulePlacement::Before => {
+            merged.rules.splice(0..0, foreign);
+        }
+        RulePlacement::After => merged.rules.extend(foreign),
+    }
+
+    for (key, value) in &existing.extra {
+        merged
+            .extra
+            .entry(key.clone())
+            .or_insert_with(|| value.clone());
+    }
+
+    Some(merged)
+}
+
+fn merge_list<T>(
+    existing: &[T],
+    compiled: Vec<T>,
+    strategy: MergeStrategy,
+    identity: &str,
+) -> Result<Vec<T>>
+where
+    T: Serialize + DeserializeOwned + Clone,
+{
+    match strategy {
+        MergeStrategy::Pkl => Ok(compiled),
+        MergeStrategy::Existing if !existing.is_empty() => Ok(existing.to_vec()),
+        MergeStrategy::Existing => Ok(compiled),
+        MergeStrategy::Deep => deep_merge_typed(&existing.to_vec(), &compiled, Some(identity)),
+    }
+}
+
+// A field Pkl leaves unset never removes what is already in karabiner.json.
+fn merge_option<T>(
+    existing: Option<T>,
+    compiled: Option<T>,
+    strategy: MergeStrategy,
+) -> Result<Option<T>>
+where
+    T: Serialize + DeserializeOwned,
+{
+    match (existing, compiled) {
+        (existing, None) => Ok(existing),
+        (None, compiled) => Ok(compiled),
+        (Some(existing), Some(compiled)) => match strategy {
+            MergeStrategy::Pkl => Ok(Some(compiled)),
+            MergeStrategy::Existing => Ok(Some(existing)),
+            MergeStrategy::Deep => deep_merge_typed(&existing, &compiled, None).map(Some),
+        },
+    }
+}
+
+fn deep_merge_typed<T>(existing: &T, compiled: &T, identity: Option<&str>) -> Result<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let existing = serde_json::to_value(existing)
+        .map_err(|e| KarabinerPklError::JsonParseError { source: e })?;
+    let compiled = serde_json::to_value(compiled)
+        .map_err(|e| KarabinerPklError::JsonParseError { source: e })?;
+
+    serde_json::from_value(deep_merge(existing, compiled, identity))
+        .map_err(|e| KarabinerPklError::JsonParseError { source: e })
+}
+
+/// Objects merge recursively with `compiled` winning on conflicts. Lists of objects sharing an
+/// `identity` key (e.g. device `identifiers`) merge element-wise; other lists are replaced.
+pub fn deep_merge(existing: Value, compiled: Value, identity: Option<&str>) -> Value {
+    match (existing, compiled) {
+        (Value::Object(mut existing), Value::Object(compiled)) => {
+            for (key, value) in compiled {
+                let merged = match existing.remove(&key) {
+                    Some(current) => deep_merge(current, value, None),
+                    None => value,
+                };
+                existing.insert(key, merged);
+            }
+            Value::Object(existing)
+        }
+        (Value::Array(mut existing), Value::Array(compiled)) if identity.is_some() => {
+            let key = identity.unwrap_or_default();
+            for item in compiled {
+                let position = item.get(key).and_then(|id| {
+                    existing
+                        .iter()
+                        .position(|current| current.get(key).is_some_and(|c| same_identity(c, id)))
+                });
+                match position {
+                    Some(index) => {
+                        let current = std::mem::take(&mut existing[index]);
+                        existing[index] = deep_merge(current, item, None);
+                    }
+                    None => existing.push(item),
+                }
+            }
+            Value::Array(existing)
+        }
+        (_, compiled) => compiled,
+    }
+}
+
+// Karabiner omits `false` flags such as `is_pointing_device` that Pkl always writes out.
+fn same_identity(left: &Value, right: &Value) -> bool {
+    match (as_event(left).as_ref(), as_event(right).as_ref()) {
+        (Value::Object(left), Value::Object(right)) => left.keys().chain(right.keys()).all(|key| {
+            is_unset(left.get(key)) && is_unset(right.get(key)) || left.get(key) == right.get(key)
+        }),
+        (left, right) => left == right,
+    }
+}
+
+// A bare key code, as ankura writes an fn function key's `from`, is the `{"key_code": ...}`
+// Karabiner writes back.
+fn as_event(value: &Value) -> Cow<'_, Value> {
+    match value {
+        Value::String(key_code) => Cow::Owned(json!({ "key_code": key_code })),
+        _ => Cow::Borrowed(value),
+    }
+}
+
+fn is_unset(value: Option<&Value>) -> bool {
+    matches!(value, None | Some(Value::Null) | Some(Value::Bool(false)))
+}
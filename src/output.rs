@@ -0,0 +1,107 @@
+use crate::cli::homebrew_var_dir;
+use crate::error::{KarabinerPklError, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Advisory lock held by the CLI and the daemon around a read-merge-write of karabiner.json,
+/// so two writers never interleave. Released on drop.
+pub struct OutputLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl OutputLock {
+    pub fn acquire() -> Result<Self> {
+        let runtime_dir = homebrew_var_dir()?.join("run");
+        fs::create_dir_all(&runtime_dir).map_err(|e| KarabinerPklError::DaemonError {
+            message: format!(
+                "Failed to create runtime directory {}: {e}",
+                runtime_dir.display()
+            ),
+        })?;
+
+        let path = runtime_dir.join("ankura-write.lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| KarabinerPklError::DaemonError {
+                message: format!("Failed to open lock file {}: {e}", path.display()),
+            })?;
+
+        let started = Instant::now();
+        loop {
+            let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+            if result == 0 {
+                break;
+            }
+
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() != Some(libc::EWOULDBLOCK) {
+                return Err(KarabinerPklError::DaemonError {
+                    message: format!("Failed to lock {}: {error}", path.display()),
+                });
+            }
+            if started.elapsed() >= LOCK_TIMEOUT {
+                return Err(KarabinerPklError::DaemonError {
+                    message: format!(
+                        "Timed out waiting for another ankura process to finish writing ({})",
+                        path.display()
+                    ),
+                });
+            }
+            std::thread::sleep(LOCK_POLL_INTERVAL);
+        }
+
+        debug!("Acquired {}", path.display());
+        Ok(Self { file, path })
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+        debug!("Released {}", self.path.display());
+    }
+}
+
+/// Writes `contents` to a temp file next to `path` and renames it into place, so readers see
+/// either the old or the new file. The original file mode is kept and symlinks are followed.
+pub fn write_atomic(path: &Path, contents: &str) -> std::io::Result<()> {
+    let target = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => fs::canonicalize(path)?,
+        _ => path.to_path_buf(),
+    };
+
+    let dir = target.parent().unwrap_or(Path::new("."));
+    let file_name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let temp_path = dir.join(format!(".{file_name}.{}.tmp", std::process::id()));
+
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+
+        if let Ok(metadata) = fs::metadata(&target) {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+        }
+
+        fs::rename(&temp_path, &target)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
@@ -0,0 +1,105 @@
+use crate::backup;
+use crate::error::{KarabinerPklError, Result};
+use crate::karabiner::Config;
+use crate::merge::MergePolicy;
+use serde::Deserialize;
+
+/// The key `pkl/config.pkl` renders ankura's own settings under.
+pub const SETTINGS_KEY: &str = "ankura";
+
+/// Settings for ankura itself, read from the `ankura` block of the compiled config.
+/// They are never written to karabiner.json.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub merge: MergePolicy,
+    pub backups: BackupSettings,
+    pub daemon: DaemonSettings,
+    pub notifications: NotificationSettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BackupSettings {
+    /// How many karabiner.json backups to retain.
+    pub keep: usize,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        Self {
+            keep: backup::DEFAULT_KEEP,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DaemonSettings {
+    /// Seconds a compile may run before pkl is stopped and the change reported as failed.
+    pub compile_timeout: u64,
+}
+
+impl Default for DaemonSettings {
+    fn default() -> Self {
+        Self {
+            compile_timeout: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationBackend {
+    /// Notification Center on macOS, the log elsewhere.
+    #[default]
+    System,
+    Log,
+    Command,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct NotificationSettings {
+    pub backend: NotificationBackend,
+    /// Program and leading arguments for the `command` backend.
+    pub command: Vec<String>,
+    /// Seconds during which an error identical to the last one is not shown again.
+    pub dedupe_window: u64,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            backend: NotificationBackend::default(),
+            command: Vec::new(),
+            dedupe_window: 60,
+        }
+    }
+}
+
+impl Settings {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let Some(value) = config.extra.get(SETTINGS_KEY) else {
+            return Ok(Self::default());
+        };
+
+        let settings: Self = serde_path_to_error::deserialize(value).map_err(|e| {
+            KarabinerPklError::SchemaError {
+                path: format!("{SETTINGS_KEY}.{}", e.path()),
+                message: e.into_inner().to_string(),
+            }
+        })?;
+
+        let notifications = &settings.notifications;
+        if notifications.backend == NotificationBackend::Command && notifications.command.is_empty()
+        {
+            return Err(KarabinerPklError::SchemaError {
+                path: format!("{SETTINGS_KEY}.notifications.command"),
+                message: "The \"command\" backend needs a program to run".to_string(),
+            });
+        }
+
+        Ok(settings)
+    }
+}
@@ -0,0 +1,896 @@
+pub mod timeline;
+
+pub use timeline::{InputEvent, Timeline};
+
+use crate::karabiner::{
+    ComplexModificationParameters, Condition, FromEvent, KeyOrder, KeyUpWhen, Manipulator,
+    ManipulatorParameters, ManipulatorType, Profile, SimpleFrom, SimpleTo, ToEvent, VariableValue,
+};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+
+const MODIFIER_KEYS: &[&str] = &[
+    "left_command",
+    "right_command",
+    "left_control",
+    "right_control",
+    "left_option",
+    "right_option",
+    "left_shift",
+    "right_shift",
+    "fn",
+];
+
+/// State outside the key stream that conditions can depend on.
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    /// Bundle identifier matched by `frontmost_application_if`/`_unless`.
+    pub frontmost_application: Option<String>,
+    /// Matched by `keyboard_type_if`/`_unless`; Karabiner's default is `ansi`.
+    pub keyboard_type: Option<String>,
+    /// Variables set before the first event.
+    pub variables: BTreeMap<String, VariableValue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    KeyDown {
+        key: String,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        modifiers: Vec<String>,
+    },
+    KeyUp {
+        key: String,
+    },
+    ShellCommand {
+        command: String,
+    },
+    SetVariable {
+        name: String,
+        value: VariableValue,
+    },
+    /// Input source, mouse key, sticky modifier and software function events, as written.
+    Other {
+        event: Value,
+    },
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::KeyDown { key, modifiers } if modifiers.is_empty() => write!(f, "{key} down"),
+            Action::KeyDown { key, modifiers } => {
+                write!(f, "{key} down ({})", modifiers.join("+"))
+            }
+            Action::KeyUp { key } => write!(f, "{key} up"),
+            Action::ShellCommand { command } => write!(f, "shell: {command}"),
+            Action::SetVariable { name, value } => write!(f, "set {name} = {value}"),
+            Action::Other { event } => write!(f, "{event}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Emitted {
+    /// Milliseconds since the first input event.
+    pub time: u64,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// What Karabiner would send for a timeline, and the variables it leaves behind.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Simulation {
+    pub events: Vec<Emitted>,
+    pub variables: BTreeMap<String, VariableValue>,
+}
+
+impl fmt::Display for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.events.is_empty() {
+            writeln!(f, "No events emitted")?;
+        }
+        for event in &self.events {
+            writeln!(f, "{:>6}ms  {}", event.time, event.action)?;
+        }
+
+        if self.variables.is_empty() {
+            return writeln!(f, "Variables: none");
+        }
+        writeln!(f, "Variables:")?;
+        for (name, value) in &self.variables {
+            writeln!(f, "  {name} = {value}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Timing {
+    simultaneous_threshold: u64,
+    to_if_alone_timeout: u64,
+    to_if_held_down_threshold: u64,
+    to_delayed_action_delay: u64,
+}
+
+impl Timing {
+    // Karabiner-Elements' defaults for `basic.*` parameters.
+    fn of_profile(parameters: Option<&ComplexModificationParameters>) -> Self {
+        let defaults = Self {
+            simultaneous_threshold: 50,
+            to_if_alone_timeout: 1000,
+            to_if_held_down_threshold: 500,
+            to_delayed_action_delay: 500,
+        };
+        let Some(parameters) = parameters else {
+            return defaults;
+        };
+
+        Self {
+            simultaneous_threshold: millis(
+                parameters.simultaneous_threshold_milliseconds,
+                defaults.simultaneous_threshold,
+            ),
+            to_if_alone_timeout: millis(
+                parameters.to_if_alone_timeout_milliseconds,
+                defaults.to_if_alone_timeout,
+            ),
+            to_if_held_down_threshold: millis(
+                parameters.to_if_held_down_threshold_milliseconds,
+                defaults.to_if_held_down_threshold,
+            ),
+            to_delayed_action_delay: millis(
+                parameters.to_delayed_action_delay_milliseconds,
+                defaults.to_delayed_action_delay,
+            ),
+        }
+    }
+
+    fn with_overrides(self, parameters: Option<&ManipulatorParameters>) -> Self {
+        let Some(parameters) = parameters else {
+            return self;
+        };
+
+        Self {
+            simultaneous_threshold: millis(
+                parameters.simultaneous_threshold_milliseconds,
+                self.simultaneous_threshold,
+            ),
+            to_if_alone_timeout: millis(
+                parameters.to_if_alone_timeout_milliseconds,
+                self.to_if_alone_timeout,
+            ),
+            to_if_held_down_threshold: millis(
+                parameters.to_if_held_down_threshold_milliseconds,
+                self.to_if_held_down_threshold,
+            ),
+            to_delayed_action_delay: millis(
+                parameters.to_delayed_action_delay_milliseconds,
+                self.to_delayed_action_delay,
+            ),
+        }
+    }
+}
+
+fn millis(value: Option<i64>, default: u64) -> u64 {
+    value
+        .and_then(|value| u64::try_from(value).ok())
+        .unwrap_or(default)
+}
+
+/// Replays timelines through one profile's simple and complex modifications.
+///
+/// Simple modifications are applied as key renames before complex ones. Manipulators are
+/// tried in rule order and the first match wins, as in Karabiner-Elements. Device and input
+/// source conditions can't be known here, so `*_if` variants never match and `*_unless` always do.
+pub struct Simulator<'a> {
+    manipulators: Vec<&'a Manipulator>,
+    renames: HashMap<String, String>,
+    timing: Timing,
+    environment: Environment,
+}
+
+impl<'a> Simulator<'a> {
+    pub fn new(profile: &'a Profile) -> Self {
+        let manipulators = profile
+            .rules()
+            .iter()
+            .flat_map(|rule| rule.manipulators.iter())
+            .filter(|manipulator| manipulator.manipulator_type == ManipulatorType::Basic)
+            .collect();
+
+        let renames = profile
+            .simple_modifications
+            .iter()
+            .filter_map(|modification| {
+                let from = match &modification.from {
+                    SimpleFrom::KeyCode(key) => key.clone(),
+                    SimpleFrom::Event(event) => event.key_code.clone()?,
+                };
+                let to = match &modification.to {
+                    SimpleTo::KeyCode(key) => key.clone(),
+                    SimpleTo::Event(event) => event.key_code.clone()?,
+                    SimpleTo::Events(events) => events.first()?.key_code.clone()?,
+                };
+                Some((from, to))
+            })
+            .collect();
+
+        let parameters = profile
+            .complex_modifications
+            .as_ref()
+            .and_then(|complex| complex.parameters.as_ref());
+
+        Self {
+            manipulators,
+            renames,
+            timing: Timing::of_profile(parameters),
+            environment: Environment::default(),
+        }
+    }
+
+    pub fn with_environment(mut self, environment: Environment) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    pub fn run(&self, timeline: &Timeline) -> Simulation {
+        let queue = timeline
+            .events
+            .iter()
+            .map(|event| InputEvent {
+                key: self
+                    .renames
+                    .get(&event.key)
+                    .cloned()
+                    .unwrap_or_else(|| event.key.clone()),
+                ..event.clone()
+            })
+            .collect();
+
+        let mut run = Run {
+            simulator: self,
+            now: 0,
+            queue,
+            variables: self.environment.variables.clone(),
+            active: Vec::new(),
+            passthrough: Vec::new(),
+            delayed: None,
+            events: Vec::new(),
+        };
+
+        while let Some(event) = run.queue.pop_front() {
+            run.advance(event.time);
+            if event.pressed {
+                run.key_down(event.key);
+            } else {
+                run.key_up(&event.key);
+            }
+        }
+        run.advance(timeline.end);
+
+        Simulation {
+            events: run.events,
+            variables: run.variables,
+        }
+    }
+}
+
+// A manipulator that matched a key down and hasn't seen all of its keys released yet.
+struct Active<'a> {
+    manipulator: &'a Manipulator,
+    timing: Timing,
+    keys: Vec<String>,
+    released: Vec<String>,
+    consumed: Vec<String>,
+    pressed_at: u64,
+    interrupted: bool,
+    held_down_fired: bool,
+    finished: bool,
+    held: Vec<Held>,
+    key_up_variables: Vec<(String, VariableValue)>,
+}
+
+impl Active<'_> {
+    fn held_down_deadline(&self) -> Option<u64> {
+        (self.manipulator.to_if_held_down.is_some()
+            && !self.held_down_fired
+            && !self.interrupted
+            && !self.finished)
+            .then_some(self.pressed_at + self.timing.to_if_held_down_threshold)
+    }
+}
+
+struct Held {
+    key: String,
+    modifiers: Vec<String>,
+    emitted: bool,
+}
+
+struct Delayed<'a> {
+    manipulator: &'a Manipulator,
+    deadline: u64,
+}
+
+struct Run<'s, 'a> {
+    simulator: &'s Simulator<'a>,
+    now: u64,
+    queue: VecDeque<InputEvent>,
+    variables: BTreeMap<String, VariableValue>,
+    active: Vec<Active<'a>>,
+    passthrough: Vec<String>,
+    delayed: Option<Delayed<'a>>,
+    events: Vec<Emitted>,
+}
+
+enum Timer {
+    HeldDown(usize),
+    DelayedAction,
+}
+
+impl<'a> Run<'_, 'a> {
+    // Fires every held-down and delayed-action timer due by `time`, in order.
+    fn advance(&mut self, time: u64) {
+        loop {
+            let held_down = self
+                .active
+                .iter()
+                .enumerate()
+                .filter_map(|(index, active)| {
+                    active
+                        .held_down_deadline()
+                        .map(|deadline| (deadline, Timer::HeldDown(index)))
+                })
+                .min_by_key(|(deadline, _)| *deadline);
+            let delayed = self
+                .delayed
+                .as_ref()
+                .map(|delayed| (delayed.deadline, Timer::DelayedAction));
+
+            let next = match (held_down, delayed) {
+                (Some(a), Some(b)) => Some(if b.0 < a.0 { b } else { a }),
+                (a, b) => a.or(b),
+            };
+            let Some((deadline, timer)) = next.filter(|(deadline, _)| *deadline <= time) else {
+                break;
+            };
+
+            self.now = self.now.max(deadline);
+            match timer {
+                Timer::HeldDown(index) => {
+                    let active = &mut self.active[index];
+                    active.held_down_fired = true;
+                    let manipulator = active.manipulator;
+                    let consumed = active.consumed.clone();
+                    let held = self.press(
+                        manipulator.to_if_held_down.as_deref().unwrap_or_default(),
+                        &consumed,
+                    );
+                    self.active[index].held.extend(held);
+                }
+                Timer::DelayedAction => {
+                    if let Some(delayed) = self.delayed.take() {
+                        let events = delayed.manipulator.to_delayed_action.as_ref();
+                        self.tap(
+                            events
+                                .map(|e| e.to_if_invoked.as_slice())
+                                .unwrap_or_default(),
+                            &[],
+                        );
+                    }
+                }
+            }
+        }
+        self.now = self.now.max(time);
+    }
+
+    fn key_down(&mut self, key: String) {
+        if let Some(delayed) = self.delayed.take() {
+            let events = delayed.manipulator.to_delayed_action.as_ref();
+            self.tap(
+                events
+                    .map(|e| e.to_if_canceled.as_slice())
+                    .unwrap_or_default(),
+                &[],
+            );
+        }
+
+        // Another key cancels `to_if_alone` and `to_if_held_down`, and sends lazy modifiers.
+        let mut lazy = Vec::new();
+        for active in self.active.iter_mut().filter(|active| !active.finished) {
+            active.interrupted = true;
+            for held in active.held.iter_mut().filter(|held| !held.emitted) {
+                held.emitted = true;
+                lazy.push(Action::KeyDown {
+                    key: held.key.clone(),
+                    modifiers: held.modifiers.clone(),
+                });
+            }
+        }
+        for action in lazy {
+            self.emit(action);
+        }
+
+        let simulator = self.simulator;
+        for &manipulator in &simulator.manipulators {
+            let matched = if manipulator.from.simultaneous.is_some() {
+                self.match_simultaneous(manipulator, &key)
+            } else {
+                self.match_key(manipulator, &key)
+                    .map(|consumed| (vec![key.clone()], consumed))
+            };
+
+            if let Some((keys, consumed)) = matched {
+                self.activate(manipulator, keys, consumed);
+                return;
+            }
+        }
+
+        let modifiers = self.effective_modifiers(&[], &[], &key);
+        self.emit(Action::KeyDown {
+            key: key.clone(),
+            modifiers,
+        });
+        self.passthrough.push(key);
+    }
+
+    fn key_up(&mut self, key: &str) {
+        if let Some(position) = self.passthrough.iter().position(|held| held == key) {
+            self.passthrough.remove(position);
+            self.emit(Action::KeyUp {
+                key: key.to_string(),
+            });
+            return;
+        }
+
+        let Some(index) = self
+            .active
+            .iter()
+            .position(|active| active.keys.iter().any(|held| held == key))
+        else {
+            self.emit(Action::KeyUp {
+                key: key.to_string(),
+            });
+            return;
+        };
+
+        let active = &mut self.active[index];
+        active.keys.retain(|held| held != key);
+        active.released.push(key.to_string());
+
+        let key_up_when = active
+            .manipulator
+            .from
+            .simultaneous_options
+            .as_ref()
+            .and_then(|options| options.key_up_when)
+            .unwrap_or_default();
+        if !active.finished && (key_up_when == KeyUpWhen::Any || active.keys.is_empty()) {
+            self.release(index);
+        }
+
+        if self.active[index].keys.is_empty() {
+            let active = self.active.remove(index);
+            self.finish_simultaneous(&active);
+        }
+    }
+
+    fn activate(&mut self, manipulator: &'a Manipulator, keys: Vec<String>, consumed: Vec<String>) {
+        let timing = self
+            .simulator
+            .timing
+            .with_overrides(manipulator.parameters.as_ref());
+
+        let key_up_variables = manipulator
+            .to
+            .iter()
+            .flatten()
+            .filter_map(key_up_value)
+            .collect();
+
+        let held = self.press(manipulator.to.as_deref().unwrap_or_default(), &consumed);
+
+        if manipulator.to_delayed_action.is_some() {
+            self.delayed = Some(Delayed {
+                manipulator,
+                deadline: self.now + timing.to_delayed_action_delay,
+            });
+        }
+
+        self.active.push(Active {
+            manipulator,
+            timing,
+            keys,
+            released: Vec::new(),
+            consumed,
+            pressed_at: self.now,
+            interrupted: false,
+            held_down_fired: false,
+            finished: false,
+            held,
+            key_up_variables,
+        });
+    }
+
+    // Releases what the manipulator holds, then sends `to_if_alone` and `to_after_key_up`.
+    fn release(&mut self, index: usize) {
+        let active = &mut self.active[index];
+        active.finished = true;
+        let held = std::mem::take(&mut active.held);
+        let key_up_variables = std::mem::take(&mut active.key_up_variables);
+        let manipulator = active.manipulator;
+        let consumed = active.consumed.clone();
+        let alone = !active.interrupted
+            && !active.held_down_fired
+            && self.now.saturating_sub(active.pressed_at) < active.timing.to_if_alone_timeout;
+
+        for held in held.into_iter().rev().filter(|held| held.emitted) {
+            self.emit(Action::KeyUp { key: held.key });
+        }
+        for (name, value) in key_up_variables {
+            self.set_variable(name, value);
+        }
+
+        if alone {
+            self.tap(
+                manipulator.to_if_alone.as_deref().unwrap_or_default(),
+                &consumed,
+            );
+        }
+        self.tap(
+            manipulator.to_after_key_up.as_deref().unwrap_or_default(),
+            &consumed,
+        );
+    }
+
+    // `simultaneous_options.to_after_key_up` waits for every key and honours `key_up_order`.
+    fn finish_simultaneous(&mut self, active: &Active<'a>) {
+        let Some(options) = active.manipulator.from.simultaneous_options.as_ref() else {
+            return;
+        };
+        let Some(events) = options.to_after_key_up.as_deref() else {
+            return;
+        };
+
+        let order = options.key_up_order.unwrap_or_default();
+        if in_order(
+            &simultaneous_keys(&active.manipulator.from),
+            &active.released,
+            order,
+        ) {
+            self.tap(events, &[]);
+        }
+    }
+
+    fn match_key(&self, manipulator: &Manipulator, key: &str) -> Option<Vec<String>> {
+        let from = &manipulator.from;
+        let matches_key = from.key_code.as_deref() == Some(key)
+            || (from.key_code.is_none() && from.any.as_deref() == Some("key_code"));
+        if !matches_key {
+            return None;
+        }
+        self.match_state(manipulator)
+    }
+
+    // Looks ahead in the timeline for the rest of the keys, all pressed within the threshold.
+    fn match_simultaneous(
+        &mut self,
+        manipulator: &Manipulator,
+        key: &str,
+    ) -> Option<(Vec<String>, Vec<String>)> {
+        let from = &manipulator.from;
+        let members = simultaneous_keys(from);
+        if members.len() != from.simultaneous.as_ref().map_or(0, Vec::len)
+            || !members.iter().any(|member| member == key)
+        {
+            return None;
+        }
+        let consumed = self.match_state(manipulator)?;
+
+        let threshold = self
+            .simulator
+            .timing
+            .with_overrides(manipulator.parameters.as_ref())
+            .simultaneous_threshold;
+        let deadline = self.now + threshold;
+
+        let mut pressed = vec![key.to_string()];
+        let mut positions = Vec::new();
+        let mut last_time = self.now;
+        for (position, event) in self.queue.iter().enumerate() {
+            if pressed.len() == members.len() || event.time > deadline {
+                break;
+            }
+            let member = members.contains(&event.key);
+            match (event.pressed, member) {
+                (true, true) if !pressed.contains(&event.key) => {
+                    pressed.push(event.key.clone());
+                    positions.push(position);
+                    last_time = event.time;
+                }
+                (true, _) => return None,
+                (false, _) if pressed.contains(&event.key) => return None,
+                (false, _) => {}
+            }
+        }
+
+        let order = from
+            .simultaneous_options
+            .as_ref()
+            .and_then(|options| options.key_down_order)
+            .unwrap_or_default();
+        if pressed.len() != members.len() || !in_order(&members, &pressed, order) {
+            return None;
+        }
+
+        for position in positions.into_iter().rev() {
+            self.queue.remove(position);
+        }
+        self.advance(last_time);
+        Some((pressed, consumed))
+    }
+
+    fn match_state(&self, manipulator: &Manipulator) -> Option<Vec<String>> {
+        let consumed = match_modifiers(&manipulator.from, &self.held_modifiers())?;
+        manipulator
+            .conditions
+            .iter()
+            .flatten()
+            .all(|condition| self.condition_holds(condition))
+            .then_some(consumed)
+    }
+
+    fn condition_holds(&self, condition: &Condition) -> bool {
+        let environment = &self.simulator.environment;
+        match condition.condition_type.as_str() {
+            "variable_if" => condition.value.as_ref() == Some(&self.variable(condition)),
+            "variable_unless" => condition.value.as_ref() != Some(&self.variable(condition)),
+            kind @ ("frontmost_application_if" | "frontmost_application_unless") => {
+                let matched = environment
+                    .frontmost_application
+                    .as_deref()
+                    .is_some_and(|app| {
+                        condition
+                            .bundle_identifiers
+                            .iter()
+                            .flatten()
+                            .any(|pattern| {
+                                Regex::new(pattern).is_ok_and(|regex| regex.is_match(app))
+                            })
+                    });
+                matched == kind.ends_with("_if")
+            }
+            kind @ ("keyboard_type_if" | "keyboard_type_unless") => {
+                let keyboard_type = environment.keyboard_type.as_deref().unwrap_or("ansi");
+                let matched = condition
+                    .keyboard_types
+                    .iter()
+                    .flatten()
+                    .any(|candidate| candidate == keyboard_type);
+                matched == kind.ends_with("_if")
+            }
+            other => other.ends_with("_unless"),
+        }
+    }
+
+    // Karabiner treats a variable that was never set as 0.
+    fn variable(&self, condition: &Condition) -> VariableValue {
+        condition
+            .name
+            .as_ref()
+            .and_then(|name| self.variables.get(name))
+            .cloned()
+            .unwrap_or(VariableValue::Int(0))
+    }
+
+    fn held_modifiers(&self) -> Vec<String> {
+        let mut modifiers: Vec<String> = Vec::new();
+        let held = self.active.iter().flat_map(|active| active.held.iter());
+        let candidates = self
+            .passthrough
+            .iter()
+            .filter(|key| is_modifier(key))
+            .chain(
+                held.clone()
+                    .map(|held| &held.key)
+                    .filter(|key| is_modifier(key)),
+            )
+            .chain(held.flat_map(|held| held.modifiers.iter()));
+
+        for modifier in candidates {
+            if !modifiers.contains(modifier) {
+                modifiers.push(modifier.clone());
+            }
+        }
+        modifiers
+    }
+
+    // Held modifiers minus those the manipulator consumed, plus the event's own.
+    fn effective_modifiers(&self, consumed: &[String], extra: &[String], key: &str) -> Vec<String> {
+        let mut modifiers: Vec<String> = self
+            .held_modifiers()
+            .into_iter()
+            .filter(|modifier| !consumed.contains(modifier))
+            .collect();
+        for modifier in extra {
+            if !modifiers.contains(modifier) {
+                modifiers.push(modifier.clone());
+            }
+        }
+        modifiers.retain(|modifier| modifier != key);
+        modifiers
+    }
+
+    // Sends `to` events: every key but the last is tapped, the last one stays held.
+    fn press(&mut self, events: &[ToEvent], consumed: &[String]) -> Vec<Held> {
+        let last_key = events.iter().rposition(|event| event.key_code.is_some());
+        let mut held = Vec::new();
+
+        for (index, event) in events.iter().enumerate() {
+            if let Some(key) = &event.key_code {
+                let own = event.modifiers.clone().unwrap_or_default();
+                if Some(index) == last_key {
+                    let lazy = event.lazy == Some(true);
+                    if !lazy {
+                        let modifiers = self.effective_modifiers(consumed, &own, key);
+                        self.emit(Action::KeyDown {
+                            key: key.clone(),
+                            modifiers,
+                        });
+                    }
+                    held.push(Held {
+                        key: key.clone(),
+                        modifiers: own,
+                        emitted: !lazy,
+                    });
+                } else {
+                    self.tap_key(key, &own, consumed);
+                }
+            }
+            self.perform(event);
+        }
+        held
+    }
+
+    fn tap(&mut self, events: &[ToEvent], consumed: &[String]) {
+        for event in events {
+            if let Some(key) = &event.key_code {
+                self.tap_key(
+                    key,
+                    event.modifiers.as_deref().unwrap_or_default(),
+                    consumed,
+                );
+            }
+            self.perform(event);
+            if let Some((name, value)) = key_up_value(event) {
+                self.set_variable(name, value);
+            }
+        }
+    }
+
+    fn tap_key(&mut self, key: &str, own: &[String], consumed: &[String]) {
+        let modifiers = self.effective_modifiers(consumed, own, key);
+        self.emit(Action::KeyDown {
+            key: key.to_string(),
+            modifiers,
+        });
+        self.emit(Action::KeyUp {
+            key: key.to_string(),
+        });
+    }
+
+    // Everything a `to` event does besides pressing a key.
+    fn perform(&mut self, event: &ToEvent) {
+        if let Some(command) = &event.shell_command {
+            self.emit(Action::ShellCommand {
+                command: command.clone(),
+            });
+        }
+        if let Some(variable) = &event.set_variable {
+            if let Some(value) = &variable.value {
+                self.set_variable(variable.name.clone(), value.clone());
+            }
+        }
+
+        let other = ToEvent {
+            select_input_source: event.select_input_source.clone(),
+            mouse_key: event.mouse_key.clone(),
+            sticky_modifier: event.sticky_modifier.clone(),
+            software_function: event.software_function.clone(),
+            ..Default::default()
+        };
+        if other != ToEvent::default() {
+            self.emit(Action::Other {
+                event: serde_json::to_value(&other).unwrap_or_default(),
+            });
+        }
+    }
+
+    fn set_variable(&mut self, name: String, value: VariableValue) {
+        self.variables.insert(name.clone(), value.clone());
+        self.emit(Action::SetVariable { name, value });
+    }
+
+    fn emit(&mut self, action: Action) {
+        self.events.push(Emitted {
+            time: self.now,
+            action,
+        });
+    }
+}
+
+fn is_modifier(key: &str) -> bool {
+    MODIFIER_KEYS.contains(&key)
+}
+
+fn sided_generic(modifier: &str) -> Option<&'static str> {
+    match modifier {
+        "left_command" | "right_command" => Some("command"),
+        "left_control" | "right_control" => Some("control"),
+        "left_option" | "right_option" => Some("option"),
+        "left_shift" | "right_shift" => Some("shift"),
+        _ => None,
+    }
+}
+
+fn satisfies(held: &str, wanted: &str) -> bool {
+    held == wanted || sided_generic(held) == Some(wanted)
+}
+
+// Every mandatory modifier must be held and every other held modifier must be optional.
+// Returns the held modifiers the mandatory ones consumed.
+fn match_modifiers(from: &FromEvent, held: &[String]) -> Option<Vec<String>> {
+    let (mandatory, optional) = from
+        .modifiers
+        .as_ref()
+        .map(|modifiers| {
+            (
+                modifiers.mandatory.as_slice(),
+                modifiers.optional.as_slice(),
+            )
+        })
+        .unwrap_or_default();
+
+    let mut remaining: Vec<&String> = held.iter().collect();
+    let mut consumed = Vec::new();
+    for wanted in mandatory {
+        let position = remaining.iter().position(|held| satisfies(held, wanted))?;
+        consumed.push(remaining.remove(position).clone());
+    }
+
+    let accepts_any = optional.iter().any(|modifier| modifier == "any");
+    let rest_optional = remaining
+        .iter()
+        .all(|held| optional.iter().any(|wanted| satisfies(held, wanted)));
+    (accepts_any || rest_optional).then_some(consumed)
+}
+
+// `set_variable.key_up_value` is applied when the key that set the variable is released.
+fn key_up_value(event: &ToEvent) -> Option<(String, VariableValue)> {
+    let variable = event.set_variable.as_ref()?;
+    let value = variable.extra.get("key_up_value")?;
+    Some((
+        variable.name.clone(),
+        serde_json::from_value(value.clone()).ok()?,
+    ))
+}
+
+fn simultaneous_keys(from: &FromEvent) -> Vec<String> {
+    from.simultaneous
+        .iter()
+        .flatten()
+        .filter_map(|event| event.key_code.clone())
+        .collect()
+}
+
+fn in_order(expected: &[String], actual: &[String], order: KeyOrder) -> bool {
+    match order {
+        KeyOrder::Insensitive => true,
+        KeyOrder::Strict => expected == actual,
+        KeyOrder::StrictInverse => expected.iter().rev().eq(actual.iter()),
+    }
+}
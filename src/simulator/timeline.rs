@@ -0,0 +1,124 @@
+use crate::analysis::keycodes::{self, CodeKind, KEY_CODES};
+use crate::error::{KarabinerPklError, Result};
+use std::str::FromStr;
+
+/// Milliseconds between consecutive key events unless a `wait` says otherwise.
+pub const DEFAULT_STEP_MS: u64 = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputEvent {
+    pub time: u64,
+    pub key: String,
+    pub pressed: bool,
+}
+
+/// Physical key presses with timestamps, in the order they happen.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timeline {
+    pub events: Vec<InputEvent>,
+    /// When the timeline stops; timers that are due by then still fire.
+    pub end: u64,
+}
+
+impl Timeline {
+    /// Parses comma-separated steps such as `caps_lock down, h, wait 300, caps_lock up`.
+    ///
+    /// A step is `KEY down`, `KEY up`, a bare `KEY` (pressed and released), or `wait MS`.
+    /// Keys joined with `+` are pressed in order and released in reverse.
+    pub fn parse(input: &str, step: u64) -> Result<Self> {
+        let mut timeline = Timeline::default();
+        let mut clock = 0;
+
+        for token in input.split([',', '\n']).map(str::trim) {
+            if token.is_empty() {
+                continue;
+            }
+
+            if let Some(duration) = parse_wait(token)? {
+                clock += duration;
+                continue;
+            }
+
+            let words: Vec<&str> = token.split_whitespace().collect();
+            let (chord, action) = match words.as_slice() {
+                [chord] => (*chord, None),
+                [chord, action @ ("down" | "up")] => (*chord, Some(*action == "down")),
+                _ => {
+                    return Err(KarabinerPklError::InvalidTimeline {
+                        message: format!("Cannot read step \"{token}\""),
+                        help: Some(
+                            "Use `KEY down`, `KEY up`, `KEY` for a tap, or `wait MS`".to_string(),
+                        ),
+                    })
+                }
+            };
+
+            let keys = chord
+                .split('+')
+                .map(|key| validate_key(key.trim()))
+                .collect::<Result<Vec<String>>>()?;
+
+            let mut push = |key: &String, pressed: bool| {
+                timeline.events.push(InputEvent {
+                    time: clock,
+                    key: key.clone(),
+                    pressed,
+                });
+                clock += step;
+            };
+
+            match action {
+                Some(true) => keys.iter().for_each(|key| push(key, true)),
+                Some(false) => keys.iter().rev().for_each(|key| push(key, false)),
+                None => {
+                    keys.iter().for_each(|key| push(key, true));
+                    keys.iter().rev().for_each(|key| push(key, false));
+                }
+            }
+        }
+
+        timeline.end = clock;
+        Ok(timeline)
+    }
+}
+
+impl FromStr for Timeline {
+    type Err = KarabinerPklError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        Self::parse(input, DEFAULT_STEP_MS)
+    }
+}
+
+fn parse_wait(token: &str) -> Result<Option<u64>> {
+    let duration = match token.strip_prefix("wait") {
+        Some(rest) if rest.starts_with(char::is_whitespace) => rest.trim(),
+        _ if token.ends_with("ms") && token.starts_with(|c: char| c.is_ascii_digit()) => token,
+        _ => return Ok(None),
+    };
+
+    duration
+        .trim_end_matches("ms")
+        .trim()
+        .parse()
+        .map(Some)
+        .map_err(|_| KarabinerPklError::InvalidTimeline {
+            message: format!("Invalid wait \"{token}\""),
+            help: Some("Waits are whole milliseconds, e.g. `wait 300` or `300ms`".to_string()),
+        })
+}
+
+fn validate_key(key: &str) -> Result<String> {
+    if KEY_CODES.contains(&key) {
+        return Ok(key.to_string());
+    }
+
+    let suggestions = keycodes::suggest(CodeKind::KeyCode, key);
+    Err(KarabinerPklError::InvalidTimeline {
+        message: format!("Unknown key_code \"{key}\""),
+        help: (!suggestions.is_empty()).then(|| {
+            let names: Vec<String> = suggestions.iter().map(|s| format!("`{s}`")).collect();
+            format!("did you mean {}?", names.join(" or "))
+        }),
+    })
+}
@@ -0,0 +1,107 @@
+use crate::diff::{self, ConfigDiff};
+use crate::error::{KarabinerPklError, Result};
+use crate::karabiner::Config;
+use crate::output;
+use similar::TextDiff;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// `ankura.pkl` is snapshotted to `ankura.snapshot.json` next to it.
+pub fn default_path(config_path: &Path) -> PathBuf {
+    let stem = config_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "ankura".to_string());
+    config_path.with_file_name(format!("{stem}.snapshot.json"))
+}
+
+// Only the keys Karabiner reads: the rest of the rendered Pkl module (rules, keys, the
+// `ankura` settings block) is input, not output.
+fn snapshot_config(config: &Config) -> Config {
+    Config {
+        title: config.title.clone(),
+        global: config.global.clone(),
+        profiles: config.profiles.clone(),
+        extra: Default::default(),
+    }
+}
+
+pub fn render(config: &Config) -> Result<String> {
+    let mut json = snapshot_config(config).to_json_pretty()?;
+    json.push('\n');
+    Ok(json)
+}
+
+/// Writes the snapshot; returns false when it was already up to date.
+pub fn write(path: &Path, config: &Config) -> Result<bool> {
+    let contents = render(config)?;
+    if std::fs::read_to_string(path).is_ok_and(|existing| existing == contents) {
+        return Ok(false);
+    }
+
+    let write_error = |source| KarabinerPklError::ConfigWriteError {
+        path: path.to_path_buf(),
+        source,
+    };
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent).map_err(write_error)?;
+    }
+    output::write_atomic(path, &contents).map_err(write_error)?;
+    Ok(true)
+}
+
+/// How the compiled output differs from a snapshot.
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    pub changes: ConfigDiff,
+    /// Unified diff of the snapshot file against the freshly rendered one.
+    pub unified: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.changes.is_empty() {
+            writeln!(f, "{}", self.changes)?;
+        }
+        write!(f, "{}", self.unified)
+    }
+}
+
+/// Compares the compiled output with the snapshot at `path`; `None` when they match.
+pub fn check(path: &Path, config: &Config) -> Result<Option<Mismatch>> {
+    let expected = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(KarabinerPklError::ValidationError {
+                message: format!(
+                    "No snapshot at {}; run `ankura snapshot` to create it",
+                    path.display()
+                ),
+            })
+        }
+        Err(e) => {
+            return Err(KarabinerPklError::ConfigReadError {
+                path: path.to_path_buf(),
+                source: e,
+            })
+        }
+    };
+
+    let actual = render(config)?;
+    if expected == actual {
+        return Ok(None);
+    }
+
+    let previous = Config::from_json_str(&expected)?;
+    let changes = diff::diff_configs(&previous, &snapshot_config(config));
+    let unified = TextDiff::from_lines(&expected, &actual)
+        .unified_diff()
+        .context_radius(3)
+        .header(&path.display().to_string(), "compiled")
+        .to_string();
+
+    Ok(Some(Mismatch { changes, unified }))
+}
@@ -0,0 +1,371 @@
+use crate::compiler::Compiler;
+use crate::error::{KarabinerPklError, Result};
+use crate::karabiner::{Config, Profile, VariableValue};
+use crate::simulator::timeline::DEFAULT_STEP_MS;
+use crate::simulator::{Action, Environment, Simulation, Simulator, Timeline};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+// JSON suites are only read when named explicitly; directories often hold other JSON files.
+const SUITE_EXTENSIONS: &[&str] = &["toml", "pkl"];
+
+/// A file of keymap tests. Mirrors `pkl/test.pkl`; TOML suites use `[[test]]` tables.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Suite {
+    /// Config under test, relative to the suite file. Defaults to `ankura --config`.
+    #[serde(default)]
+    pub config: Option<PathBuf>,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default, alias = "test")]
+    pub tests: Vec<Case>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Case {
+    pub name: String,
+    pub input: String,
+    /// Events expected in order, possibly with others in between. A bare key name matches
+    /// that key going down with any modifiers; anything else is compared with the
+    /// `ankura simulate` rendering of an event.
+    #[serde(default)]
+    pub expect: Vec<String>,
+    /// `expect` lists every emitted event.
+    #[serde(default)]
+    pub exact: bool,
+    #[serde(default)]
+    pub absent: Vec<String>,
+    /// Shell commands expected in order.
+    #[serde(default)]
+    pub shell: Vec<String>,
+    /// Variable values expected after the last event.
+    #[serde(default)]
+    pub expect_variables: BTreeMap<String, VariableValue>,
+    /// Variables set before the first event.
+    #[serde(default)]
+    pub initial_variables: BTreeMap<String, VariableValue>,
+    #[serde(default)]
+    pub app: Option<String>,
+    #[serde(default)]
+    pub step: Option<u64>,
+}
+
+impl Suite {
+    pub async fn load(path: &Path, compiler: &Compiler) -> Result<Self> {
+        if path.extension().and_then(|ext| ext.to_str()) != Some("pkl") {
+            return Self::read(path);
+        }
+
+        let value = compiler.evaluate_json(path).await?;
+        serde_path_to_error::deserialize(value).map_err(|e| KarabinerPklError::InvalidTestSuite {
+            path: path.to_path_buf(),
+            message: format!("at `{}`: {}", e.path(), e.inner()),
+        })
+    }
+
+    /// Reads a TOML or JSON suite; Pkl suites are evaluated by `load`.
+    pub fn read(path: &Path) -> Result<Self> {
+        let invalid = |message: String| KarabinerPklError::InvalidTestSuite {
+            path: path.to_path_buf(),
+            message,
+        };
+        let read = || {
+            std::fs::read_to_string(path).map_err(|e| KarabinerPklError::ConfigReadError {
+                path: path.to_path_buf(),
+                source: e,
+            })
+        };
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&read()?).map_err(|e| invalid(e.to_string())),
+            Some("json") => serde_json::from_str(&read()?).map_err(|e| invalid(e.to_string())),
+            Some("pkl") => Err(invalid(
+                "Pkl suites need a compiler to evaluate".to_string(),
+            )),
+            _ => Err(invalid("Expected a .toml, .pkl or .json file".to_string())),
+        }
+    }
+
+    pub fn run(&self, profile: &Profile) -> Vec<CaseResult> {
+        self.tests.iter().map(|case| case.run(profile)).collect()
+    }
+}
+
+/// Suite files among `paths`; directories are searched recursively.
+pub fn collect_suites(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut suites = Vec::new();
+
+    for path in paths {
+        if !path.is_dir() {
+            suites.push(path.clone());
+            continue;
+        }
+
+        let mut pending = vec![path.clone()];
+        let mut found = Vec::new();
+        while let Some(dir) = pending.pop() {
+            let entries =
+                std::fs::read_dir(&dir).map_err(|e| KarabinerPklError::ConfigReadError {
+                    path: dir.clone(),
+                    source: e,
+                })?;
+            for entry in entries.flatten() {
+                let entry_path = entry.path();
+                if entry_path.is_dir() {
+                    pending.push(entry_path);
+                } else if entry_path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| SUITE_EXTENSIONS.contains(&ext))
+                {
+                    found.push(entry_path);
+                }
+            }
+        }
+        found.sort();
+        suites.extend(found);
+    }
+
+    Ok(suites)
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseResult {
+    pub name: String,
+    pub failures: Vec<String>,
+    pub simulation: Simulation,
+}
+
+impl CaseResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SuiteReport {
+    pub path: PathBuf,
+    pub cases: Vec<CaseResult>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub suites: Vec<SuiteReport>,
+}
+
+impl Report {
+    pub fn passed(&self) -> usize {
+        self.cases().filter(|case| case.passed()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.cases().filter(|case| !case.passed()).count()
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed() == 0
+    }
+
+    fn cases(&self) -> impl Iterator<Item = &CaseResult> {
+        self.suites.iter().flat_map(|suite| suite.cases.iter())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for suite in &self.suites {
+            writeln!(f, "{}", suite.path.display())?;
+            for case in &suite.cases {
+                if case.passed() {
+                    writeln!(f, "  ✅ {}", case.name)?;
+                    continue;
+                }
+
+                writeln!(f, "  ❌ {}", case.name)?;
+                for failure in &case.failures {
+                    writeln!(f, "      {failure}")?;
+                }
+                writeln!(f, "      emitted:")?;
+                for line in case.simulation.to_string().lines() {
+                    writeln!(f, "      {line}")?;
+                }
+            }
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{} passed, {} failed", self.passed(), self.failed())
+    }
+}
+
+/// Runs every suite under `paths` against its config, compiling each config once.
+/// `config_path` and `profile_name` apply to suites that don't name their own.
+pub async fn run(
+    compiler: &Compiler,
+    config_path: &Path,
+    paths: &[PathBuf],
+    profile_name: Option<&str>,
+) -> Result<Report> {
+    let mut configs: HashMap<(PathBuf, Option<String>), Config> = HashMap::new();
+    let mut report = Report::default();
+
+    for path in collect_suites(paths)? {
+        let suite = Suite::load(&path, compiler).await?;
+
+        let config = match &suite.config {
+            Some(config) => path.parent().unwrap_or(Path::new(".")).join(config),
+            None => config_path.to_path_buf(),
+        };
+        let profile = profile_name.map(str::to_string).or(suite.profile.clone());
+        let key = (config, profile);
+
+        if !configs.contains_key(&key) {
+            debug!("Compiling {} for {}", key.0.display(), path.display());
+            let compiled = compiler.compile(&key.0, key.1.as_deref()).await?;
+            configs.insert(key.clone(), compiled);
+        }
+        let Some(profile) = configs[&key].active_profile() else {
+            return Err(KarabinerPklError::ValidationError {
+                message: format!("{} has no profiles to test", key.0.display()),
+            });
+        };
+
+        report.suites.push(SuiteReport {
+            cases: suite.run(profile),
+            path,
+        });
+    }
+
+    Ok(report)
+}
+
+impl Case {
+    pub fn run(&self, profile: &Profile) -> CaseResult {
+        let timeline = match Timeline::parse(&self.input, self.step.unwrap_or(DEFAULT_STEP_MS)) {
+            Ok(timeline) => timeline,
+            Err(e) => {
+                let help = match &e {
+                    KarabinerPklError::InvalidTimeline {
+                        help: Some(help), ..
+                    } => format!(" ({help})"),
+                    _ => String::new(),
+                };
+                return CaseResult {
+                    name: self.name.clone(),
+                    failures: vec![format!("{e}{help}")],
+                    simulation: Simulation::default(),
+                };
+            }
+        };
+
+        let simulation = Simulator::new(profile)
+            .with_environment(Environment {
+                frontmost_application: self.app.clone(),
+                variables: self.initial_variables.clone(),
+                ..Default::default()
+            })
+            .run(&timeline);
+
+        CaseResult {
+            name: self.name.clone(),
+            failures: self.check(&simulation),
+            simulation,
+        }
+    }
+
+    fn check(&self, simulation: &Simulation) -> Vec<String> {
+        let actions: Vec<&Action> = simulation.events.iter().map(|e| &e.action).collect();
+        let mut failures = Vec::new();
+
+        if self.exact {
+            let matches = actions.len() == self.expect.len()
+                && self
+                    .expect
+                    .iter()
+                    .zip(&actions)
+                    .all(|(expected, action)| matches(expected, action));
+            if !matches {
+                failures.push(format!(
+                    "expected exactly [{}]",
+                    self.expect
+                        .iter()
+                        .map(|e| format!("`{e}`"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+        } else if let Some(failure) = missing_in_order(&self.expect, &actions, matches) {
+            failures.push(failure);
+        }
+
+        for unwanted in &self.absent {
+            if let Some(event) = simulation
+                .events
+                .iter()
+                .find(|event| matches(unwanted, &event.action))
+            {
+                failures.push(format!("`{unwanted}` was emitted at {}ms", event.time));
+            }
+        }
+
+        let shell_matches = |expected: &String, action: &Action| matches!(action, Action::ShellCommand { command } if command == expected);
+        if let Some(failure) = missing_in_order(&self.shell, &actions, shell_matches) {
+            failures.push(failure);
+        }
+
+        for (name, expected) in &self.expect_variables {
+            // Karabiner reads a variable that was never set as 0.
+            let actual = simulation
+                .variables
+                .get(name)
+                .cloned()
+                .unwrap_or(VariableValue::Int(0));
+            if &actual != expected {
+                failures.push(format!(
+                    "variable `{name}` is {actual}, expected {expected}"
+                ));
+            }
+        }
+
+        failures
+    }
+}
+
+fn matches(expected: &String, action: &Action) -> bool {
+    match action {
+        Action::KeyDown { key, .. } if key == expected => true,
+        _ => action.to_string() == *expected,
+    }
+}
+
+fn missing_in_order(
+    expected: &[String],
+    actions: &[&Action],
+    matches: impl Fn(&String, &Action) -> bool,
+) -> Option<String> {
+    let mut cursor = 0;
+    let mut previous: Option<&String> = None;
+
+    for wanted in expected {
+        match actions[cursor..]
+            .iter()
+            .position(|action| matches(wanted, action))
+        {
+            Some(offset) => {
+                cursor += offset + 1;
+                previous = Some(wanted);
+            }
+            None => {
+                return Some(match previous {
+                    Some(previous) => format!("`{wanted}` was not emitted after `{previous}`"),
+                    None => format!("`{wanted}` was not emitted"),
+                })
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,37 @@
+use ankura::karabiner::{Config, Profile};
+use ankura::simulator::{Simulator, Timeline};
+use std::path::PathBuf;
+
+pub fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+pub fn load_fixture(name: &str) -> String {
+    std::fs::read_to_string(fixture_path(name))
+        .unwrap_or_else(|e| panic!("Failed to read fixture {name}: {e}"))
+}
+
+pub fn config(json: &str) -> Config {
+    Config::from_json_str(json).expect("Failed to parse config")
+}
+
+pub fn fixture_config(name: &str) -> Config {
+    config(&load_fixture(name))
+}
+
+pub fn profile(config: &Config) -> &Profile {
+    config.active_profile().expect("Config has no profiles")
+}
+
+/// Runs `input` through `profile` and renders each emitted event as `ankura simulate` does.
+pub fn simulate(profile: &Profile, input: &str) -> Vec<String> {
+    let timeline: Timeline = input.parse().expect("Failed to parse timeline");
+    Simulator::new(profile)
+        .run(&timeline)
+        .events
+        .iter()
+        .map(|event| event.action.to_string())
+        .collect()
+}
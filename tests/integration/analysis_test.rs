@@ -0,0 +1,74 @@
+use crate::helpers::config;
+use ankura::analysis::{analyze_config, FindingKind};
+use pretty_assertions::assert_eq;
+
+// Rules binding `h` under the given conditions, in order.
+fn bindings(conditions: &[&str]) -> ankura::karabiner::Config {
+    let rules: Vec<String> = conditions
+        .iter()
+        .enumerate()
+        .map(|(index, conditions)| {
+            format!(
+                r#"{{"description": "Rule {index}", "manipulators": [{{
+                    "type": "basic",
+                    "from": {{"key_code": "h"}},
+                    "to": [{{"key_code": "left_arrow"}}],
+                    "conditions": [{conditions}]
+                }}]}}"#
+            )
+        })
+        .collect();
+    config(&format!(
+        r#"{{"profiles": [{{"name": "Default", "complex_modifications": {{"rules": [{}]}}}}]}}"#,
+        rules.join(",")
+    ))
+}
+
+fn kinds(config: &ankura::karabiner::Config, layer_overlaps: bool) -> Vec<FindingKind> {
+    analyze_config(config, layer_overlaps)
+        .into_iter()
+        .map(|finding| finding.kind)
+        .collect()
+}
+
+#[test]
+fn test_exclusive_conditions_dont_conflict() {
+    let values = bindings(&[
+        r#"{"type": "variable_if", "name": "mode", "value": "sym"}"#,
+        r#"{"type": "variable_if", "name": "mode", "value": "num"}"#,
+    ]);
+    let negated = bindings(&[
+        r#"{"type": "variable_if", "name": "nav", "value": 1}"#,
+        r#"{"type": "variable_unless", "name": "nav", "value": 1}"#,
+    ]);
+
+    assert_eq!(kinds(&values, true), vec![]);
+    assert_eq!(kinds(&negated, true), vec![]);
+}
+
+#[test]
+fn test_different_layers_are_opt_in() {
+    // A SimLayer held while a layer key is down sets both variables.
+    let config = bindings(&[
+        r#"{"type": "variable_if", "name": "nav", "value": 1}"#,
+        r#"{"type": "variable_if", "name": "sym-simlayer", "value": true}"#,
+    ]);
+
+    assert_eq!(kinds(&config, false), vec![]);
+    assert_eq!(kinds(&config, true), vec![FindingKind::LayerOverlap]);
+}
+
+#[test]
+fn test_off_layer_still_conflicts() {
+    // `nav = 0` and `sym = false` are the base layer, which the `nav` rule competes with.
+    let config = bindings(&[
+        r#"{"type": "variable_if", "name": "sym", "value": false}"#,
+        r#"{"type": "variable_if", "name": "nav", "value": 0}"#,
+        r#"{"type": "variable_if", "name": "sym", "value": false}, {"type": "variable_if", "name": "nav", "value": 1}"#,
+    ]);
+
+    assert_eq!(
+        kinds(&config, false),
+        vec![FindingKind::Conflict, FindingKind::Conflict]
+    );
+}
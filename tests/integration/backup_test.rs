@@ -0,0 +1,48 @@
+use ankura::backup::BackupStore;
+use pretty_assertions::assert_eq;
+
+#[test]
+fn test_backups_in_the_same_millisecond_are_kept() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let source = dir.path().join("karabiner.json");
+    let store = BackupStore::at(dir.path().join("backups"));
+
+    // Fast enough that several land in the same millisecond and need a suffix.
+    let mut ids = Vec::new();
+    for write in 0..12 {
+        std::fs::write(&source, write.to_string()).expect("Failed to write source");
+        let backup = store.create(&source, 20).expect("Failed to back up");
+        ids.push(backup.expect("Source exists").id);
+    }
+
+    let backups = store.list().expect("Failed to list backups");
+    let contents: Vec<String> = backups
+        .iter()
+        .map(|backup| std::fs::read_to_string(&backup.path).expect("Failed to read backup"))
+        .collect();
+    let expected: Vec<String> = (0..12).rev().map(|write| write.to_string()).collect();
+    assert_eq!(contents, expected);
+
+    for id in &ids {
+        assert_eq!(&store.find(Some(id)).expect("Failed to find backup").id, id);
+    }
+}
+
+#[test]
+fn test_backups_are_pruned_to_keep() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let source = dir.path().join("karabiner.json");
+    let store = BackupStore::at(dir.path().join("backups"));
+
+    for write in 0..5 {
+        std::fs::write(&source, write.to_string()).expect("Failed to write source");
+        store.create(&source, 3).expect("Failed to back up");
+    }
+
+    let newest = store.find(None).expect("Failed to find newest backup");
+    assert_eq!(store.list().expect("Failed to list backups").len(), 3);
+    assert_eq!(
+        std::fs::read_to_string(&newest.path).expect("Failed to read backup"),
+        "4"
+    );
+}
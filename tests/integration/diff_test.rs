@@ -0,0 +1,200 @@
+use crate::helpers::{config, fixture_config};
+use ankura::diff::{diff_configs, Change, Moved};
+use ankura::karabiner::Config;
+use pretty_assertions::assert_eq;
+
+fn edited(edit: impl FnOnce(&mut serde_json::Value)) -> Config {
+    let mut value: serde_json::Value =
+        serde_json::to_value(fixture_config("keymap.json")).expect("Failed to encode config");
+    edit(&mut value);
+    config(&value.to_string())
+}
+
+fn rules(value: &mut serde_json::Value) -> &mut Vec<serde_json::Value> {
+    value["profiles"][0]["complex_modifications"]["rules"]
+        .as_array_mut()
+        .expect("rules")
+}
+
+#[test]
+fn test_identical_configs() {
+    let current = fixture_config("keymap.json");
+
+    let diff = diff_configs(&current, &current.clone());
+    assert!(diff.is_empty());
+    assert_eq!(diff.to_string(), "No changes\n");
+}
+
+#[test]
+fn test_managed_marker_is_not_a_change() {
+    let current = fixture_config("keymap.json");
+    let proposed = edited(|value| {
+        for rule in rules(value) {
+            rule["ankura_managed"] = true.into();
+        }
+    });
+
+    assert!(diff_configs(&current, &proposed).is_empty());
+}
+
+#[test]
+fn test_added_and_removed_rules() {
+    let current = fixture_config("keymap.json");
+    let proposed = edited(|value| {
+        let rules = rules(value);
+        rules.remove(1);
+        rules.push(serde_json::json!({
+            "description": "Escape to Caps Lock",
+            "manipulators": [{"type": "basic", "from": {"key_code": "escape"}, "to": [{"key_code": "caps_lock"}]}]
+        }));
+    });
+
+    let diff = diff_configs(&current, &proposed);
+    let changes: Vec<(Change, &str)> = diff.profiles[0]
+        .rules
+        .iter()
+        .map(|rule| (rule.change, rule.description.as_str()))
+        .collect();
+    assert_eq!(
+        changes,
+        vec![
+            (Change::Added, "Escape to Caps Lock"),
+            (Change::Removed, "Tab to F18 when held"),
+        ]
+    );
+    assert_eq!(
+        diff.profiles[0].rules[0].manipulators[0].binding,
+        "escape → caps_lock"
+    );
+}
+
+#[test]
+fn test_changed_manipulator_lists_fields() {
+    let current = fixture_config("keymap.json");
+    let proposed = edited(|value| {
+        rules(value)[0]["manipulators"][0]["to_if_alone"] =
+            serde_json::json!([{"key_code": "f13"}]);
+    });
+
+    let diff = diff_configs(&current, &proposed);
+    let rule = &diff.profiles[0].rules[0];
+    assert_eq!(rule.change, Change::Changed);
+    assert_eq!(rule.manipulators.len(), 1);
+    assert_eq!(rule.manipulators[0].change, Change::Changed);
+    assert_eq!(rule.manipulators[0].fields, vec!["to_if_alone"]);
+    assert!(diff
+        .to_string()
+        .contains("~ caps_lock → left_control (alone: f13) (to_if_alone changed)"));
+}
+
+#[test]
+fn test_profile_fields_and_profiles() {
+    let current = fixture_config("keymap.json");
+    let proposed = edited(|value| {
+        value["profiles"][0]["selected"] = false.into();
+        value["profiles"]
+            .as_array_mut()
+            .expect("profiles")
+            .push(serde_json::json!({"name": "Work"}));
+    });
+
+    let diff = diff_configs(&current, &proposed);
+    assert_eq!(diff.profiles[0].fields[0].field, "selected");
+    assert_eq!(diff.profiles[1].name, "Work");
+    assert_eq!(diff.profiles[1].change, Change::Added);
+
+    let reverse = diff_configs(&proposed, &current);
+    assert_eq!(reverse.profiles[1].change, Change::Removed);
+}
+
+#[test]
+fn test_reordered_rules_are_moved() {
+    let current = fixture_config("keymap.json");
+    let proposed = edited(|value| {
+        let rules = rules(value);
+        let rule = rules.remove(3);
+        rules.insert(0, rule);
+    });
+
+    let diff = diff_configs(&current, &proposed);
+    assert!(!diff.is_empty());
+    assert_eq!(diff.profiles[0].rules.len(), 1);
+    let rule = &diff.profiles[0].rules[0];
+    assert_eq!(rule.change, Change::Moved);
+    assert_eq!(rule.moved, Some(Moved { from: 4, to: 1 }));
+    assert!(rule.manipulators.is_empty());
+    assert_eq!(
+        diff.to_string(),
+        "Profile \"Default\":\n  ↕ rule \"Spacebar navigation layer\" (moved from 4 to 1)\n"
+    );
+
+    let json = serde_json::to_value(&diff).expect("Failed to encode diff");
+    assert_eq!(
+        json["profiles"][0]["rules"][0]["moved"],
+        serde_json::json!({"from": 4, "to": 1})
+    );
+}
+
+#[test]
+fn test_insertion_does_not_move_later_rules() {
+    let current = fixture_config("keymap.json");
+    let proposed = edited(|value| {
+        rules(value).insert(
+            0,
+            serde_json::json!({"description": "New first rule", "manipulators": []}),
+        );
+    });
+
+    let diff = diff_configs(&current, &proposed);
+    let changes: Vec<Change> = diff.profiles[0]
+        .rules
+        .iter()
+        .map(|rule| rule.change)
+        .collect();
+    assert_eq!(changes, vec![Change::Added]);
+}
+
+#[test]
+fn test_reordered_manipulators_are_moved() {
+    let current = fixture_config("keymap.json");
+    let proposed = edited(|value| {
+        let manipulators = rules(value)[3]["manipulators"]
+            .as_array_mut()
+            .expect("manipulators");
+        let mut manipulator = manipulators.remove(2);
+        manipulator["to"] = serde_json::json!([{"key_code": "end"}]);
+        manipulators.insert(0, manipulator);
+    });
+
+    let diff = diff_configs(&current, &proposed);
+    let rule = &diff.profiles[0].rules[0];
+    assert_eq!(rule.change, Change::Changed);
+    assert_eq!(rule.moved, None);
+
+    let manipulators: Vec<(Change, Option<Moved>)> = rule
+        .manipulators
+        .iter()
+        .map(|manipulator| (manipulator.change, manipulator.moved))
+        .collect();
+    assert_eq!(
+        manipulators,
+        vec![(Change::Changed, Some(Moved { from: 3, to: 1 }))]
+    );
+    assert_eq!(
+        diff.to_string(),
+        "Profile \"Default\":\n  ~ rule \"Spacebar navigation layer\"\n      ~ l → end (to changed; moved from 3 to 1)\n"
+    );
+
+    // Swapping two neighbours is one move, not two.
+    let proposed = edited(|value| {
+        rules(value)[3]["manipulators"]
+            .as_array_mut()
+            .expect("manipulators")
+            .swap(1, 2);
+    });
+    let diff = diff_configs(&current, &proposed);
+    let rule = &diff.profiles[0].rules[0];
+    assert_eq!(rule.change, Change::Changed);
+    assert_eq!(rule.manipulators.len(), 1);
+    assert_eq!(rule.manipulators[0].change, Change::Moved);
+}
@@ -0,0 +1,78 @@
+use ankura::import::edn::{parse, Edn};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+
+#[test]
+fn test_scalars() {
+    assert_eq!(parse("nil"), Ok(Edn::Nil));
+    assert_eq!(parse("true"), Ok(Edn::Bool(true)));
+    assert_eq!(parse("-42"), Ok(Edn::Int(-42)));
+    assert_eq!(parse("1.5"), Ok(Edn::Float(1.5)));
+    assert_eq!(
+        parse(r#""a \"b\"""#),
+        Ok(Edn::String("a \"b\"".to_string()))
+    );
+    assert_eq!(
+        parse(":left_command"),
+        Ok(Edn::Keyword("left_command".to_string()))
+    );
+    assert_eq!(parse(r"\a"), Ok(Edn::Char('a')));
+}
+
+#[test]
+fn test_collections_keep_order() {
+    let value = parse("{:b [1 2], :a #{:x}, :c (nil)}").expect("Failed to parse");
+
+    let keys: Vec<&str> = value
+        .as_map()
+        .expect("map")
+        .iter()
+        .filter_map(|(key, _)| key.as_keyword())
+        .collect();
+    assert_eq!(keys, vec!["b", "a", "c"]);
+    assert_eq!(
+        value.get("b"),
+        Some(&Edn::Vector(vec![Edn::Int(1), Edn::Int(2)]))
+    );
+    assert_eq!(
+        value.get("a"),
+        Some(&Edn::Set(vec![Edn::Keyword("x".to_string())]))
+    );
+    assert_eq!(value.get("c"), Some(&Edn::List(vec![Edn::Nil])));
+}
+
+#[test]
+fn test_comments_and_discard() {
+    let value = parse("; Goku config\n{:main [#_ [:old :rule] [:a :b]] ; trailing\n}")
+        .expect("Failed to parse");
+
+    assert_eq!(
+        value.get("main").and_then(Edn::as_seq).map(<[Edn]>::len),
+        Some(1)
+    );
+}
+
+#[test]
+fn test_to_json() {
+    let value = parse(r#"{:type "basic" :from {:key_code :a} :to [{:key_code :b}]}"#)
+        .expect("Failed to parse");
+
+    assert_eq!(
+        value.to_json(),
+        Some(json!({"type": "basic", "from": {"key_code": "a"}, "to": [{"key_code": "b"}]}))
+    );
+}
+
+#[test]
+fn test_errors_have_positions() {
+    let error = parse("{:a 1\n :b}").unwrap_err();
+    assert_eq!(error.line, 1);
+    assert!(error.message.contains("even number"));
+
+    let error = parse("[1 2\n3").unwrap_err();
+    assert_eq!(error.line, 2);
+    assert!(error.message.contains("`]`"));
+
+    assert!(parse("[1] [2]").is_err());
+    assert!(parse("\"open").is_err());
+}
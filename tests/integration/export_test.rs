@@ -0,0 +1,183 @@
+use crate::helpers::{config, fixture_config, fixture_path};
+use ankura::export::firmware::Layout;
+use ankura::export::{export, Exported, Format};
+
+fn render(format: Format) -> Exported {
+    let layout = Layout::load("ansi").expect("Failed to load layout");
+    export(
+        &fixture_config("keymap.json"),
+        format,
+        &fixture_path("keymap.json"),
+        &layout,
+    )
+    .expect("Failed to export")
+}
+
+// Command shortcuts, none of which is a layer.
+const COMMAND_SHORTCUTS: &str = r#"{"profiles": [{"name": "Default", "complex_modifications": {"rules": [{
+    "description": "Command shortcuts",
+    "manipulators": [{
+        "type": "basic",
+        "from": {"key_code": "w", "modifiers": {"mandatory": ["left_command"]}},
+        "to": [{"shell_command": "open -a Safari"}]
+    }, {
+        "type": "basic",
+        "from": {"key_code": "q", "modifiers": {"mandatory": ["left_command"]}},
+        "to": [{"key_code": "vk_none"}],
+        "conditions": [{"type": "frontmost_application_if", "bundle_identifiers": ["^com\\.apple\\.Terminal$"]}]
+    }, {
+        "type": "basic",
+        "from": {"key_code": "h", "modifiers": {"mandatory": ["left_command"]}},
+        "to": [{"key_code": "left_arrow"}]
+    }]
+}]}}]}"#;
+
+fn render_shortcuts(format: Format) -> Exported {
+    let layout = Layout::load("ansi").expect("Failed to load layout");
+    export(
+        &config(COMMAND_SHORTCUTS),
+        format,
+        &fixture_path("keymap.json"),
+        &layout,
+    )
+    .expect("Failed to export")
+}
+
+// The tokens of the first line of `block` that starts with `prefix`.
+fn row<'a>(source: &'a str, block: &str, prefix: &str) -> Vec<&'a str> {
+    let start = source.find(block).expect("block") + block.len();
+    source[start..]
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with(prefix))
+        .expect("row")
+        .split_whitespace()
+        .collect()
+}
+
+#[test]
+fn test_kanata() {
+    let Exported { source, summary } = render(Format::Kanata);
+
+    assert!(source.contains("caps (tap-hold-press 300 300 esc lctl)"));
+    assert!(source.contains("tab (tap-hold-press 300 300 tab f18)"));
+    assert!(source.contains("(defchords chords 50"));
+    assert!(source.contains("(j k) esc"));
+    assert_eq!(
+        row(&source, "(defsrc", "tab"),
+        vec!["tab", "caps", "h", "j", "k", "l", "rmet"]
+    );
+    assert_eq!(
+        row(&source, "(deflayer base", "@tab"),
+        vec!["@tab", "@caps", "_", "@j", "@k", "_", "ralt"]
+    );
+    assert_eq!(
+        row(&source, "(deflayer nav", "_"),
+        vec!["_", "_", "left", "_", "_", "rght", "_"]
+    );
+    assert_eq!(summary.manipulators, 7);
+}
+
+#[test]
+fn test_qmk() {
+    let Exported { source, summary } = render(Format::Qmk);
+
+    assert!(source.contains("#include QMK_KEYBOARD_H"));
+    assert!(source.contains("[_BASE] = LAYOUT_60_ansi("));
+    assert!(source.contains("MT(MOD_LCTL, KC_ESC)"));
+    assert!(source.contains("[_NAV] = LAYOUT_60_ansi("));
+    assert!(source.contains("KC_LEFT"));
+    assert!(summary
+        .skipped
+        .iter()
+        .any(|skipped| skipped.contains("simultaneous keys need combos")));
+
+    // Every layer places all 61 keys of the layout.
+    let base = source
+        .split("[_BASE] = LAYOUT_60_ansi(")
+        .nth(1)
+        .and_then(|rest| rest.split("\n    ),").next())
+        .expect("base layer")
+        .replace("MT(MOD_LCTL, KC_ESC)", "MT");
+    assert_eq!(base.split(',').count(), 61);
+}
+
+#[test]
+fn test_zmk() {
+    let Exported { source, .. } = render(Format::Zmk);
+
+    assert!(source.contains("#define L_BASE 0"));
+    assert!(source.contains("#define L_NAV 1"));
+    assert!(source.contains("&mt LCTRL ESC"));
+    assert!(source.contains("nav_layer {"));
+    assert!(source.contains("&kp LEFT"));
+}
+
+#[test]
+fn test_edn() {
+    let Exported { source, .. } = render(Format::Edn);
+
+    assert!(source.contains("[:##caps_lock :left_control nil {:alone :escape}]"));
+    assert!(source.contains("[:tab nil nil {:alone :tab :held :f18}]"));
+    assert!(source.contains("[[:j :k] :escape]"));
+    assert!(source.contains("[:!COt \"open -a Terminal\"]"));
+}
+
+#[test]
+fn test_layout_files() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let path = dir.path().join("macropad.txt");
+    std::fs::write(
+        &path,
+        "# A 2x2 pad\nmacro LAYOUT_ortho_2x2\nq w\n_ caps_lock\n",
+    )
+    .expect("Failed to write layout");
+
+    let layout = Layout::load(&path.to_string_lossy()).expect("Failed to load layout");
+    let exported = export(
+        &fixture_config("keymap.json"),
+        Format::Qmk,
+        &fixture_path("keymap.json"),
+        &layout,
+    )
+    .expect("Failed to export");
+    assert!(exported.source.contains("LAYOUT_ortho_2x2("));
+    assert!(exported.source.contains("MT(MOD_LCTL, KC_ESC)"));
+
+    assert!(Layout::load("no-such-layout").is_err());
+}
+
+#[test]
+fn test_firmware_keeps_modifiers_plain() {
+    let Exported { source, summary } = render_shortcuts(Format::Qmk);
+
+    assert!(!source.contains("LEFT_COMMAND"), "{source}");
+    assert!(source.contains("KC_LGUI"));
+    assert_eq!(source.matches("LAYOUT_60_ansi(").count(), 1);
+    assert_eq!(
+        summary.skipped,
+        vec![
+            "rule \"Command shortcuts\": shell_command (macOS only)",
+            "rule \"Command shortcuts\": frontmost_application_if condition (macOS only)",
+            "rule \"Command shortcuts\": h with left_command held needs a key override, which isn't exported",
+        ]
+    );
+
+    let Exported { source, .. } = render_shortcuts(Format::Zmk);
+    assert!(!source.contains("L_LEFT_COMMAND"), "{source}");
+    assert!(source.contains("&kp LGUI"));
+}
+
+#[test]
+fn test_kanata_reports_macos_only_first() {
+    let Exported { summary, .. } = render_shortcuts(Format::Kanata);
+
+    assert_eq!(
+        summary.skipped,
+        vec![
+            "rule \"Command shortcuts\": shell_command (macOS only)",
+            "rule \"Command shortcuts\": frontmost_application_if condition (macOS only)",
+            "rule \"Command shortcuts\": h with left_command held can't be a layer key",
+        ]
+    );
+}
@@ -0,0 +1,134 @@
+use crate::helpers::{fixture_path, load_fixture};
+use ankura::import::goku;
+use ankura::KarabinerPklError;
+use std::path::Path;
+
+fn convert(source: &str) -> (String, String) {
+    let (pkl, summary) =
+        goku::generate(source, None, Path::new("karabiner.edn")).expect("Failed to convert");
+    (pkl, summary.to_string())
+}
+
+#[test]
+fn test_fixture_conversion() {
+    let (pkl, summary) = goku::generate(
+        &load_fixture("karabiner.edn"),
+        None,
+        &fixture_path("karabiner.edn"),
+    )
+    .expect("Failed to convert");
+
+    assert!(pkl.contains("name = \"Default\""));
+    assert!(pkl.contains("new DualUse {"));
+    assert!(pkl.contains("tap = keys.escape"));
+    assert!(pkl.contains("hold = keys.leftControl"));
+    assert!(pkl.contains("shell_command = \"open -a Terminal\""));
+    assert!(pkl.contains("type = \"frontmost_application_if\""));
+    assert!(pkl.contains("`basic.to_if_alone_timeout_milliseconds` = 800"));
+    assert_eq!(summary.rules, 4);
+}
+
+#[test]
+fn test_modifier_prefixes() {
+    let (pkl, _) = convert(r#"{:main [{:des "Hyper" :rules [[:!CTOSh :home] [:##a :b]]}]}"#);
+
+    assert!(pkl.contains(
+        r#"modifiers = List("left_command", "left_control", "left_option", "left_shift")"#
+    ));
+    assert!(pkl.contains(r#"optional = List("any")"#));
+}
+
+#[test]
+fn test_simlayer_at_sim_layer_threshold() {
+    let (pkl, summary) = convert(
+        r#"{:simlayer-threshold 200
+            :simlayers {:nav-mode {:key :f}}
+            :main [{:des "Navigation" :rules [:nav-mode [:h :left_arrow] [:l :right_arrow]]}]}"#,
+    );
+
+    assert!(pkl.contains("new SimLayer {"), "{pkl}");
+    assert!(pkl.contains("trigger = \"f\""));
+    assert!(pkl.contains(r#"maps { ["h"] = keys.leftArrow; ["l"] = keys.rightArrow }"#));
+    assert!(summary.contains("uninterrupted key down"));
+}
+
+#[test]
+fn test_simlayer_keeps_goku_threshold() {
+    // Without :simlayer-threshold, Goku uses 250ms.
+    let (pkl, summary) = convert(
+        r#"{:simlayers {:nav-mode {:key :f}}
+            :main [{:des "Navigation" :rules [:nav-mode [:h :left_arrow]]}]}"#,
+    );
+
+    assert!(pkl.contains("new SimLayer {"), "{pkl}");
+    assert!(pkl.contains("threshold = 250"), "{pkl}");
+    assert!(!summary.contains("threshold"), "{summary}");
+}
+
+#[test]
+fn test_layers() {
+    let (pkl, _) = convert(
+        r#"{:layers {:sym-mode {:key :spacebar :alone {:key :spacebar}}}
+            :main [{:des "Symbols" :rules [:sym-mode [:j :open_bracket]]}]}"#,
+    );
+
+    assert!(pkl.contains("\"sym-mode\""), "{pkl}");
+    assert!(pkl.contains("open_bracket"));
+}
+
+#[test]
+fn test_unknown_keys_are_skipped() {
+    let (pkl, summary) = goku::generate(
+        r#"{:simlayers {:bad-mode {:key :ff}}
+            :main [{:des "Keys" :rules [[:é :b]
+                                        [:hyper-mode :a]
+                                        [:!Ccapslock :escape]
+                                        [:a :b]]}]}"#,
+        None,
+        Path::new("karabiner.edn"),
+    )
+    .expect("Failed to convert");
+
+    assert_eq!(summary.rules, 1);
+    assert!(!pkl.contains("é") && !pkl.contains("hyper-mode") && !pkl.contains("capslock"));
+    let skipped = summary.skipped.join("\n");
+    assert!(skipped.contains("unknown key_code `é`"), "{skipped}");
+    assert!(
+        skipped.contains("unknown key_code `hyper-mode`"),
+        "{skipped}"
+    );
+    assert!(
+        skipped.contains("unknown key_code `capslock`, did you mean `caps_lock`?"),
+        "{skipped}"
+    );
+    assert!(skipped.contains(":simlayers :bad-mode"), "{skipped}");
+}
+
+#[test]
+fn test_unknown_profile() {
+    let error = goku::generate(
+        &load_fixture("karabiner.edn"),
+        Some("Work"),
+        Path::new("karabiner.edn"),
+    )
+    .unwrap_err();
+
+    match error {
+        KarabinerPklError::ValidationError { message } => {
+            assert!(message.contains("available profiles: Default"))
+        }
+        other => panic!("Expected ValidationError, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_invalid_edn_reports_position() {
+    let error =
+        goku::generate("{:main [\n  [:a :b]\n", None, Path::new("karabiner.edn")).unwrap_err();
+
+    match error {
+        KarabinerPklError::InvalidEdn { line, .. } => assert_eq!(line, 3),
+        other => panic!("Expected InvalidEdn, got {other:?}"),
+    }
+    assert!(goku::generate("[:a]", None, Path::new("karabiner.edn")).is_err());
+}
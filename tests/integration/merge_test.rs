@@ -0,0 +1,201 @@
+use crate::helpers::config;
+use ankura::merge::{deep_merge, is_managed, merge_config, MergePolicy, MergeStrategy};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+
+const EXISTING: &str = r#"{
+    "global": {"show_in_menu_bar": false},
+    "profiles": [{
+        "name": "Default",
+        "selected": true,
+        "virtual_hid_keyboard": {"keyboard_type_v2": "iso"},
+        "devices": [{
+            "identifiers": {"vendor_id": 1452, "product_id": 834, "is_keyboard": true},
+            "ignore": true
+        }],
+        "complex_modifications": {"rules": [
+            {"description": "Old managed rule", "ankura_managed": true, "manipulators": []},
+            {"description": "From the gallery", "manipulators": []}
+        ]},
+        "karabiner_only": 1
+    }]
+}"#;
+
+const COMPILED: &str = r#"{
+    "global": {"check_for_updates_on_startup": true},
+    "profiles": [{
+        "name": "Default",
+        "virtual_hid_keyboard": {"keyboard_type_v2": "ansi"},
+        "devices": [{
+            "identifiers": {"vendor_id": 1452, "product_id": 834, "is_keyboard": true, "is_pointing_device": false},
+            "manipulate_caps_lock_led": false
+        }, {
+            "identifiers": {"vendor_id": 1133, "product_id": 50475, "is_keyboard": true},
+            "ignore": false
+        }],
+        "complex_modifications": {"rules": [
+            {"description": "Caps Lock to Escape", "manipulators": []}
+        ]}
+    }, {
+        "name": "Work",
+        "complex_modifications": {"rules": []}
+    }]
+}"#;
+
+fn merged(policy: &MergePolicy) -> ankura::karabiner::Config {
+    merge_config(config(EXISTING), config(COMPILED), policy).expect("Failed to merge")
+}
+
+#[test]
+fn test_rules_replace_managed_and_keep_foreign() {
+    let merged = merged(&MergePolicy::default());
+    let rules = merged.profiles[0].rules();
+
+    let descriptions: Vec<&str> = rules.iter().map(|rule| rule.description.as_str()).collect();
+    assert_eq!(
+        descriptions,
+        vec!["Caps Lock to Escape", "From the gallery"]
+    );
+    assert!(is_managed(&rules[0]));
+    assert!(!is_managed(&rules[1]));
+}
+
+#[test]
+fn test_foreign_rules_before() {
+    let mut policy = MergePolicy::default();
+    policy
+        .apply_overrides(&["foreign_rules=before".to_string()])
+        .expect("Failed to apply override");
+
+    let merged = merged(&policy);
+    assert_eq!(
+        merged.profiles[0].rules()[0].description,
+        "From the gallery"
+    );
+}
+
+#[test]
+fn test_profile_keeps_selection_and_unknown_keys() {
+    let merged = merged(&MergePolicy::default());
+
+    assert!(merged.profiles[0].selected);
+    assert_eq!(merged.profiles[0].extra["karabiner_only"], json!(1));
+    assert_eq!(merged.profiles[1].name, "Work");
+}
+
+#[test]
+fn test_default_policy_keeps_existing_keyboard() {
+    let merged = merged(&MergePolicy::default());
+
+    assert_eq!(
+        merged.profiles[0]
+            .virtual_hid_keyboard
+            .as_ref()
+            .and_then(|keyboard| keyboard.keyboard_type_v2.as_deref()),
+        Some("iso")
+    );
+}
+
+#[test]
+fn test_devices_deep_merge_by_identifiers() {
+    let merged = merged(&MergePolicy::default());
+    let devices = &merged.profiles[0].devices;
+
+    // `is_pointing_device: false` is the same device as one without the flag.
+    assert_eq!(devices.len(), 2);
+    assert_eq!(devices[0].ignore, Some(true));
+    assert_eq!(devices[0].manipulate_caps_lock_led, Some(false));
+    assert_eq!(devices[1].identifiers.vendor_id, Some(1133));
+}
+
+#[test]
+fn test_pkl_strategy_replaces() {
+    let mut policy = MergePolicy::default();
+    policy
+        .apply_overrides(&["all=pkl".to_string()])
+        .expect("Failed to apply override");
+    assert_eq!(policy.devices, MergeStrategy::Pkl);
+
+    let merged = merged(&policy);
+    let profile = &merged.profiles[0];
+    assert_eq!(profile.devices[0].ignore, None);
+    assert_eq!(
+        profile
+            .virtual_hid_keyboard
+            .as_ref()
+            .and_then(|keyboard| keyboard.keyboard_type_v2.as_deref()),
+        Some("ansi")
+    );
+    assert_eq!(
+        merged.global.and_then(|global| global.show_in_menu_bar),
+        None
+    );
+}
+
+#[test]
+fn test_global_deep_merge() {
+    let merged = merged(&MergePolicy::default());
+    let global = merged.global.expect("global is kept");
+
+    assert_eq!(global.show_in_menu_bar, Some(false));
+    assert_eq!(global.check_for_updates_on_startup, Some(true));
+}
+
+#[test]
+fn test_invalid_overrides() {
+    let mut policy = MergePolicy::default();
+
+    assert!(policy.apply_overrides(&["devices".to_string()]).is_err());
+    assert!(policy
+        .apply_overrides(&["devices=sometimes".to_string()])
+        .is_err());
+    assert!(policy.apply_overrides(&["keys=pkl".to_string()]).is_err());
+}
+
+#[test]
+fn test_deep_merge_values() {
+    let existing = json!({"a": 1, "nested": {"keep": true, "swap": 1}, "list": [1, 2]});
+    let compiled = json!({"b": 2, "nested": {"swap": 2}, "list": [3]});
+
+    assert_eq!(
+        deep_merge(existing, compiled, None),
+        json!({"a": 1, "b": 2, "nested": {"keep": true, "swap": 2}, "list": [3]})
+    );
+}
+
+#[test]
+fn test_fn_function_keys_match_bare_key_codes() {
+    // Karabiner writes `from` as an event; ankura compiles it to a bare key code.
+    let existing = config(
+        r#"{"profiles": [{"name": "Default", "fn_function_keys": [
+            {"from": {"key_code": "f1"}, "to": [{"consumer_key_code": "display_brightness_decrement"}]},
+            {"from": {"key_code": "f2"}, "to": [{"consumer_key_code": "display_brightness_increment"}]}
+        ]}]}"#,
+    );
+    let compiled = config(
+        r#"{"profiles": [{"name": "Default", "fn_function_keys": [
+            {"from": "f1", "to": [{"key_code": "f1"}]}
+        ]}]}"#,
+    );
+
+    let merged =
+        merge_config(existing, compiled, &MergePolicy::default()).expect("Failed to merge");
+    let keys = serde_json::to_value(&merged.profiles[0].fn_function_keys)
+        .expect("Failed to encode fn function keys");
+    assert_eq!(
+        keys,
+        json!([
+            {"from": "f1", "to": [{"key_code": "f1"}]},
+            {"from": {"key_code": "f2"}, "to": [{"consumer_key_code": "display_brightness_increment"}]}
+        ])
+    );
+
+    // Merging the result again adds nothing.
+    let again = merge_config(
+        merged.clone(),
+        config(r#"{"profiles": [{"name": "Default", "fn_function_keys": [{"from": "f1", "to": [{"key_code": "f1"}]}]}]}"#),
+        &MergePolicy::default(),
+    )
+    .expect("Failed to merge");
+    assert_eq!(again.profiles[0].fn_function_keys.len(), 2);
+}
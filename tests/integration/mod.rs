@@ -0,0 +1,11 @@
+mod analysis_test;
+mod backup_test;
+mod diff_test;
+mod edn_test;
+mod export_test;
+mod goku_test;
+mod merge_test;
+mod notifier_test;
+mod pkl_error_test;
+mod simulator_test;
+mod testing_test;
@@ -0,0 +1,81 @@
+use ankura::daemon::notifier::{Level, NotificationManager, Recorder};
+use ankura::settings::{NotificationBackend, NotificationSettings};
+use pretty_assertions::assert_eq;
+use std::sync::Arc;
+
+fn pinned() -> (Arc<Recorder>, NotificationManager) {
+    let recorder = Arc::new(Recorder::default());
+    let manager = NotificationManager::pinned(recorder.clone());
+    (recorder, manager)
+}
+
+fn messages(recorder: &Recorder) -> Vec<(Level, String)> {
+    recorder
+        .sent()
+        .into_iter()
+        .map(|sent| (sent.level, sent.message))
+        .collect()
+}
+
+#[test]
+fn test_identical_errors_within_window_are_dropped() {
+    let (recorder, manager) = pinned();
+
+    manager.send_error("line 3: unknown key");
+    manager.send_error("line 3: unknown key");
+    manager.send_error("line 4: unknown key");
+
+    assert_eq!(
+        messages(&recorder),
+        vec![
+            (Level::Error, "line 3: unknown key".to_string()),
+            (Level::Error, "line 4: unknown key".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_success_resets_dedupe() {
+    let (recorder, manager) = pinned();
+
+    manager.send_error("line 3: unknown key");
+    manager.send_success("Configuration compiled");
+    manager.send_error("line 3: unknown key");
+
+    assert_eq!(
+        messages(&recorder),
+        vec![
+            (Level::Error, "line 3: unknown key".to_string()),
+            (Level::Success, "Configuration compiled".to_string()),
+            (Level::Error, "line 3: unknown key".to_string()),
+        ]
+    );
+    assert_eq!(recorder.sent()[1].title, "Ankura - Success");
+}
+
+#[test]
+fn test_zero_window_repeats_errors() {
+    let (recorder, manager) = pinned();
+
+    manager.configure(&NotificationSettings {
+        dedupe_window: 0,
+        ..Default::default()
+    });
+    manager.send_error("line 3: unknown key");
+    manager.send_error("line 3: unknown key");
+
+    assert_eq!(recorder.sent().len(), 2);
+}
+
+#[test]
+fn test_pinned_notifier_survives_backend_change() {
+    let (recorder, manager) = pinned();
+
+    manager.configure(&NotificationSettings {
+        backend: NotificationBackend::Log,
+        ..Default::default()
+    });
+    manager.send_success("Configuration compiled");
+
+    assert_eq!(recorder.sent().len(), 1);
+}
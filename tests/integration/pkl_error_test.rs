@@ -0,0 +1,25 @@
+use ankura::compiler::pkl_error::parse;
+use pretty_assertions::assert_eq;
+use std::path::PathBuf;
+
+#[test]
+fn test_frame_paths_are_percent_decoded() {
+    let report = parse(
+        "–– Pkl Error ––\n\
+         Expected value of type `Int`.\n\
+         \n\
+         at config#layers (file:///tmp/my%20dir/caf%C3%A9.pkl, line 3)\n\
+         at config#keys (file:///tmp/café%2Fkeys%20, line 7)\n\
+         at config#rest (file:///tmp/ü%zz%2, line 9)",
+    );
+
+    let paths: Vec<Option<PathBuf>> = report.frames.into_iter().map(|frame| frame.path).collect();
+    assert_eq!(
+        paths,
+        vec![
+            Some(PathBuf::from("/tmp/my dir/café.pkl")),
+            Some(PathBuf::from("/tmp/café/keys ")),
+            Some(PathBuf::from("/tmp/ü%zz%2")),
+        ]
+    );
+}
@@ -0,0 +1,251 @@
+use crate::helpers::{config, fixture_config, profile, simulate};
+use ankura::karabiner::VariableValue;
+use ankura::simulator::{Environment, Simulator, Timeline};
+use pretty_assertions::assert_eq;
+use std::collections::BTreeMap;
+
+#[test]
+fn test_timeline_taps_and_waits() {
+    let timeline = Timeline::parse("caps_lock down, h, wait 300, caps_lock up", 10)
+        .expect("Failed to parse timeline");
+
+    let steps: Vec<(u64, &str, bool)> = timeline
+        .events
+        .iter()
+        .map(|event| (event.time, event.key.as_str(), event.pressed))
+        .collect();
+    assert_eq!(
+        steps,
+        vec![
+            (0, "caps_lock", true),
+            (10, "h", true),
+            (20, "h", false),
+            (330, "caps_lock", false),
+        ]
+    );
+    assert_eq!(timeline.end, 340);
+}
+
+#[test]
+fn test_timeline_chords_release_in_reverse() {
+    let timeline: Timeline = "left_shift+a".parse().expect("Failed to parse timeline");
+
+    let keys: Vec<(&str, bool)> = timeline
+        .events
+        .iter()
+        .map(|event| (event.key.as_str(), event.pressed))
+        .collect();
+    assert_eq!(
+        keys,
+        vec![
+            ("left_shift", true),
+            ("a", true),
+            ("a", false),
+            ("left_shift", false)
+        ]
+    );
+}
+
+#[test]
+fn test_timeline_rejects_unknown_keys() {
+    let error = "caps_lok".parse::<Timeline>().unwrap_err();
+    assert!(error.to_string().contains("caps_lok"));
+
+    assert!("a sideways".parse::<Timeline>().is_err());
+    assert!("wait soon".parse::<Timeline>().is_err());
+}
+
+#[test]
+fn test_to_if_alone_on_tap() {
+    let config = fixture_config("keymap.json");
+
+    assert_eq!(
+        simulate(profile(&config), "caps_lock"),
+        vec![
+            "left_control down",
+            "left_control up",
+            "escape down",
+            "escape up"
+        ]
+    );
+}
+
+#[test]
+fn test_to_if_alone_cancelled_by_another_key() {
+    let config = fixture_config("keymap.json");
+
+    assert_eq!(
+        simulate(profile(&config), "caps_lock down, h, caps_lock up"),
+        vec![
+            "left_control down",
+            "h down (left_control)",
+            "h up",
+            "left_control up"
+        ]
+    );
+}
+
+#[test]
+fn test_to_if_alone_cancelled_by_timeout() {
+    let config = fixture_config("keymap.json");
+
+    // The fixture sets basic.to_if_alone_timeout_milliseconds to 300.
+    assert_eq!(
+        simulate(profile(&config), "caps_lock down, wait 400, caps_lock up"),
+        vec!["left_control down", "left_control up"]
+    );
+}
+
+#[test]
+fn test_held_down_fires_after_threshold() {
+    let config = fixture_config("keymap.json");
+    let timeline: Timeline = "tab down, wait 250, tab up"
+        .parse()
+        .expect("Failed to parse timeline");
+
+    let simulation = Simulator::new(profile(&config)).run(&timeline);
+    let events: Vec<(u64, String)> = simulation
+        .events
+        .iter()
+        .map(|event| (event.time, event.action.to_string()))
+        .collect();
+    assert_eq!(
+        events,
+        vec![(200, "f18 down".to_string()), (260, "f18 up".to_string())]
+    );
+}
+
+#[test]
+fn test_held_down_not_reached_is_alone() {
+    let config = fixture_config("keymap.json");
+
+    assert_eq!(
+        simulate(profile(&config), "tab"),
+        vec!["tab down", "tab up"]
+    );
+}
+
+#[test]
+fn test_simultaneous_within_threshold() {
+    let config = fixture_config("keymap.json");
+
+    assert_eq!(
+        simulate(profile(&config), "j+k"),
+        vec!["escape down", "escape up"]
+    );
+    // key_down_order defaults to insensitive.
+    assert_eq!(
+        simulate(profile(&config), "k+j"),
+        vec!["escape down", "escape up"]
+    );
+}
+
+#[test]
+fn test_simultaneous_outside_threshold_passes_through() {
+    let config = fixture_config("keymap.json");
+
+    assert_eq!(
+        simulate(profile(&config), "j, wait 100, k"),
+        vec!["j down", "j up", "k down", "k up"]
+    );
+}
+
+#[test]
+fn test_simultaneous_strict_key_down_order() {
+    let config = config(
+        r#"{"profiles": [{"name": "Strict", "complex_modifications": {"rules": [{
+            "description": "j then k",
+            "manipulators": [{
+                "type": "basic",
+                "from": {
+                    "simultaneous": [{"key_code": "j"}, {"key_code": "k"}],
+                    "simultaneous_options": {"key_down_order": "strict"}
+                },
+                "to": [{"key_code": "escape"}]
+            }]
+        }]}}]}"#,
+    );
+
+    assert_eq!(
+        simulate(profile(&config), "j+k"),
+        vec!["escape down", "escape up"]
+    );
+    assert_eq!(
+        simulate(profile(&config), "k+j"),
+        vec!["k down", "j down", "j up", "k up"]
+    );
+}
+
+#[test]
+fn test_key_up_value_resets_layer_variable() {
+    let config = fixture_config("keymap.json");
+    let timeline: Timeline = "spacebar down, h, spacebar up, h"
+        .parse()
+        .expect("Failed to parse timeline");
+
+    let simulation = Simulator::new(profile(&config)).run(&timeline);
+    let events: Vec<String> = simulation
+        .events
+        .iter()
+        .map(|event| event.action.to_string())
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            "set nav = 1",
+            "left_arrow down",
+            "left_arrow up",
+            "set nav = 0",
+            "h down",
+            "h up"
+        ]
+    );
+    assert_eq!(simulation.variables["nav"], VariableValue::Int(0));
+}
+
+#[test]
+fn test_key_up_value_not_applied_while_held() {
+    let config = fixture_config("keymap.json");
+    let timeline: Timeline = "spacebar down, l"
+        .parse()
+        .expect("Failed to parse timeline");
+
+    let simulation = Simulator::new(profile(&config)).run(&timeline);
+    assert_eq!(simulation.variables["nav"], VariableValue::Int(1));
+}
+
+#[test]
+fn test_initial_variables_satisfy_conditions() {
+    let config = fixture_config("keymap.json");
+    let timeline: Timeline = "h".parse().expect("Failed to parse timeline");
+
+    let simulation = Simulator::new(profile(&config))
+        .with_environment(Environment {
+            variables: BTreeMap::from([("nav".to_string(), VariableValue::Int(1))]),
+            ..Default::default()
+        })
+        .run(&timeline);
+    assert_eq!(
+        simulation.events[0].action.to_string(),
+        "left_arrow down".to_string()
+    );
+}
+
+#[test]
+fn test_mandatory_modifiers_and_shell_command() {
+    let config = fixture_config("keymap.json");
+
+    assert!(simulate(profile(&config), "left_command+left_option+t")
+        .contains(&"shell: open -a Terminal".to_string()));
+    assert_eq!(simulate(profile(&config), "t"), vec!["t down", "t up"]);
+}
+
+#[test]
+fn test_simple_modifications_rename_keys() {
+    let config = fixture_config("keymap.json");
+
+    assert_eq!(
+        simulate(profile(&config), "right_command"),
+        vec!["right_option down", "right_option up"]
+    );
+}
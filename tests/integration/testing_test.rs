@@ -0,0 +1,129 @@
+use crate::helpers::{fixture_config, fixture_path, profile};
+use ankura::compiler::Compiler;
+use ankura::error::KarabinerPklError;
+use ankura::testing::{collect_suites, CaseResult, Suite};
+use pretty_assertions::assert_eq;
+
+fn run_suite(suite: &Suite) -> Vec<CaseResult> {
+    let config = fixture_config("keymap.json");
+    suite.run(profile(&config))
+}
+
+fn failures(results: &[CaseResult]) -> Vec<(&str, Vec<&str>)> {
+    results
+        .iter()
+        .filter(|result| !result.passed())
+        .map(|result| {
+            let failures = result.failures.iter().map(String::as_str).collect();
+            (result.name.as_str(), failures)
+        })
+        .collect()
+}
+
+#[test]
+fn test_toml_suite_passes() {
+    let suite = Suite::read(&fixture_path("suites/keymap.toml")).expect("Failed to read suite");
+    let results = run_suite(&suite);
+
+    assert_eq!(results.len(), 6);
+    assert_eq!(failures(&results), vec![]);
+}
+
+#[test]
+fn test_json_suite_passes() {
+    let suite = Suite::read(&fixture_path("suites/keymap.json")).expect("Failed to read suite");
+    let results = run_suite(&suite);
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(failures(&results), vec![]);
+}
+
+#[tokio::test]
+async fn test_pkl_suite_passes() {
+    // Evaluating a Pkl suite needs the pkl CLI.
+    let Ok(compiler) = Compiler::new() else {
+        eprintln!("pkl not found; skipping");
+        return;
+    };
+    let suite = Suite::load(&fixture_path("suites/keymap.pkl"), &compiler)
+        .await
+        .expect("Failed to load suite");
+    let results = run_suite(&suite);
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(failures(&results), vec![]);
+}
+
+#[test]
+fn test_failing_suite_reports_each_failure() {
+    let suite = Suite::read(&fixture_path("suites/failing.toml")).expect("Failed to read suite");
+    let results = run_suite(&suite);
+
+    let mut reported = failures(&results);
+    let unknown_key = reported.pop().expect("Unknown key case");
+    assert_eq!(
+        reported,
+        vec![
+            (
+                "Missing event",
+                vec!["`tab` was not emitted after `escape`"]
+            ),
+            ("Not exact", vec!["expected exactly [`escape down`]"]),
+            ("Unwanted event", vec!["`escape` was emitted at 10ms"]),
+            ("Wrong variable", vec!["variable `nav` is 1, expected 0"]),
+            (
+                "Missing shell command",
+                vec!["`open -a Terminal` was not emitted"]
+            ),
+        ]
+    );
+    assert_eq!(unknown_key.0, "Unknown key");
+    assert!(unknown_key.1[0].contains("caps_lok"));
+}
+
+#[test]
+fn test_unknown_field_is_rejected() {
+    let path = fixture_path("suites/unknown_field.toml");
+    let error = Suite::read(&path).unwrap_err();
+
+    match error {
+        KarabinerPklError::InvalidTestSuite {
+            path: error_path,
+            message,
+        } => {
+            assert_eq!(error_path, path);
+            assert!(message.contains("unknown field `expected`"), "{message}");
+        }
+        other => panic!("Expected InvalidTestSuite, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_pkl_suite_needs_compiler() {
+    assert!(matches!(
+        Suite::read(&fixture_path("suites/keymap.pkl")),
+        Err(KarabinerPklError::InvalidTestSuite { .. })
+    ));
+}
+
+#[test]
+fn test_collect_suites_skips_json_in_directories() {
+    let dir = fixture_path("suites");
+    let suites =
+        collect_suites(&[dir.clone(), dir.join("keymap.json")]).expect("Failed to collect suites");
+
+    let names: Vec<String> = suites
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            "failing.toml",
+            "keymap.pkl",
+            "keymap.toml",
+            "unknown_field.toml",
+            "keymap.json"
+        ]
+    );
+}